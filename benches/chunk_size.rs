@@ -0,0 +1,68 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::sync::Arc;
+use streamapp::client::client_manager::ClientInterface;
+use streamapp::network::common::LengthDelimitedCodecConfig;
+use streamapp::server::server_manager::Server;
+
+const ADDRESS: &str = "localhost";
+const BASE_PORT: u16 = 8300;
+const PATH_INPUT: &str = "/tmp/bench_chunk_size.wav";
+// Ten seconds of 48 kHz stereo 16-bit audio, streamed over loopback.
+const CHANNELS: u16 = 2;
+const SAMPLE_RATE: u32 = 48000;
+const SECONDS: u32 = 10;
+const CHUNK_SIZES: [usize; 4] = [512, 4096, 16384, 65536];
+// Large enough for frames of the largest chunk, on both ends
+const CODEC_CONFIG: LengthDelimitedCodecConfig = LengthDelimitedCodecConfig {
+    max_frame_length: 128 * 1024,
+};
+
+fn write_input() -> u64 {
+    let spec = hound::WavSpec {
+        channels: CHANNELS,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(PATH_INPUT, spec).unwrap();
+    let samples = SAMPLE_RATE * SECONDS * CHANNELS as u32;
+    for i in 0..samples {
+        writer.write_sample((i % 2000) as i16 - 1000).unwrap();
+    }
+    writer.finalize().unwrap();
+    samples as u64 * 2
+}
+
+async fn stream(port: u16) {
+    let mut client = ClientInterface::connect(ADDRESS.to_string(), port)
+        .await
+        .unwrap();
+    client.set_codec_config(CODEC_CONFIG);
+    client.start_playing().await.unwrap();
+}
+
+fn bench_chunk_size(c: &mut Criterion) {
+    let audio_bytes = write_input();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("chunk_size");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(audio_bytes));
+    for (port, chunk_size) in (BASE_PORT..).zip(CHUNK_SIZES) {
+        runtime.block_on(async {
+            let mut server = Server::new(ADDRESS.to_string(), port, PATH_INPUT.to_string())
+                .await
+                .unwrap();
+            server.set_codec_config(CODEC_CONFIG).unwrap();
+            server.set_chunk_size(chunk_size).unwrap();
+            tokio::spawn(Arc::new(server).run());
+        });
+        group.bench_function(chunk_size.to_string(), |b| {
+            b.iter(|| runtime.block_on(stream(port)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_chunk_size);
+criterion_main!(benches);
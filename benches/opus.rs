@@ -0,0 +1,30 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use streamapp::audio::opus::{OPUS_FRAME_DURATION_MS, OpusDecoder, OpusEncoder};
+
+// One 10 ms frame of 48 kHz stereo audio, the unit the server sends. The
+// round trip should stay far below the 20 ms budget it adds to latency.
+const CHANNELS: u8 = 2;
+const SAMPLE_RATE: u32 = 48000;
+
+fn bench_opus(c: &mut Criterion) {
+    let frames = (SAMPLE_RATE * OPUS_FRAME_DURATION_MS / 1000) as usize;
+    let samples: Vec<f32> = (0..frames * CHANNELS as usize)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / SAMPLE_RATE as f32).sin() * 0.5)
+        .collect();
+    let mut encoder = OpusEncoder::new(SAMPLE_RATE, CHANNELS).unwrap();
+    let mut decoder = OpusDecoder::new(SAMPLE_RATE, CHANNELS).unwrap();
+
+    let mut group = c.benchmark_group("opus");
+    group.bench_function("encode", |b| b.iter(|| encoder.encode(&samples).unwrap()));
+    group.bench_function("round_trip", |b| {
+        b.iter(|| {
+            for packet in encoder.encode(&samples).unwrap() {
+                decoder.decode(&packet).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_opus);
+criterion_main!(benches);
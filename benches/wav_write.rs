@@ -0,0 +1,60 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use streamapp::audio::file::AudioWriter;
+use streamapp::audio::wav::{DEFAULT_WRITE_BATCH_SIZE, WavFileWrite};
+use streamapp::protocol::AudioHeader;
+
+const PATH_OUTPUT: &str = "/tmp/bench_wav_write.wav";
+// One second of 8 channel, 192 kHz audio delivered in 4 KiB frames, as
+// 16-bit samples, which hound writes in batches, and 32-bit ones, which it
+// writes one at a time.
+const CHANNELS: u16 = 8;
+const SAMPLE_RATE: u32 = 192_000;
+const FRAME_SIZE: usize = 4096;
+
+fn write_stream(batch_size: usize, header: &AudioHeader, data: &[u8]) {
+    let mut writer = WavFileWrite::with_batch_size(PATH_OUTPUT.to_string(), batch_size);
+    writer.update_format(header).unwrap();
+    for frame in data.chunks(FRAME_SIZE) {
+        writer.write(frame).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+fn bench_wav_write(c: &mut Criterion) {
+    let samples = SAMPLE_RATE * CHANNELS as u32;
+    let streams = [
+        (
+            16,
+            (0..samples)
+                .flat_map(|i| (i as i16).to_le_bytes())
+                .collect::<Vec<u8>>(),
+        ),
+        (
+            32,
+            (0..samples)
+                .flat_map(|i| (i as i32).to_le_bytes())
+                .collect(),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("wav_write");
+    group.sample_size(10);
+    for (bits, data) in &streams {
+        let header = AudioHeader::from_wav_spec(&hound::WavSpec {
+            channels: CHANNELS,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: *bits,
+            sample_format: hound::SampleFormat::Int,
+        });
+        group.bench_function(format!("unbatched_{}bit", bits), |b| {
+            b.iter(|| write_stream(0, &header, data))
+        });
+        group.bench_function(format!("batched_{}bit", bits), |b| {
+            b.iter(|| write_stream(DEFAULT_WRITE_BATCH_SIZE, &header, data))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_wav_write);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use streamapp::protocol::{self, Message};
+
+// Every frame a peer sends goes through these, so no input may make them
+// panic: malformed frames must come back as errors.
+fuzz_target!(|data: &[u8]| {
+    let _ = protocol::extract_message_type(data);
+    if let Ok(message) = Message::decode(data) {
+        // Whatever decodes encodes back to a frame that decodes the same
+        assert_eq!(Message::decode(&message.encode()), Ok(message));
+    }
+    if let [min_a, max_a, min_b, max_b, ..] = *data {
+        let _ = protocol::negotiate_version((min_a, max_a), (min_b, max_b));
+    }
+});
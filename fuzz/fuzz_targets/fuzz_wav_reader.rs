@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::wav::WavFileRead;
+use streamapp::protocol::AudioHeader;
+
+// Served files are only as trustworthy as whoever put them there: a
+// malformed WAV file must fail to open or to read, never panic.
+fuzz_target!(|data: &[u8]| {
+    let path = std::env::temp_dir().join(format!("fuzz_wav_reader_{}.wav", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+
+    let mut reader = WavFileRead::new();
+    if reader.open_file(path.to_str().unwrap()).is_ok() {
+        let mut header = AudioHeader::new();
+        reader.update_header(&mut header);
+        let _ = reader.total_samples();
+        let mut buffer = [0u8; 4096];
+        while let Ok(n) = reader.read(&mut buffer) {
+            if n == 0 {
+                break;
+            }
+        }
+        let _ = reader.seek_to_sample(0);
+    }
+    let _ = std::fs::remove_file(&path);
+});
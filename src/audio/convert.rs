@@ -0,0 +1,160 @@
+use anyhow::Result;
+use cpal::{FromSample, Sample};
+
+use crate::protocol::{AudioHeader, SampleFormat};
+
+// Decodes interleaved little-endian PCM bytes described by `header` into f32
+// samples in [-1.0, 1.0]. Trailing bytes that do not form a whole sample are
+// ignored.
+pub fn bytes_to_f32(data: &[u8], header: &AudioHeader) -> Result<Vec<f32>> {
+    let samples = match (header.get_sample_format(), header.get_bits_per_sample()) {
+        (SampleFormat::Int, 16) => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]).to_sample::<f32>())
+            .collect(),
+        // Placed in the upper bytes of an i32, which keeps the full scale
+        (SampleFormat::Int, 24) => data
+            .chunks_exact(3)
+            .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]).to_sample::<f32>())
+            .collect(),
+        (SampleFormat::Int, 32) => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]).to_sample::<f32>())
+            .collect(),
+        (SampleFormat::Float, 32) => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        (SampleFormat::Float64, 64) => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+            .collect(),
+        (format, bits) => {
+            return Err(anyhow::anyhow!(
+                "Unsupported sample format for conversion: {:?} {} bits",
+                format,
+                bits
+            ));
+        }
+    };
+    Ok(samples)
+}
+
+// Scales interleaved little-endian PCM bytes in place. Integer samples
+// saturate at their range and float ones are clamped to [-1.0, 1.0], so gains
+// above 1.0 cannot wrap around.
+pub fn apply_gain(data: &mut [u8], header: &AudioHeader, gain: f32) -> Result<()> {
+    match (header.get_sample_format(), header.get_bits_per_sample()) {
+        (SampleFormat::Int, 16) => {
+            for b in data.chunks_exact_mut(2) {
+                let sample = i16::from_le_bytes([b[0], b[1]]);
+                // Float to integer casts saturate
+                let scaled = (sample as f32 * gain).round() as i16;
+                b.copy_from_slice(&scaled.to_le_bytes());
+            }
+        }
+        (SampleFormat::Int, 24) => {
+            for b in data.chunks_exact_mut(3) {
+                let sample = i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8;
+                let scaled = (sample as f32 * gain)
+                    .round()
+                    .clamp(-8_388_608.0, 8_388_607.0) as i32;
+                b.copy_from_slice(&scaled.to_le_bytes()[..3]);
+            }
+        }
+        (SampleFormat::Int, 32) => {
+            for b in data.chunks_exact_mut(4) {
+                let sample = i32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                // f64 keeps the full 32-bit precision
+                let scaled = (sample as f64 * gain as f64).round() as i32;
+                b.copy_from_slice(&scaled.to_le_bytes());
+            }
+        }
+        (SampleFormat::Float, 32) => {
+            for b in data.chunks_exact_mut(4) {
+                let sample = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                let scaled = (sample * gain).clamp(-1.0, 1.0);
+                b.copy_from_slice(&scaled.to_le_bytes());
+            }
+        }
+        (SampleFormat::Float64, 64) => {
+            for b in data.chunks_exact_mut(8) {
+                let sample = f64::from_le_bytes((&*b).try_into().unwrap());
+                let scaled = (sample * gain as f64).clamp(-1.0, 1.0);
+                b.copy_from_slice(&scaled.to_le_bytes());
+            }
+        }
+        (format, bits) => {
+            return Err(anyhow::anyhow!(
+                "Unsupported sample format for volume control: {:?} {} bits",
+                format,
+                bits
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Maps interleaved audio between channel layouts, `convert` for whole
+// frames at once and `process` for a stream split anywhere.
+pub struct ChannelConverter {
+    in_ch: usize,
+    out_ch: usize,
+    // Samples of a frame split across two chunks
+    partial: Vec<f32>,
+}
+
+impl ChannelConverter {
+    pub fn new(in_ch: usize, out_ch: usize) -> Self {
+        Self {
+            in_ch,
+            out_ch,
+            partial: Vec::new(),
+        }
+    }
+
+    // Converts the whole frames of what was passed so far. A trailing
+    // partial frame is kept until the next call completes it.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let frame = self.in_ch.max(1);
+        if self.partial.is_empty() && input.len().is_multiple_of(frame) {
+            return Self::convert(input, self.in_ch, self.out_ch);
+        }
+        self.partial.extend_from_slice(input);
+        let whole = self.partial.len() / frame * frame;
+        let output = Self::convert(&self.partial[..whole], self.in_ch, self.out_ch);
+        self.partial.drain(..whole);
+        output
+    }
+
+    // Mono output averages every input channel and mono input is copied to
+    // every output channel. Between other layouts the first channels are kept
+    // and missing ones are silent. `input` holds whole frames: a trailing
+    // partial one is left out, see `process`.
+    pub fn convert<T>(input: &[T], in_ch: usize, out_ch: usize) -> Vec<T>
+    where
+        T: Sample + FromSample<f32>,
+        f32: FromSample<T>,
+    {
+        if in_ch == out_ch || in_ch == 0 || out_ch == 0 {
+            return input.to_vec();
+        }
+        let frames = input.chunks_exact(in_ch);
+        let mut output = Vec::with_capacity(frames.len() * out_ch);
+        for frame in frames {
+            if out_ch == 1 {
+                let sum: f32 = frame.iter().map(|s| s.to_sample::<f32>()).sum();
+                output.push(T::from_sample(sum / in_ch as f32));
+            } else if in_ch == 1 {
+                output.extend(std::iter::repeat_n(frame[0], out_ch));
+            } else {
+                output.extend((0..out_ch).map(|c| frame.get(c).copied().unwrap_or(T::EQUILIBRIUM)));
+            }
+        }
+        output
+    }
+}
+
+pub fn f32_to_bytes(samples: &[f32]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
@@ -1,36 +1,227 @@
 use anyhow::Result;
+use bytes::Bytes;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Device, FromSample, Sample};
-use std::collections::VecDeque;
 use std::fs::File;
-use std::io::BufWriter;
-use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
 use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
 
+use crate::audio::convert::{ChannelConverter, bytes_to_f32, f32_to_bytes};
+use crate::audio::crossfade::Crossfader;
 use crate::audio::file::{AudioPlayer, AudioRecorder, AudioWriter, FileFormat};
+use crate::audio::resample::Resampler;
+use crate::audio::split::{AutoSplit, SplitWavWriter};
+use crate::audio::vad::VadGate;
 use crate::protocol::AudioHeader;
 
 pub struct CpalInterface;
 
+// What `CpalInterface::record_with_config` records: `duration` seconds from
+// the input device called `device_name`, or the default one, into `path`.
+// With `vad_threshold`, only what a `VadGate` with that threshold admits is
+// kept.
+#[derive(Debug, Clone)]
+pub struct CpalRecorderConfig {
+    pub device_name: Option<String>,
+    pub duration: u64,
+    pub path: String,
+    pub format: FileFormat,
+    pub vad_threshold: Option<f32>,
+}
+
 impl AudioPlayer for CpalInterface {
     fn play_from_file(&self, file_path: &str, format: FileFormat) -> Result<()> {
-        match format {
-            FileFormat::Wav => play_audio_from_wav(file_path),
-        }
+        play_from_file(file_path, format, None)
     }
 }
 
 impl AudioRecorder for CpalInterface {
-    fn record_into_file(
+    async fn record_into_file(&self, duration: u64, path: &str, format: FileFormat) -> Result<()> {
+        record_into_file(duration, path, format, None, None, |_, _| {}).await
+    }
+}
+
+impl CpalInterface {
+    // Records like `record_into_file`, starting a new numbered file after
+    // every silent passage. Returns the paths of the recorded takes.
+    pub async fn record_with_auto_split(
         &self,
         duration: u64,
         path: &str,
-        format: FileFormat,
-    ) -> impl std::future::Future<Output = Result<()>> + Send {
-        match format {
-            FileFormat::Wav => record_audio(duration, path),
+        auto_split: AutoSplit,
+    ) -> Result<Vec<String>> {
+        record_audio(duration, path, Some(auto_split), None).await
+    }
+
+    // Fails before recording anything if no input device is called
+    // `config.device_name`.
+    pub async fn record_with_config(config: CpalRecorderConfig) -> Result<()> {
+        Self::record_with_config_and_progress(config, |_, _| {}).await
+    }
+
+    // Same as `record_with_config`, see `record_audio_with_progress`.
+    pub async fn record_with_config_and_progress(
+        config: CpalRecorderConfig,
+        progress_fn: impl Fn(u64, u64) + Send + 'static,
+    ) -> Result<()> {
+        if let Some(name) = &config.device_name {
+            check_device_name(name, &input_device_names()?)?;
         }
+        record_into_file(
+            config.duration,
+            &config.path,
+            config.format,
+            config.device_name.as_deref(),
+            config.vad_threshold,
+            progress_fn,
+        )
+        .await
+    }
+
+    pub fn list_output_devices() -> Result<Vec<String>> {
+        output_device_names()
+    }
+
+    pub fn list_input_devices() -> Result<Vec<String>> {
+        input_device_names()
+    }
+
+    // Plays on the output device called `name` instead of the default one.
+    // Fails if no such device is available.
+    pub fn with_output_device(name: &str) -> Result<CpalInterfaceWithDevice> {
+        CpalInterfaceWithDevice::default().with_output_device(name)
+    }
+
+    // Records from the input device called `name` instead of the default one.
+    // Fails if no such device is available.
+    pub fn with_input_device(name: &str) -> Result<CpalInterfaceWithDevice> {
+        CpalInterfaceWithDevice::default().with_input_device(name)
+    }
+}
+
+// CpalInterface bound to given devices. Devices left unset are the default
+// ones.
+#[derive(Debug, Clone, Default)]
+pub struct CpalInterfaceWithDevice {
+    output_device: Option<String>,
+    input_device: Option<String>,
+}
+
+impl CpalInterfaceWithDevice {
+    pub fn with_output_device(mut self, name: &str) -> Result<Self> {
+        check_device_name(name, &output_device_names()?)?;
+        self.output_device = Some(name.to_string());
+        Ok(self)
+    }
+
+    pub fn with_input_device(mut self, name: &str) -> Result<Self> {
+        check_device_name(name, &input_device_names()?)?;
+        self.input_device = Some(name.to_string());
+        Ok(self)
+    }
+
+    // See `record_audio_until_signal`.
+    pub async fn record_until_signal(
+        &self,
+        stop_rx: tokio::sync::watch::Receiver<bool>,
+        path: &str,
+    ) -> Result<()> {
+        record_audio_until(stop_rx, path, None, self.input_device.as_deref(), None).await?;
+        Ok(())
+    }
+
+    // See `CpalInterface::record_with_auto_split`.
+    pub async fn record_with_auto_split(
+        &self,
+        duration: u64,
+        path: &str,
+        auto_split: AutoSplit,
+    ) -> Result<Vec<String>> {
+        record_audio(
+            duration,
+            path,
+            Some(auto_split),
+            self.input_device.as_deref(),
+        )
+        .await
+    }
+}
+
+impl AudioPlayer for CpalInterfaceWithDevice {
+    fn play_from_file(&self, file_path: &str, format: FileFormat) -> Result<()> {
+        play_from_file(file_path, format, self.output_device.as_deref())
+    }
+}
+
+impl AudioRecorder for CpalInterfaceWithDevice {
+    async fn record_into_file(&self, duration: u64, path: &str, format: FileFormat) -> Result<()> {
+        record_into_file(
+            duration,
+            path,
+            format,
+            self.input_device.as_deref(),
+            None,
+            |_, _| {},
+        )
+        .await
+    }
+}
+
+fn check_device_name(name: &str, available: &[String]) -> Result<()> {
+    if !available.iter().any(|device| device == name) {
+        return Err(anyhow::anyhow!(
+            "No audio device named '{}', available: {}",
+            name,
+            available.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+fn play_from_file(file_path: &str, format: FileFormat, device: Option<&str>) -> Result<()> {
+    match format {
+        FileFormat::Wav => play_audio_from_wav(file_path, device),
+        FileFormat::Flac => Err(anyhow::anyhow!("Playing FLAC files is not supported")),
+        FileFormat::Mp3 => Err(anyhow::anyhow!("Playing MP3 files is not supported")),
+        FileFormat::Ogg => Err(anyhow::anyhow!("Playing OGG files is not supported")),
+        FileFormat::Opus => Err(anyhow::anyhow!("Playing Opus files is not supported")),
+    }
+}
+
+async fn record_into_file(
+    duration: u64,
+    path: &str,
+    format: FileFormat,
+    device: Option<&str>,
+    vad_threshold: Option<f32>,
+    progress_fn: impl Fn(u64, u64) + Send + 'static,
+) -> Result<()> {
+    match format {
+        FileFormat::Wav => {
+            record_audio_reporting(duration, path, None, device, vad_threshold, progress_fn)
+                .await?;
+            Ok(())
+        }
+        FileFormat::Flac => Err(anyhow::anyhow!("Recording to FLAC is not supported")),
+        FileFormat::Mp3 => Err(anyhow::anyhow!("Recording to MP3 is not supported")),
+        FileFormat::Ogg => Err(anyhow::anyhow!("Recording to OGG is not supported")),
+        FileFormat::Opus => Err(anyhow::anyhow!("Recording to Opus is not supported")),
+    }
+}
+
+// Device called `name`, or the default one when `name` is None.
+fn find_device(
+    mut devices: impl Iterator<Item = Device>,
+    name: Option<&str>,
+    default: Option<Device>,
+) -> Result<Device> {
+    match name {
+        Some(name) => devices
+            .find(|device| device.name().is_ok_and(|n| n == name))
+            .ok_or_else(|| anyhow::anyhow!("No audio device named '{}'", name)),
+        None => default.ok_or_else(|| anyhow::anyhow!("No default audio device available")),
     }
 }
 
@@ -51,7 +242,7 @@ where
 
     let mut samples_iter = samples.into_iter();
 
-    let err_fn = move |err| eprintln!("an error occurred on stream: {err}");
+    let err_fn = move |err| tracing::error!("an error occurred on stream: {err}");
 
     let stream = device.build_output_stream(
         &config,
@@ -60,7 +251,7 @@ where
                 *sample = samples_iter.next().unwrap_or(T::EQUILIBRIUM);
             }
             if samples_iter.len() == 0 {
-                tx.send(()).unwrap();
+                let _ = tx.send(());
             }
         },
         err_fn,
@@ -69,34 +260,30 @@ where
 
     stream.play()?;
 
-    rx.recv().unwrap();
+    rx.recv()
+        .map_err(|_| anyhow::anyhow!("Playback stream stopped before the end of the file"))?;
 
     Ok(())
 }
 
-pub fn play_audio_from_wav(path: &str) -> Result<()> {
+// Plays the file on the output device called `device`, or on the default one.
+pub fn play_audio_from_wav(path: &str, device: Option<&str>) -> Result<()> {
     let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .ok_or_else(|| anyhow::anyhow!("No output device available"))?;
-    println!("Output device: {}", device.name()?);
+    let device = find_device(host.output_devices()?, device, host.default_output_device())?;
+    tracing::info!("Output device: {}", device.name()?);
 
     let reader: hound::WavReader<std::io::BufReader<File>> = hound::WavReader::open(path)?;
     let spec = reader.spec();
-    let config = cpal::StreamConfig {
-        channels: spec.channels as u16,
-        sample_rate: cpal::SampleRate(spec.sample_rate),
-        buffer_size: cpal::BufferSize::Default,
-    };
+    let config = AudioHeader::from(&spec).to_cpal_config();
     match spec.sample_format {
         hound::SampleFormat::Float => match spec.bits_per_sample {
             32 => play_audio_wav_file::<f32>(reader, device, config),
-            _ => unimplemented!(),
+            bits => Err(anyhow::anyhow!("Unsupported bits per sample: {}", bits)),
         },
         hound::SampleFormat::Int => match spec.bits_per_sample {
             32 => play_audio_wav_file::<i32>(reader, device, config),
             16 => play_audio_wav_file::<i16>(reader, device, config),
-            _ => unimplemented!(),
+            bits => Err(anyhow::anyhow!("Unsupported bits per sample: {}", bits)),
         },
     }
 }
@@ -109,10 +296,11 @@ fn build_record_stream<T, U>(
 where
     T: cpal::Sample + FromSample<U> + cpal::SizedSample,
     U: cpal::Sample + hound::Sample + cpal::FromSample<T>,
+    f32: FromSample<T>,
 {
     let writer_2 = writer.clone();
     let err_fn = move |err| {
-        eprintln!("an error occurred on stream: {err}");
+        tracing::error!("an error occurred on stream: {err}");
     };
     device
         .build_input_stream(
@@ -124,20 +312,86 @@ where
         .map_err(anyhow::Error::from)
 }
 
-async fn record_audio(duration: u64, path: &str) -> Result<()> {
+// Records `duration` seconds, see `record_audio_until`.
+async fn record_audio(
+    duration: u64,
+    path: &str,
+    auto_split: Option<AutoSplit>,
+    device: Option<&str>,
+) -> Result<Vec<String>> {
+    record_audio_reporting(duration, path, auto_split, device, None, |_, _| {}).await
+}
+
+// Records `duration` seconds from the default input device into the WAV file
+// at `path`, calling `progress_fn` with the seconds elapsed and `duration`
+// every second.
+pub async fn record_audio_with_progress(
+    duration: u64,
+    path: &str,
+    progress_fn: impl Fn(u64, u64) + Send + 'static,
+) -> Result<()> {
+    record_audio_reporting(duration, path, None, None, None, progress_fn).await?;
+    Ok(())
+}
+
+async fn record_audio_reporting(
+    duration: u64,
+    path: &str,
+    auto_split: Option<AutoSplit>,
+    device: Option<&str>,
+    vad_threshold: Option<f32>,
+    progress_fn: impl Fn(u64, u64) + Send + 'static,
+) -> Result<Vec<String>> {
+    let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+    let timer = tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(Duration::from_secs(1));
+        // The first tick is immediate
+        ticks.tick().await;
+        for elapsed in 1..=duration {
+            ticks.tick().await;
+            progress_fn(elapsed, duration);
+        }
+        let _ = stop_tx.send(true);
+    });
+    let result = record_audio_until(stop_rx, path, auto_split, device, vad_threshold).await;
+    // Recording may fail before the end, e.g. without an input device
+    timer.abort();
+    result
+}
+
+// Records from the default input device into the WAV file at `path` until
+// `stop_rx` turns true or its sender is dropped.
+pub async fn record_audio_until_signal(
+    stop_rx: tokio::sync::watch::Receiver<bool>,
+    path: &str,
+) -> Result<()> {
+    record_audio_until(stop_rx, path, None, None, None).await?;
+    Ok(())
+}
+
+async fn record_audio_until(
+    mut stop_rx: tokio::sync::watch::Receiver<bool>,
+    path: &str,
+    auto_split: Option<AutoSplit>,
+    device: Option<&str>,
+    vad_threshold: Option<f32>,
+) -> Result<Vec<String>> {
     let host = cpal::default_host();
 
-    let device = host.default_input_device().unwrap();
+    let device = find_device(host.input_devices()?, device, host.default_input_device())?;
 
-    println!("Input device: {}", device.name()?);
+    tracing::info!("Input device: {}", device.name()?);
 
     let config = device.default_input_config()?;
 
-    let spec = wav_spec_from_config(&config);
-    let writer = hound::WavWriter::create(path, spec)?;
+    let spec = AudioHeader::from(&config).to_wavspec();
+    let mut writer = SplitWavWriter::new(path, spec, auto_split)?;
+    if let Some(threshold) = vad_threshold {
+        writer = writer.with_vad_gate(VadGate::for_sample_rate(threshold, spec.sample_rate));
+    }
     let writer = Arc::new(Mutex::new(Some(writer)));
 
-    println!("Begin recording...");
+    tracing::info!("Begin recording...");
 
     let stream = match config.sample_format() {
         cpal::SampleFormat::I8 => build_record_stream::<i8, i8>(&device, &config, &writer),
@@ -153,190 +407,803 @@ async fn record_audio(duration: u64, path: &str) -> Result<()> {
 
     stream.play()?;
 
-    tokio::time::sleep(std::time::Duration::from_secs(duration)).await;
+    // A dropped sender stops the recording too
+    let _ = stop_rx.wait_for(|stop| *stop).await;
     drop(stream);
-    writer.lock().unwrap().take().unwrap().finalize()?;
-    println!("Recording {path} complete!");
-    Ok(())
+    let files = writer
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Recording writer lock poisoned"))?
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("Recording writer already finalized"))?
+        .finalize()?;
+    tracing::info!("Recording {path} complete!");
+    Ok(files)
 }
 
-fn sample_format(format: cpal::SampleFormat) -> hound::SampleFormat {
-    if format.is_float() {
-        hound::SampleFormat::Float
-    } else {
-        hound::SampleFormat::Int
+// Microphone capture started by `capture_live`, stopped when dropped.
+pub struct LiveCapture {
+    header: AudioHeader,
+    stop_tx: mpsc::Sender<()>,
+}
+
+impl LiveCapture {
+    pub fn header(&self) -> AudioHeader {
+        self.header
     }
 }
 
-fn wav_spec_from_config(config: &cpal::SupportedStreamConfig) -> hound::WavSpec {
-    hound::WavSpec {
-        channels: config.channels() as _,
-        sample_rate: config.sample_rate().0 as _,
-        bits_per_sample: (config.sample_format().sample_size() * 8) as _,
-        sample_format: sample_format(config.sample_format()),
+impl Drop for LiveCapture {
+    fn drop(&mut self) {
+        let _ = self.stop_tx.send(());
     }
 }
 
-type WavWriterHandle = Arc<Mutex<Option<hound::WavWriter<BufWriter<File>>>>>;
+fn build_live_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    sender: tokio::sync::broadcast::Sender<Bytes>,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample,
+    i16: FromSample<T>,
+{
+    let err_fn = move |err| {
+        tracing::error!("an error occurred on stream: {err}");
+    };
+    device
+        .build_input_stream(
+            &config.clone().into(),
+            move |data: &[T], _: &_| {
+                let bytes: Vec<u8> = data
+                    .iter()
+                    .flat_map(|&sample| i16::from_sample(sample).to_le_bytes())
+                    .collect();
+                // Fails only when nobody is subscribed
+                let _ = sender.send(Bytes::from(bytes));
+            },
+            err_fn,
+            None,
+        )
+        .map_err(anyhow::Error::from)
+}
+
+fn start_live_stream(
+    sender: tokio::sync::broadcast::Sender<Bytes>,
+) -> Result<(cpal::Stream, AudioHeader)> {
+    let host = cpal::default_host();
+    let device = host
+        .default_input_device()
+        .ok_or_else(|| anyhow::anyhow!("No input device available"))?;
+    tracing::info!("Input device: {}", device.name()?);
+
+    let config = device.default_input_config()?;
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::I8 => build_live_stream::<i8>(&device, &config, sender),
+        cpal::SampleFormat::I16 => build_live_stream::<i16>(&device, &config, sender),
+        cpal::SampleFormat::I32 => build_live_stream::<i32>(&device, &config, sender),
+        cpal::SampleFormat::F32 => build_live_stream::<f32>(&device, &config, sender),
+        sample_format => {
+            return Err(anyhow::anyhow!(
+                "Unsupported sample format '{sample_format}'"
+            ));
+        }
+    }?;
+    stream.play()?;
+
+    let header = AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels: config.channels(),
+        sample_rate: config.sample_rate().0,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    });
+    Ok((stream, header))
+}
+
+// Publishes what the default input device records to `sender`, as chunks of
+// 16-bit PCM in the format of the returned capture's header. The cpal stream
+// cannot leave the thread it was created on, so it lives on its own thread.
+pub fn capture_live(sender: tokio::sync::broadcast::Sender<Bytes>) -> Result<LiveCapture> {
+    let (ready_tx, ready_rx) = mpsc::channel();
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    std::thread::spawn(move || match start_live_stream(sender) {
+        Ok((stream, header)) => {
+            let _ = ready_tx.send(Ok(header));
+            // Returns on stop or when the capture is dropped
+            let _ = stop_rx.recv();
+            drop(stream);
+        }
+        Err(e) => {
+            let _ = ready_tx.send(Err(e));
+        }
+    });
+    let header = ready_rx
+        .recv()
+        .map_err(|_| anyhow::anyhow!("Live capture thread stopped unexpectedly"))??;
+    Ok(LiveCapture { header, stop_tx })
+}
+
+type WavWriterHandle = Arc<Mutex<Option<SplitWavWriter>>>;
 
 fn write_input_data<T, U>(input: &[T], writer: &WavWriterHandle)
 where
     T: Sample,
     U: Sample + hound::Sample + FromSample<T>,
+    f32: FromSample<T>,
 {
-    if let Ok(mut guard) = writer.try_lock() {
-        if let Some(writer) = guard.as_mut() {
-            for &sample in input.iter() {
-                let sample: U = U::from_sample(sample);
-                writer.write_sample(sample).ok();
-            }
+    if let Ok(mut guard) = writer.try_lock()
+        && let Some(writer) = guard.as_mut()
+    {
+        writer.write::<T, U>(input).ok();
+    }
+}
+
+pub fn output_device_names() -> Result<Vec<String>> {
+    let host = cpal::default_host();
+    Ok(host
+        .output_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+pub fn input_device_names() -> Result<Vec<String>> {
+    let host = cpal::default_host();
+    Ok(host
+        .input_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+// Returns the first device of the preference chain that is currently
+// available, or None when the default device should be used.
+pub fn select_output_device<'a>(preferred: &'a [String], available: &[String]) -> Option<&'a str> {
+    preferred
+        .iter()
+        .find(|name| available.contains(name))
+        .map(String::as_str)
+}
+
+// Samples an output stream asks for, in the format it was opened with.
+pub enum OutputBuffer<'a> {
+    I16(&'a mut [i16]),
+    I32(&'a mut [i32]),
+    F32(&'a mut [f32]),
+}
+
+// Fills each buffer of an output stream, from the audio thread.
+pub type OutputCallback = Box<dyn FnMut(OutputBuffer<'_>) + Send>;
+
+pub type StreamErrorCallback = Box<dyn FnMut(cpal::StreamError) + Send>;
+
+// Where CpalFileWrite plays: a cpal::Device, or anything standing in for a
+// sound card, e.g. in tests.
+pub trait OutputDevice {
+    fn device_name(&self) -> Result<String>;
+    // Native sample rate and channels
+    fn native_format(&self) -> Result<(u32, u16)>;
+    // Opens a stream of `format` samples, paused until played. `data` is
+    // called whenever the device needs more samples.
+    fn open_output_stream(
+        &self,
+        config: &cpal::StreamConfig,
+        format: cpal::SampleFormat,
+        data: OutputCallback,
+        error: StreamErrorCallback,
+    ) -> Result<Box<dyn OutputStream>>;
+}
+
+// Output stream opened by an `OutputDevice`, closed when dropped.
+pub trait OutputStream {
+    fn start(&self) -> Result<()>;
+    fn stop(&self) -> Result<()>;
+}
+
+impl OutputDevice for Device {
+    fn device_name(&self) -> Result<String> {
+        Ok(self.name()?)
+    }
+
+    fn native_format(&self) -> Result<(u32, u16)> {
+        let config = self.default_output_config()?;
+        Ok((config.sample_rate().0, config.channels()))
+    }
+
+    fn open_output_stream(
+        &self,
+        config: &cpal::StreamConfig,
+        format: cpal::SampleFormat,
+        mut data: OutputCallback,
+        error: StreamErrorCallback,
+    ) -> Result<Box<dyn OutputStream>> {
+        let stream = match format {
+            cpal::SampleFormat::I16 => self.build_output_stream(
+                config,
+                move |output: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                    data(OutputBuffer::I16(output))
+                },
+                error,
+                None,
+            )?,
+            cpal::SampleFormat::I32 => self.build_output_stream(
+                config,
+                move |output: &mut [i32], _: &cpal::OutputCallbackInfo| {
+                    data(OutputBuffer::I32(output))
+                },
+                error,
+                None,
+            )?,
+            cpal::SampleFormat::F32 => self.build_output_stream(
+                config,
+                move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    data(OutputBuffer::F32(output))
+                },
+                error,
+                None,
+            )?,
+            format => return Err(anyhow::anyhow!("Unsupported output format {}", format)),
+        };
+        Ok(Box::new(stream))
+    }
+}
+
+impl OutputStream for cpal::Stream {
+    fn start(&self) -> Result<()> {
+        Ok(self.play()?)
+    }
+
+    fn stop(&self) -> Result<()> {
+        Ok(self.pause()?)
+    }
+}
+
+// Samples output streams are opened with.
+trait OutputSample: Sized {
+    const FORMAT: cpal::SampleFormat;
+    fn output_slice(buffer: OutputBuffer<'_>) -> Option<&mut [Self]>;
+}
+
+impl OutputSample for i16 {
+    const FORMAT: cpal::SampleFormat = cpal::SampleFormat::I16;
+    fn output_slice(buffer: OutputBuffer<'_>) -> Option<&mut [Self]> {
+        match buffer {
+            OutputBuffer::I16(output) => Some(output),
+            _ => None,
+        }
+    }
+}
+
+impl OutputSample for i32 {
+    const FORMAT: cpal::SampleFormat = cpal::SampleFormat::I32;
+    fn output_slice(buffer: OutputBuffer<'_>) -> Option<&mut [Self]> {
+        match buffer {
+            OutputBuffer::I32(output) => Some(output),
+            _ => None,
+        }
+    }
+}
+
+impl OutputSample for f32 {
+    const FORMAT: cpal::SampleFormat = cpal::SampleFormat::F32;
+    fn output_slice(buffer: OutputBuffer<'_>) -> Option<&mut [Self]> {
+        match buffer {
+            OutputBuffer::F32(output) => Some(output),
+            _ => None,
         }
     }
 }
 
+// Output devices CpalFileWrite picks from.
+pub trait DeviceProvider: Send {
+    fn output_devices(&self) -> Result<Vec<Box<dyn OutputDevice>>>;
+    fn default_output_device(&self) -> Result<Box<dyn OutputDevice>>;
+}
+
+// Devices of a cpal host, the default host unless another one is given.
+pub struct CpalDeviceProvider {
+    host: cpal::Host,
+}
+
+impl CpalDeviceProvider {
+    pub fn new(host: cpal::Host) -> Self {
+        Self { host }
+    }
+}
+
+impl Default for CpalDeviceProvider {
+    fn default() -> Self {
+        Self::new(cpal::default_host())
+    }
+}
+
+impl DeviceProvider for CpalDeviceProvider {
+    fn output_devices(&self) -> Result<Vec<Box<dyn OutputDevice>>> {
+        Ok(self
+            .host
+            .output_devices()?
+            .map(|device| Box::new(device) as Box<dyn OutputDevice>)
+            .collect())
+    }
+
+    fn default_output_device(&self) -> Result<Box<dyn OutputDevice>> {
+        self.host
+            .default_output_device()
+            .map(|device| Box::new(device) as Box<dyn OutputDevice>)
+            .ok_or_else(|| anyhow::anyhow!("No output device available"))
+    }
+}
+
+// Audio buffered before playback starts when none is configured, enough to
+// absorb the jitter of a local network.
+pub const DEFAULT_PREBUFFER: Duration = Duration::from_millis(100);
+
+pub const DEFAULT_RING_BUFFER_CAPACITY: usize = 400_000;
+
+// Longest wait for the buffered audio to be played once the stream ended.
+pub const DEFAULT_FINALIZE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Consumer end of the playback ring, handed to each output stream built. The
+// audio callback only ever try-locks it: the lock is held elsewhere only while
+// a stream is being replaced, and the callback plays silence meanwhile
+// rather than waiting.
+type PlaybackConsumer = Arc<Mutex<rtrb::Consumer<u8>>>;
+
+// Largest frame the callback copies: 255 channels of 64-bit samples.
+const MAX_FRAME_SIZE: usize = u8::MAX as usize * 8;
+
+// Received audio goes through a lock-free ring buffer: `write` is its
+// producer and the output stream callback its consumer.
 pub struct CpalFileWrite {
-    buf: Arc<Mutex<VecDeque<u8>>>,
+    producer: rtrb::Producer<u8>,
+    // Audio written while the ring was full, pushed before anything else
+    overflow: Vec<u8>,
+    consumer: PlaybackConsumer,
     play_done_tx: mpsc::Sender<()>,
     play_done_rx: mpsc::Receiver<()>,
     first_play: AtomicBool,
-    stream: Option<cpal::Stream>,
+    stream: Option<Box<dyn OutputStream>>,
     header: Option<AudioHeader>,
+    fixed_output_rate: Option<u32>,
+    // Native rate of the output device, when it differs from the stream's
+    device_rate: Option<u32>,
+    // Channels of the output device, when they differ from the stream's
+    device_channels: Option<u16>,
+    channel_converter: Option<ChannelConverter>,
+    // Bytes of a frame split across two writes, when converting
+    partial: Vec<u8>,
+    resampling: bool,
+    resampler: Option<Resampler>,
+    preferred_devices: Vec<String>,
+    // Set from the stream error callback when the output device goes away
+    device_lost: Arc<AtomicBool>,
+    crossfade: Option<Duration>,
+    crossfader: Option<Crossfader>,
+    prebuffer: Duration,
+    // Playback gain as the bits of an f32, read by the output callback
+    volume: Arc<AtomicU32>,
+    // Frames played as silence for lack of buffered audio, and samples
+    // played from the buffer, counted by the output callback
+    underrun_count: Arc<AtomicU64>,
+    samples_played: Arc<AtomicU64>,
+    provider: Box<dyn DeviceProvider>,
 }
 
 impl CpalFileWrite {
     pub fn new() -> Self {
-        const DEFAULT_CAPACITY: usize = 400_000;
+        Self::new_with_provider(Box::new(CpalDeviceProvider::default()))
+    }
+
+    // Plays on the devices of `provider` instead of those of the default
+    // cpal host.
+    pub fn new_with_provider(provider: Box<dyn DeviceProvider>) -> Self {
         let (tx, rx) = mpsc::channel();
+        let (producer, consumer) = rtrb::RingBuffer::new(DEFAULT_RING_BUFFER_CAPACITY);
 
         Self {
-            buf: Arc::new(Mutex::new(VecDeque::with_capacity(DEFAULT_CAPACITY))),
+            producer,
+            overflow: vec![],
+            consumer: Arc::new(Mutex::new(consumer)),
             play_done_tx: tx,
             play_done_rx: rx,
             first_play: AtomicBool::new(true),
             stream: None,
             header: None,
+            fixed_output_rate: None,
+            device_rate: None,
+            device_channels: None,
+            channel_converter: None,
+            partial: vec![],
+            resampling: true,
+            resampler: None,
+            preferred_devices: vec![],
+            device_lost: Arc::new(AtomicBool::new(false)),
+            crossfade: None,
+            crossfader: None,
+            prebuffer: DEFAULT_PREBUFFER,
+            volume: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            underrun_count: Arc::new(AtomicU64::new(0)),
+            samples_played: Arc::new(AtomicU64::new(0)),
+            provider,
         }
     }
 
-    fn play_audio_from_buf(&mut self) -> Result<()> {
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or_else(|| anyhow::anyhow!("No output device available"))?;
+    // Output frames filled with silence because the buffer ran dry. Growing
+    // while a stream plays means the pre-buffer is too short. Stream frames
+    // lost on the network are counted by the client instead, see
+    // `ClientInterface::underrun_count`.
+    pub fn underrun_count(&self) -> u64 {
+        self.underrun_count.load(Ordering::Relaxed)
+    }
+
+    // Samples played from received audio, across all channels.
+    pub fn samples_played(&self) -> u64 {
+        self.samples_played.load(Ordering::Relaxed)
+    }
 
-        if self.header.is_none() {
-            return Err(anyhow::anyhow!("Audio format header not set"));
+    // Scales played samples by `gain`, clamped to the range of the output
+    // samples. Takes effect on the next output callback, from any thread.
+    pub fn set_volume(&self, gain: f32) {
+        self.volume
+            .store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    // Shares the playback gain with `volume`, so it can be changed after the
+    // writer is boxed into a client.
+    pub fn with_volume(mut self, volume: Arc<AtomicU32>) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    // Bytes of audio buffered at most between the network and the output
+    // device. Once it is full, the writer is not ready until playback makes
+    // room. It always holds at least one frame.
+    pub fn with_ring_buffer_capacity(mut self, bytes: usize) -> Self {
+        let (producer, consumer) = rtrb::RingBuffer::new(bytes.max(MAX_FRAME_SIZE));
+        self.producer = producer;
+        self.consumer = Arc::new(Mutex::new(consumer));
+        self
+    }
+
+    fn buffered(&self) -> usize {
+        self.producer.buffer().capacity() - self.producer.slots()
+    }
+
+    // Queues `data` for playback without waiting, what does not fit in the
+    // ring being kept for later.
+    fn push(&mut self, data: &[u8]) -> Result<()> {
+        self.overflow.extend_from_slice(data);
+        self.push_overflow()
+    }
+
+    // Moves what fits of the overflow into the ring. When the ring is full,
+    // playback is started even short of the pre-buffer, as it is what makes
+    // room.
+    fn push_overflow(&mut self) -> Result<()> {
+        let n = self.overflow.len().min(self.producer.slots());
+        if n > 0 {
+            let chunk = self.producer.write_chunk_uninit(n)?;
+            chunk.fill_from_iter(self.overflow.drain(..n));
         }
-        let header = self.header.as_ref().unwrap();
-        dbg!(header);
-        let config = cpal::StreamConfig {
-            channels: header.get_channels() as u16,
-            sample_rate: cpal::SampleRate(header.get_sample_rate()),
-            buffer_size: cpal::BufferSize::Default,
-        };
+        if !self.overflow.is_empty() && self.first_play.load(Ordering::Relaxed) {
+            self.start_playback()?;
+        }
+        Ok(())
+    }
 
-        let err_fn = move |err| eprintln!("an error occurred on stream: {err}");
-        let cloned_buf = Arc::clone(&self.buf);
+    // Output devices tried in order, by name. The default device is used when
+    // none of them is available.
+    pub fn with_preferred_devices(mut self, devices: Vec<String>) -> Self {
+        self.preferred_devices = devices;
+        self
+    }
+
+    // Overlaps the end of each track with the start of the next one over
+    // `duration`. Tracks whose playback formats differ are not crossfaded.
+    pub fn with_crossfade(mut self, duration: Duration) -> Self {
+        self.crossfade = Some(duration);
+        self
+    }
+
+    // Waits for `duration` of audio to be buffered before starting playback,
+    // which absorbs network jitter at the cost of latency. Defaults to
+    // DEFAULT_PREBUFFER, zero starts playback on the first write.
+    pub fn with_prebuffer(mut self, duration: Duration) -> Self {
+        self.prebuffer = duration;
+        self
+    }
+
+    fn prebuffer_bytes(&self) -> Result<usize> {
+        if self.prebuffer.is_zero() {
+            return Ok(0);
+        }
+        let header = self.playback_header()?;
+        let frames = self.prebuffer.as_secs_f64() * header.get_sample_rate() as f64;
+        Ok(frames as usize * header.frame_size())
+    }
+
+    fn start_playback(&mut self) -> Result<()> {
+        self.play_audio_from_buf()?;
+        if let Some(stream) = &self.stream {
+            stream.start()?;
+        }
+        self.first_play.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn select_device(&self) -> Result<Box<dyn OutputDevice>> {
+        let mut devices = self.provider.output_devices()?;
+        let names: Vec<String> = devices
+            .iter()
+            .map(|device| device.device_name().unwrap_or_default())
+            .collect();
+        if let Some(name) = select_output_device(&self.preferred_devices, &names)
+            && let Some(index) = names.iter().position(|n| n == name)
+        {
+            return Ok(devices.swap_remove(index));
+        }
+        self.provider.default_output_device()
+    }
+
+    // Rebuilds the output stream on the next device of the chain after the
+    // current one disappeared. Buffered audio resumes where it stopped.
+    fn recover_lost_device(&mut self) -> Result<()> {
+        if !self.device_lost.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        tracing::warn!("Output device lost, selecting a new one");
+        self.stream = None;
+        self.play_audio_from_buf()?;
+        if let Some(stream) = &self.stream {
+            stream.start()?;
+        }
+        Ok(())
+    }
+
+    // Always opens the output device at `sample_rate` and resamples incoming
+    // audio to it, whatever the rate of the received stream.
+    pub fn with_fixed_output_rate(sample_rate: u32) -> Self {
+        Self {
+            fixed_output_rate: Some(sample_rate),
+            ..Self::new()
+        }
+    }
+
+    // Resamples streams whose rate differs from the native rate of the output
+    // device, instead of playing them too fast or too slow. Enabled by
+    // default, for when rates are known to match.
+    pub fn with_resampling(mut self, enable: bool) -> Self {
+        self.resampling = enable;
+        self
+    }
+
+    // Rate the output stream is opened at, when it differs from the stream's
+    fn output_rate(&self) -> Option<u32> {
+        self.fixed_output_rate.or(self.device_rate)
+    }
+
+    // Native rate and channels of the output device. Without a usable
+    // device the stream is played in its own format.
+    fn device_format(&self) -> Option<(u32, u16)> {
+        self.select_device().ok()?.native_format().ok()
+    }
+
+    // Format the output stream is opened with. When converting or
+    // crossfading, the audio is buffered as f32 samples, in the output rate
+    // and channels if any.
+    fn playback_header(&self) -> Result<AudioHeader> {
+        let header = self
+            .header
+            .ok_or_else(|| anyhow::anyhow!("Audio format header not set"))?;
+        if self.output_rate().is_none()
+            && self.device_channels.is_none()
+            && self.crossfade.is_none()
+        {
+            return Ok(header);
+        }
+        let sample_rate = self.output_rate().unwrap_or(header.get_sample_rate());
+
+        Ok(AudioHeader::from_wav_spec(&hound::WavSpec {
+            channels: self.device_channels.unwrap_or(header.get_channels() as u16),
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        }))
+    }
+
+    fn play_audio_from_buf(&mut self) -> Result<()> {
+        let device = self.select_device()?;
+        tracing::info!("Output device: {}", device.device_name()?);
+
+        let header = self.playback_header()?;
+        tracing::debug!(?header, "Opening output stream");
+        let config = header.to_cpal_config();
+
+        let device_lost = Arc::clone(&self.device_lost);
+        let err_fn = move |err| {
+            if let cpal::StreamError::DeviceNotAvailable = err {
+                device_lost.store(true, Ordering::Relaxed);
+            }
+            tracing::error!("an error occurred on stream: {err}");
+        };
+        let cloned_buf = Arc::clone(&self.consumer);
+        let sample_size = header.get_bits_per_sample() as usize / 8;
 
         match header.get_sample_format() {
             crate::protocol::SampleFormat::Int => match header.get_bits_per_sample() {
-                16 => return self.build_output_stream::<i16>(device, config, cloned_buf, err_fn),
-                32 => return self.build_output_stream::<i32>(device, config, cloned_buf, err_fn),
-                _ => return Err(anyhow::anyhow!("Unsupported bits per sample")),
+                16 => {
+                    self.build_output_stream::<i16>(device, config, cloned_buf, sample_size, err_fn)
+                }
+                // 24-bit samples are played as i32
+                24 | 32 => {
+                    self.build_output_stream::<i32>(device, config, cloned_buf, sample_size, err_fn)
+                }
+                _ => Err(anyhow::anyhow!("Unsupported bits per sample")),
             },
             crate::protocol::SampleFormat::Float => match header.get_bits_per_sample() {
-                32 => return self.build_output_stream::<f32>(device, config, cloned_buf, err_fn),
-                _ => return Err(anyhow::anyhow!("Unsupported bits per sample")),
+                32 => {
+                    self.build_output_stream::<f32>(device, config, cloned_buf, sample_size, err_fn)
+                }
+                _ => Err(anyhow::anyhow!("Unsupported bits per sample")),
+            },
+            // Played as f32, which output devices support
+            crate::protocol::SampleFormat::Float64 => match header.get_bits_per_sample() {
+                64 => {
+                    self.build_output_stream::<f32>(device, config, cloned_buf, sample_size, err_fn)
+                }
+                _ => Err(anyhow::anyhow!("Unsupported bits per sample")),
             },
         }
     }
 
-    fn extract_bytes_from_buf(buf: &mut VecDeque<u8>, sample_size: usize) -> Option<Vec<u8>> {
-        if buf.len() < sample_size {
-            return None;
+    // Plays what is left in the buffer and waits for it to be played, failing
+    // when it takes longer than `timeout`, as a stream that silently stopped
+    // would never signal the end. `finalize` waits DEFAULT_FINALIZE_TIMEOUT.
+    pub fn finalize_with_timeout(&mut self, timeout: Duration) -> Result<()> {
+        if let Some(crossfader) = self.crossfader.as_mut() {
+            let tail = f32_to_bytes(&crossfader.flush());
+            self.push(&tail)?;
+        }
+        // Streams shorter than the pre-buffer are played once complete
+        if self.first_play.load(Ordering::Relaxed) {
+            if self.buffered() == 0 {
+                return Ok(());
+            }
+            self.start_playback()?;
+        }
+        let deadline = std::time::Instant::now() + timeout;
+        while !self.overflow.is_empty() {
+            if std::time::Instant::now() >= deadline {
+                self.stream = None;
+                return Err(anyhow::anyhow!(
+                    "Playback did not end within {:?} of the end of the stream",
+                    timeout
+                ));
+            }
+            self.recover_lost_device()?;
+            std::thread::sleep(Duration::from_millis(1));
+            self.push_overflow()?;
+        }
+        while let Err(mpsc::RecvTimeoutError::Timeout) = self
+            .play_done_rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+        {
+            if std::time::Instant::now() >= deadline {
+                self.stream = None;
+                return Err(anyhow::anyhow!(
+                    "Playback did not end within {:?} of the end of the stream",
+                    timeout
+                ));
+            }
+            self.recover_lost_device()?;
         }
-        Some(buf.drain(..sample_size).collect())
+        tracing::debug!("Buffer emptied, stopping stream");
+        if let Some(stream) = &self.stream {
+            stream.stop()?;
+            self.stream = None;
+        }
+
+        Ok(())
     }
 
-    fn get_sample_value<T>(mut buf: &mut VecDeque<u8>, sample_size: usize) -> Result<T>
+    // Converts the little-endian bytes of one buffered sample.
+    fn get_sample_value<T>(bytes: &[u8]) -> T
     where
-        T: cpal::Sample
-            + cpal::SizedSample
-            + FromSample<i16>
-            + FromSample<i32>
-            + FromSample<f32>
-            + Send
-            + 'static,
+        T: cpal::Sample + FromSample<i16> + FromSample<i32> + FromSample<f32> + Send + 'static,
     {
-        let bytes = Self::extract_bytes_from_buf(&mut buf, sample_size).unwrap();
-        let value = match sample_size {
-            2 => {
-                let arr: [u8; 2] = bytes.try_into().expect("bytes must be 2 long");
-                let val = i16::from_le_bytes(arr);
-                T::from_sample(val)
-            }
-            4 => {
-                let arr: [u8; 4] = bytes.try_into().expect("bytes must be 4 long");
+        match *bytes {
+            [b0, b1] => T::from_sample(i16::from_le_bytes([b0, b1])),
+            // Placed in the upper bytes of an i32, which keeps the full
+            // scale
+            [b0, b1, b2] => T::from_sample(i32::from_le_bytes([0, b0, b1, b2])),
+            [b0, b1, b2, b3] => {
                 if std::any::TypeId::of::<T>() == std::any::TypeId::of::<f32>() {
-                    let val = f32::from_le_bytes(arr);
-                    T::from_sample(val)
+                    T::from_sample(f32::from_le_bytes([b0, b1, b2, b3]))
                 } else {
-                    let val = i32::from_le_bytes(arr);
-                    T::from_sample(val)
+                    T::from_sample(i32::from_le_bytes([b0, b1, b2, b3]))
                 }
             }
+            [b0, b1, b2, b3, b4, b5, b6, b7] => {
+                T::from_sample(f64::from_le_bytes([b0, b1, b2, b3, b4, b5, b6, b7]) as f32)
+            }
             _ => T::EQUILIBRIUM,
-        };
-        Ok(value)
+        }
     }
 
     fn build_output_stream<T>(
         &mut self,
-        device: cpal::Device,
+        device: Box<dyn OutputDevice>,
         config: cpal::StreamConfig,
-        buf: Arc<Mutex<VecDeque<u8>>>,
+        buf: PlaybackConsumer,
+        // Size of the buffered samples, which may differ from `T`
+        sample_size: usize,
         err_fn: impl Fn(cpal::StreamError) + Send + 'static,
     ) -> Result<(), anyhow::Error>
     where
         T: cpal::Sample
-            + cpal::SizedSample
+            + OutputSample
             + FromSample<i16>
             + FromSample<i32>
             + FromSample<f32>
             + Send
             + 'static,
+        f32: FromSample<T>,
     {
         let channels = config.channels as usize;
-        let sample_size = std::mem::size_of::<T>();
         let frame_size = channels * sample_size;
         let tx1 = self.play_done_tx.clone();
         let notified = std::sync::Arc::new(AtomicBool::new(false));
         let notified_clone = notified.clone();
-        let stream = device.build_output_stream(
+        let volume = Arc::clone(&self.volume);
+        let underrun_count = Arc::clone(&self.underrun_count);
+        let samples_played = Arc::clone(&self.samples_played);
+        let stream = device.open_output_stream(
             &config,
-            move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
-                let mut buf = buf.lock().unwrap();
+            T::FORMAT,
+            Box::new(move |output: OutputBuffer<'_>| {
+                let Some(output) = T::output_slice(output) else {
+                    return;
+                };
+                let Ok(mut buf) = buf.try_lock() else {
+                    output.fill(T::EQUILIBRIUM);
+                    return;
+                };
+                let gain = f32::from_bits(volume.load(Ordering::Relaxed));
+                // Stack copy of one frame, so nothing is allocated here
+                let mut bytes = [0u8; MAX_FRAME_SIZE];
+                let (mut played, mut underruns) = (0, 0);
                 for frame in output.chunks_mut(channels) {
-                    if buf.len() >= frame_size {
-                        for sample in frame.iter_mut() {
-                            let value = Self::get_sample_value::<T>(&mut buf, sample_size)
-                                .unwrap_or(T::EQUILIBRIUM);
-                            *sample = value;
+                    match buf.read_chunk(frame_size) {
+                        Ok(chunk) if frame_size <= MAX_FRAME_SIZE => {
+                            let (first, second) = chunk.as_slices();
+                            bytes[..first.len()].copy_from_slice(first);
+                            bytes[first.len()..frame_size].copy_from_slice(second);
+                            chunk.commit_all();
+                            for (sample, sample_bytes) in
+                                frame.iter_mut().zip(bytes.chunks_exact(sample_size))
+                            {
+                                *sample = Self::get_sample_value::<T>(sample_bytes);
+                                if gain != 1.0 {
+                                    let scaled = sample.to_sample::<f32>() * gain;
+                                    *sample = T::from_sample(scaled.clamp(-1.0, 1.0));
+                                }
+                            }
+                            played += frame.len() as u64;
                         }
-                    } else {
-                        for sample in frame.iter_mut() {
-                            *sample = T::EQUILIBRIUM;
+                        _ => {
+                            frame.fill(T::EQUILIBRIUM);
+                            underruns += 1;
                         }
                     }
+                }
+                samples_played.fetch_add(played, Ordering::Relaxed);
+                underrun_count.fetch_add(underruns, Ordering::Relaxed);
 
-                    if buf.is_empty() && !notified_clone.load(Ordering::Relaxed) {
-                        tx1.send(()).unwrap();
-                        notified_clone.store(true, Ordering::Relaxed);
-                    }
+                // Checked once the whole output is filled, so a last chunk
+                // shorter than the output doesn't signal the end early
+                if buf.is_empty() && !notified_clone.load(Ordering::Relaxed) {
+                    let _ = tx1.send(());
+                    notified_clone.store(true, Ordering::Relaxed);
                 }
-            },
-            err_fn,
-            None,
+            }),
+            Box::new(err_fn),
         )?;
 
         self.stream = Some(stream);
@@ -344,33 +1211,119 @@ impl CpalFileWrite {
     }
 }
 
+impl Default for CpalFileWrite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AudioWriter for CpalFileWrite {
     fn write(&mut self, data: &[u8]) -> Result<()> {
-        if self.first_play.load(Ordering::Relaxed) {
-            self.play_audio_from_buf()?;
-            if let Some(stream) = &self.stream {
-                stream.play()?;
+        self.recover_lost_device()?;
+        let converted;
+        let data = match self.header.as_ref() {
+            Some(header)
+                if self.resampler.is_some()
+                    || self.device_channels.is_some()
+                    || self.crossfader.is_some() =>
+            {
+                // Only whole frames are converted, the rest waits for the
+                // next write
+                self.partial.extend_from_slice(data);
+                let frame_size = header.frame_size().max(1);
+                let whole = self.partial.len() / frame_size * frame_size;
+                let mut samples = bytes_to_f32(&self.partial[..whole], header)?;
+                self.partial.drain(..whole);
+                if let Some(resampler) = self.resampler.as_mut() {
+                    samples = resampler.process(&samples);
+                }
+                if let Some(converter) = self.channel_converter.as_mut() {
+                    samples = converter.process(&samples);
+                }
+                if let Some(crossfader) = self.crossfader.as_mut() {
+                    samples = crossfader.process(&samples);
+                }
+                converted = f32_to_bytes(&samples);
+                &converted[..]
             }
-            self.first_play.store(false, Ordering::Relaxed);
+            _ => data,
+        };
+        self.push(data)?;
+        if self.first_play.load(Ordering::Relaxed) && self.buffered() >= self.prebuffer_bytes()? {
+            self.start_playback()?;
         }
-        let mut buf = self.buf.lock().unwrap();
-        buf.extend(data);
         Ok(())
     }
 
-    fn finalize(&mut self) -> Result<()> {
-        self.play_done_rx.recv().unwrap();
-        dbg!("Buffer emptied, stopping stream.");
-        if let Some(stream) = &self.stream {
-            stream.pause()?;
-            self.stream = None;
+    fn end_of_track(&mut self) -> Result<()> {
+        if let Some(crossfader) = self.crossfader.as_mut() {
+            crossfader.end_of_track();
         }
-
         Ok(())
     }
 
+    // Ready once the ring has taken all the audio written so far.
+    fn poll_ready(&mut self) -> Result<bool> {
+        self.recover_lost_device()?;
+        self.push_overflow()?;
+        Ok(self.overflow.is_empty())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.finalize_with_timeout(DEFAULT_FINALIZE_TIMEOUT)
+    }
+
     fn update_format(&mut self, header: &crate::protocol::AudioHeader) -> Result<()> {
-        self.header = Some(header.clone());
+        let previous = self.header.and_then(|_| self.playback_header().ok());
+        if !self.partial.is_empty() {
+            tracing::warn!(
+                "Dropping {} bytes of an incomplete frame at a format change",
+                self.partial.len()
+            );
+            self.partial.clear();
+        }
+        self.header = Some(*header);
+        let device = self.device_format();
+        self.device_rate = match (self.fixed_output_rate, device) {
+            (None, Some((rate, _))) if self.resampling && rate != header.get_sample_rate() => {
+                Some(rate)
+            }
+            _ => None,
+        };
+        self.device_channels = device
+            .map(|(_, channels)| channels)
+            .filter(|&channels| channels != header.get_channels() as u16);
+        self.channel_converter = self.device_channels.map(|channels| {
+            ChannelConverter::new(header.get_channels() as usize, channels as usize)
+        });
+        if let Some(duration) = self.crossfade {
+            let playback = self.playback_header()?;
+            let same_format = previous.is_some_and(|previous| {
+                previous.get_channels() == playback.get_channels()
+                    && previous.get_sample_rate() == playback.get_sample_rate()
+            });
+            // Samples of different formats cannot be mixed: the tail of the
+            // previous track is played as is.
+            if !same_format {
+                if let Some(mut crossfader) = self.crossfader.take() {
+                    let tail = f32_to_bytes(&crossfader.flush());
+                    self.push(&tail)?;
+                }
+                let fade_frames =
+                    (duration.as_secs_f64() * playback.get_sample_rate() as f64) as usize;
+                self.crossfader = Some(Crossfader::new(
+                    fade_frames,
+                    playback.get_channels() as usize,
+                ));
+            }
+        }
+        self.resampler = self.output_rate().map(|output_rate| {
+            Resampler::new(
+                header.get_sample_rate(),
+                output_rate,
+                header.get_channels() as usize,
+            )
+        });
         Ok(())
     }
 }
@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+
+// Crossfades consecutive tracks of an interleaved f32 stream. The last
+// `fade_frames` frames of the current track are held back; at the end of the
+// track they are mixed into the head of the next one with complementary
+// linear gain ramps.
+pub struct Crossfader {
+    channels: usize,
+    fade_frames: usize,
+    tail: VecDeque<f32>,
+    // Tail of the previous track still being mixed, and frames mixed so far
+    fading: Option<(Vec<f32>, usize)>,
+}
+
+impl Crossfader {
+    pub fn new(fade_frames: usize, channels: usize) -> Self {
+        let channels = channels.max(1);
+        Self {
+            channels,
+            fade_frames,
+            tail: VecDeque::with_capacity(fade_frames * channels),
+            fading: None,
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let channels = self.channels;
+        let mut output = Vec::with_capacity(input.len());
+        for frame in input.chunks_exact(channels) {
+            match self.fading.as_mut() {
+                Some((old, pos)) => {
+                    let old_frames = old.len() / channels;
+                    let gain = (*pos as f32 + 0.5) / old_frames as f32;
+                    let old_frame = &old[*pos * channels..(*pos + 1) * channels];
+                    for (new, old) in frame.iter().zip(old_frame) {
+                        self.tail.push_back(old * (1.0 - gain) + new * gain);
+                    }
+                    *pos += 1;
+                    if *pos == old_frames {
+                        self.fading = None;
+                    }
+                }
+                None => self.tail.extend(frame),
+            }
+            while self.tail.len() > self.fade_frames * channels {
+                output.push(self.tail.pop_front().unwrap_or_default());
+            }
+        }
+        output
+    }
+
+    // Starts fading the current track out into the next one.
+    pub fn end_of_track(&mut self) {
+        self.finish_fade();
+        if !self.tail.is_empty() {
+            self.fading = Some((self.tail.drain(..).collect(), 0));
+        }
+    }
+
+    // Returns everything held back, without fading. Used at the end of the
+    // stream, or when the next track cannot be mixed with the previous one.
+    pub fn flush(&mut self) -> Vec<f32> {
+        self.finish_fade();
+        self.tail.drain(..).collect()
+    }
+
+    // A track shorter than the fade ends before the previous one is fully
+    // mixed in: the rest of the previous one follows it unmixed.
+    fn finish_fade(&mut self) {
+        if let Some((old, pos)) = self.fading.take() {
+            self.tail.extend(&old[pos * self.channels..]);
+        }
+    }
+}
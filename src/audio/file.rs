@@ -1,20 +1,224 @@
 use anyhow::Result;
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FileFormat {
     Wav,
+    Flac,
+    Mp3,
+    Ogg,
+    Opus,
+}
+
+impl FileFormat {
+    // Guessed from the extension of `path`, whatever its case. None for
+    // unknown extensions and paths without one.
+    pub fn from_path(path: &str) -> Option<FileFormat> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "wav" => Some(FileFormat::Wav),
+            "flac" => Some(FileFormat::Flac),
+            "mp3" => Some(FileFormat::Mp3),
+            "ogg" => Some(FileFormat::Ogg),
+            "opus" => Some(FileFormat::Opus),
+            _ => None,
+        }
+    }
 }
 
 pub trait AudioWriter {
     fn write(&mut self, data: &[u8]) -> Result<()>;
     fn finalize(&mut self) -> Result<()>;
     fn update_format(&mut self, header: &crate::protocol::AudioHeader) -> Result<()>;
+    // Called between the tracks of a multi-track stream
+    fn end_of_track(&mut self) -> Result<()> {
+        Ok(())
+    }
+    // Whether the writer can take more audio right away. Writers that drain
+    // at their own pace, like playback, return false until they catch up,
+    // and callers on an async task wait for it instead of writing ahead.
+    fn poll_ready(&mut self) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+// Writes the same audio to several writers. A failing writer does not stop
+// the others: all of them are attempted and their errors returned together.
+// Writers that failed are skipped until the end of the stream, where they are
+// still finalized.
+pub struct AudioWriterChain {
+    writers: Vec<ChainedWriter>,
+}
+
+struct ChainedWriter {
+    writer: Box<dyn AudioWriter>,
+    failed: bool,
+}
+
+impl AudioWriterChain {
+    pub fn new(writers: Vec<Box<dyn AudioWriter>>) -> Self {
+        let mut chain = Self { writers: vec![] };
+        for writer in writers {
+            chain.push(writer);
+        }
+        chain
+    }
+
+    pub fn push(&mut self, writer: Box<dyn AudioWriter>) {
+        self.writers.push(ChainedWriter {
+            writer,
+            failed: false,
+        });
+    }
+
+    pub fn pop(&mut self) -> Option<Box<dyn AudioWriter>> {
+        self.writers.pop().map(|chained| chained.writer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.writers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writers.is_empty()
+    }
+
+    // Waits, without blocking the task, for every working writer to be
+    // ready. A writer that fails is marked as failed like on `write`.
+    pub async fn ready(&mut self) -> Result<()> {
+        loop {
+            let mut ready = true;
+            self.for_each(false, |writer| {
+                ready &= writer.poll_ready()?;
+                Ok(())
+            })?;
+            if ready {
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+    }
+
+    // Writers that have not failed during the current stream.
+    pub fn working(&self) -> usize {
+        self.writers
+            .iter()
+            .filter(|chained| !chained.failed)
+            .count()
+    }
+
+    fn for_each(
+        &mut self,
+        with_failed: bool,
+        mut f: impl FnMut(&mut dyn AudioWriter) -> Result<()>,
+    ) -> Result<()> {
+        let mut errors = vec![];
+        for chained in &mut self.writers {
+            if chained.failed && !with_failed {
+                continue;
+            }
+            if let Err(e) = f(chained.writer.as_mut()) {
+                chained.failed = true;
+                errors.push(e);
+            }
+        }
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            n => Err(anyhow::anyhow!(
+                "{} audio writers failed: {}",
+                n,
+                errors
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            )),
+        }
+    }
+}
+
+impl AudioWriter for AudioWriterChain {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.for_each(false, |writer| writer.write(data))
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let result = self.for_each(true, |writer| writer.finalize());
+        // Every writer gets a new chance with the next stream
+        for chained in &mut self.writers {
+            chained.failed = false;
+        }
+        result
+    }
+
+    fn update_format(&mut self, header: &crate::protocol::AudioHeader) -> Result<()> {
+        self.for_each(false, |writer| writer.update_format(header))
+    }
+
+    fn end_of_track(&mut self) -> Result<()> {
+        self.for_each(false, |writer| writer.end_of_track())
+    }
 }
 
 pub trait AudioReader {
     fn read(&mut self, data: &mut [u8]) -> Result<usize>;
     fn open_file(&mut self, file_path: &str) -> Result<()>;
     fn update_header(&mut self, header: &mut crate::protocol::AudioHeader);
+    fn cover_art(&self) -> Option<crate::protocol::CoverArt> {
+        None
+    }
+    // Moves to `offset` frames from the start of the source.
+    fn seek_to_sample(&mut self, _offset: u64) -> Result<()> {
+        Err(anyhow::anyhow!("This source does not support seeking"))
+    }
+    // Length of the source in frames, 0 when unknown.
+    fn total_samples(&self) -> u64 {
+        0
+    }
+}
+
+// Lets boxed and borrowed readers, e.g. from `open_audio_file`, be wrapped
+// like others.
+impl<R: AudioReader + ?Sized> AudioReader for Box<R> {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        (**self).read(data)
+    }
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        (**self).open_file(file_path)
+    }
+    fn update_header(&mut self, header: &mut crate::protocol::AudioHeader) {
+        (**self).update_header(header)
+    }
+    fn cover_art(&self) -> Option<crate::protocol::CoverArt> {
+        (**self).cover_art()
+    }
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        (**self).seek_to_sample(offset)
+    }
+    fn total_samples(&self) -> u64 {
+        (**self).total_samples()
+    }
+}
+
+impl<R: AudioReader + ?Sized> AudioReader for &mut R {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        (**self).read(data)
+    }
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        (**self).open_file(file_path)
+    }
+    fn update_header(&mut self, header: &mut crate::protocol::AudioHeader) {
+        (**self).update_header(header)
+    }
+    fn cover_art(&self) -> Option<crate::protocol::CoverArt> {
+        (**self).cover_art()
+    }
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        (**self).seek_to_sample(offset)
+    }
+    fn total_samples(&self) -> u64 {
+        (**self).total_samples()
+    }
 }
 
 pub trait AudioPlayer {
@@ -0,0 +1,70 @@
+use crate::audio::convert::apply_gain;
+use crate::audio::file::AudioReader;
+use crate::protocol::{AudioHeader, CoverArt};
+use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Scales what `inner` reads by a gain that may change between reads, e.g.
+// when a client sends VOLUME_CONTROL during a stream. Samples are read in
+// the format of the inner reader's header and saturate like `apply_gain`.
+pub struct GainFilter<R: AudioReader> {
+    inner: R,
+    // f32 bits, shared with whoever changes the volume
+    gain: Arc<AtomicU32>,
+}
+
+impl<R: AudioReader> GainFilter<R> {
+    pub fn new(inner: R, gain: f32) -> Self {
+        Self::with_shared_gain(inner, Arc::new(AtomicU32::new(gain.to_bits())))
+    }
+
+    pub fn with_shared_gain(inner: R, gain: Arc<AtomicU32>) -> Self {
+        Self { inner, gain }
+    }
+
+    pub fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AudioReader> AudioReader for GainFilter<R> {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(data)?;
+        let gain = self.gain();
+        if n > 0 && gain != 1.0 {
+            let mut header = AudioHeader::new();
+            self.inner.update_header(&mut header);
+            apply_gain(&mut data[..n], &header, gain)?;
+        }
+        Ok(n)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        self.inner.open_file(file_path)
+    }
+
+    fn update_header(&mut self, header: &mut AudioHeader) {
+        self.inner.update_header(header)
+    }
+
+    fn cover_art(&self) -> Option<CoverArt> {
+        self.inner.cover_art()
+    }
+
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek_to_sample(offset)
+    }
+
+    fn total_samples(&self) -> u64 {
+        self.inner.total_samples()
+    }
+}
@@ -0,0 +1,169 @@
+use crate::audio::file::AudioReader;
+use crate::protocol::AudioHeader;
+use anyhow::Result;
+
+pub struct FlacFileRead {
+    reader: Option<claxon::FlacReader<std::fs::File>>,
+    // Reopened to seek, as claxon only decodes forward
+    file_path: Option<String>,
+    // Interleaved samples of the last decoded block, from `position` on
+    // not read yet
+    pending: Vec<i32>,
+    position: usize,
+    // Reused by the decoder from one block to the next
+    block_buffer: Vec<i32>,
+    // From the tags read when the file is opened
+    cover_art: Option<crate::protocol::CoverArt>,
+}
+
+impl FlacFileRead {
+    pub fn new() -> Self {
+        Self {
+            reader: None,
+            file_path: None,
+            pending: Vec::new(),
+            position: 0,
+            block_buffer: Vec::new(),
+            cover_art: None,
+        }
+    }
+
+    // Returns false at the end of the stream.
+    fn decode_next_block(&mut self) -> Result<bool> {
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(false);
+        };
+        let buffer = std::mem::take(&mut self.block_buffer);
+        let Some(block) = reader.blocks().read_next_or_eof(buffer)? else {
+            return Ok(false);
+        };
+
+        self.pending.clear();
+        self.position = 0;
+        for i in 0..block.duration() {
+            for channel in 0..block.channels() {
+                self.pending.push(block.sample(channel, i));
+            }
+        }
+        self.block_buffer = block.into_buffer();
+        Ok(true)
+    }
+
+    fn open(&mut self, file_path: &str) -> Result<()> {
+        let reader = claxon::FlacReader::open(file_path)?;
+        let bits = reader.streaminfo().bits_per_sample;
+        if bits > 32 {
+            return Err(anyhow::anyhow!("Unsupported bit depth: {}", bits));
+        }
+        self.reader = Some(reader);
+        self.file_path = Some(file_path.to_string());
+        self.pending.clear();
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl Default for FlacFileRead {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioReader for FlacFileRead {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let Some(reader) = &self.reader else {
+            return Ok(0);
+        };
+        let source_bits = reader.streaminfo().bits_per_sample;
+        let mut header = AudioHeader::new();
+        header.update_from_flac(&reader.streaminfo());
+        let stream_bits = header.get_bits_per_sample() as u32;
+        let sample_size = stream_bits as usize / 8;
+        // Samples are scaled up to the width they are streamed with
+        let shift = stream_bits - source_bits;
+
+        let mut pos = 0;
+        while pos + sample_size <= data.len() {
+            if self.position == self.pending.len() && !self.decode_next_block()? {
+                break;
+            }
+            let sample = self.pending[self.position] << shift;
+            self.position += 1;
+
+            if sample_size == 2 {
+                data[pos..pos + 2].copy_from_slice(&(sample as i16).to_le_bytes());
+            } else {
+                data[pos..pos + 4].copy_from_slice(&sample.to_le_bytes());
+            }
+            pos += sample_size;
+        }
+        Ok(pos)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        if self.reader.is_some() {
+            return Err(anyhow::anyhow!("File already opened"));
+        }
+        self.open(file_path)?;
+        // A malformed tag must not prevent the audio from being streamed.
+        self.cover_art = crate::audio::tags::read_flac_cover_art(file_path)
+            .ok()
+            .flatten();
+        Ok(())
+    }
+
+    fn update_header(&mut self, header: &mut AudioHeader) {
+        if let Some(reader) = &self.reader {
+            header.update_from_flac(&reader.streaminfo());
+        }
+    }
+
+    fn cover_art(&self) -> Option<crate::protocol::CoverArt> {
+        self.cover_art.clone()
+    }
+
+    // Decodes from the start again up to `offset`, FLAC files not being
+    // required to have a seek table.
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        let Some(file_path) = self.file_path.clone() else {
+            return Err(anyhow::anyhow!("No file opened"));
+        };
+        self.open(&file_path)?;
+        let channels = self
+            .reader
+            .as_ref()
+            .map_or(1, |reader| reader.streaminfo().channels as usize);
+        let mut skipped = 0;
+        while skipped < offset as usize * channels {
+            if self.position == self.pending.len() && !self.decode_next_block()? {
+                break;
+            }
+            let n = (offset as usize * channels - skipped).min(self.pending.len() - self.position);
+            self.position += n;
+            skipped += n;
+        }
+        Ok(())
+    }
+
+    // From STREAMINFO, where encoders may leave it unknown.
+    fn total_samples(&self) -> u64 {
+        self.reader
+            .as_ref()
+            .and_then(|reader| reader.streaminfo().samples)
+            .unwrap_or(0)
+    }
+}
+
+pub fn flac_duration(file_path: &str) -> Result<std::time::Duration> {
+    let reader = claxon::FlacReader::open(file_path)?;
+    let info = reader.streaminfo();
+    let samples = info
+        .samples
+        .ok_or_else(|| anyhow::anyhow!("Unknown duration for {}", file_path))?;
+    if info.sample_rate == 0 {
+        return Err(anyhow::anyhow!("Invalid sample rate in {}", file_path));
+    }
+    Ok(std::time::Duration::from_secs_f64(
+        samples as f64 / info.sample_rate as f64,
+    ))
+}
@@ -0,0 +1,88 @@
+use crate::audio::file::AudioReader;
+use anyhow::Result;
+
+const SAMPLE_SIZE: usize = std::mem::size_of::<f32>();
+
+// Procedural source: samples are produced on demand by calling
+// `generator(frame_index, channel)` and streamed as 32-bit float PCM.
+// Without a frame limit the source never reaches EOF.
+pub struct GeneratorReader<F>
+where
+    F: FnMut(u64, u16) -> f32,
+{
+    generator: F,
+    sample_rate: u32,
+    channels: u16,
+    next_frame: u64,
+    next_channel: u16,
+    total_frames: Option<u64>,
+}
+
+impl<F> GeneratorReader<F>
+where
+    F: FnMut(u64, u16) -> f32,
+{
+    pub fn new(sample_rate: u32, channels: u16, generator: F) -> Self {
+        Self {
+            generator,
+            sample_rate,
+            channels: channels.max(1),
+            next_frame: 0,
+            next_channel: 0,
+            total_frames: None,
+        }
+    }
+
+    pub fn with_total_frames(mut self, total_frames: u64) -> Self {
+        self.total_frames = Some(total_frames);
+        self
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.total_frames
+            .is_some_and(|total_frames| self.next_frame >= total_frames)
+    }
+}
+
+impl<F> AudioReader for GeneratorReader<F>
+where
+    F: FnMut(u64, u16) -> f32,
+{
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let mut pos = 0;
+        while pos + SAMPLE_SIZE <= data.len() && !self.is_exhausted() {
+            let sample = (self.generator)(self.next_frame, self.next_channel);
+            data[pos..pos + SAMPLE_SIZE].copy_from_slice(&sample.to_le_bytes());
+            pos += SAMPLE_SIZE;
+
+            self.next_channel += 1;
+            if self.next_channel == self.channels {
+                self.next_channel = 0;
+                self.next_frame += 1;
+            }
+        }
+        Ok(pos)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        Err(anyhow::anyhow!(
+            "Generator sources cannot open files ({})",
+            file_path
+        ))
+    }
+
+    fn update_header(&mut self, header: &mut crate::protocol::AudioHeader) {
+        *header = crate::protocol::AudioHeader::from_wav_spec(&hound::WavSpec {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        });
+    }
+
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        self.next_frame = offset;
+        self.next_channel = 0;
+        Ok(())
+    }
+}
@@ -1,3 +1,19 @@
+pub mod convert;
 pub mod cpal;
+pub mod crossfade;
 pub mod file;
+pub mod filter;
+pub mod flac;
+pub mod generator;
+pub mod mp3;
+pub mod normalize;
+pub mod ogg;
+pub mod opus;
+pub mod pipeline;
+pub mod resample;
+pub mod ring;
+pub mod silence;
+pub mod split;
+pub mod tags;
+pub mod vad;
 pub mod wav;
@@ -0,0 +1,157 @@
+use crate::audio::file::AudioReader;
+use crate::protocol::AudioHeader;
+use anyhow::Result;
+
+pub struct Mp3FileRead {
+    decoder: Option<minimp3::Decoder<std::fs::File>>,
+    // Reopened to seek, as minimp3 only decodes forward
+    file_path: Option<String>,
+    // Set from the first frame when the file is opened
+    header: AudioHeader,
+    // Interleaved samples of the last decoded frame, from `position` on not
+    // read yet. MP3 frames vary in size, so they are decoded one at a time.
+    pending: Vec<i16>,
+    position: usize,
+    // From the tags read when the file is opened
+    cover_art: Option<crate::protocol::CoverArt>,
+}
+
+impl Mp3FileRead {
+    pub fn new() -> Self {
+        Self {
+            decoder: None,
+            file_path: None,
+            header: AudioHeader::new(),
+            pending: Vec::new(),
+            position: 0,
+            cover_art: None,
+        }
+    }
+
+    // Returns None at the end of the file.
+    fn next_frame(&mut self) -> Result<Option<minimp3::Frame>> {
+        let Some(decoder) = self.decoder.as_mut() else {
+            return Ok(None);
+        };
+        match decoder.next_frame() {
+            Ok(frame) => Ok(Some(frame)),
+            Err(minimp3::Error::Eof) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Returns false at the end of the file.
+    fn decode_next_frame(&mut self) -> Result<bool> {
+        let Some(frame) = self.next_frame()? else {
+            return Ok(false);
+        };
+        if frame.channels != self.header.get_channels() as usize
+            || frame.sample_rate as u32 != self.header.get_sample_rate()
+        {
+            return Err(anyhow::anyhow!(
+                "MP3 format changes within a file are not supported"
+            ));
+        }
+        self.pending = frame.data;
+        self.position = 0;
+        Ok(true)
+    }
+
+    fn open(&mut self, file_path: &str) -> Result<()> {
+        let file = std::fs::File::open(file_path)?;
+        self.decoder = Some(minimp3::Decoder::new(file));
+
+        // The format is only known once a frame is decoded
+        let Some(frame) = self.next_frame()? else {
+            self.decoder = None;
+            return Err(anyhow::anyhow!("No MP3 frame found in {}", file_path));
+        };
+        self.header.update_from_mp3_frame(&frame);
+        self.pending = frame.data;
+        self.position = 0;
+        self.file_path = Some(file_path.to_string());
+        Ok(())
+    }
+}
+
+impl Default for Mp3FileRead {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioReader for Mp3FileRead {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let mut pos = 0;
+        while pos + 2 <= data.len() {
+            if self.position == self.pending.len() && !self.decode_next_frame()? {
+                break;
+            }
+            let sample = self.pending[self.position];
+            self.position += 1;
+
+            data[pos..pos + 2].copy_from_slice(&sample.to_le_bytes());
+            pos += 2;
+        }
+        Ok(pos)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        if self.decoder.is_some() {
+            return Err(anyhow::anyhow!("File already opened"));
+        }
+        self.open(file_path)?;
+        self.cover_art = crate::audio::tags::read_mp3_cover_art(file_path)
+            .ok()
+            .flatten();
+        Ok(())
+    }
+
+    fn update_header(&mut self, header: &mut AudioHeader) {
+        if self.decoder.is_some() {
+            *header = self.header;
+        }
+    }
+
+    fn cover_art(&self) -> Option<crate::protocol::CoverArt> {
+        self.cover_art.clone()
+    }
+
+    // Decodes from the start again up to `offset`. The length of the file
+    // stays unknown (see `mp3_duration`), so `total_samples` is 0.
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        let Some(file_path) = self.file_path.clone() else {
+            return Err(anyhow::anyhow!("No file opened"));
+        };
+        self.open(&file_path)?;
+        let channels = self.header.get_channels() as usize;
+        let mut skipped = 0;
+        while skipped < offset as usize * channels {
+            if self.position == self.pending.len() && !self.decode_next_frame()? {
+                break;
+            }
+            let n = (offset as usize * channels - skipped).min(self.pending.len() - self.position);
+            self.position += n;
+            skipped += n;
+        }
+        Ok(())
+    }
+}
+
+// MP3 files do not store their duration, the whole file is decoded to get it.
+pub fn mp3_duration(file_path: &str) -> Result<std::time::Duration> {
+    let mut decoder = minimp3::Decoder::new(std::fs::File::open(file_path)?);
+    let mut seconds = 0.0;
+    loop {
+        match decoder.next_frame() {
+            Ok(frame) if frame.channels > 0 && frame.sample_rate > 0 => {
+                let frames = frame.data.len() / frame.channels;
+                seconds += frames as f64 / frame.sample_rate as f64;
+            }
+            Ok(_) => {}
+            Err(minimp3::Error::Eof) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(std::time::Duration::from_secs_f64(seconds))
+}
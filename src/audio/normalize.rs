@@ -0,0 +1,94 @@
+use crate::audio::convert::{apply_gain, bytes_to_f32};
+use crate::audio::file::AudioReader;
+use crate::protocol::{AudioHeader, CoverArt};
+use anyhow::Result;
+
+// Level the loudest sample is brought to, relative to full scale.
+pub const NORMALIZED_PEAK: f32 = 0.9;
+
+// Bytes read at a time while scanning for the peak.
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
+
+// Scales what `inner` reads so that its loudest sample reaches
+// `NORMALIZED_PEAK` of full scale, quiet recordings up and loud ones down.
+// The whole source is read once to find its peak before being read again
+// from the start, so `inner` must support seeking. Samples saturate like
+// `apply_gain`, in the format of the inner reader's header.
+pub struct AudioNormalizer<R: AudioReader> {
+    inner: R,
+    gain: f32,
+}
+
+impl<R: AudioReader> AudioNormalizer<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let gain = normalizing_gain(&mut inner)?;
+        Ok(Self { inner, gain })
+    }
+
+    // Gain applied to every sample, 1.0 for silent sources.
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+// Reads `reader` whole for its peak, then rewinds it.
+fn normalizing_gain<R: AudioReader + ?Sized>(reader: &mut R) -> Result<f32> {
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    let mut buffer = vec![0u8; SCAN_CHUNK_SIZE];
+    let mut peak = 0.0f32;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        peak = bytes_to_f32(&buffer[..n], &header)?
+            .into_iter()
+            .fold(peak, |peak, sample| peak.max(sample.abs()));
+    }
+    reader.seek_to_sample(0)?;
+    Ok(if peak > 0.0 {
+        NORMALIZED_PEAK / peak
+    } else {
+        1.0
+    })
+}
+
+impl<R: AudioReader> AudioReader for AudioNormalizer<R> {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(data)?;
+        if n > 0 && self.gain != 1.0 {
+            let mut header = AudioHeader::new();
+            self.inner.update_header(&mut header);
+            apply_gain(&mut data[..n], &header, self.gain)?;
+        }
+        Ok(n)
+    }
+
+    // The new file gets a gain of its own.
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        self.inner.open_file(file_path)?;
+        self.gain = normalizing_gain(&mut self.inner)?;
+        Ok(())
+    }
+
+    fn update_header(&mut self, header: &mut AudioHeader) {
+        self.inner.update_header(header)
+    }
+
+    fn cover_art(&self) -> Option<CoverArt> {
+        self.inner.cover_art()
+    }
+
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek_to_sample(offset)
+    }
+
+    fn total_samples(&self) -> u64 {
+        self.inner.total_samples()
+    }
+}
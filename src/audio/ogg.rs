@@ -0,0 +1,169 @@
+use crate::audio::file::AudioReader;
+use crate::protocol::AudioHeader;
+use anyhow::Result;
+use cpal::Sample;
+use lewton::inside_ogg::OggStreamReader;
+use lewton::samples::InterleavedSamples;
+
+pub struct OggVorbisFileRead {
+    reader: Option<OggStreamReader<std::fs::File>>,
+    // Reopened to seek from the start
+    file_path: Option<String>,
+    // Frames in the stream, from the granule position of its last page
+    total_samples: u64,
+    // Interleaved samples of the last decoded packet, from `position` on not
+    // read yet
+    pending: Vec<i16>,
+    position: usize,
+    // From the tags read when the file is opened
+    cover_art: Option<crate::protocol::CoverArt>,
+}
+
+impl OggVorbisFileRead {
+    pub fn new() -> Self {
+        Self {
+            reader: None,
+            file_path: None,
+            total_samples: 0,
+            pending: Vec::new(),
+            position: 0,
+            cover_art: None,
+        }
+    }
+
+    // Returns false at the end of the stream. Packets may decode to no
+    // samples, e.g. the first one of a stream.
+    fn decode_next_packet(&mut self) -> Result<bool> {
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(false);
+        };
+        loop {
+            let Some(packet) = reader.read_dec_packet_generic::<InterleavedSamples<f32>>()? else {
+                return Ok(false);
+            };
+            if packet.samples.is_empty() {
+                continue;
+            }
+            self.pending.clear();
+            self.pending
+                .extend(packet.samples.iter().map(|s| s.to_sample::<i16>()));
+            self.position = 0;
+            return Ok(true);
+        }
+    }
+
+    fn open(&mut self, file_path: &str) -> Result<()> {
+        let file = std::fs::File::open(file_path)?;
+        self.reader = Some(OggStreamReader::new(file)?);
+        self.file_path = Some(file_path.to_string());
+        self.pending.clear();
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl Default for OggVorbisFileRead {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioReader for OggVorbisFileRead {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let mut pos = 0;
+        while pos + 2 <= data.len() {
+            if self.position == self.pending.len() && !self.decode_next_packet()? {
+                break;
+            }
+            let sample = self.pending[self.position];
+            self.position += 1;
+
+            data[pos..pos + 2].copy_from_slice(&sample.to_le_bytes());
+            pos += 2;
+        }
+        Ok(pos)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        if self.reader.is_some() {
+            return Err(anyhow::anyhow!("File already opened"));
+        }
+        self.open(file_path)?;
+        self.total_samples = last_granule_position(file_path)?;
+        self.cover_art = self.reader.as_ref().and_then(|reader| {
+            crate::audio::tags::vorbis_comment_picture(
+                reader
+                    .comment_hdr
+                    .comment_list
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            )
+        });
+        Ok(())
+    }
+
+    fn update_header(&mut self, header: &mut AudioHeader) {
+        if let Some(reader) = &self.reader {
+            header.update_from_vorbis(&reader.ident_hdr);
+        }
+    }
+
+    fn cover_art(&self) -> Option<crate::protocol::CoverArt> {
+        self.cover_art.clone()
+    }
+
+    // Decodes from the start again up to `offset`, which unlike seeking to
+    // a page is exact.
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        let Some(file_path) = self.file_path.clone() else {
+            return Err(anyhow::anyhow!("No file opened"));
+        };
+        self.open(&file_path)?;
+        let channels = self
+            .reader
+            .as_ref()
+            .map_or(1, |reader| reader.ident_hdr.audio_channels as usize);
+        let mut skipped = 0;
+        while skipped < offset as usize * channels {
+            if self.position == self.pending.len() && !self.decode_next_packet()? {
+                break;
+            }
+            let n = (offset as usize * channels - skipped).min(self.pending.len() - self.position);
+            self.position += n;
+            skipped += n;
+        }
+        Ok(())
+    }
+
+    fn total_samples(&self) -> u64 {
+        self.total_samples
+    }
+}
+
+// Read from the pages of the stream without decoding them: for Vorbis the
+// granule position of the last page is the number of frames.
+fn last_granule_position(file_path: &str) -> Result<u64> {
+    let file = std::io::BufReader::new(std::fs::File::open(file_path)?);
+    let mut packets = ogg::PacketReader::new(file);
+    let mut end = 0;
+    while let Some(packet) = packets.read_packet()? {
+        end = packet.absgp_page();
+    }
+    Ok(end)
+}
+
+// Decodes the whole stream, Vorbis headers do not store the duration.
+pub fn ogg_duration(file_path: &str) -> Result<std::time::Duration> {
+    let mut reader = OggStreamReader::new(std::fs::File::open(file_path)?)?;
+    let sample_rate = reader.ident_hdr.audio_sample_rate;
+    if sample_rate == 0 {
+        return Err(anyhow::anyhow!("Invalid sample rate in {}", file_path));
+    }
+    let mut frames = 0;
+    while let Some(packet) = reader.read_dec_packet_generic::<InterleavedSamples<f32>>()? {
+        frames += packet.samples.len() / packet.channel_count.max(1);
+    }
+    Ok(std::time::Duration::from_secs_f64(
+        frames as f64 / sample_rate as f64,
+    ))
+}
@@ -0,0 +1,428 @@
+use crate::audio::file::AudioReader;
+use crate::protocol::{AudioCodec, AudioHeader};
+use anyhow::Result;
+use std::ptr::NonNull;
+use unsafe_libopus::{
+    OPUS_APPLICATION_RESTRICTED_LOWDELAY, OPUS_OK, opus_decode, opus_decoder_create,
+    opus_decoder_destroy, opus_encode_float, opus_encoder_create, opus_encoder_destroy,
+};
+
+// With the restricted low-delay mode, 10 ms frames keep the algorithmic delay
+// of an encode and decode round trip at 12.5 ms.
+pub const OPUS_FRAME_DURATION_MS: u32 = 10;
+// Recommended by libopus for a single frame
+const MAX_PACKET_SIZE: usize = 4000;
+// Longest frame a packet can hold
+const MAX_FRAME_DURATION_MS: u32 = 120;
+// Ogg Opus streams are always decoded at 48 kHz
+const OGG_OPUS_SAMPLE_RATE: u32 = 48000;
+
+pub fn opus_supports(header: &AudioHeader) -> bool {
+    matches!(
+        header.get_sample_rate(),
+        8000 | 12000 | 16000 | 24000 | 48000
+    ) && matches!(header.get_channels(), 1 | 2)
+}
+
+// libopus is used through `unsafe_libopus`, a translation of its C code
+// that keeps the C API: states are raw pointers and every call is unsafe.
+// The wrappers below own their state, from the successful `*_create` to the
+// `*_destroy` in `Drop`, and only ever hand it to libopus along with buffers
+// of the sizes libopus was told about.
+
+pub struct OpusEncoder {
+    // Owned, valid until dropped
+    encoder: NonNull<unsafe_libopus::OpusEncoder>,
+    channels: usize,
+    // Per channel
+    frame_samples: usize,
+    // Interleaved samples waiting for a whole frame
+    pending: Vec<f32>,
+}
+
+// SAFETY: the state is a block of memory owned by this value alone, never
+// shared and only reached through `&mut self`, so it can move to another
+// thread. libopus keeps no thread-local data, and its only statics are
+// constant tables (declared `static mut` by the translation, never written).
+unsafe impl Send for OpusEncoder {}
+
+impl OpusEncoder {
+    pub fn new(sample_rate: u32, channels: u8) -> Result<Self> {
+        let mut error = 0;
+        // SAFETY: `error` is a valid place for the error code. Unsupported
+        // rates or channel counts make libopus fail, not misbehave.
+        let encoder = unsafe {
+            opus_encoder_create(
+                sample_rate as i32,
+                channels as i32,
+                OPUS_APPLICATION_RESTRICTED_LOWDELAY,
+                &mut error,
+            )
+        };
+        let Some(encoder) = NonNull::new(encoder).filter(|_| error == OPUS_OK) else {
+            return Err(anyhow::anyhow!(
+                "Cannot create an Opus encoder for {} Hz, {} channels (error {})",
+                sample_rate,
+                channels,
+                error
+            ));
+        };
+        Ok(Self {
+            encoder,
+            channels: channels as usize,
+            frame_samples: (sample_rate * OPUS_FRAME_DURATION_MS / 1000) as usize,
+            pending: Vec::new(),
+        })
+    }
+
+    // Returns a packet for every whole frame of interleaved `samples`. The
+    // rest is kept for the next call.
+    pub fn encode(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>> {
+        self.pending.extend_from_slice(samples);
+        let frame_len = self.frame_samples * self.channels;
+        let whole = self.pending.len() / frame_len * frame_len;
+        let frames = self.pending.drain(..whole).collect::<Vec<f32>>();
+        frames
+            .chunks_exact(frame_len)
+            .map(|frame| self.encode_frame(frame))
+            .collect()
+    }
+
+    // Encodes what is left, padded with silence to a whole frame.
+    pub fn flush(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let mut frame = std::mem::take(&mut self.pending);
+        frame.resize(self.frame_samples * self.channels, 0.0);
+        self.encode_frame(&frame).map(Some)
+    }
+
+    fn encode_frame(&mut self, frame: &[f32]) -> Result<Vec<u8>> {
+        assert_eq!(frame.len(), self.frame_samples * self.channels);
+        let mut packet = vec![0u8; MAX_PACKET_SIZE];
+        // SAFETY: the encoder is valid and not used elsewhere meanwhile
+        // (`&mut self`). libopus reads `frame_samples` samples per channel
+        // from `frame`, which holds exactly that many, and writes at most
+        // `packet.len()` bytes to `packet`.
+        let n = unsafe {
+            opus_encode_float(
+                self.encoder.as_ptr(),
+                frame.as_ptr(),
+                self.frame_samples as i32,
+                packet.as_mut_ptr(),
+                packet.len() as i32,
+            )
+        };
+        if n < 0 {
+            return Err(anyhow::anyhow!("Opus encoding failed (error {})", n));
+        }
+        packet.truncate(n as usize);
+        Ok(packet)
+    }
+}
+
+impl Drop for OpusEncoder {
+    fn drop(&mut self) {
+        // SAFETY: the encoder came from `opus_encoder_create` and is
+        // destroyed once, here, after its last use.
+        unsafe { opus_encoder_destroy(self.encoder.as_ptr()) }
+    }
+}
+
+pub struct OpusDecoder {
+    // Owned, valid until dropped
+    decoder: NonNull<unsafe_libopus::OpusDecoder>,
+    channels: usize,
+    // Per channel
+    max_frame_samples: usize,
+}
+
+// SAFETY: same as `OpusEncoder`, the state is owned by this value alone and
+// only reached through `&mut self`.
+unsafe impl Send for OpusDecoder {}
+
+impl OpusDecoder {
+    pub fn new(sample_rate: u32, channels: u8) -> Result<Self> {
+        let mut error = 0;
+        // SAFETY: same as `opus_encoder_create`
+        let decoder =
+            unsafe { opus_decoder_create(sample_rate as i32, channels as i32, &mut error) };
+        let Some(decoder) = NonNull::new(decoder).filter(|_| error == OPUS_OK) else {
+            return Err(anyhow::anyhow!(
+                "Cannot create an Opus decoder for {} Hz, {} channels (error {})",
+                sample_rate,
+                channels,
+                error
+            ));
+        };
+        Ok(Self {
+            decoder,
+            channels: channels as usize,
+            max_frame_samples: (sample_rate * MAX_FRAME_DURATION_MS / 1000) as usize,
+        })
+    }
+
+    // Decoder for the audio frames of a stream, if they are Opus coded.
+    pub fn for_header(header: &AudioHeader) -> Result<Option<Self>> {
+        match header.get_codec() {
+            AudioCodec::Raw => Ok(None),
+            AudioCodec::Opus => {
+                Self::new(header.get_sample_rate(), header.get_channels()).map(Some)
+            }
+        }
+    }
+
+    // Returns the interleaved samples of one packet.
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<i16>> {
+        let mut samples = vec![0i16; self.max_frame_samples * self.channels];
+        let len = i32::try_from(packet.len())
+            .map_err(|_| anyhow::anyhow!("Opus packet of {} bytes", packet.len()))?;
+        // SAFETY: the decoder is valid and not used elsewhere meanwhile
+        // (`&mut self`). libopus reads `len` bytes of `packet` and writes at
+        // most `max_frame_samples` samples per channel to `samples`, which
+        // has room for them.
+        let n = unsafe {
+            opus_decode(
+                self.decoder.as_ptr(),
+                packet.as_ptr(),
+                len,
+                samples.as_mut_ptr(),
+                self.max_frame_samples as i32,
+                0,
+            )
+        };
+        if n < 0 {
+            return Err(anyhow::anyhow!("Opus decoding failed (error {})", n));
+        }
+        samples.truncate(n as usize * self.channels);
+        Ok(samples)
+    }
+
+    // Same as `decode`, as little-endian PCM bytes.
+    pub fn decode_to_bytes(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let samples = self.decode(packet)?;
+        Ok(samples.iter().flat_map(|s| s.to_le_bytes()).collect())
+    }
+}
+
+impl Drop for OpusDecoder {
+    fn drop(&mut self) {
+        // SAFETY: same as `OpusEncoder::drop`
+        unsafe { opus_decoder_destroy(self.decoder.as_ptr()) }
+    }
+}
+
+type OggPacketReader = ogg::PacketReader<std::io::BufReader<std::fs::File>>;
+
+// Fields of the OpusHead packet that opens an Ogg Opus stream.
+struct OpusHead {
+    channels: u8,
+    // Samples at 48 kHz to drop at the start of the stream
+    pre_skip: u16,
+}
+
+fn parse_opus_head(data: &[u8]) -> Result<OpusHead> {
+    if data.len() < 19 || &data[..8] != b"OpusHead" {
+        return Err(anyhow::anyhow!("Not an Ogg Opus stream"));
+    }
+    // Channel mapping family 0 is mono or stereo, others need a
+    // multistream decoder
+    if data[18] != 0 {
+        return Err(anyhow::anyhow!(
+            "Unsupported Opus channel mapping family {}",
+            data[18]
+        ));
+    }
+    Ok(OpusHead {
+        channels: data[9],
+        pre_skip: u16::from_le_bytes([data[10], data[11]]),
+    })
+}
+
+// Opens an Ogg Opus file, skipping its OpusTags packet.
+fn open_ogg_opus(file_path: &str) -> Result<(OggPacketReader, OpusHead)> {
+    let file = std::fs::File::open(file_path)?;
+    let mut packets = ogg::PacketReader::new(std::io::BufReader::new(file));
+    let head = packets
+        .read_packet()?
+        .ok_or_else(|| anyhow::anyhow!("Empty Ogg stream in {}", file_path))?;
+    let head = parse_opus_head(&head.data)?;
+    packets
+        .read_packet()?
+        .ok_or_else(|| anyhow::anyhow!("Missing OpusTags in {}", file_path))?;
+    Ok((packets, head))
+}
+
+pub struct OpusFileRead {
+    packets: Option<OggPacketReader>,
+    // Reopened to seek from the start
+    file_path: Option<String>,
+    // Frames in the stream, pre-skip excluded
+    total_samples: u64,
+    decoder: Option<OpusDecoder>,
+    header: AudioHeader,
+    // Interleaved samples of the last decoded packet, from `position` on not
+    // read yet
+    pending: Vec<i16>,
+    position: usize,
+    // Samples per channel decoded so far and still to drop at the start
+    decoded: u64,
+    pre_skip: u64,
+    // From the tags read when the file is opened
+    cover_art: Option<crate::protocol::CoverArt>,
+}
+
+impl OpusFileRead {
+    pub fn new() -> Self {
+        Self {
+            packets: None,
+            file_path: None,
+            total_samples: 0,
+            decoder: None,
+            header: AudioHeader::new(),
+            pending: Vec::new(),
+            position: 0,
+            decoded: 0,
+            pre_skip: 0,
+            cover_art: None,
+        }
+    }
+
+    // Returns false at the end of the stream.
+    fn decode_next_packet(&mut self) -> Result<bool> {
+        let (Some(packets), Some(decoder)) = (self.packets.as_mut(), self.decoder.as_mut()) else {
+            return Ok(false);
+        };
+        let channels = self.header.get_channels() as usize;
+        loop {
+            let Some(packet) = packets.read_packet()? else {
+                return Ok(false);
+            };
+            let mut samples = decoder.decode(&packet.data)?;
+            let frames = (samples.len() / channels) as u64;
+            // The granule position of the last page tells where the audio
+            // ends within its last packet.
+            if packet.last_in_stream() {
+                let end = packet.absgp_page().saturating_sub(self.decoded).min(frames);
+                samples.truncate(end as usize * channels);
+            }
+            let skip = self.pre_skip.saturating_sub(self.decoded).min(frames);
+            self.decoded += frames;
+            samples.drain(..(skip as usize * channels).min(samples.len()));
+            if samples.is_empty() {
+                if packet.last_in_stream() {
+                    return Ok(false);
+                }
+                continue;
+            }
+            self.pending = samples;
+            self.position = 0;
+            return Ok(true);
+        }
+    }
+
+    fn open(&mut self, file_path: &str) -> Result<()> {
+        let (packets, head) = open_ogg_opus(file_path)?;
+        self.decoder = Some(OpusDecoder::new(OGG_OPUS_SAMPLE_RATE, head.channels)?);
+        self.header = AudioHeader::from_wav_spec(&hound::WavSpec {
+            channels: head.channels as u16,
+            sample_rate: OGG_OPUS_SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        });
+        self.pre_skip = head.pre_skip as u64;
+        self.decoded = 0;
+        self.pending.clear();
+        self.position = 0;
+        self.packets = Some(packets);
+        self.file_path = Some(file_path.to_string());
+        Ok(())
+    }
+}
+
+impl Default for OpusFileRead {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioReader for OpusFileRead {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let mut pos = 0;
+        while pos + 2 <= data.len() {
+            if self.position == self.pending.len() && !self.decode_next_packet()? {
+                break;
+            }
+            let sample = self.pending[self.position];
+            self.position += 1;
+
+            data[pos..pos + 2].copy_from_slice(&sample.to_le_bytes());
+            pos += 2;
+        }
+        Ok(pos)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        if self.packets.is_some() {
+            return Err(anyhow::anyhow!("File already opened"));
+        }
+        self.open(file_path)?;
+        self.total_samples = opus_total_samples(file_path)?;
+        self.cover_art = crate::audio::tags::read_opus_cover_art(file_path)
+            .ok()
+            .flatten();
+        Ok(())
+    }
+
+    fn update_header(&mut self, header: &mut AudioHeader) {
+        if self.packets.is_some() {
+            *header = self.header;
+        }
+    }
+
+    fn cover_art(&self) -> Option<crate::protocol::CoverArt> {
+        self.cover_art.clone()
+    }
+
+    // Decodes from the start again up to `offset`: Opus needs the packets
+    // before the target to converge anyway.
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        let Some(file_path) = self.file_path.clone() else {
+            return Err(anyhow::anyhow!("No file opened"));
+        };
+        self.open(&file_path)?;
+        let channels = self.header.get_channels() as usize;
+        let mut skipped = 0;
+        while skipped < offset as usize * channels {
+            if self.position == self.pending.len() && !self.decode_next_packet()? {
+                break;
+            }
+            let n = (offset as usize * channels - skipped).min(self.pending.len() - self.position);
+            self.position += n;
+            skipped += n;
+        }
+        Ok(())
+    }
+
+    fn total_samples(&self) -> u64 {
+        self.total_samples
+    }
+}
+
+// Read from the granule position of the last page, without decoding.
+fn opus_total_samples(file_path: &str) -> Result<u64> {
+    let (mut packets, head) = open_ogg_opus(file_path)?;
+    let mut end = 0;
+    while let Some(packet) = packets.read_packet()? {
+        end = packet.absgp_page();
+    }
+    Ok(end.saturating_sub(head.pre_skip as u64))
+}
+
+pub fn opus_duration(file_path: &str) -> Result<std::time::Duration> {
+    let frames = opus_total_samples(file_path)?;
+    Ok(std::time::Duration::from_secs_f64(
+        frames as f64 / OGG_OPUS_SAMPLE_RATE as f64,
+    ))
+}
@@ -0,0 +1,270 @@
+use crate::audio::convert::{ChannelConverter, apply_gain};
+use crate::audio::file::AudioReader;
+use crate::protocol::{AudioHeader, CoverArt, SampleFormat};
+use anyhow::Result;
+use cpal::{FromSample, Sample};
+
+// A transformation applied to the audio of an `AudioPipeline`.
+pub trait AudioFilter: Send {
+    // Format of what `process` returns for audio in the `input` format.
+    fn output_header(&self, input: &AudioHeader) -> AudioHeader {
+        *input
+    }
+    // Transforms whole frames in the format of `header`. The length of `buf`
+    // may change.
+    fn process(&mut self, buf: &mut Vec<u8>, header: &AudioHeader) -> Result<()>;
+    // Audio added after the end of the source, in the output format.
+    fn flush(&mut self, _header: &AudioHeader) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+// Scales samples by the gain, saturating like the volume control.
+pub struct GainFilter(pub f32);
+
+impl AudioFilter for GainFilter {
+    fn process(&mut self, buf: &mut Vec<u8>, header: &AudioHeader) -> Result<()> {
+        if self.0 != 1.0 {
+            apply_gain(buf, header, self.0)?;
+        }
+        Ok(())
+    }
+}
+
+// Maps `from` channels to `to` channels as ChannelConverter does, keeping
+// the sample format. Audio with another number of channels than `from` is
+// left as it is.
+pub struct ChannelDownmix {
+    pub from: u8,
+    pub to: u8,
+}
+
+impl ChannelDownmix {
+    fn applies(&self, header: &AudioHeader) -> bool {
+        header.get_channels() == self.from && self.from != self.to && self.to > 0
+    }
+}
+
+impl AudioFilter for ChannelDownmix {
+    fn output_header(&self, input: &AudioHeader) -> AudioHeader {
+        if !self.applies(input) {
+            return *input;
+        }
+        let mut spec = input.to_wavspec();
+        spec.channels = self.to as u16;
+        let mut output = AudioHeader::from_wav_spec(&spec);
+        output.set_codec(input.get_codec());
+        output.set_total_samples(input.get_total_samples());
+        output
+    }
+
+    fn process(&mut self, buf: &mut Vec<u8>, header: &AudioHeader) -> Result<()> {
+        if !self.applies(header) {
+            return Ok(());
+        }
+        let (from, to) = (self.from as usize, self.to as usize);
+        *buf = match (header.get_sample_format(), header.get_bits_per_sample()) {
+            (SampleFormat::Int, 16) => {
+                remap::<i16, 2>(buf, from, to, i16::from_le_bytes, |s| s.to_le_bytes())
+            }
+            // Placed in the upper bytes of an i32, as when converting to f32
+            (SampleFormat::Int, 24) => remap::<i32, 3>(
+                buf,
+                from,
+                to,
+                |b| i32::from_le_bytes([0, b[0], b[1], b[2]]),
+                |s| s.to_le_bytes()[1..].try_into().unwrap(),
+            ),
+            (SampleFormat::Int, 32) => {
+                remap::<i32, 4>(buf, from, to, i32::from_le_bytes, |s| s.to_le_bytes())
+            }
+            (SampleFormat::Float, 32) => {
+                remap::<f32, 4>(buf, from, to, f32::from_le_bytes, |s| s.to_le_bytes())
+            }
+            (SampleFormat::Float64, 64) => {
+                remap::<f64, 8>(buf, from, to, f64::from_le_bytes, |s| s.to_le_bytes())
+            }
+            (format, bits) => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported sample format for channel conversion: {:?} {} bits",
+                    format,
+                    bits
+                ));
+            }
+        };
+        Ok(())
+    }
+}
+
+fn remap<T, const N: usize>(
+    buf: &[u8],
+    from: usize,
+    to: usize,
+    decode: impl Fn([u8; N]) -> T,
+    encode: impl Fn(T) -> [u8; N],
+) -> Vec<u8>
+where
+    T: Sample + FromSample<f32>,
+    f32: FromSample<T>,
+{
+    let samples: Vec<T> = buf
+        .chunks_exact(N)
+        .map(|b| decode(b.try_into().unwrap()))
+        .collect();
+    ChannelConverter::convert(&samples, from, to)
+        .into_iter()
+        .flat_map(encode)
+        .collect()
+}
+
+// Appends this many frames of silence once the source is over.
+pub struct SilencePad(pub usize);
+
+impl AudioFilter for SilencePad {
+    fn process(&mut self, _buf: &mut Vec<u8>, _header: &AudioHeader) -> Result<()> {
+        Ok(())
+    }
+
+    // Zero bytes are silence in every supported sample format.
+    fn flush(&mut self, header: &AudioHeader) -> Result<Vec<u8>> {
+        Ok(vec![0; std::mem::take(&mut self.0) * header.frame_size()])
+    }
+}
+
+// Reads `inner` through a chain of filters, applied in the order they were
+// added. Filters only ever see whole frames, and `read` returns whole
+// samples of the output format like any other reader. Frames added by the
+// filters are not counted in `total_samples`.
+pub struct AudioPipeline<R: AudioReader> {
+    inner: R,
+    filters: Vec<Box<dyn AudioFilter>>,
+    // Start of a frame read from `inner`, completed by the next read
+    partial: Vec<u8>,
+    // Filtered audio not returned yet
+    pending: Vec<u8>,
+    pending_pos: usize,
+    finished: bool,
+}
+
+impl<R: AudioReader> AudioPipeline<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            filters: Vec::new(),
+            partial: Vec::new(),
+            pending: Vec::new(),
+            pending_pos: 0,
+            finished: false,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: impl AudioFilter + 'static) -> Self {
+        self.push(Box::new(filter));
+        self
+    }
+
+    pub fn push(&mut self, filter: Box<dyn AudioFilter>) -> &mut Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Format of the source followed by the output format of every filter.
+    fn headers(&mut self) -> Vec<AudioHeader> {
+        let mut header = AudioHeader::new();
+        self.inner.update_header(&mut header);
+        let mut headers = vec![header];
+        for filter in &self.filters {
+            header = filter.output_header(&header);
+            headers.push(header);
+        }
+        headers
+    }
+
+    // Runs `buf` through the filters from the `first` one on.
+    fn run(&mut self, first: usize, buf: &mut Vec<u8>, headers: &[AudioHeader]) -> Result<()> {
+        for (i, filter) in self.filters.iter_mut().enumerate().skip(first) {
+            filter.process(buf, &headers[i])?;
+        }
+        Ok(())
+    }
+
+    // What the filters add at the end, each flush going through the filters
+    // after it.
+    fn flush(&mut self, headers: &[AudioHeader]) -> Result<Vec<u8>> {
+        let mut tail = Vec::new();
+        for i in 0..self.filters.len() {
+            let mut added = self.filters[i].flush(&headers[i + 1])?;
+            self.run(i + 1, &mut added, headers)?;
+            tail.extend(added);
+        }
+        Ok(tail)
+    }
+
+    fn refill(&mut self, size: usize) -> Result<()> {
+        let headers = self.headers();
+        let frame_size = headers[0].frame_size().max(1);
+        let mut chunk = std::mem::take(&mut self.partial);
+        let start = chunk.len();
+        chunk.resize(start + size.max(frame_size), 0);
+        let n = self.inner.read(&mut chunk[start..])?;
+        chunk.truncate(start + n);
+        if n == 0 {
+            self.finished = true;
+            self.pending = self.flush(&headers)?;
+        } else {
+            let whole = chunk.len() / frame_size * frame_size;
+            self.partial = chunk.split_off(whole);
+            self.run(0, &mut chunk, &headers)?;
+            self.pending = chunk;
+        }
+        self.pending_pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: AudioReader> AudioReader for AudioPipeline<R> {
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let sample_size =
+            (self.headers().last().unwrap().get_bits_per_sample() as usize / 8).max(1);
+        let wanted = data.len() / sample_size * sample_size;
+        while self.pending_pos == self.pending.len() {
+            if self.finished || wanted == 0 {
+                return Ok(0);
+            }
+            self.refill(wanted)?;
+        }
+        let n = wanted.min(self.pending.len() - self.pending_pos);
+        data[..n].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + n]);
+        self.pending_pos += n;
+        Ok(n)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        self.inner.open_file(file_path)
+    }
+
+    fn update_header(&mut self, header: &mut AudioHeader) {
+        *header = *self.headers().last().unwrap();
+    }
+
+    fn cover_art(&self) -> Option<CoverArt> {
+        self.inner.cover_art()
+    }
+
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek_to_sample(offset)?;
+        self.finished = false;
+        self.partial.clear();
+        self.pending.clear();
+        self.pending_pos = 0;
+        Ok(())
+    }
+
+    fn total_samples(&self) -> u64 {
+        self.inner.total_samples()
+    }
+}
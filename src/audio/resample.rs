@@ -0,0 +1,71 @@
+// Streaming linear-interpolation resampler for interleaved f32 audio. The
+// last input frame of each chunk is kept so interpolation is continuous
+// across chunk boundaries, and so is a trailing partial frame until the next
+// chunk completes it.
+pub struct Resampler {
+    channels: usize,
+    output_rate: u32,
+    step: f64,
+    pos: f64,
+    prev: Option<Vec<f32>>,
+    partial: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32, channels: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+            output_rate,
+            step: input_rate as f64 / output_rate as f64,
+            pos: 0.0,
+            prev: None,
+            partial: Vec::new(),
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let channels = self.channels;
+        let joined;
+        let input = if self.partial.is_empty() {
+            input
+        } else {
+            joined = [std::mem::take(&mut self.partial).as_slice(), input].concat();
+            &joined[..]
+        };
+        let whole = input.len() / channels * channels;
+        self.partial.extend_from_slice(&input[whole..]);
+        let input = &input[..whole];
+
+        let mut frames: Vec<&[f32]> = Vec::with_capacity(input.len() / channels + 1);
+        if let Some(prev) = &self.prev {
+            frames.push(prev);
+        }
+        frames.extend(input.chunks_exact(channels));
+        if frames.len() < 2 {
+            if let Some(last) = frames.last() {
+                self.prev = Some(last.to_vec());
+            }
+            return Vec::new();
+        }
+
+        let last_index = (frames.len() - 1) as f64;
+        let mut output = Vec::with_capacity(((last_index / self.step) as usize + 1) * channels);
+        while self.pos < last_index {
+            let index = self.pos as usize;
+            let frac = (self.pos - index as f64) as f32;
+            let (a, b) = (frames[index], frames[index + 1]);
+            for channel in 0..channels {
+                output.push(a[channel] + (b[channel] - a[channel]) * frac);
+            }
+            self.pos += self.step;
+        }
+
+        self.pos -= last_index;
+        self.prev = frames.last().map(|frame| frame.to_vec());
+        output
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::audio::file::AudioWriter;
+use crate::protocol::AudioHeader;
+
+// State shared by both ends, outside of the audio path.
+#[derive(Default)]
+struct Shared {
+    header: Mutex<Option<AudioHeader>>,
+    finished: AtomicBool,
+}
+
+// Creates a single-producer single-consumer ring buffer of `capacity` bytes.
+// The writer receives the stream, the reader is drained by the application,
+// typically from its audio callback.
+pub fn ring_buffer(capacity: usize) -> (RingBufferWriter, RingBufferReader) {
+    let (producer, consumer) = rtrb::RingBuffer::new(capacity);
+    let shared = Arc::new(Shared::default());
+    (
+        RingBufferWriter {
+            producer,
+            overflow: vec![],
+            shared: Arc::clone(&shared),
+        },
+        RingBufferReader { consumer, shared },
+    )
+}
+
+pub struct RingBufferWriter {
+    producer: rtrb::Producer<u8>,
+    // Written audio the ring had no room for yet, pushed as the reader
+    // makes room
+    overflow: Vec<u8>,
+    shared: Arc<Shared>,
+}
+
+impl RingBufferWriter {
+    // Moves what fits of the overflow into the ring. Fails once the reader
+    // is gone, as nothing would make room anymore.
+    fn push_overflow(&mut self) -> Result<()> {
+        if self.producer.is_abandoned() {
+            return Err(anyhow::anyhow!("Ring buffer reader was dropped"));
+        }
+        let n = self.overflow.len().min(self.producer.slots());
+        if n > 0 {
+            let chunk = self.producer.write_chunk_uninit(n)?;
+            chunk.fill_from_iter(self.overflow.drain(..n));
+        }
+        Ok(())
+    }
+}
+
+impl AudioWriter for RingBufferWriter {
+    // Keeps what the ring has no room for, so no audio is dropped and the
+    // caller is never blocked. Callers wait for `poll_ready` before writing
+    // more.
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.overflow.extend_from_slice(data);
+        self.push_overflow()
+    }
+
+    // Ready once the ring has taken all the audio written so far.
+    fn poll_ready(&mut self) -> Result<bool> {
+        self.push_overflow()?;
+        Ok(self.overflow.is_empty())
+    }
+
+    // Audio still in the overflow is lost: callers wait for `poll_ready`
+    // first.
+    fn finalize(&mut self) -> Result<()> {
+        self.push_overflow()?;
+        if !self.overflow.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Ring buffer finalized with {} bytes it had no room for",
+                self.overflow.len()
+            ));
+        }
+        self.shared.finished.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn update_format(&mut self, header: &AudioHeader) -> Result<()> {
+        *self.shared.header.lock().unwrap() = Some(*header);
+        Ok(())
+    }
+}
+
+// Consumer end of the ring. `read` neither locks nor allocates, so it can be
+// called from a real-time thread.
+pub struct RingBufferReader {
+    consumer: rtrb::Consumer<u8>,
+    shared: Arc<Shared>,
+}
+
+impl RingBufferReader {
+    // Copies up to `data.len()` bytes of PCM and returns how many were read.
+    pub fn read(&mut self, data: &mut [u8]) -> usize {
+        let n = data.len().min(self.consumer.slots());
+        let Ok(chunk) = self.consumer.read_chunk(n) else {
+            return 0;
+        };
+        let (first, second) = chunk.as_slices();
+        data[..first.len()].copy_from_slice(first);
+        data[first.len()..n].copy_from_slice(second);
+        chunk.commit_all();
+        n
+    }
+
+    pub fn available(&self) -> usize {
+        self.consumer.slots()
+    }
+
+    // Format of the bytes in the ring, known once the stream has started.
+    // Locks, so it should be read outside of the real-time thread.
+    pub fn header(&self) -> Option<AudioHeader> {
+        *self.shared.header.lock().unwrap()
+    }
+
+    // True once the whole stream has been received and read.
+    pub fn is_finished(&self) -> bool {
+        self.shared.finished.load(Ordering::Acquire) && self.consumer.is_empty()
+    }
+}
@@ -0,0 +1,51 @@
+use crate::audio::file::AudioReader;
+use crate::protocol::AudioHeader;
+use anyhow::Result;
+
+// `duration_ms` of silence in the format of `header`, e.g. to separate
+// tracks without changing the format of the stream.
+pub struct SilenceReader {
+    header: AudioHeader,
+    frames: u64,
+    // Bytes left to produce, always whole frames
+    remaining: usize,
+}
+
+impl SilenceReader {
+    pub fn new(header: AudioHeader, duration_ms: u64) -> Self {
+        // Rounded down to whole frames
+        let total_bytes = header.bytes_for_duration(duration_ms as f64 / 1000.0) as usize;
+        let frames = (total_bytes / header.frame_size().max(1)) as u64;
+        Self {
+            header,
+            frames,
+            remaining: frames as usize * header.frame_size(),
+        }
+    }
+}
+
+impl AudioReader for SilenceReader {
+    // Zero bytes are silence in every supported sample format.
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let frame_size = self.header.frame_size().max(1);
+        let n = (data.len() / frame_size * frame_size).min(self.remaining);
+        data[..n].fill(0);
+        self.remaining -= n;
+        Ok(n)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        Err(anyhow::anyhow!(
+            "Silence sources cannot open files ({})",
+            file_path
+        ))
+    }
+
+    fn update_header(&mut self, header: &mut AudioHeader) {
+        *header = self.header;
+    }
+
+    fn total_samples(&self) -> u64 {
+        self.frames
+    }
+}
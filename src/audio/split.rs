@@ -0,0 +1,134 @@
+use crate::audio::vad::VadGate;
+use anyhow::Result;
+use cpal::{FromSample, Sample};
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AutoSplit {
+    // Peak level in [0.0, 1.0] under which a frame counts as silent
+    pub threshold: f32,
+    // Silence longer than this ends the current take
+    pub min_gap: Duration,
+}
+
+// WAV recorder writing into `path`, or with auto-split into numbered files
+// (`take_001.wav`, `take_002.wav`, ...) derived from `path`, one per take.
+// In auto-split mode the silence before the first take and after each take
+// is dropped. With a VAD gate, frames it does not admit are dropped before
+// any of this.
+pub struct SplitWavWriter {
+    spec: hound::WavSpec,
+    path: String,
+    auto_split: Option<AutoSplit>,
+    vad: Option<VadGate>,
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+    silent_frames: u64,
+    files: Vec<String>,
+}
+
+impl SplitWavWriter {
+    pub fn new(path: &str, spec: hound::WavSpec, auto_split: Option<AutoSplit>) -> Result<Self> {
+        let mut recorder = Self {
+            spec,
+            path: path.to_string(),
+            auto_split,
+            vad: None,
+            writer: None,
+            silent_frames: 0,
+            files: vec![],
+        };
+        if auto_split.is_none() {
+            recorder.start_take()?;
+        }
+        Ok(recorder)
+    }
+
+    pub fn with_vad_gate(mut self, gate: VadGate) -> Self {
+        self.vad = Some(gate);
+        self
+    }
+
+    fn take_path(&self, take: usize) -> String {
+        let path = std::path::Path::new(&self.path);
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("take");
+        let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("wav");
+        path.with_file_name(format!("{}_{:03}.{}", stem, take, ext))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn start_take(&mut self) -> Result<()> {
+        let path = match self.auto_split {
+            Some(_) => self.take_path(self.files.len() + 1),
+            None => self.path.clone(),
+        };
+        self.writer = Some(hound::WavWriter::create(&path, self.spec)?);
+        self.files.push(path);
+        Ok(())
+    }
+
+    fn end_take(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+        Ok(())
+    }
+
+    pub fn write<T, U>(&mut self, input: &[T]) -> Result<()>
+    where
+        T: Sample,
+        U: Sample + hound::Sample + FromSample<T>,
+        f32: FromSample<T>,
+    {
+        let gated;
+        let input = match self.vad.as_mut() {
+            Some(vad) => {
+                gated = vad.filter(input, self.spec.channels as usize);
+                &gated[..]
+            }
+            None => input,
+        };
+
+        let Some(auto_split) = self.auto_split else {
+            if let Some(writer) = self.writer.as_mut() {
+                for &sample in input {
+                    writer.write_sample(U::from_sample(sample))?;
+                }
+            }
+            return Ok(());
+        };
+
+        let min_gap_frames = auto_split.min_gap.as_secs_f64() * self.spec.sample_rate as f64;
+        for frame in input.chunks(self.spec.channels as usize) {
+            let peak = frame
+                .iter()
+                .map(|&s| f32::from_sample(s).abs())
+                .fold(0.0, f32::max);
+            if peak > auto_split.threshold {
+                self.silent_frames = 0;
+                if self.writer.is_none() {
+                    self.start_take()?;
+                }
+            } else {
+                self.silent_frames += 1;
+                if self.silent_frames as f64 > min_gap_frames {
+                    self.end_take()?;
+                }
+            }
+            if let Some(writer) = self.writer.as_mut() {
+                for &sample in frame {
+                    writer.write_sample(U::from_sample(sample))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Finalizes the current take and returns the paths of every file written.
+    pub fn finalize(mut self) -> Result<Vec<String>> {
+        self.end_take()?;
+        Ok(self.files)
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+use crate::protocol::CoverArt;
+
+// Picture embedded in the ID3v2 tag of a WAV file ("id3 " RIFF chunk), if any.
+pub fn read_wav_cover_art(file_path: &str) -> Result<Option<CoverArt>> {
+    let mut file = BufReader::new(File::open(file_path)?);
+
+    let mut riff_header = [0u8; 12];
+    file.read_exact(&mut riff_header)?;
+    if &riff_header[0..4] != b"RIFF" || &riff_header[8..12] != b"WAVE" {
+        return Err(anyhow::anyhow!("{} is not a RIFF/WAVE file", file_path));
+    }
+
+    let mut chunk_header = [0u8; 8];
+    while file.read_exact(&mut chunk_header).is_ok() {
+        let size = u32::from_le_bytes(chunk_header[4..8].try_into()?) as u64;
+        if chunk_header[0..4].eq_ignore_ascii_case(b"id3 ") {
+            let mut tag = Vec::new();
+            file.by_ref().take(size).read_to_end(&mut tag)?;
+            return Ok(extract_id3_picture(&tag));
+        }
+        // Chunks are padded to an even size.
+        file.seek(SeekFrom::Current((size + size % 2) as i64))?;
+    }
+
+    Ok(None)
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0usize, |acc, byte| (acc << 7) | (*byte & 0x7F) as usize)
+}
+
+// First APIC frame of an ID3v2.3/2.4 tag.
+pub fn extract_id3_picture(tag: &[u8]) -> Option<CoverArt> {
+    if tag.len() < 10 || &tag[0..3] != b"ID3" {
+        return None;
+    }
+    let version = tag[3];
+    if version != 3 && version != 4 {
+        return None;
+    }
+    let flags = tag[5];
+    let end = (10 + syncsafe(&tag[6..10])).min(tag.len());
+
+    let mut pos = 10;
+    if flags & 0x40 != 0 {
+        let extended = tag.get(pos..pos + 4)?;
+        pos += match version {
+            3 => 4 + u32::from_be_bytes(extended.try_into().ok()?) as usize,
+            _ => syncsafe(extended),
+        };
+    }
+
+    while pos + 10 <= end {
+        let id = &tag[pos..pos + 4];
+        if id[0] == 0 {
+            break;
+        }
+        let size_bytes = &tag[pos + 4..pos + 8];
+        let size = match version {
+            3 => u32::from_be_bytes(size_bytes.try_into().ok()?) as usize,
+            _ => syncsafe(size_bytes),
+        };
+        let body = tag.get(pos + 10..pos + 10 + size)?;
+        if id == b"APIC" {
+            return parse_apic(body);
+        }
+        pos += 10 + size;
+    }
+
+    None
+}
+
+// [encoding][mime\0][picture type][description\0][data]
+fn parse_apic(body: &[u8]) -> Option<CoverArt> {
+    let encoding = *body.first()?;
+    let mime_end = 1 + body[1..].iter().position(|b| *b == 0)?;
+    let mime_type = String::from_utf8_lossy(&body[1..mime_end]).into_owned();
+
+    let description_start = mime_end + 2;
+    let description = body.get(description_start..)?;
+    let data_start = match encoding {
+        // UTF-16 descriptions end with a two-byte null on an even offset.
+        1 | 2 => description.chunks_exact(2).position(|c| c == [0, 0])? * 2 + 2,
+        _ => description.iter().position(|b| *b == 0)? + 1,
+    };
+
+    let data = description.get(data_start..)?.to_vec();
+    if data.is_empty() {
+        return None;
+    }
+    Some(CoverArt { mime_type, data })
+}
+
+// First PICTURE metadata block of a FLAC file, if any.
+pub fn read_flac_cover_art(file_path: &str) -> Result<Option<CoverArt>> {
+    let mut file = BufReader::new(File::open(file_path)?);
+
+    let mut marker = [0u8; 4];
+    file.read_exact(&mut marker)?;
+    if &marker != b"fLaC" {
+        return Err(anyhow::anyhow!("{} is not a FLAC file", file_path));
+    }
+
+    // [last block flag and type][24-bit size] before each metadata block
+    let mut block_header = [0u8; 4];
+    loop {
+        file.read_exact(&mut block_header)?;
+        let size = u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]);
+        if block_header[0] & 0x7F == 6 {
+            let mut block = Vec::new();
+            file.by_ref().take(size as u64).read_to_end(&mut block)?;
+            return Ok(parse_flac_picture(&block));
+        }
+        if block_header[0] & 0x80 != 0 {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Current(size as i64))?;
+    }
+}
+
+// First METADATA_BLOCK_PICTURE of Vorbis comments, as found in Ogg Vorbis
+// and Opus files.
+pub fn vorbis_comment_picture<'a>(
+    comments: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Option<CoverArt> {
+    use base64::Engine;
+
+    comments
+        .into_iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case("METADATA_BLOCK_PICTURE"))
+        .filter_map(|(_, value)| base64::engine::general_purpose::STANDARD.decode(value).ok())
+        .find_map(|block| parse_flac_picture(&block))
+}
+
+// Picture of the OpusTags packet of an Ogg Opus file, if any.
+pub fn read_opus_cover_art(file_path: &str) -> Result<Option<CoverArt>> {
+    let mut packets = ogg::PacketReader::new(BufReader::new(File::open(file_path)?));
+    // OpusHead, then OpusTags
+    packets.read_packet()?;
+    let Some(tags) = packets.read_packet()? else {
+        return Ok(None);
+    };
+    Ok(parse_opus_tags(&tags.data).and_then(|comments| {
+        vorbis_comment_picture(comments.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+    }))
+}
+
+// ["OpusTags"][vendor][comment count]["KEY=value"]..., lengths as u32 LE
+fn parse_opus_tags(packet: &[u8]) -> Option<Vec<(String, String)>> {
+    fn field<'a>(packet: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+        let len = u32::from_le_bytes(packet.get(*pos..*pos + 4)?.try_into().ok()?) as usize;
+        let value = packet.get(*pos + 4..*pos + 4 + len)?;
+        *pos += 4 + len;
+        Some(value)
+    }
+
+    if packet.get(0..8)? != b"OpusTags" {
+        return None;
+    }
+    let mut pos = 8;
+    field(packet, &mut pos)?;
+    let count = u32::from_le_bytes(packet.get(pos..pos + 4)?.try_into().ok()?);
+    pos += 4;
+
+    let mut comments = Vec::new();
+    for _ in 0..count {
+        let comment = String::from_utf8_lossy(field(packet, &mut pos)?).into_owned();
+        if let Some((key, value)) = comment.split_once('=') {
+            comments.push((key.to_string(), value.to_string()));
+        }
+    }
+    Some(comments)
+}
+
+// APIC frame of the ID3v2 tag an MP3 file starts with, if any.
+pub fn read_mp3_cover_art(file_path: &str) -> Result<Option<CoverArt>> {
+    let mut file = BufReader::new(File::open(file_path)?);
+
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Ok(None);
+    }
+    let mut tag = header.to_vec();
+    file.take(syncsafe(&header[6..10]) as u64)
+        .read_to_end(&mut tag)?;
+    Ok(extract_id3_picture(&tag))
+}
+
+// [type][mime][description][width][height][depth][colors][data], with
+// u32 BE lengths before mime, description and data
+pub fn parse_flac_picture(block: &[u8]) -> Option<CoverArt> {
+    fn field<'a>(block: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+        let len = u32::from_be_bytes(block.get(*pos..*pos + 4)?.try_into().ok()?) as usize;
+        let value = block.get(*pos + 4..*pos + 4 + len)?;
+        *pos += 4 + len;
+        Some(value)
+    }
+
+    let mut pos = 4;
+    let mime_type = String::from_utf8_lossy(field(block, &mut pos)?).into_owned();
+    field(block, &mut pos)?;
+    pos += 16;
+    let data = field(block, &mut pos)?.to_vec();
+    if data.is_empty() {
+        return None;
+    }
+    Some(CoverArt { mime_type, data })
+}
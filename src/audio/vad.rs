@@ -0,0 +1,82 @@
+use cpal::{FromSample, Sample};
+use std::collections::VecDeque;
+
+// Window of the RMS when recording, in milliseconds.
+pub const DEFAULT_VAD_WINDOW_MS: u64 = 20;
+
+// Voice activity detection: lets through the frames recorded while the RMS
+// level over the last `window` frames is above `threshold`, in [0.0, 1.0],
+// and drops the others, e.g. the silence between sentences of a long
+// recording. Frames are gated whole, every channel at once.
+#[derive(Debug, Clone)]
+pub struct VadGate {
+    threshold: f32,
+    window: usize,
+    // Mean square of each frame of the window, oldest first
+    squares: VecDeque<f32>,
+    sum: f32,
+}
+
+impl VadGate {
+    pub fn new(threshold: f32, window: usize) -> Self {
+        Self {
+            threshold,
+            window: window.max(1),
+            squares: VecDeque::new(),
+            sum: 0.0,
+        }
+    }
+
+    // Window of `DEFAULT_VAD_WINDOW_MS` at `sample_rate`.
+    pub fn for_sample_rate(threshold: f32, sample_rate: u32) -> Self {
+        Self::new(
+            threshold,
+            (sample_rate as u64 * DEFAULT_VAD_WINDOW_MS / 1000) as usize,
+        )
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    // RMS level of the window so far.
+    pub fn rms(&self) -> f32 {
+        if self.squares.is_empty() {
+            return 0.0;
+        }
+        // Rounding can leave the running sum slightly negative
+        (self.sum.max(0.0) / self.squares.len() as f32).sqrt()
+    }
+
+    // Whether `frame`, one sample per channel, is to be kept.
+    pub fn admit<T: Sample>(&mut self, frame: &[T]) -> bool
+    where
+        f32: FromSample<T>,
+    {
+        let square = frame
+            .iter()
+            .map(|&s| f32::from_sample(s).powi(2))
+            .sum::<f32>()
+            / frame.len().max(1) as f32;
+        self.squares.push_back(square);
+        self.sum += square;
+        if self.squares.len() > self.window {
+            self.sum -= self.squares.pop_front().unwrap_or_default();
+        }
+        self.rms() > self.threshold
+    }
+
+    // The samples of the frames of `input` to keep, `channels` samples a
+    // frame.
+    pub fn filter<T: Sample>(&mut self, input: &[T], channels: usize) -> Vec<T>
+    where
+        f32: FromSample<T>,
+    {
+        input
+            .chunks(channels.max(1))
+            .filter(|frame| self.admit(frame))
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
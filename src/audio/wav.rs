@@ -1,15 +1,144 @@
 use crate::audio::file::{AudioReader, AudioWriter};
 use anyhow::Result;
 
-use std::io::BufWriter;
+use std::io::{BufWriter, Read, Seek};
 
 pub struct WavFileRead {
     reader: Option<hound::WavReader<std::io::BufReader<std::fs::File>>>,
+    // Set instead of `reader` for 64-bit float files
+    float64_reader: Option<Float64WavReader>,
+    cover_art: Option<crate::protocol::CoverArt>,
+    // Samples read through `reader`, over all channels
+    samples_read: u64,
+    looping: bool,
+    loops_completed: u64,
 }
 
 impl WavFileRead {
     pub fn new() -> Self {
-        Self { reader: None }
+        Self {
+            reader: None,
+            float64_reader: None,
+            cover_art: None,
+            samples_read: 0,
+            looping: false,
+            loops_completed: 0,
+        }
+    }
+
+    /// Restarts from the beginning of the file when its end is reached,
+    /// instead of reading nothing.
+    pub fn set_looping(&mut self, looping: bool) -> &mut Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Times the end of the file was reached and reading restarted.
+    pub fn loops_completed(&self) -> u64 {
+        self.loops_completed
+    }
+
+    /// Frame the next read starts at, in the unit of `seek_to_sample`.
+    pub fn position(&self) -> u64 {
+        if let Some(reader) = &self.float64_reader {
+            return reader.position / reader.spec.channels.max(1) as u64;
+        }
+        match &self.reader {
+            Some(reader) => self.samples_read / reader.spec().channels.max(1) as u64,
+            None => 0,
+        }
+    }
+}
+
+// hound rejects 64-bit float files when opening them, so their chunks are
+// walked here. Samples are read straight from the data chunk.
+struct Float64WavReader {
+    reader: std::io::BufReader<std::fs::File>,
+    spec: hound::WavSpec,
+    data_start: u64,
+    // In samples, over all channels
+    len: u64,
+    position: u64,
+}
+
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+impl Float64WavReader {
+    fn open(file_path: &str) -> Result<Self> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(file_path)?);
+        let mut riff = [0u8; 12];
+        reader.read_exact(&mut riff)?;
+        if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+            return Err(anyhow::anyhow!("Not a WAV file: {}", file_path));
+        }
+        let mut spec = None;
+        loop {
+            let mut chunk = [0u8; 8];
+            reader.read_exact(&mut chunk)?;
+            let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+            match &chunk[..4] {
+                b"fmt " => {
+                    let mut fmt = vec![0u8; len as usize];
+                    reader.read_exact(&mut fmt)?;
+                    spec = Some(parse_float64_fmt(&fmt)?);
+                    if len % 2 == 1 {
+                        reader.seek_relative(1)?;
+                    }
+                }
+                b"data" => {
+                    let spec =
+                        spec.ok_or_else(|| anyhow::anyhow!("Missing fmt chunk in {}", file_path))?;
+                    return Ok(Self {
+                        data_start: reader.stream_position()?,
+                        reader,
+                        spec,
+                        len: len / 8,
+                        position: 0,
+                    });
+                }
+                // Chunks are padded to an even size
+                _ => reader.seek_relative((len + len % 2) as i64)?,
+            }
+        }
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<()> {
+        let channels = self.spec.channels as u64;
+        self.position = frame
+            .saturating_mul(channels)
+            .min(self.len / channels * channels);
+        self.reader.seek(std::io::SeekFrom::Start(
+            self.data_start + self.position * 8,
+        ))?;
+        Ok(())
+    }
+}
+
+fn parse_float64_fmt(fmt: &[u8]) -> Result<hound::WavSpec> {
+    if fmt.len() < 16 {
+        return Err(anyhow::anyhow!("Invalid fmt chunk"));
+    }
+    let mut format = u16::from_le_bytes([fmt[0], fmt[1]]);
+    // The sub format GUID starts with the format tag
+    if format == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 40 {
+        format = u16::from_le_bytes([fmt[24], fmt[25]]);
+    }
+    let bits_per_sample = u16::from_le_bytes([fmt[14], fmt[15]]);
+    if format != WAVE_FORMAT_IEEE_FLOAT || bits_per_sample != 64 {
+        return Err(anyhow::anyhow!("Not a 64-bit float WAV file"));
+    }
+    Ok(hound::WavSpec {
+        channels: u16::from_le_bytes([fmt[2], fmt[3]]),
+        sample_rate: u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]),
+        bits_per_sample,
+        sample_format: hound::SampleFormat::Float,
+    })
+}
+
+impl Default for WavFileRead {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -33,6 +162,27 @@ fn read_i32_samples(
     Ok(pos)
 }
 
+// 24-bit samples are read by hound as i32 and packed back into 3 bytes.
+fn read_i24_samples(
+    reader: &mut hound::WavReader<std::io::BufReader<std::fs::File>>,
+    data: &mut [u8],
+) -> Result<usize> {
+    let mut pos = 0;
+    for sample in reader.samples::<i32>().take(data.len() / 3) {
+        if pos + 3 > data.len() {
+            break;
+        }
+
+        let s = sample?;
+        let bytes = s.to_le_bytes();
+
+        data[pos..pos + 3].copy_from_slice(&bytes[..3]);
+
+        pos += 3;
+    }
+    Ok(pos)
+}
+
 fn read_i16_samples(
     reader: &mut hound::WavReader<std::io::BufReader<std::fs::File>>,
     data: &mut [u8],
@@ -73,14 +223,23 @@ fn read_f32_samples(
     Ok(pos)
 }
 
-impl AudioReader for WavFileRead {
-    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+fn read_f64_samples(reader: &mut Float64WavReader, data: &mut [u8]) -> Result<usize> {
+    let samples = ((data.len() / 8) as u64).min(reader.len - reader.position) as usize;
+    // Stored as little-endian f64, which is how they are streamed
+    reader.reader.read_exact(&mut data[..samples * 8])?;
+    reader.position += samples as u64;
+    Ok(samples * 8)
+}
+
+impl WavFileRead {
+    fn read_samples(&mut self, data: &mut [u8]) -> Result<usize> {
         if let Some(reader) = &mut self.reader {
             let sample_format = reader.spec().sample_format;
 
             let pos = match sample_format {
                 hound::SampleFormat::Int => match reader.spec().bits_per_sample {
                     16 => read_i16_samples(reader, data)?,
+                    24 => read_i24_samples(reader, data)?,
                     32 => read_i32_samples(reader, data)?,
                     bits => {
                         return Err(anyhow::anyhow!("Unsupported bit depth: {}", bits));
@@ -88,97 +247,269 @@ impl AudioReader for WavFileRead {
                 },
                 hound::SampleFormat::Float => match reader.spec().bits_per_sample {
                     32 => read_f32_samples(reader, data)?,
-                    64 => return Err(anyhow::anyhow!("64-bit float samples not supported")),
                     bits => {
                         return Err(anyhow::anyhow!("Unsupported bit depth: {}", bits));
                     }
                 },
             };
+            self.samples_read += (pos / (reader.spec().bits_per_sample as usize / 8)) as u64;
             return Ok(pos);
         }
+        if let Some(reader) = &mut self.float64_reader {
+            return read_f64_samples(reader, data);
+        }
 
         Ok(0)
     }
+}
+
+impl AudioReader for WavFileRead {
+    // Empty files are not looped, which would never return.
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let n = self.read_samples(data)?;
+        if n > 0 || !self.looping || self.total_samples() == 0 {
+            return Ok(n);
+        }
+        self.seek_to_sample(0)?;
+        self.loops_completed += 1;
+        self.read_samples(data)
+    }
 
     fn open_file(&mut self, file_path: &str) -> Result<()> {
-        if self.reader.is_some() {
+        if self.reader.is_some() || self.float64_reader.is_some() {
             return Err(anyhow::anyhow!("File already opened"));
         }
-        let reader = hound::WavReader::open(file_path)?;
-        self.reader = Some(reader);
+        match hound::WavReader::open(file_path) {
+            Ok(reader) => self.reader = Some(reader),
+            // Reported with hound's error when it is not a 64-bit float file
+            Err(e) => self.float64_reader = Some(Float64WavReader::open(file_path).map_err(|_| e)?),
+        }
+        // A malformed tag must not prevent the audio from being streamed.
+        self.cover_art = crate::audio::tags::read_wav_cover_art(file_path)
+            .ok()
+            .flatten();
         Ok(())
     }
 
     fn update_header(&mut self, header: &mut crate::protocol::AudioHeader) {
         if let Some(reader) = &self.reader {
-            let spec = reader.spec();
-            header.update_wavspec(&spec);
+            *header = crate::protocol::AudioHeader::from_wav_spec(&reader.spec());
+        }
+        if let Some(reader) = &self.float64_reader {
+            *header = crate::protocol::AudioHeader::from_wav_spec(&reader.spec);
+        }
+    }
+
+    fn cover_art(&self) -> Option<crate::protocol::CoverArt> {
+        self.cover_art.clone()
+    }
+
+    // Offsets past the end of the file move to the end.
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        if let Some(reader) = &mut self.float64_reader {
+            return reader.seek(offset);
         }
+        let reader = self
+            .reader
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("No file opened"))?;
+        let offset = offset.min(reader.duration() as u64) as u32;
+        reader.seek(offset)?;
+        self.samples_read = offset as u64 * reader.spec().channels as u64;
+        Ok(())
+    }
+
+    fn total_samples(&self) -> u64 {
+        if let Some(reader) = &self.float64_reader {
+            return reader.len / reader.spec.channels.max(1) as u64;
+        }
+        self.reader
+            .as_ref()
+            .map_or(0, |reader| reader.duration() as u64)
     }
 }
 
+// Duration as declared by the size of the data chunk, so chunks stored after
+// the audio (LIST, bext, cue...) are not counted; the samples themselves are
+// not read.
+pub fn wav_duration(file_path: &str) -> Result<std::time::Duration> {
+    let (spec, duration) = match hound::WavReader::open(file_path) {
+        Ok(reader) => (reader.spec(), reader.duration() as u64),
+        Err(e) => {
+            let reader = Float64WavReader::open(file_path).map_err(|_| e)?;
+            (reader.spec, reader.len / reader.spec.channels.max(1) as u64)
+        }
+    };
+    if spec.sample_rate == 0 {
+        return Err(anyhow::anyhow!("Invalid sample rate in {}", file_path));
+    }
+    Ok(std::time::Duration::from_secs_f64(
+        duration as f64 / spec.sample_rate as f64,
+    ))
+}
+
+pub const DEFAULT_WRITE_BATCH_SIZE: usize = 64 * 1024;
+// About 6 seconds of 44.1 kHz stereo 16-bit audio
+pub const DEFAULT_FLUSH_INTERVAL: usize = 1024 * 1024;
+
+type HoundWriter = hound::WavWriter<BufWriter<std::fs::File>>;
+
+// Received audio is combined into batches of `batch_size` bytes before being
+// handed to hound, instead of one hound call per sample of every frame.
 pub struct WavFileWrite {
-    writer: Option<hound::WavWriter<BufWriter<std::fs::File>>>,
+    writer: Option<HoundWriter>,
     file_path: String,
+    pending: Vec<u8>,
+    batch_size: usize,
+    // Where `file_path` is moved once finalized, for atomic writes
+    final_path: Option<String>,
+    // Bytes written between two flushes to disk, 0 to never flush
+    flush_interval: usize,
+    unflushed: usize,
 }
 
 impl WavFileWrite {
     pub fn new(file_path: String) -> Self {
+        Self::with_batch_size(file_path, DEFAULT_WRITE_BATCH_SIZE)
+    }
+
+    pub fn with_batch_size(file_path: String, batch_size: usize) -> Self {
         Self {
             writer: None,
             file_path,
+            pending: Vec::with_capacity(batch_size),
+            batch_size,
+            final_path: None,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            unflushed: 0,
         }
     }
-}
 
-impl AudioWriter for WavFileWrite {
-    fn write(&mut self, data: &[u8]) -> Result<()> {
+    // Flushes to disk every `bytes` bytes written, so a crash loses at most
+    // that much audio. 0 only flushes on explicit `flush` calls.
+    pub fn set_flush_interval(&mut self, bytes: usize) -> &mut Self {
+        self.flush_interval = bytes;
+        self
+    }
+
+    // Writes everything received so far to disk, with a header valid for
+    // the samples written, so the file can be read before being finalized.
+    pub fn flush(&mut self) -> Result<()> {
+        self.flush_pending()?;
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        self.unflushed = 0;
+        Ok(())
+    }
+
+    // Writes to `file_path` with a ".tmp" suffix and renames it on finalize,
+    // so a crash never leaves a truncated file at `file_path`.
+    pub fn new_atomic(file_path: String) -> Self {
+        let mut writer = Self::new(format!("{}.tmp", file_path));
+        writer.final_path = Some(file_path);
+        writer
+    }
+
+    // Writes every whole sample of the pending batch. A trailing partial
+    // sample stays pending until the rest of its bytes arrive.
+    fn flush_pending(&mut self) -> Result<()> {
         let writer = self
             .writer
             .as_mut()
             .ok_or(anyhow::anyhow!("Writer not initialized"))?;
-        let spec = writer.spec();
-        match spec.sample_format {
-            hound::SampleFormat::Int => match spec.bits_per_sample {
-                16 => {
-                    for chunk in data.chunks_exact(2) {
-                        let sample = i16::from_le_bytes([chunk[0], chunk[1]]);
-                        writer.write_sample(sample)?;
-                    }
+        let written = write_samples(writer, &self.pending)?;
+        self.pending.drain(..written);
+        Ok(())
+    }
+}
+
+fn write_samples(writer: &mut HoundWriter, data: &[u8]) -> Result<usize> {
+    let spec = writer.spec();
+    let sample_size = (spec.bits_per_sample / 8) as usize;
+    if sample_size == 0 {
+        return Err(anyhow::anyhow!(
+            "Unsupported bits per sample: {}",
+            spec.bits_per_sample
+        ));
+    }
+    let samples = data.len() / sample_size;
+    match spec.sample_format {
+        hound::SampleFormat::Int => match spec.bits_per_sample {
+            16 => {
+                let mut sample_writer = writer.get_i16_writer(samples as u32);
+                for chunk in data.chunks_exact(2) {
+                    sample_writer.write_sample(i16::from_le_bytes([chunk[0], chunk[1]]));
                 }
-                32 => {
-                    for chunk in data.chunks_exact(4) {
-                        let sample = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
-                        writer.write_sample(sample)?;
-                    }
+                sample_writer.flush()?;
+            }
+            24 => {
+                for chunk in data.chunks_exact(3) {
+                    // Sign extended by the arithmetic shift
+                    let sample = i32::from_le_bytes([0, chunk[0], chunk[1], chunk[2]]) >> 8;
+                    writer.write_sample(sample)?;
                 }
-                _ => unimplemented!(),
-            },
-            hound::SampleFormat::Float => match spec.bits_per_sample {
-                32 => {
-                    for chunk in data.chunks_exact(4) {
-                        let sample = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
-                        writer.write_sample(sample)?;
-                    }
+            }
+            32 => {
+                for chunk in data.chunks_exact(4) {
+                    let sample = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                    writer.write_sample(sample)?;
                 }
-                _ => unimplemented!(),
-            },
+            }
+            bits => return Err(anyhow::anyhow!("Unsupported bits per sample: {}", bits)),
+        },
+        hound::SampleFormat::Float => match spec.bits_per_sample {
+            32 => {
+                for chunk in data.chunks_exact(4) {
+                    let sample = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                    writer.write_sample(sample)?;
+                }
+            }
+            bits => return Err(anyhow::anyhow!("Unsupported bits per sample: {}", bits)),
+        },
+    }
+    Ok(samples * sample_size)
+}
+
+impl AudioWriter for WavFileWrite {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.writer.is_none() {
+            return Err(anyhow::anyhow!("Writer not initialized"));
+        }
+        self.pending.extend_from_slice(data);
+        self.unflushed += data.len();
+        if self.flush_interval > 0 && self.unflushed >= self.flush_interval {
+            self.flush()?;
+        } else if self.pending.len() >= self.batch_size {
+            self.flush_pending()?;
         }
         Ok(())
     }
 
     fn finalize(&mut self) -> Result<()> {
-        if let Some(writer) = self.writer.take() {
-            writer.finalize().map_err(|e| anyhow::anyhow!(e))
-        } else {
-            Ok(())
+        if self.writer.is_some() {
+            self.flush_pending()?;
         }
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        writer.finalize().map_err(|e| anyhow::anyhow!(e))?;
+        if let Some(final_path) = &self.final_path {
+            std::fs::rename(&self.file_path, final_path)?;
+        }
+        Ok(())
     }
     fn update_format(&mut self, header: &crate::protocol::AudioHeader) -> Result<()> {
-        if self.writer.is_none() {
-            let spec = header.to_wavspec();
-            let writer = hound::WavWriter::create(&self.file_path, spec)?;
-            self.writer = Some(writer);
+        let spec = header.to_wavspec();
+        match &self.writer {
+            None => self.writer = Some(hound::WavWriter::create(&self.file_path, spec)?),
+            Some(writer) if writer.spec() != spec => {
+                return Err(anyhow::anyhow!(
+                    "Cannot change the format of {} while writing it",
+                    self.file_path
+                ));
+            }
+            Some(_) => {}
         }
         Ok(())
     }
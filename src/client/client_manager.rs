@@ -1,39 +1,665 @@
-use crate::audio::file::{AudioPlayer, AudioWriter};
+use crate::audio::file::{AudioPlayer, AudioWriter, AudioWriterChain};
+use crate::audio::opus::OpusDecoder;
 use crate::audio::wav::WavFileWrite;
+use crate::network::common::{FramedTransport, LengthDelimitedCodecConfig};
+use crate::network::crypto::{EncryptionKey, FrameCipher};
+use crate::network::latency::{self, LatencyEstimate, LatencyTracker};
+use crate::network::reorder::FrameReorderBuffer;
+use crate::network::trace;
+use crate::network::transport::{self, Transport};
+use crate::protocol::{
+    AudioHeader, CoverArt, MAX_COVER_ART_SIZE, Message, TrackInfo, WireCoverArtChunk,
+};
 use crate::{audio, network, protocol};
 use anyhow::Result;
-use tokio::io::AsyncReadExt;
+use bytes::Bytes;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::sync::mpsc;
+
+// Longest wait between two attempts of `connect_with_retry`.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// Audio buffered between the stream and the reader of `into_audio_stream`.
+const AUDIO_STREAM_CAPACITY: usize = 64 * 1024;
 
 pub struct ClientInterface {
-    tcp_stream: tokio::net::TcpStream,
-    audio_capabilities: Vec<Box<dyn AudioWriter>>,
+    stream: FramedTransport,
+    // Identifies this connection in protocol traces
+    connection: String,
+    audio_capabilities: AudioWriterChain,
     play_audio_after_download: Option<String>,
     audio_player: Box<dyn AudioPlayer>,
-    #[allow(unused)]
     protocol_info: crate::protocol::ProtocolInfo,
+    encryption_key: Option<EncryptionKey>,
+    // Set while the server sends Opus packets
+    opus_decoder: Option<OpusDecoder>,
+    cover_art: CoverArtAssembler,
+    output_devices: Vec<String>,
+    crossfade: Option<Duration>,
+    prebuffer: Option<Duration>,
+    // Gain of real-time playback, shared with every playback capability
+    playback_volume: Arc<AtomicU32>,
+    latency: LatencyTracker,
+    // Holds AUDIO_FRAMEs received out of order
+    reorder: FrameReorderBuffer,
+    // Of the last AUDIO_FRAME received
+    last_sequence: u64,
+    last_timestamp_us: u64,
+    tracks: Vec<TrackInfo>,
+    // Announced by the server in the header of the current stream
+    stream_total_samples: u64,
+    track_limit: Option<usize>,
+    controls: StreamControls,
+    control_rx: mpsc::UnboundedReceiver<Message>,
+    on_progress: Option<ProgressCallback>,
+    on_progress_percent: Option<PercentCallback>,
+    // Bytes of one frame of decoded audio in the current format
+    stream_frame_size: usize,
+    // Receives the decoded audio for `into_audio_stream`
+    audio_sink: Option<DuplexStream>,
+}
+
+type ProgressCallback = Arc<dyn Fn(u64) + Send + Sync>;
+type PercentCallback = Arc<dyn Fn(u8) + Send + Sync>;
+
+// Runs the progress callback of one stream on a blocking task of its own,
+// so that a slow callback does not hold up the stream, with the totals in
+// the order they were reported.
+struct ProgressReporter {
+    tx: mpsc::UnboundedSender<u64>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ProgressReporter {
+    fn spawn(on_progress: ProgressCallback) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let task = tokio::task::spawn_blocking(move || {
+            while let Some(received_bytes) = rx.blocking_recv() {
+                on_progress(received_bytes);
+            }
+        });
+        Self { tx, task }
+    }
+
+    fn report(&self, received_bytes: u64) {
+        let _ = self.tx.send(received_bytes);
+    }
+
+    // Returns once the callback has seen every total.
+    async fn finish(self) {
+        drop(self.tx);
+        let _ = self.task.await;
+    }
+}
+
+// Progress of one stream, reported to the callbacks the client set.
+struct StreamProgress {
+    bytes: Option<ProgressReporter>,
+    percent: Option<ProgressReporter>,
+    received_bytes: u64,
+    // Decoded audio received, in frames plus the bytes of one not whole yet
+    received_frames: u64,
+    partial_frame: usize,
+}
+
+impl StreamProgress {
+    fn new(
+        on_progress: Option<ProgressCallback>,
+        on_progress_percent: Option<PercentCallback>,
+        total_samples: u64,
+    ) -> Self {
+        // Without a known length there is nothing to compare with.
+        let percent = on_progress_percent
+            .filter(|_| total_samples > 0)
+            .map(|callback| {
+                let last = Mutex::new(None);
+                ProgressReporter::spawn(Arc::new(move |frames: u64| {
+                    let percent = (frames.min(total_samples) * 100 / total_samples) as u8;
+                    if last.lock().unwrap().replace(percent) != Some(percent) {
+                        callback(percent);
+                    }
+                }))
+            });
+        Self {
+            bytes: on_progress.map(ProgressReporter::spawn),
+            percent,
+            received_bytes: 0,
+            received_frames: 0,
+            partial_frame: 0,
+        }
+    }
+
+    // One frame of `frame_len` bytes received, decoded to `audio_len`
+    // bytes of audio in frames of `frame_size` bytes.
+    fn record(&mut self, frame_len: usize, audio_len: usize, frame_size: usize) {
+        self.received_bytes += frame_len as u64;
+        if let Some(bytes) = &self.bytes {
+            bytes.report(self.received_bytes);
+        }
+
+        let audio_len = self.partial_frame + audio_len;
+        let frame_size = frame_size.max(1);
+        self.received_frames += (audio_len / frame_size) as u64;
+        self.partial_frame = audio_len % frame_size;
+        if let Some(percent) = &self.percent {
+            percent.report(self.received_frames);
+        }
+    }
+
+    async fn finish(self) {
+        for reporter in [self.bytes, self.percent].into_iter().flatten() {
+            reporter.finish().await;
+        }
+    }
+}
+
+// Returned by `into_audio_stream`. The stream runs inside `poll_read`, so it
+// needs no task of its own; the client is dropped with it once it ends, which
+// closes the pipe. Not `Send`, since the
+// client it drives is not.
+struct AudioStream {
+    play: Option<Pin<Box<dyn Future<Output = Result<()>>>>>,
+    reader: DuplexStream,
+}
+
+impl AsyncRead for AudioStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if let Some(play) = self.play.as_mut()
+            && let Poll::Ready(result) = play.as_mut().poll(cx)
+        {
+            self.play = None;
+            if let Err(e) = result {
+                return Poll::Ready(Err(std::io::Error::other(e)));
+            }
+        }
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+// Handle sending control messages to the server during a stream, usable
+// from another task while `start_playing` runs. Messages sent between two
+// streams go out at the start of the next one.
+#[derive(Clone)]
+pub struct StreamControls {
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl StreamControls {
+    // Restarts the stream `sample_offset` frames from the start of the
+    // current source.
+    pub fn seek(&self, sample_offset: u64) -> Result<()> {
+        self.send(Message::Seek(sample_offset))
+    }
+
+    // Scales the rest of the stream by `gain`, from 0.0 to 2.0.
+    pub fn set_volume(&self, gain: f32) -> Result<()> {
+        if !protocol::is_valid_volume_gain(gain) {
+            return Err(anyhow::anyhow!(
+                "Volume gain {} is outside of [{}, {}]",
+                gain,
+                protocol::MIN_VOLUME_GAIN,
+                protocol::MAX_VOLUME_GAIN
+            ));
+        }
+        self.send(Message::VolumeControl(gain))
+    }
+
+    // Stops the stream without closing the connection, until `resume`.
+    pub fn pause(&self) -> Result<()> {
+        self.send(Message::Pause)
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.send(Message::Resume)
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.tx
+            .send(message)
+            .map_err(|_| anyhow::anyhow!("Client connection was dropped"))
+    }
+}
+
+// Reassembles cover art sent over several COVER_ART messages.
+#[derive(Default)]
+struct CoverArtAssembler {
+    pending: Option<CoverArt>,
+    complete: Option<CoverArt>,
+}
+
+impl CoverArtAssembler {
+    fn push(&mut self, chunk: WireCoverArtChunk) {
+        let total_len = chunk.total_len as usize;
+        if total_len > MAX_COVER_ART_SIZE {
+            tracing::warn!("Ignoring cover art of {} bytes (too large)", total_len);
+            return;
+        }
+
+        let pending = self.pending.get_or_insert_with(|| CoverArt {
+            mime_type: chunk.mime_type.clone(),
+            data: Vec::with_capacity(total_len),
+        });
+        if chunk.offset as usize != pending.data.len()
+            || pending.data.len() + chunk.data.len() > total_len
+        {
+            tracing::warn!("Discarding inconsistent cover art chunk");
+            self.pending = None;
+            return;
+        }
+
+        pending.data.extend_from_slice(&chunk.data);
+        if pending.data.len() == total_len {
+            self.complete = self.pending.take();
+        }
+    }
+}
+
+// Decoded audio of a whole stream, as returned by `collect_samples`.
+#[derive(Debug, Clone, Default)]
+pub struct CollectedAudio {
+    pub header: AudioHeader,
+    // Interleaved samples in [-1.0, 1.0]
+    pub samples: Vec<f32>,
+}
+
+impl CollectedAudio {
+    pub fn channel(&self, index: usize) -> Vec<f32> {
+        let channels = (self.header.get_channels() as usize).max(1);
+        self.samples
+            .iter()
+            .skip(index)
+            .step_by(channels)
+            .copied()
+            .collect()
+    }
+}
+
+// Audio writer converting the received stream to f32 samples in memory.
+struct SampleCollector {
+    collected: Arc<Mutex<CollectedAudio>>,
+    // Bytes of a sample split across two frames
+    remainder: Vec<u8>,
+}
+
+impl AudioWriter for SampleCollector {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let mut collected = self.collected.lock().unwrap();
+        let sample_size = (collected.header.get_bits_per_sample() as usize / 8).max(1);
+        self.remainder.extend_from_slice(data);
+        let whole = self.remainder.len() / sample_size * sample_size;
+        let samples = audio::convert::bytes_to_f32(&self.remainder[..whole], &collected.header)?;
+        collected.samples.extend(samples);
+        self.remainder.drain(..whole);
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn update_format(&mut self, header: &AudioHeader) -> Result<()> {
+        self.collected.lock().unwrap().header = *header;
+        Ok(())
+    }
 }
 
 #[allow(unused)]
 pub enum Capabilities {
     SaveToFile(String),
     RealTimePlayback,
+    // Playback with the device opened at a fixed sample rate (Hz)
+    RealTimePlaybackAtRate(u32),
+    // Received PCM pushed into a ring buffer drained by the application,
+    // see `audio::ring::ring_buffer`
+    RingBuffer(audio::ring::RingBufferWriter),
 }
 
-use bytes::Bytes;
-use tokio_stream::StreamExt;
-use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
+// Fluent way to connect a client with its capabilities. At least one of
+// `save_to_file` and `real_time_playback` is required.
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    address: String,
+    port: u16,
+    save_to_file: Option<String>,
+    real_time_playback: bool,
+    connect_timeout: Option<Duration>,
+    volume: Option<f32>,
+    prebuffer: Option<Duration>,
+    codec_config: Option<LengthDelimitedCodecConfig>,
+    socks5_proxy: Option<SocketAddr>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            address: "localhost".to_string(),
+            port: 8080,
+            save_to_file: None,
+            real_time_playback: false,
+            connect_timeout: None,
+            volume: None,
+            prebuffer: None,
+            codec_config: None,
+            socks5_proxy: None,
+        }
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn address(mut self, address: &str) -> Self {
+        self.address = address.to_string();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn save_to_file(mut self, path: &str) -> Self {
+        self.save_to_file = Some(path.to_string());
+        self
+    }
+
+    pub fn real_time_playback(mut self) -> Self {
+        self.real_time_playback = true;
+        self
+    }
+
+    /// Gives up when the connection and handshake take longer than `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// See `ClientInterface::set_volume`.
+    pub fn volume(mut self, gain: f32) -> Self {
+        self.volume = Some(gain);
+        self
+    }
+
+    /// See `ClientInterface::set_prebuffer`.
+    pub fn prebuffer_ms(mut self, ms: u64) -> Self {
+        self.prebuffer = Some(Duration::from_millis(ms));
+        self
+    }
+
+    /// See `ClientInterface::set_codec_config`.
+    pub fn codec_config(mut self, config: LengthDelimitedCodecConfig) -> Self {
+        self.codec_config = Some(config);
+        self
+    }
+
+    /// Connects through the SOCKS5 proxy at `proxy`, see
+    /// `ClientInterface::connect_via_socks5`.
+    pub fn socks5_proxy(mut self, proxy: SocketAddr) -> Self {
+        self.socks5_proxy = Some(proxy);
+        self
+    }
+
+    pub async fn connect(self) -> Result<ClientInterface> {
+        if self.save_to_file.is_none() && !self.real_time_playback {
+            return Err(anyhow::anyhow!(
+                "No capability added: call save_to_file or real_time_playback"
+            ));
+        }
+        if let Some(gain) = self.volume
+            && !protocol::is_valid_volume_gain(gain)
+        {
+            return Err(anyhow::anyhow!(
+                "Volume gain {} is outside of [{}, {}]",
+                gain,
+                protocol::MIN_VOLUME_GAIN,
+                protocol::MAX_VOLUME_GAIN
+            ));
+        }
+
+        let mut client = match (self.socks5_proxy, self.connect_timeout) {
+            (Some(proxy), Some(timeout)) => {
+                let server = transport::host_port(&self.address, self.port);
+                let connect = ClientInterface::connect_via_socks5(
+                    proxy,
+                    self.address.clone(),
+                    self.port,
+                    None,
+                );
+                tokio::time::timeout(timeout, connect).await.map_err(|_| {
+                    anyhow::anyhow!(
+                        "Timed out connecting to {} after {}ms",
+                        server,
+                        timeout.as_millis()
+                    )
+                })??
+            }
+            (Some(proxy), None) => {
+                ClientInterface::connect_via_socks5(proxy, self.address.clone(), self.port, None)
+                    .await?
+            }
+            (None, Some(timeout)) => {
+                ClientInterface::connect_timeout(self.address.clone(), self.port, timeout).await?
+            }
+            (None, None) => ClientInterface::connect(self.address.clone(), self.port).await?,
+        };
+
+        if let Some(path) = self.save_to_file {
+            client.add_capability(Capabilities::SaveToFile(path));
+        }
+        if let Some(duration) = self.prebuffer {
+            client.set_prebuffer(duration);
+        }
+        if self.real_time_playback {
+            client.add_capability(Capabilities::RealTimePlayback);
+        }
+        if let Some(gain) = self.volume {
+            client.set_volume(gain)?;
+        }
+        if let Some(config) = self.codec_config {
+            client.set_codec_config(config);
+        }
+        Ok(client)
+    }
+}
+
+// Tries every address `address` resolves to, in order.
+async fn connect_tcp(address: &str, port: u16) -> std::io::Result<tokio::net::TcpStream> {
+    let addrs = transport::resolve(address, port)?;
+    tokio::net::TcpStream::connect(&addrs[..]).await
+}
 
 impl ClientInterface {
+    /// Connects over TCP. `address` is a host name or an IP address, IPv6
+    /// ones with or without brackets.
     pub async fn connect(address: String, port: u16) -> Result<ClientInterface> {
-        let addr = format!("{}:{}", address, port);
-        let mut stream = tokio::net::TcpStream::connect(addr).await?;
-        let pinfo = network::common::client_authenticate(&mut stream).await?;
+        let stream = connect_tcp(&address, port).await?;
+        let connection = stream.local_addr()?.to_string();
+        Self::with_stream(Box::new(stream), connection, None).await
+    }
+
+    /// Same as `connect`, giving up if the server cannot be reached and the
+    /// handshake completed within `timeout`.
+    pub async fn connect_timeout(
+        address: String,
+        port: u16,
+        timeout: Duration,
+    ) -> Result<ClientInterface> {
+        let server = transport::host_port(&address, port);
+        tokio::time::timeout(timeout, Self::connect(address, port))
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Timed out connecting to {} after {}ms",
+                    server,
+                    timeout.as_millis()
+                )
+            })?
+    }
+
+    /// Same as `connect`, trying again up to `max_attempts` times when the
+    /// server cannot be reached. The delay between attempts starts at
+    /// `base_delay_ms` and doubles every time, up to 30 seconds.
+    pub async fn connect_with_retry(
+        address: String,
+        port: u16,
+        max_attempts: u32,
+        base_delay_ms: u64,
+    ) -> Result<ClientInterface> {
+        let addr = transport::host_port(&address, port);
+        let mut delay = Duration::from_millis(base_delay_ms);
+        let mut attempt = 1;
+        let stream = loop {
+            match connect_tcp(&address, port).await {
+                Ok(stream) => break stream,
+                Err(e) if attempt < max_attempts => {
+                    tracing::warn!(
+                        "Cannot connect to {} ({}), attempt {}/{}, retrying in {:?}",
+                        addr,
+                        e,
+                        attempt,
+                        max_attempts,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let connection = stream.local_addr()?.to_string();
+        Self::with_stream(Box::new(stream), connection, None).await
+    }
+
+    /// Connects and identifies this client to the server with `token`.
+    pub async fn connect_with_token(
+        address: String,
+        port: u16,
+        token: String,
+    ) -> Result<ClientInterface> {
+        let stream = connect_tcp(&address, port).await?;
+        let connection = stream.local_addr()?.to_string();
+        Self::with_stream(Box::new(stream), connection, Some(&token)).await
+    }
+
+    /// Connects over TLS, identified by `token` if any. The server
+    /// certificate is verified against the PEM certificates of
+    /// `ca_cert_path`, or against the web PKI roots without it.
+    pub async fn connect_tls(
+        address: String,
+        port: u16,
+        ca_cert_path: Option<String>,
+        token: Option<String>,
+    ) -> Result<ClientInterface> {
+        let connector = network::tls::client_connector(ca_cert_path.as_deref())?;
+        let stream = connect_tcp(&address, port).await?;
+        let connection = stream.local_addr()?.to_string();
+        let server_name = address.trim_start_matches('[').trim_end_matches(']');
+        let stream = network::tls::connect(&connector, server_name, Box::new(stream)).await?;
+        Self::with_stream(stream, connection, token.as_deref()).await
+    }
+
+    /// Connects through the SOCKS5 proxy at `proxy`, identified by `token`
+    /// if any. The proxy resolves `address` itself. Only proxies requiring
+    /// no authentication are supported.
+    pub async fn connect_via_socks5(
+        proxy: SocketAddr,
+        address: String,
+        port: u16,
+        token: Option<String>,
+    ) -> Result<ClientInterface> {
+        let host = address.trim_start_matches('[').trim_end_matches(']');
+        let stream = tokio_socks::tcp::Socks5Stream::connect(proxy, (host, port))
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Cannot reach {} through proxy {}: {}",
+                    transport::host_port(&address, port),
+                    proxy,
+                    e
+                )
+            })?;
+        let connection = stream.local_addr()?.to_string();
+        Self::with_stream(Box::new(stream), connection, token.as_deref()).await
+    }
+
+    pub async fn connect_unix(socket_path: String) -> Result<ClientInterface> {
+        let stream = tokio::net::UnixStream::connect(&socket_path).await?;
+        Self::with_stream(Box::new(stream), socket_path, None).await
+    }
+
+    /// Same as `connect_unix`, identifying this client with `token`.
+    pub async fn connect_unix_with_token(
+        socket_path: String,
+        token: String,
+    ) -> Result<ClientInterface> {
+        let stream = tokio::net::UnixStream::connect(&socket_path).await?;
+        Self::with_stream(Box::new(stream), socket_path, Some(&token)).await
+    }
+
+    /// Connects to a server created with `Server::new_loopback`, over an
+    /// in-memory connection.
+    pub async fn connect_loopback(
+        connector: &network::loopback::LoopbackConnector,
+    ) -> Result<ClientInterface> {
+        let stream = connector.connect()?;
+        Self::with_stream(Box::new(stream), "loopback".to_string(), None).await
+    }
+
+    async fn with_stream(
+        stream: Box<dyn Transport>,
+        connection: String,
+        token: Option<&str>,
+    ) -> Result<ClientInterface> {
+        let mut stream = network::common::new_framed(stream);
+        // The server traces the same connection as server/<client address>
+        let connection = format!("client/{}", connection);
+        let pinfo = trace::scope(
+            connection.clone(),
+            network::common::client_authenticate(&mut stream, token),
+        )
+        .await?;
+        let (tx, control_rx) = mpsc::unbounded_channel();
         let interface = ClientInterface {
-            tcp_stream: stream,
-            audio_capabilities: vec![],
+            stream,
+            connection,
+            audio_capabilities: AudioWriterChain::new(vec![]),
             play_audio_after_download: None,
             audio_player: Box::new(audio::cpal::CpalInterface),
             protocol_info: pinfo,
+            encryption_key: None,
+            opus_decoder: None,
+            cover_art: CoverArtAssembler::default(),
+            output_devices: vec![],
+            crossfade: None,
+            prebuffer: None,
+            playback_volume: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            latency: LatencyTracker::default(),
+            reorder: FrameReorderBuffer::default(),
+            last_sequence: 0,
+            last_timestamp_us: 0,
+            tracks: vec![],
+            stream_total_samples: 0,
+            track_limit: None,
+            controls: StreamControls { tx },
+            control_rx,
+            on_progress: None,
+            on_progress_percent: None,
+            stream_frame_size: 0,
+            audio_sink: None,
         };
         Ok(interface)
     }
@@ -41,80 +667,523 @@ impl ClientInterface {
     pub fn add_capability(&mut self, capability: Capabilities) -> &mut ClientInterface {
         match capability {
             Capabilities::SaveToFile(s) => {
-                self.audio_capabilities.push(Box::new(WavFileWrite::new(s)));
+                self.audio_capabilities
+                    .push(Box::new(WavFileWrite::new_atomic(s)));
             }
             Capabilities::RealTimePlayback => {
+                let playback = audio::cpal::CpalFileWrite::new()
+                    .with_preferred_devices(self.output_devices.clone());
+                self.audio_capabilities
+                    .push(Box::new(self.configure_playback(playback)));
+            }
+            Capabilities::RealTimePlaybackAtRate(sample_rate) => {
+                let playback = audio::cpal::CpalFileWrite::with_fixed_output_rate(sample_rate)
+                    .with_preferred_devices(self.output_devices.clone());
                 self.audio_capabilities
-                    .push(Box::new(audio::cpal::CpalFileWrite::new()));
+                    .push(Box::new(self.configure_playback(playback)));
+            }
+            Capabilities::RingBuffer(writer) => {
+                self.audio_capabilities.push(Box::new(writer));
             }
         }
         self
     }
 
-    fn update_audio_capabilities(&mut self, header: &crate::protocol::AudioHeader) -> Result<()> {
-        for capability in &mut self.audio_capabilities {
-            capability.update_format(header)?;
+    /// Output devices tried in order for real-time playback capabilities
+    /// added after this call. Playback falls back to the next available one
+    /// if the current device disappears, and to the default device last.
+    pub fn set_output_devices(&mut self, devices: Vec<String>) -> &mut ClientInterface {
+        self.output_devices = devices;
+        self
+    }
+
+    /// Crossfades consecutive tracks of a multi-track stream over `duration`
+    /// in real-time playback capabilities added after this call.
+    pub fn set_crossfade(&mut self, duration: Duration) -> &mut ClientInterface {
+        self.crossfade = Some(duration);
+        self
+    }
+
+    /// Buffers `duration` of audio, instead of the default 100 ms, before
+    /// starting real-time playback capabilities added after this call.
+    pub fn set_prebuffer(&mut self, duration: Duration) -> &mut ClientInterface {
+        self.prebuffer = Some(duration);
+        self
+    }
+
+    fn configure_playback(
+        &self,
+        mut playback: audio::cpal::CpalFileWrite,
+    ) -> audio::cpal::CpalFileWrite {
+        if let Some(duration) = self.crossfade {
+            playback = playback.with_crossfade(duration);
         }
-        Ok(())
+        if let Some(duration) = self.prebuffer {
+            playback = playback.with_prebuffer(duration);
+        }
+        playback.with_volume(Arc::clone(&self.playback_volume))
+    }
+
+    /// Pre-shared key used to decrypt audio frames when the server
+    /// advertises encrypted audio.
+    pub fn set_encryption_key(&mut self, key: EncryptionKey) -> &mut ClientInterface {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Protocol version agreed on with the server during the handshake.
+    pub fn negotiated_version(&self) -> u8 {
+        self.protocol_info.version()
+    }
+
+    /// Length of the current stream in frames, when the server knows it in
+    /// advance. Live and endless streams report none.
+    pub fn stream_total_samples(&self) -> Option<u64> {
+        (self.stream_total_samples > 0).then_some(self.stream_total_samples)
+    }
+
+    /// Cover art sent by the server for the current stream, if any.
+    pub fn cover_art(&self) -> Option<&CoverArt> {
+        self.cover_art.complete.as_ref()
+    }
+
+    /// Streams the whole file and returns it decoded to f32 samples, without
+    /// writing it anywhere. Other capabilities still receive the stream.
+    pub async fn collect_samples(&mut self) -> Result<CollectedAudio> {
+        let collected = Arc::new(Mutex::new(CollectedAudio::default()));
+        self.audio_capabilities.push(Box::new(SampleCollector {
+            collected: Arc::clone(&collected),
+            remainder: Vec::new(),
+        }));
+        let result = self.start_playing().await;
+        self.audio_capabilities.pop();
+        result?;
+
+        let collected = std::mem::take(&mut *collected.lock().unwrap());
+        Ok(collected)
+    }
+
+    /// Leaves a multi-track (radio) stream once `tracks` tracks have been
+    /// fully received. Without a limit, playback lasts until the server
+    /// stops the stream.
+    pub fn set_track_limit(&mut self, tracks: usize) -> &mut ClientInterface {
+        self.track_limit = Some(tracks);
+        self
+    }
+
+    /// Metadata of every track started so far in a multi-track stream.
+    pub fn tracks(&self) -> &[TrackInfo] {
+        &self.tracks
+    }
+
+    /// Calls `callback` after every audio frame received with the number of
+    /// bytes received since the start of the stream, e.g. to show progress.
+    /// It runs in order on a blocking thread so it cannot slow the stream
+    /// down, and has seen every frame once the stream returns.
+    pub fn set_progress_callback(
+        &mut self,
+        callback: impl Fn(u64) + Send + Sync + 'static,
+    ) -> &mut ClientInterface {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Calls `callback` with the share of the stream received so far, from
+    /// 0 to 100, each time it changes. It is computed from the decoded
+    /// frames and `stream_total_samples`, so streams of unknown length
+    /// never report it. Runs like the callback of `set_progress_callback`.
+    pub fn on_progress_percent(
+        &mut self,
+        callback: impl Fn(u8) + Send + Sync + 'static,
+    ) -> &mut ClientInterface {
+        self.on_progress_percent = Some(Arc::new(callback));
+        self
+    }
+
+    /// Rolling latency estimate, available when the server timestamps its
+    /// audio frames. Clocks are not synchronized, so it reports variations
+    /// of the transit time rather than an absolute latency.
+    pub fn latency(&self) -> Option<LatencyEstimate> {
+        self.latency.estimate()
+    }
+
+    /// Limits the frames accepted from the server, 64 KiB by default. A
+    /// longer frame ends the stream with an error before anything is
+    /// allocated for it.
+    pub fn set_codec_config(&mut self, config: LengthDelimitedCodecConfig) -> &mut Self {
+        config.apply(&mut self.stream);
+        self
     }
 
-    fn end_audio(&mut self) -> Result<()> {
-        for capability in &mut self.audio_capabilities {
-            capability.finalize()?;
+    /// Holds up to `frames` audio frames received ahead of a missing one
+    /// before giving up on it (4 by default). Frames given up on are counted
+    /// by `underrun_count`.
+    pub fn with_reorder_window(&mut self, frames: usize) -> &mut ClientInterface {
+        self.reorder = FrameReorderBuffer::new(frames);
+        self
+    }
+
+    /// Audio frames of the stream skipped because they were missing for
+    /// longer than the reorder window. Those are lost on the network; a
+    /// playback device running dry while waiting for audio is counted on
+    /// its own, by `CpalFileWrite::underrun_count`, in output frames.
+    pub fn underrun_count(&self) -> u64 {
+        self.reorder.underrun_count()
+    }
+
+    /// Sequence number of the last audio frame received, 0 until the first
+    /// one or with servers speaking protocol version 1.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Send time of the last audio frame received, in microseconds since the
+    /// UNIX epoch on the server clock.
+    pub fn last_timestamp_us(&self) -> u64 {
+        self.last_timestamp_us
+    }
+
+    /// Handle to seek and control the stream from another task while
+    /// `start_playing` or `request_file` runs.
+    pub fn stream_controls(&self) -> StreamControls {
+        self.controls.clone()
+    }
+
+    /// Moves the stream to `sample_offset` frames from the start of the
+    /// source. Called before a stream starts, it applies to that stream.
+    pub fn seek(&self, sample_offset: u64) -> Result<()> {
+        self.controls.seek(sample_offset)
+    }
+
+    /// Has the server scale the samples it sends by `gain`, from 0.0 to 2.0,
+    /// so the client does not have to. Samples saturate instead of wrapping
+    /// around above 1.0.
+    pub fn set_volume(&self, gain: f32) -> Result<()> {
+        self.controls.set_volume(gain)
+    }
+
+    /// Scales real-time playback by `gain` on this side, unlike `set_volume`
+    /// which has the server scale the stream. Defaults to 1.0 and applies to
+    /// playback capabilities already added, even while playing.
+    pub fn set_playback_volume(&self, gain: f32) {
+        self.playback_volume
+            .store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Stops the stream without closing the connection. The server keeps
+    /// its position until `resume`.
+    pub fn pause(&self) -> Result<()> {
+        self.controls.pause()
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.controls.resume()
+    }
+
+    fn stream_key(&self) -> Result<Option<&EncryptionKey>> {
+        match (
+            self.protocol_info.is_audio_encrypted(),
+            &self.encryption_key,
+        ) {
+            (true, Some(key)) => Ok(Some(key)),
+            (false, None) => Ok(None),
+            (true, None) => Err(anyhow::anyhow!(
+                "Server streams encrypted audio but no encryption key is configured"
+            )),
+            (false, Some(_)) => Err(anyhow::anyhow!(
+                "Encryption key configured but server does not encrypt audio"
+            )),
         }
-        Ok(())
     }
 
-    async fn recv_data_and_write_it(&mut self) -> Result<()> {
-        let mut framed = FramedRead::new(&mut self.tcp_stream, LengthDelimitedCodec::new());
+    fn update_audio_capabilities(&mut self, header: &crate::protocol::AudioHeader) -> Result<()> {
+        self.stream_frame_size = header.frame_size();
+        let result = self.audio_capabilities.update_format(header);
+        self.check_capabilities(result)
+    }
 
-        while let Some(frame) = framed.next().await {
-            let bytes: Bytes = frame?.into();
+    // A failing capability does not end the stream while others still work,
+    // so a broken file does not stop real-time playback.
+    fn check_capabilities(&self, result: Result<()>) -> Result<()> {
+        match result {
+            Err(e) if self.audio_capabilities.working() == 0 => Err(e),
+            Err(e) => {
+                tracing::warn!("Audio capability failed, continuing without it: {}", e);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
 
-            if protocol::is_stop_playing_message(&bytes) {
-                dbg!("Stop message received");
-                break;
+    // Writers still holding audio they had no room for get to drain it
+    // first. The server may ping us in the meantime.
+    async fn end_audio(&mut self) -> Result<()> {
+        let result = loop {
+            tokio::select! {
+                result = self.audio_capabilities.ready() => break result,
+                message = network::common::read_message(&mut self.stream, "end of stream") => {
+                    let message = message?;
+                    if message != Message::Ping {
+                        return Err(anyhow::anyhow!(
+                            "Unexpected message type after the stream: {:?}",
+                            message.message_type()
+                        ));
+                    }
+                    network::common::send_message(&mut self.stream, &Message::Pong).await?;
+                }
             }
-            for capability in &mut self.audio_capabilities {
-                capability.write(&bytes)?;
+        };
+        self.check_capabilities(result)?;
+        self.audio_capabilities.finalize()
+    }
+
+    async fn recv_data_and_write_it(&mut self, mut cipher: Option<FrameCipher>) -> Result<()> {
+        let mut completed_tracks = 0;
+        let mut leaving = false;
+        let mut progress = StreamProgress::new(
+            self.on_progress.clone(),
+            self.on_progress_percent.clone(),
+            self.stream_total_samples,
+        );
+        self.reorder.reset();
+
+        loop {
+            let message = tokio::select! {
+                message = network::common::read_message(&mut self.stream, "audio stream") => {
+                    message?
+                }
+                Some(message) = self.control_rx.recv() => {
+                    network::common::send_message(&mut self.stream, &message).await?;
+                    continue;
+                }
+            };
+
+            let frames = match message {
+                Message::StopPlaying => break,
+                Message::Ping => {
+                    network::common::send_message(&mut self.stream, &Message::Pong).await?;
+                    continue;
+                }
+                // Whatever the server sent before handling our STOP_PLAY is dropped.
+                _ if leaving => continue,
+                Message::CoverArt(chunk) => {
+                    self.cover_art.push(chunk);
+                    continue;
+                }
+                Message::TrackInfo(info) => {
+                    tracing::info!("Now playing: {}", info.title);
+                    self.cover_art = CoverArtAssembler::default();
+                    self.tracks.push(info);
+                    continue;
+                }
+                // Sent when the format changes
+                Message::AudioHeader(header) => {
+                    self.opus_decoder = OpusDecoder::for_header(&header)?;
+                    self.update_audio_capabilities(&header)?;
+                    continue;
+                }
+                Message::TrackChange(header) => {
+                    // The next track sends its own picture, if it has one.
+                    self.cover_art = CoverArtAssembler::default();
+                    self.opus_decoder = OpusDecoder::for_header(&header)?;
+                    self.update_audio_capabilities(&header)?;
+                    continue;
+                }
+                Message::EndOfTrack => {
+                    let result = self.audio_capabilities.end_of_track();
+                    self.check_capabilities(result)?;
+                    completed_tracks += 1;
+                    // The server answers with STOP_PLAY, which ends this loop.
+                    if self.track_limit == Some(completed_tracks) {
+                        network::common::send_message(&mut self.stream, &Message::StopPlaying)
+                            .await?;
+                        leaving = true;
+                    }
+                    continue;
+                }
+                Message::TimedAudioData { sent_at_us, data } => {
+                    self.latency.record(sent_at_us, latency::now_micros());
+                    vec![(None, data)]
+                }
+                Message::AudioData(data) => vec![(None, data)],
+                Message::AudioFrame(mut frame) => {
+                    if !protocol::strip_checksum(&mut frame.data) {
+                        tracing::warn!(
+                            "Audio frame {} failed its CRC check, asking for it again",
+                            frame.sequence
+                        );
+                        network::common::send_message(
+                            &mut self.stream,
+                            &Message::Nack(frame.sequence),
+                        )
+                        .await?;
+                        continue;
+                    }
+                    self.latency
+                        .record(frame.timestamp_us, latency::now_micros());
+                    self.last_sequence = frame.sequence;
+                    self.last_timestamp_us = frame.timestamp_us;
+                    self.reorder
+                        .push(frame)
+                        .into_iter()
+                        .map(|frame| (Some(frame.sequence), frame.data))
+                        .collect()
+                }
+                message => {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected message in audio stream: {:?}",
+                        message.message_type()
+                    ));
+                }
+            };
+            for (sequence, payload) in frames {
+                self.write_frame(&mut cipher, sequence, payload, &mut progress)
+                    .await?;
             }
         }
 
+        // Frames held behind a gap that the stream ended before filling
+        for frame in self.reorder.flush() {
+            self.write_frame(&mut cipher, Some(frame.sequence), frame.data, &mut progress)
+                .await?;
+        }
+
+        progress.finish().await;
         Ok(())
     }
-    async fn update_audio_header(&mut self) -> Result<()> {
-        let mut recv_buf = [0u8; 4096];
-        match self.tcp_stream.read(&mut recv_buf).await {
-            Ok(0) => Err(anyhow::anyhow!(
-                "Connection closed by the server during audio header"
-            )),
-            Ok(n) => {
-                let recv_buf = &recv_buf[..n];
-                let header =
-                    crate::protocol::extract_wav_header(&recv_buf[..n]).ok_or_else(|| {
-                        anyhow::anyhow!("Failed to extract audio header from server response")
-                    })?;
-                dbg!("Received audio header from server: {:?}", header);
-                self.update_audio_capabilities(&header)
+
+    // Decrypts and decodes one audio frame, numbered `sequence` unless it
+    // came from a version 1 server, and writes it to the capabilities.
+    async fn write_frame(
+        &mut self,
+        cipher: &mut Option<FrameCipher>,
+        sequence: Option<u64>,
+        payload: Vec<u8>,
+        progress: &mut StreamProgress,
+    ) -> Result<()> {
+        let frame_len = payload.len();
+        let payload = match cipher.as_mut() {
+            Some(cipher) => {
+                if let Some(sequence) = sequence {
+                    cipher.set_sequence(sequence);
+                }
+                Bytes::from(cipher.decrypt(&payload)?)
+            }
+            None => Bytes::from(payload),
+        };
+        let payload = match self.opus_decoder.as_mut() {
+            Some(decoder) => Bytes::from(decoder.decode_to_bytes(&payload)?),
+            None => payload,
+        };
+        // Playback takes audio at its own pace, waited for here rather than
+        // in `write` so that controls and pings keep flowing
+        let result = self.audio_capabilities.ready().await;
+        self.check_capabilities(result)?;
+        let result = self.audio_capabilities.write(&payload);
+        self.check_capabilities(result)?;
+        if let Some(sink) = self.audio_sink.as_mut() {
+            sink.write_all(&payload).await?;
+        }
+        progress.record(frame_len, payload.len(), self.stream_frame_size);
+        Ok(())
+    }
+
+    // Next message from the server. The PINGs it sends between streams too
+    // are answered on the way.
+    async fn read_server_message(&mut self, step: &str) -> Result<Message> {
+        loop {
+            let message = network::common::read_message(&mut self.stream, step).await?;
+            if message != Message::Ping {
+                return Ok(message);
             }
-            Err(e) => Err(anyhow::anyhow!("Error reading from socket: {}", e)),
+            network::common::send_message(&mut self.stream, &Message::Pong).await?;
         }
     }
 
+    // Encrypted streams start with the salt of their cipher.
+    async fn read_stream_cipher(&mut self) -> Result<Option<FrameCipher>> {
+        let Some(key) = self.stream_key()?.copied() else {
+            return Ok(None);
+        };
+        let message = self.read_server_message("stream salt").await?;
+        network::common::check_rejection(&message)?;
+        let Message::StreamSalt(salt) = message else {
+            return Err(anyhow::anyhow!(
+                "Expected the stream salt of an encrypted stream, got {:?}",
+                message.message_type()
+            ));
+        };
+        Ok(Some(FrameCipher::with_salt(&key, salt)))
+    }
+
+    async fn update_audio_header(&mut self) -> Result<()> {
+        let message = self.read_server_message("audio header").await?;
+        network::common::check_rejection(&message)?;
+        let Message::AudioHeader(header) = message else {
+            return Err(anyhow::anyhow!(
+                "Failed to extract audio header from server response"
+            ));
+        };
+        self.opus_decoder = OpusDecoder::for_header(&header)?;
+        self.stream_total_samples = header.get_total_samples();
+        self.update_audio_capabilities(&header)
+    }
+
     pub async fn start_playing(&mut self) -> Result<()> {
-        network::common::send_start_playing(&mut self.tcp_stream).await?;
+        self.play(None).await
+    }
+
+    /// Streams the default file and returns its decoded audio, in the format
+    /// of the stream header, as a reader. Reading drives the stream, which
+    /// ends with the reader hitting EOF; capabilities still receive it.
+    ///
+    /// The reader is not `Send`: it owns the client, whose player and
+    /// capabilities may hold audio device handles that must stay on their
+    /// thread. Read it from the task that created it, or hand it to
+    /// `tokio::task::spawn_local` inside a `LocalSet`, not `tokio::spawn`.
+    pub fn into_audio_stream(mut self) -> impl AsyncRead + Unpin {
+        let (reader, writer) = tokio::io::duplex(AUDIO_STREAM_CAPACITY);
+        self.audio_sink = Some(writer);
+        AudioStream {
+            play: Some(Box::pin(async move { self.start_playing().await })),
+            reader,
+        }
+    }
 
+    /// Streams `file`, a path relative to the server's media directory,
+    /// instead of the file the server serves by default.
+    pub async fn request_file(&mut self, file: &str) -> Result<()> {
+        self.play(Some(file)).await
+    }
+
+    async fn play(&mut self, file: Option<&str>) -> Result<()> {
+        trace::scope(self.connection.clone(), self.play_traced(file)).await
+    }
+
+    async fn play_traced(&mut self, file: Option<&str>) -> Result<()> {
+        self.stream_key()?;
+        // Pictures of a previous stream must not show for this one.
+        self.cover_art = CoverArtAssembler::default();
+
+        match file {
+            Some(file) => network::common::send_request_file(&mut self.stream, file).await?,
+            None => network::common::send_start_playing(&mut self.stream).await?,
+        }
+
+        let cipher = self.read_stream_cipher().await?;
         self.update_audio_header().await?;
 
-        network::common::send_ok_message(&mut self.tcp_stream).await?;
+        network::common::send_ok_message(&mut self.stream).await?;
 
-        self.recv_data_and_write_it().await?;
+        self.recv_data_and_write_it(cipher).await?;
 
-        self.end_audio()?;
+        self.end_audio().await?;
 
-        network::common::send_bye_message(&mut self.tcp_stream).await?;
+        network::common::send_bye_message(&mut self.stream).await?;
 
-        network::common::expect_bye_message(&mut self.tcp_stream).await?;
+        if self.read_server_message("BYE message").await? != Message::Bye {
+            return Err(anyhow::anyhow!("Did not receive BYE message from server"));
+        }
 
         if let Some(file) = self.play_audio_after_download.as_ref() {
             self.audio_player
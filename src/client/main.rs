@@ -1,6 +1,10 @@
 use anyhow::Result;
 use clap::Parser;
+use std::net::ToSocketAddrs;
+use streamapp::audio::cpal::CpalInterface;
 use streamapp::client::client_manager;
+use streamapp::client::udp_client::UdpClientInterface;
+use streamapp::network::transport;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Audio Streaming Client")]
@@ -9,30 +13,211 @@ struct Args {
     #[arg(long, default_value = "/tmp/client_output.wav")]
     output: String,
 
-    /// Server address
-    #[arg(long, default_value = "localhost")]
-    address: String,
+    /// Server address, a host name or an IPv4 or IPv6 address
+    /// Default is localhost, or :: with --ipv6
+    #[arg(long)]
+    address: Option<String>,
+
+    /// Default the server address to the IPv6 one, ::
+    #[arg(long, default_value_t = false)]
+    ipv6: bool,
 
     /// Server port
     #[arg(long, default_value_t = 8080)]
     port: u16,
 
+    /// Transport to the server: tcp, or udp to skip lost audio instead of
+    /// waiting for it (--play and --output only)
+    #[arg(long, default_value = "tcp")]
+    transport: String,
+
+    /// Connect to a server on this Unix domain socket instead of TCP
+    #[arg(long)]
+    socket: Option<String>,
+
+    /// Token identifying this client to servers requiring one
+    #[arg(long)]
+    auth_token: Option<String>,
+
+    /// Connect through the SOCKS5 proxy at this host:port (TCP only, without
+    /// TLS). Proxies requiring authentication are not supported yet
+    #[arg(long, conflicts_with_all = ["socket", "tls"])]
+    proxy: Option<String>,
+
+    /// Connect over TLS (TCP only)
+    #[arg(long, default_value_t = false, conflicts_with = "socket")]
+    tls: bool,
+
+    /// PEM certificates to verify the server with instead of the web PKI
+    /// roots, e.g. for a self-signed server (with --tls)
+    #[arg(long, requires = "tls")]
+    tls_ca: Option<String>,
+
+    /// Connection attempts before giving up on an unreachable server (TCP
+    /// only)
+    #[arg(long, default_value_t = 1)]
+    retry_attempts: u32,
+
+    /// Delay before the first retry, doubled after each attempt
+    #[arg(long, default_value_t = 500)]
+    retry_base_ms: u64,
+
+    /// Give up connecting after this many milliseconds, handshake and
+    /// retries included
+    #[arg(long)]
+    connect_timeout_ms: Option<u64>,
+
     /// Play audio after download
     /// Default is false
     #[arg(long, default_value_t = false)]
     play: bool,
+
+    /// Open the output device at this sample rate and resample to it
+    #[arg(long)]
+    output_rate: Option<u32>,
+
+    /// Preferred output device, can be repeated to form a fallback chain
+    #[arg(long = "device", visible_alias = "output-device")]
+    devices: Vec<String>,
+
+    /// Print the available output devices and exit
+    #[arg(long, default_value_t = false)]
+    list_devices: bool,
+
+    /// Audio buffered before playback starts, in milliseconds (100 when not
+    /// set)
+    #[arg(long)]
+    prebuffer_ms: Option<u64>,
+
+    /// Crossfade consecutive tracks over this many milliseconds
+    #[arg(long)]
+    crossfade_ms: Option<u64>,
+
+    /// Log every protocol message sent and received to stderr
+    #[arg(long, default_value_t = false)]
+    trace: bool,
+
+    /// Most verbose log level written to stderr: error, warn, info, debug or
+    /// trace
+    #[arg(long, default_value = "info")]
+    log_level: tracing::Level,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(args.log_level)
+        .with_writer(std::io::stderr)
+        .init();
+    if args.list_devices {
+        for device in CpalInterface::list_output_devices()? {
+            println!("{}", device);
+        }
+        return Ok(());
+    }
+    if args.trace {
+        streamapp::network::trace::enable();
+    }
 
-    let mut handler = client_manager::ClientInterface::connect(args.address, args.port)
-        .await
-        .expect("Failed to connect to server");
+    let address = args
+        .address
+        .unwrap_or_else(|| if args.ipv6 { "::" } else { "localhost" }.to_string());
+    let udp = match args.transport.as_str() {
+        "tcp" => false,
+        "udp" => true,
+        _ => return Err(anyhow::anyhow!("Invalid transport. Use 'tcp' or 'udp'.")),
+    };
+    let proxy = match &args.proxy {
+        Some(_) if udp => {
+            return Err(anyhow::anyhow!("UDP streams cannot go through a proxy"));
+        }
+        Some(proxy) => Some(
+            proxy
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("No address found for proxy {}", proxy))?,
+        ),
+        None => None,
+    };
+    if udp {
+        let mut handler = UdpClientInterface::connect(address, args.port).await?;
+        handler.add_capability(client_manager::Capabilities::SaveToFile(args.output));
+        if args.play {
+            match args.output_rate {
+                Some(rate) => handler
+                    .add_capability(client_manager::Capabilities::RealTimePlaybackAtRate(rate)),
+                None => handler.add_capability(client_manager::Capabilities::RealTimePlayback),
+            };
+        }
+        handler.start_playing().await?;
+        if handler.lost_frames() > 0 {
+            tracing::warn!("{} audio frames lost", handler.lost_frames());
+        }
+        return Ok(());
+    }
+
+    let server = match &args.socket {
+        Some(socket) => socket.clone(),
+        None => transport::host_port(&address, args.port),
+    };
+    let connect = async {
+        if let Some(proxy) = proxy {
+            return client_manager::ClientInterface::connect_via_socks5(
+                proxy,
+                address,
+                args.port,
+                args.auth_token,
+            )
+            .await;
+        }
+        match (args.socket, args.auth_token) {
+            (None, token) if args.tls => {
+                client_manager::ClientInterface::connect_tls(address, args.port, args.tls_ca, token)
+                    .await
+            }
+            (Some(socket), Some(token)) => {
+                client_manager::ClientInterface::connect_unix_with_token(socket, token).await
+            }
+            (Some(socket), None) => client_manager::ClientInterface::connect_unix(socket).await,
+            (None, Some(token)) => {
+                client_manager::ClientInterface::connect_with_token(address, args.port, token).await
+            }
+            (None, None) => {
+                client_manager::ClientInterface::connect_with_retry(
+                    address,
+                    args.port,
+                    args.retry_attempts,
+                    args.retry_base_ms,
+                )
+                .await
+            }
+        }
+    };
+    let mut handler = match args.connect_timeout_ms {
+        // Same wording as ClientInterface::connect_timeout, for every kind
+        // of connection
+        Some(ms) => tokio::time::timeout(std::time::Duration::from_millis(ms), connect)
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out connecting to {} after {}ms", server, ms))?,
+        None => connect.await,
+    }
+    .map_err(|e| anyhow::anyhow!("Failed to connect to server: {}", e))?;
 
     if args.play {
-        handler.add_capability(client_manager::Capabilities::RealTimePlayback);
+        handler.set_output_devices(args.devices);
+        if let Some(crossfade_ms) = args.crossfade_ms {
+            handler.set_crossfade(std::time::Duration::from_millis(crossfade_ms));
+        }
+        if let Some(prebuffer_ms) = args.prebuffer_ms {
+            handler.set_prebuffer(std::time::Duration::from_millis(prebuffer_ms));
+        }
+        match args.output_rate {
+            Some(rate) => {
+                handler.add_capability(client_manager::Capabilities::RealTimePlaybackAtRate(rate))
+            }
+            None => handler.add_capability(client_manager::Capabilities::RealTimePlayback),
+        };
     }
 
     handler.start_playing().await
@@ -1 +1,2 @@
 pub mod client_manager;
+pub mod udp_client;
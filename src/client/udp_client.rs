@@ -0,0 +1,150 @@
+use crate::audio::file::{AudioWriter, AudioWriterChain};
+use crate::audio::wav::WavFileWrite;
+use crate::client::client_manager::Capabilities;
+use crate::network::transport;
+use crate::network::udp::{self, MAX_DATAGRAM_LEN};
+use crate::protocol::{self, AudioHeader, Message};
+use crate::{audio, network};
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+// START_PLAY is sent again after this long without an answer, up to
+// `START_ATTEMPTS` times.
+const START_RETRY_DELAY: Duration = Duration::from_millis(500);
+const START_ATTEMPTS: u32 = 5;
+
+// The STOP_PLAY ending a stream may be lost too: the stream is considered
+// over once nothing arrived for this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Plays a stream of `UdpServer` as frames arrive. Frames that never arrive
+// are replaced with as much silence, and frames arriving after a later one
+// are dropped, so the audio keeps its timing whatever the network loses.
+pub struct UdpClientInterface {
+    socket: UdpSocket,
+    server: SocketAddr,
+    audio_capabilities: AudioWriterChain,
+    lost_frames: u64,
+}
+
+impl UdpClientInterface {
+    /// Prepares to stream from the UDP server at `address` and `port`.
+    /// Nothing is sent until `start_playing`, so an unreachable server only
+    /// shows then.
+    pub async fn connect(address: String, port: u16) -> Result<UdpClientInterface> {
+        let server = *transport::resolve(&address, port)?
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No address found for {}", address))?;
+        let socket = udp::bind_for(&server).await?;
+        socket.connect(server).await?;
+        Ok(UdpClientInterface {
+            socket,
+            server,
+            audio_capabilities: AudioWriterChain::new(vec![]),
+            lost_frames: 0,
+        })
+    }
+
+    pub fn add_capability(&mut self, capability: Capabilities) -> &mut UdpClientInterface {
+        let writer: Box<dyn AudioWriter> = match capability {
+            Capabilities::SaveToFile(s) => Box::new(WavFileWrite::new_atomic(s)),
+            Capabilities::RealTimePlayback => Box::new(audio::cpal::CpalFileWrite::new()),
+            Capabilities::RealTimePlaybackAtRate(sample_rate) => Box::new(
+                audio::cpal::CpalFileWrite::with_fixed_output_rate(sample_rate),
+            ),
+            Capabilities::RingBuffer(writer) => Box::new(writer),
+        };
+        self.audio_capabilities.push(writer);
+        self
+    }
+
+    /// Frames of the last stream replaced with silence because they were
+    /// lost, late or corrupted.
+    pub fn lost_frames(&self) -> u64 {
+        self.lost_frames
+    }
+
+    pub async fn start_playing(&mut self) -> Result<()> {
+        self.lost_frames = 0;
+        let header = self.request_stream().await?;
+        self.audio_capabilities.update_format(&header)?;
+        self.recv_data_and_write_it().await?;
+        self.audio_capabilities.finalize()
+    }
+
+    async fn request_stream(&mut self) -> Result<AudioHeader> {
+        let mut buffer = vec![0u8; MAX_DATAGRAM_LEN];
+        for _ in 0..START_ATTEMPTS {
+            udp::send_to(&self.socket, &Message::StartPlaying, self.server).await?;
+            let answer =
+                tokio::time::timeout(START_RETRY_DELAY, udp::recv_from(&self.socket, &mut buffer));
+            match answer.await {
+                Ok(answer) => {
+                    let (message, _) = answer?;
+                    network::common::check_rejection(&message)?;
+                    // Frames of a stream already started, whose header was lost
+                    let Message::AudioHeader(header) = message else {
+                        continue;
+                    };
+                    return Ok(header);
+                }
+                Err(_) => continue,
+            }
+        }
+        Err(anyhow::anyhow!(
+            "No answer from the UDP server at {}",
+            self.server
+        ))
+    }
+
+    async fn recv_data_and_write_it(&mut self) -> Result<()> {
+        let mut buffer = vec![0u8; MAX_DATAGRAM_LEN];
+        let mut next_sequence = 0;
+        loop {
+            let received =
+                tokio::time::timeout(IDLE_TIMEOUT, udp::recv_from(&self.socket, &mut buffer));
+            let Ok(received) = received.await else {
+                tracing::warn!("Nothing received for {:?}, ending the stream", IDLE_TIMEOUT);
+                return Ok(());
+            };
+            let mut frame = match received?.0 {
+                Message::StopPlaying => return Ok(()),
+                Message::AudioFrame(frame) => frame,
+                // Repeated because of a START_PLAY sent again
+                Message::AudioHeader(_) => continue,
+                message => {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected message in audio stream: {:?}",
+                        message.message_type()
+                    ));
+                }
+            };
+            if frame.sequence < next_sequence {
+                tracing::warn!("Dropping audio frame {} received too late", frame.sequence);
+                continue;
+            }
+            if !protocol::strip_checksum(&mut frame.data) {
+                tracing::warn!("Audio frame {} failed its CRC check", frame.sequence);
+                continue;
+            }
+            // Frames all hold the same number of samples but the last one
+            let missing = frame.sequence - next_sequence;
+            if missing > 0 {
+                tracing::warn!(
+                    "Audio frames {} to {} lost, playing silence instead",
+                    next_sequence,
+                    frame.sequence - 1
+                );
+                self.lost_frames += missing;
+                let silence = vec![0u8; frame.data.len() * missing as usize];
+                self.audio_capabilities.ready().await?;
+                self.audio_capabilities.write(&silence)?;
+            }
+            self.audio_capabilities.ready().await?;
+            self.audio_capabilities.write(&frame.data)?;
+            next_sequence = frame.sequence + 1;
+        }
+    }
+}
@@ -1,5 +1,6 @@
 pub mod audio;
 pub mod client;
+pub mod metrics;
 pub mod network;
 pub mod protocol;
 pub mod server;
@@ -0,0 +1,116 @@
+use crate::network::transport;
+use crate::server::registry::ConnectionRegistry;
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use prometheus::core::Collector;
+use prometheus::{Encoder, IntCounter, IntGauge, TextEncoder};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+pub const DEFAULT_METRICS_PORT: u16 = 9090;
+
+// Serves the counters of a server's registry over HTTP at `/metrics`, in
+// the Prometheus text format. Values are read from the registry on each
+// scrape, so serving them never holds up the clients.
+pub struct PrometheusExporter {
+    registry: ConnectionRegistry,
+}
+
+impl PrometheusExporter {
+    pub fn new(registry: ConnectionRegistry) -> Self {
+        Self { registry }
+    }
+
+    // The metrics as of now, in the Prometheus text format.
+    pub fn render(&self) -> Result<String> {
+        let bytes_sent = IntCounter::new(
+            "rstream_bytes_sent_total",
+            "Audio payload bytes sent to clients",
+        )?;
+        bytes_sent.inc_by(self.registry.total_bytes_sent());
+        let frames_sent =
+            IntCounter::new("rstream_frames_sent_total", "Audio frames sent to clients")?;
+        frames_sent.inc_by(self.registry.total_frames_sent());
+        let connected_clients = IntGauge::new(
+            "rstream_connected_clients_gauge",
+            "Clients currently connected",
+        )?;
+        connected_clients.set(self.registry.len() as i64);
+        let stream_errors = IntCounter::new(
+            "rstream_stream_errors_total",
+            "Client sessions ended by an error",
+        )?;
+        stream_errors.inc_by(self.registry.stream_errors());
+
+        let families = [
+            bytes_sent.collect(),
+            frames_sent.collect(),
+            connected_clients.collect(),
+            stream_errors.collect(),
+        ]
+        .concat();
+        let mut text = Vec::new();
+        TextEncoder::new().encode(&families, &mut text)?;
+        Ok(String::from_utf8(text)?)
+    }
+
+    fn respond(&self, request: &Request<Incoming>) -> Response<Full<Bytes>> {
+        let response = Response::builder();
+        if request.uri().path() != "/metrics" {
+            return response
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+        }
+        match self.render() {
+            Ok(text) => response
+                .header(
+                    hyper::header::CONTENT_TYPE,
+                    TextEncoder::new().format_type(),
+                )
+                .body(Full::new(Bytes::from(text)))
+                .unwrap(),
+            Err(e) => {
+                tracing::error!("Cannot render metrics: {}", e);
+                response
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Full::new(Bytes::new()))
+                    .unwrap()
+            }
+        }
+    }
+
+    // Answers scrapes on `address`:`port` until the task is dropped.
+    pub async fn serve(self, address: &str, port: u16) -> Result<()> {
+        let addr = transport::host_port(address, port);
+        let listener = TcpListener::bind(&addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind to {}: {}", addr, e))?;
+        tracing::info!("Metrics served on {}/metrics", addr);
+
+        let exporter = Arc::new(self);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let exporter = exporter.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| {
+                    let response = exporter.respond(&request);
+                    async move { Ok::<_, Infallible>(response) }
+                });
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!("Metrics connection failed: {}", e);
+                }
+            });
+        }
+    }
+}
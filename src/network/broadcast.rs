@@ -0,0 +1,153 @@
+use crate::{
+    audio::{convert, file::AudioReader, opus::OpusEncoder},
+    network::{
+        common::{FramedTransport, expect_stream_ok, send_message},
+        file::{
+            SendOptions, audio_frame_message, listen_client, reader_header, resend_frame,
+            send_header, send_stop_playing_message,
+        },
+        keepalive,
+    },
+    protocol::{AudioCodec, AudioHeader, Message},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{Sink, StreamExt};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+// Chunks a client may fall behind the live stream before it skips ahead.
+pub(crate) const BROADCAST_CAPACITY: usize = 256;
+
+// Publishes the payloads of `audio_reader`, coded as announced by `header`,
+// at the pace they play at. An empty chunk marks the end of the broadcast.
+// Chunks published while no client listens are lost, as on the radio.
+pub(crate) async fn read_and_publish(
+    audio_reader: &mut (dyn AudioReader + Send),
+    header: AudioHeader,
+    sender: &broadcast::Sender<Bytes>,
+) -> Result<()> {
+    let source = reader_header(audio_reader);
+    let frame_size = source.frame_size();
+    if frame_size == 0 || source.get_sample_rate() == 0 {
+        return Err(anyhow::anyhow!("Invalid source format: {:?}", source));
+    }
+    let mut encoder = match header.get_codec() {
+        AudioCodec::Raw => None,
+        AudioCodec::Opus => Some(OpusEncoder::new(
+            source.get_sample_rate(),
+            source.get_channels(),
+        )?),
+    };
+
+    let start = Instant::now();
+    let mut frames = 0u64;
+    let mut buffer = vec![0u8; 4096];
+    loop {
+        let n = audio_reader.read(&mut buffer[..])?;
+        if n == 0 {
+            break;
+        }
+        let payloads = match encoder.as_mut() {
+            Some(encoder) => encoder.encode(&convert::bytes_to_f32(&buffer[..n], &source)?)?,
+            None => vec![buffer[..n].to_vec()],
+        };
+        for payload in payloads {
+            // Fails only when nobody is subscribed
+            let _ = sender.send(Bytes::from(payload));
+        }
+
+        frames += (n / frame_size) as u64;
+        let played =
+            std::time::Duration::from_secs_f64(frames as f64 / source.get_sample_rate() as f64);
+        tokio::time::sleep_until(start + played).await;
+    }
+    if let Some(payload) = encoder.as_mut().map(|e| e.flush()).transpose()?.flatten() {
+        let _ = sender.send(Bytes::from(payload));
+    }
+    Ok(())
+}
+
+// Returns at the end of the broadcast. A client that does not keep up skips
+// the chunks it missed instead of slowing the others down.
+async fn forward_chunks<S>(
+    header: &AudioHeader,
+    receiver: &mut broadcast::Receiver<Bytes>,
+    framed: &mut S,
+    options: &mut SendOptions,
+) -> Result<()>
+where
+    S: Sink<Bytes, Error = std::io::Error> + Unpin,
+{
+    let mut pings = options.pings.clone();
+    let control = options.control.clone();
+    loop {
+        let chunk = tokio::select! {
+            chunk = receiver.recv() => chunk,
+            ping = keepalive::next_ping(&mut pings) => {
+                ping?;
+                send_message(framed, &Message::Ping).await?;
+                continue;
+            }
+            sequence = control.next_resend() => {
+                resend_frame(framed, &options.resend, sequence).await?;
+                continue;
+            }
+        };
+        let payload = match chunk {
+            Ok(chunk) if chunk.is_empty() => return Ok(()),
+            Ok(chunk) => chunk,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(
+                    "Client fell behind the broadcast, {} chunks skipped",
+                    skipped
+                );
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        let len = payload.len();
+        let payload = match options.cipher.as_mut() {
+            Some(cipher) => cipher.encrypt(&payload)?,
+            None => payload.to_vec(),
+        };
+        let frame = audio_frame_message(
+            header,
+            payload,
+            options.version,
+            options.timestamps,
+            &mut options.next_sequence,
+            &mut options.resend,
+        );
+        send_message(framed, &frame).await?;
+        options.pongs.record_progress();
+        options.sent.record(len);
+    }
+}
+
+// Joins the broadcast where it currently is: header, OK from the client,
+// then the live chunks until the broadcast or the client stops. Seek, pause
+// and volume requests are ignored, the stream being shared with other
+// clients.
+pub async fn send_broadcast(
+    header: &AudioHeader,
+    mut receiver: broadcast::Receiver<Bytes>,
+    socket: &mut FramedTransport,
+    mut options: SendOptions,
+) -> Result<()> {
+    let _keepalive = options.start_keepalive();
+    send_header(header, 0, options.cipher.as_ref(), socket).await?;
+
+    expect_stream_ok(socket, &options.pongs).await?;
+
+    let (mut framed, mut reader) = socket.split();
+    let pongs = options.pongs.clone();
+    let control = options.control.clone();
+
+    tokio::select! {
+        result = forward_chunks(header, &mut receiver, &mut framed, &mut options) => result?,
+        result = listen_client(&mut reader, &pongs, &control) => result?,
+    }
+
+    send_stop_playing_message(&mut framed).await
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::Notify;
+
+pub const DEFAULT_MAX_BUFFERED_BYTES: usize = 1 << 20;
+
+// What to do with a frame that would push a connection above its cap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferPolicy {
+    DropFrames,
+    Throttle,
+    Close,
+}
+
+// Accounts for the bytes a connection has queued but not written to its
+// socket yet. Clones share the same counters.
+#[derive(Clone)]
+pub struct BufferAccount {
+    max_bytes: usize,
+    policy: BufferPolicy,
+    used: Arc<AtomicUsize>,
+    dropped_frames: Arc<AtomicU64>,
+    released: Arc<Notify>,
+}
+
+impl BufferAccount {
+    pub fn new(max_bytes: usize, policy: BufferPolicy) -> Self {
+        Self {
+            max_bytes,
+            policy,
+            used: Arc::new(AtomicUsize::new(0)),
+            dropped_frames: Arc::new(AtomicU64::new(0)),
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn policy(&self) -> BufferPolicy {
+        self.policy
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames.load(Ordering::SeqCst)
+    }
+
+    fn fits(&self, n: usize) -> bool {
+        let used = self.used();
+        // A single frame larger than the cap still goes through on its own.
+        used == 0 || used + n <= self.max_bytes
+    }
+
+    // Returns Ok(true) when the frame may be queued, Ok(false) when it must be
+    // dropped, and an error when the connection has to be closed.
+    pub async fn reserve(&self, n: usize) -> Result<bool> {
+        loop {
+            let released = self.released.notified();
+            if self.fits(n) {
+                self.used.fetch_add(n, Ordering::SeqCst);
+                return Ok(true);
+            }
+            match self.policy {
+                BufferPolicy::DropFrames => {
+                    self.dropped_frames.fetch_add(1, Ordering::SeqCst);
+                    return Ok(false);
+                }
+                BufferPolicy::Close => {
+                    return Err(anyhow::anyhow!(
+                        "Connection buffer above {} bytes (client not keeping up)",
+                        self.max_bytes
+                    ));
+                }
+                BufferPolicy::Throttle => released.await,
+            }
+        }
+    }
+
+    pub fn release(&self, n: usize) {
+        self.used.fetch_sub(n, Ordering::SeqCst);
+        self.released.notify_waiters();
+    }
+}
+
+impl Default for BufferAccount {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_BUFFERED_BYTES, BufferPolicy::Throttle)
+    }
+}
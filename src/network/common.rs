@@ -1,135 +1,303 @@
 use anyhow::Result;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use bytes::{Bytes, BytesMut};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
-use crate::protocol::ProtocolInfo;
+use crate::network::trace;
+use crate::network::transport::Transport;
+use crate::protocol::{self, Message, ProtocolInfo};
 
-pub async fn send_hello(tcp_stream: &mut TcpStream) -> Result<()> {
-    let client_hello_msg = crate::protocol::make_client_hello_message();
-    tcp_stream
-        .write_all(&client_hello_msg)
-        .await
-        .map_err(|e: std::io::Error| anyhow::anyhow!(e))?;
-    Ok(())
+// Connection once framed: every message, handshake included, is sent as its
+// own length-delimited frame, so a message split or merged by the network is
+// still read whole.
+pub type FramedTransport = Framed<Box<dyn Transport>, LengthDelimitedCodec>;
+
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 64 * 1024;
+
+// Framing limits of a connection. A peer announcing a longer frame gets an
+// error instead of making the other side allocate for it, and longer frames
+// cannot be sent either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthDelimitedCodecConfig {
+    pub max_frame_length: usize,
+}
+
+impl LengthDelimitedCodecConfig {
+    pub fn apply(&self, framed: &mut FramedTransport) {
+        framed
+            .codec_mut()
+            .set_max_frame_length(self.max_frame_length);
+    }
+
+    /// Largest chunk of audio whose AUDIO_FRAME still fits in a frame.
+    pub fn max_chunk_size(&self) -> usize {
+        self.max_frame_length
+            .saturating_sub(protocol::MAX_AUDIO_FRAME_OVERHEAD)
+    }
 }
 
-pub async fn client_authenticate(tcp_stream: &mut TcpStream) -> Result<ProtocolInfo> {
-    send_hello(tcp_stream).await?;
-    let protocol_info = Some(expect_protocol_info(tcp_stream).await?);
-    send_ok_message(tcp_stream).await?;
-    protocol_info.ok_or(anyhow::anyhow!(
-        "Failed to receive protocol info from server"
-    ))
+impl Default for LengthDelimitedCodecConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+        }
+    }
 }
 
-pub async fn send_ok_message(tcp_stream: &mut TcpStream) -> Result<()> {
-    let ok_msg = crate::protocol::make_ok_message();
-    tcp_stream
-        .write_all(&ok_msg)
+pub fn new_framed(stream: Box<dyn Transport>) -> FramedTransport {
+    let mut framed = Framed::new(stream, LengthDelimitedCodec::new());
+    LengthDelimitedCodecConfig::default().apply(&mut framed);
+    framed
+}
+
+// Sends `message` in its own frame.
+pub(crate) async fn send_message<S>(framed: &mut S, message: &Message) -> Result<()>
+where
+    S: Sink<Bytes, Error = std::io::Error> + Unpin + ?Sized,
+{
+    let encoded = message.encode();
+    trace::sent(&encoded);
+    framed
+        .send(Bytes::from(encoded))
         .await
-        .map_err(|e| anyhow::anyhow!("Error sending OK message: {}", e))
+        .map_err(|e| anyhow::anyhow!("Error sending {:?}: {}", message.message_type(), e))
 }
 
-async fn expect_protocol_info(tcp_stream: &mut TcpStream) -> Result<crate::protocol::ProtocolInfo> {
-    let mut recv_buf = [0u8; 4096];
-    match tcp_stream.read(&mut recv_buf).await {
-        Ok(0) => Err(anyhow::anyhow!(
-            "Connection closed by the server during protocol info"
-        )),
-        Ok(n) => {
-            let recv_buf = &recv_buf[..n];
-            crate::protocol::extract_protocol_info(recv_buf).ok_or_else(|| {
-                anyhow::anyhow!("Failed to extract protocol info from server response")
-            })
+// Reads and decodes the next frame, whatever message it holds. `step` names
+// what was expected in errors.
+pub(crate) async fn read_message<R>(framed: &mut R, step: &str) -> Result<Message>
+where
+    R: Stream<Item = std::io::Result<BytesMut>> + Unpin + ?Sized,
+{
+    match framed.next().await {
+        Some(Ok(frame)) => {
+            trace::received(&frame);
+            Ok(Message::decode(&frame)?)
         }
-        Err(e) => Err(anyhow::anyhow!("Error reading from socket: {}", e)),
+        Some(Err(e)) => Err(anyhow::anyhow!("Error reading from socket: {}", e)),
+        None => Err(anyhow::anyhow!(
+            "Connection closed by the peer during {}",
+            step
+        )),
+    }
+}
+
+pub async fn send_hello(framed: &mut FramedTransport, token: Option<&str>) -> Result<()> {
+    send_message(framed, &Message::hello(token)).await
+}
+
+pub async fn client_authenticate(
+    framed: &mut FramedTransport,
+    token: Option<&str>,
+) -> Result<ProtocolInfo> {
+    send_hello(framed, token).await?;
+    let protocol_info = expect_protocol_info(framed).await?;
+    let version = crate::protocol::negotiate_version(
+        (
+            crate::protocol::PROTOCOL_VERSION_MIN,
+            crate::protocol::PROTOCOL_VERSION_MAX,
+        ),
+        protocol_info.version_range(),
+    )?;
+    send_ok_message(framed).await?;
+    Ok(protocol_info.with_version(version))
+}
+
+pub async fn send_ok_message(framed: &mut FramedTransport) -> Result<()> {
+    send_message(framed, &Message::Ok).await
+}
+
+// Fails with the reason the server gave when it sent an ERROR instead of the
+// expected message.
+pub(crate) fn check_rejection(message: &Message) -> Result<()> {
+    if let Message::Error { code, reason } = message {
+        let code = crate::protocol::ProtocolErrorCode::from_code(*code)
+            .unwrap_or(crate::protocol::ProtocolErrorCode::InternalError);
+        return Err(crate::protocol::ProtocolError::rejected(code, reason.clone()).into());
     }
+    Ok(())
 }
-pub async fn expect_bye_message(tcp_stream: &mut TcpStream) -> Result<()> {
-    let mut recv_buf = [0u8; 4096];
-    match tcp_stream.read(&mut recv_buf).await {
-        Ok(0) => Err(anyhow::anyhow!(
-            "Connection closed by the server during BYE message"
+
+async fn expect_protocol_info(
+    framed: &mut FramedTransport,
+) -> Result<crate::protocol::ProtocolInfo> {
+    let message = read_message(framed, "protocol info").await?;
+    check_rejection(&message)?;
+    match message {
+        Message::ProtocolInfo(protocol_info) => Ok(protocol_info),
+        _ => Err(anyhow::anyhow!(
+            "Failed to extract protocol info from server response"
         )),
-        Ok(n) => {
-            let recv_buf = &recv_buf[..n];
-            if crate::protocol::check_bye_message(recv_buf) {
-                Ok(())
-            } else {
-                Err(anyhow::anyhow!("Did not receive BYE message from server"))
-            }
-        }
-        Err(e) => Err(anyhow::anyhow!("Error reading from socket: {}", e)),
     }
 }
 
-pub async fn send_bye_message(tcp_stream: &mut TcpStream) -> Result<()> {
-    let bye_msg = crate::protocol::make_bye_message();
-    tcp_stream
-        .write_all(&bye_msg)
-        .await
-        .map_err(|e| anyhow::anyhow!("Error sending BYE message: {}", e))
+pub async fn expect_bye_message(framed: &mut FramedTransport) -> Result<()> {
+    match read_message(framed, "BYE message").await? {
+        Message::Bye => Ok(()),
+        _ => Err(anyhow::anyhow!("Did not receive BYE message from server")),
+    }
 }
 
-pub async fn send_start_playing(tcp_stream: &mut TcpStream) -> Result<()> {
-    let buf = crate::protocol::make_start_playing_message();
-    tcp_stream
-        .write_all(&buf)
-        .await
-        .map_err(|e: std::io::Error| anyhow::anyhow!(e))
+pub async fn send_bye_message(framed: &mut FramedTransport) -> Result<()> {
+    send_message(framed, &Message::Bye).await
 }
 
-async fn expect_hello(socket: &mut TcpStream) -> Result<()> {
-    let mut recv_buf = [0u8; 4096];
-    match socket.read(&mut recv_buf).await {
-        Ok(0) => Err(anyhow::anyhow!(
-            "Connection closed by the client during hello"
-        )),
-        Ok(_) => Ok(()),
-        Err(e) => Err(anyhow::anyhow!("Error reading from socket: {}", e)),
+pub async fn send_start_playing(framed: &mut FramedTransport) -> Result<()> {
+    send_message(framed, &Message::StartPlaying).await
+}
+
+fn check_client_string(value: &str, step: &str) -> Result<()> {
+    if value.len() > crate::protocol::MAX_CLIENT_STRING_LEN {
+        return Err(anyhow::anyhow!(
+            "String of {} bytes during {} is above the {} bytes limit",
+            value.len(),
+            step,
+            crate::protocol::MAX_CLIENT_STRING_LEN
+        ));
+    }
+    Ok(())
+}
+
+// Returns the token and the range of versions advertised by the client. The
+// protocol magic is checked in the first frame the client sends.
+pub(crate) async fn expect_hello(
+    framed: &mut FramedTransport,
+) -> Result<(Option<String>, (u8, u8))> {
+    let Ok(Message::Hello {
+        version_min,
+        version_max,
+        token,
+        ..
+    }) = read_message(framed, "hello").await
+    else {
+        return Err(anyhow::anyhow!("Did not receive a valid HELLO from client"));
+    };
+    if let Some(token) = &token {
+        check_client_string(token, "hello")?;
     }
+    Ok((token, (version_min, version_max)))
+}
+
+// Reads the next client message. File names above MAX_CLIENT_STRING_LEN are
+// refused.
+pub async fn expect_message<R>(framed: &mut R) -> Result<Message>
+where
+    R: Stream<Item = std::io::Result<BytesMut>> + Unpin + ?Sized,
+{
+    let message = read_message(framed, "message type").await?;
+    if let Message::RequestFile(file) = &message {
+        check_client_string(file, "file request")?;
+    }
+    Ok(message)
+}
+
+pub async fn send_request_file(framed: &mut FramedTransport, file: &str) -> Result<()> {
+    send_message(framed, &Message::RequestFile(file.to_string())).await
 }
 
-pub async fn expect_ok_message(socket: &mut TcpStream) -> Result<()> {
-    dbg!("Expecting OK message from server...");
-    let mut recv_buf = [0u8; 4096];
-    match socket.read(&mut recv_buf).await {
-        Ok(0) => Err(anyhow::anyhow!(
-            "Connection closed by the server during OK message"
+pub async fn expect_ok_message(framed: &mut FramedTransport) -> Result<()> {
+    match read_message(framed, "OK message").await? {
+        Message::Ok => Ok(()),
+        message => Err(anyhow::anyhow!(
+            "Did not receive OK message from client, got {:?}",
+            message.message_type()
         )),
-        Ok(n) => {
-            let recv_buf = &recv_buf[..n];
-            if crate::protocol::check_ok_message(recv_buf) {
-                Ok(())
-            } else {
-                Err(anyhow::anyhow!("Did not receive OK message from server"))
+    }
+}
+
+// OK from the client once the header of a stream is sent. PONGs answering
+// PINGs sent before the stream may come first.
+pub(crate) async fn expect_stream_ok(
+    framed: &mut FramedTransport,
+    pongs: &crate::network::keepalive::PongClock,
+) -> Result<()> {
+    loop {
+        match read_message(framed, "OK message").await? {
+            Message::Ok => return Ok(()),
+            Message::Pong => pongs.record_pong(),
+            message => {
+                return Err(anyhow::anyhow!(
+                    "Did not receive OK message from client, got {:?}",
+                    message.message_type()
+                ));
             }
         }
-        Err(e) => Err(anyhow::anyhow!("Error reading from socket: {}", e)),
     }
 }
 
-pub async fn expect_message_type(socket: &mut TcpStream) -> Result<crate::protocol::MessageType> {
-    let mut recv_buf = [0u8; 4096];
-    match socket.read(&mut recv_buf).await {
-        Ok(0) => Err(anyhow::anyhow!(
-            "Connection closed by the client during message type"
-        )),
-        Ok(n) => crate::protocol::extract_message_type(&recv_buf[..n])
-            .ok_or_else(|| anyhow::anyhow!("Failed to extract message type from received data")),
-        Err(e) => Err(anyhow::anyhow!("Error reading from socket: {}", e)),
-    }
+pub async fn send_error_message(
+    framed: &mut FramedTransport,
+    code: crate::protocol::ProtocolErrorCode,
+    reason: &str,
+) -> Result<()> {
+    send_message(framed, &Message::error(code, reason)).await
+}
+
+async fn send_protocol_info(
+    framed: &mut FramedTransport,
+    protocol_info: &ProtocolInfo,
+) -> Result<()> {
+    send_message(framed, &Message::ProtocolInfo(*protocol_info)).await
+}
+
+// Compares every byte whatever the first difference, so that response
+// times do not tell how much of a guessed token was right. Only the length
+// shows.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
 }
 
-pub async fn handshake_from_server(socket: &mut TcpStream) -> Result<()> {
+// Returns the token the client identified itself with, if any, and the
+// negotiated protocol version. When the server requires `auth_token`,
+// clients sending another token or none are rejected before getting the
+// protocol info.
+pub async fn handshake_from_server(
+    framed: &mut FramedTransport,
+    protocol_info: &ProtocolInfo,
+    auth_token: Option<&str>,
+) -> Result<(Option<String>, u8)> {
     // First check hello
-    expect_hello(socket).await?;
+    let (token, client_versions) = expect_hello(framed).await?;
+    if let Some(auth_token) = auth_token
+        && !token
+            .as_deref()
+            .is_some_and(|token| tokens_match(token, auth_token))
+    {
+        return Err(crate::protocol::ProtocolError::rejected(
+            crate::protocol::ProtocolErrorCode::AuthFailed,
+            "Invalid authentication token",
+        )
+        .into());
+    }
 
-    send_hello(socket).await?;
+    let version =
+        crate::protocol::negotiate_version(protocol_info.version_range(), client_versions);
+    // Sent even without a common version so the client knows why it failed
+    let protocol_info = protocol_info.with_version(*version.as_ref().unwrap_or(&0));
+    send_protocol_info(framed, &protocol_info).await?;
+    let version = version?;
 
-    expect_ok_message(socket).await?;
+    // A client may only start playing once it has seen the protocol info
+    // and confirmed it, so anything but OK here is a protocol violation,
+    // reported to the client before the connection is closed.
+    let message = read_message(framed, "OK message")
+        .await
+        .map_err(|e| anyhow::anyhow!("Handshake not completed: {}", e))?;
+    if message != Message::Ok {
+        return Err(crate::protocol::ProtocolError::rejected(
+            crate::protocol::ProtocolErrorCode::UnexpectedMessage,
+            format!(
+                "Handshake not completed: expected OK, got {:?}",
+                message.message_type()
+            ),
+        )
+        .into());
+    }
 
-    Ok(())
+    Ok((token, version))
 }
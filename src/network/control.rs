@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, watch};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamState {
+    Playing,
+    Paused,
+}
+
+// Requests a client makes while a stream is running, passed from the task
+// reading client messages to the one reading the audio source.
+#[derive(Debug, Clone)]
+pub(crate) struct StreamControl {
+    seek: Arc<Mutex<Option<u64>>>,
+    state: Arc<watch::Sender<StreamState>>,
+    // f32 bits
+    gain: Arc<AtomicU32>,
+    // Sequence numbers of the frames NACKed by the client, oldest first
+    resends: Arc<Mutex<VecDeque<u64>>>,
+    resend_requested: Arc<Notify>,
+    // Set when the server shuts down, which ends the stream
+    shutdown: Option<watch::Receiver<bool>>,
+}
+
+impl Default for StreamControl {
+    fn default() -> Self {
+        Self {
+            seek: Default::default(),
+            state: Arc::new(watch::Sender::new(StreamState::Playing)),
+            gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            resends: Default::default(),
+            resend_requested: Default::default(),
+            shutdown: None,
+        }
+    }
+}
+
+impl StreamControl {
+    pub(crate) fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    // Returns once the server shuts down; never without a shutdown signal.
+    pub(crate) async fn shutdown(&self) {
+        match self.shutdown.clone() {
+            Some(mut shutdown) => shutdown_requested(&mut shutdown).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    // Only the last of several seeks not yet applied is kept.
+    pub(crate) fn request_seek(&self, sample_offset: u64) {
+        *self.seek.lock().unwrap() = Some(sample_offset);
+    }
+
+    pub(crate) fn take_seek(&self) -> Option<u64> {
+        self.seek.lock().unwrap().take()
+    }
+
+    pub(crate) fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    // Shared with the GainFilter applying it to the stream
+    pub(crate) fn gain_handle(&self) -> Arc<AtomicU32> {
+        Arc::clone(&self.gain)
+    }
+
+    pub(crate) fn request_resend(&self, sequence: u64) {
+        self.resends.lock().unwrap().push_back(sequence);
+        self.resend_requested.notify_one();
+    }
+
+    // Waits for the next frame to send again.
+    pub(crate) async fn next_resend(&self) -> u64 {
+        loop {
+            if let Some(sequence) = self.resends.lock().unwrap().pop_front() {
+                return sequence;
+            }
+            self.resend_requested.notified().await;
+        }
+    }
+
+    pub(crate) fn set_state(&self, state: StreamState) {
+        self.state.send_replace(state);
+    }
+
+    // Returns right away unless the stream is paused, in which case it waits
+    // for the client to resume it.
+    pub(crate) async fn playing(&self) {
+        let mut state = self.state.subscribe();
+        // The sender lives in `self`, so waiting cannot fail
+        let _ = state.wait_for(|state| *state == StreamState::Playing).await;
+    }
+}
+
+// Returns once `shutdown` is set. A signal whose sender is gone can no longer
+// be set, so this never returns then.
+pub(crate) async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|shutdown| *shutdown).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::Result;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+pub const KEY_LEN: usize = 32;
+
+pub const SALT_LEN: usize = 16;
+
+pub type EncryptionKey = [u8; KEY_LEN];
+
+pub type StreamSalt = [u8; SALT_LEN];
+
+// Encrypts or decrypts audio frames with a pre-shared key. Both ends count
+// frames in the same order, so the nonce is derived from the frame sequence
+// number instead of being sent on the wire. Sequence numbers start over with
+// every stream and the key is shared by every client, so the nonce also
+// starts with a salt the server draws at random for each stream.
+pub struct FrameCipher {
+    cipher: XChaCha20Poly1305,
+    salt: StreamSalt,
+    sequence: u64,
+}
+
+impl FrameCipher {
+    // Cipher of a new stream, under a salt of its own.
+    pub fn new(key: &EncryptionKey) -> Self {
+        Self::with_salt(key, rand::random())
+    }
+
+    // Cipher of a stream whose salt was announced by the server.
+    pub fn with_salt(key: &EncryptionKey, salt: StreamSalt) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+            salt,
+            sequence: 0,
+        }
+    }
+
+    pub fn salt(&self) -> StreamSalt {
+        self.salt
+    }
+
+    fn next_nonce(&mut self) -> XNonce {
+        let mut nonce = [0u8; 24];
+        nonce[..SALT_LEN].copy_from_slice(&self.salt);
+        nonce[SALT_LEN..].copy_from_slice(&self.sequence.to_le_bytes());
+        self.sequence += 1;
+        XNonce::clone_from_slice(&nonce)
+    }
+
+    // Continues from frame `sequence`, e.g. after frames the receiver gave up
+    // on, which used up their nonce all the same.
+    pub fn set_sequence(&mut self, sequence: u64) {
+        self.sequence = sequence;
+    }
+
+    pub fn encrypt(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .encrypt(&nonce, data)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt audio frame"))
+    }
+
+    pub fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .decrypt(&nonce, data)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt audio frame (wrong key?)"))
+    }
+}
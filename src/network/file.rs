@@ -1,81 +1,711 @@
 use crate::{
     audio::{
+        convert,
         file::{AudioReader, FileFormat},
+        filter::GainFilter,
+        flac::FlacFileRead,
+        mp3::Mp3FileRead,
+        normalize::AudioNormalizer,
+        ogg::OggVorbisFileRead,
+        opus::{OpusEncoder, OpusFileRead, opus_supports},
         wav::WavFileRead,
     },
-    network::common::expect_ok_message,
-    protocol,
+    network::{
+        buffer::BufferAccount,
+        common::{FramedTransport, expect_message, expect_stream_ok, send_message},
+        control::{StreamControl, StreamState},
+        crypto::FrameCipher,
+        keepalive::{self, Keepalive, KeepaliveTask, PingRequests, PongClock},
+        latency,
+        rate::{self, RateLimiter},
+        resend::ResendBuffer,
+    },
+    protocol::{self, AudioCodec, Message, ProtocolError, ProtocolErrorCode},
 };
 use anyhow::Result;
-use bytes::Bytes;
-use futures::SinkExt;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
-
-async fn send_stop_playing_message(
-    framed: &mut Framed<&mut TcpStream, LengthDelimitedCodec>,
-) -> Result<()> {
-    let stop_msg = protocol::make_stop_playing_message();
-    framed.send(Bytes::from(stop_msg)).await?;
+use bytes::{Bytes, BytesMut};
+use futures::{Sink, Stream, StreamExt};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+// What the server does once the requested file has been sent whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofAction {
+    // Ends the stream
+    #[default]
+    Stop,
+    // Restarts the file from its beginning, until the client stops playing
+    Loop,
+    // Sends the file this many times in total
+    LoopN(u32),
+}
+
+// Per-stream settings chosen by the server for one client.
+pub struct SendOptions {
+    pub cipher: Option<FrameCipher>,
+    pub buffer: BufferAccount,
+    pub(crate) sent: SentCounters,
+    // Stamp every audio frame with its send time
+    pub timestamps: bool,
+    // Negotiated protocol version, which decides how audio frames are sent
+    pub version: u8,
+    // Sequence number of the next AUDIO_FRAME, kept across the sources of
+    // a stream
+    pub(crate) next_sequence: u64,
+    // Last AUDIO_FRAMEs sent, in case the client NACKs one
+    pub(crate) resend: ResendBuffer,
+    // WAV files streamed before and after the main content
+    pub pre_roll: Option<String>,
+    pub post_roll: Option<String>,
+    pub keepalive: Option<Keepalive>,
+    // Codec of the audio frames, for sources it supports
+    pub codec: AudioCodec,
+    // Bytes read from the source at a time
+    pub chunk_size: usize,
+    // Cap on the audio sent to the client, in kilobits per second
+    pub rate_limit_kbps: Option<u64>,
+    // Applied to the requested file, never to the pre-roll and post-roll
+    pub eof_action: EofAction,
+    // Bring the requested file to a standard peak level, see
+    // `AudioNormalizer`
+    pub normalize: bool,
+    pub(crate) pongs: PongClock,
+    // PINGs to send, when the connection's keepalive runs outside of the
+    // stream
+    pub(crate) pings: Option<PingRequests>,
+    pub(crate) control: StreamControl,
+}
+
+// Audio frames sent to a client and their payload bytes, before
+// encryption. Clones share the same counts.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SentCounters {
+    bytes: Arc<AtomicU64>,
+    frames: Arc<AtomicU64>,
+}
+
+impl SentCounters {
+    pub(crate) fn record(&self, payload_len: usize) {
+        self.bytes.fetch_add(payload_len as u64, Ordering::Relaxed);
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        Self {
+            cipher: None,
+            buffer: Default::default(),
+            sent: Default::default(),
+            timestamps: false,
+            version: protocol::PROTOCOL_VERSION_MAX,
+            next_sequence: 0,
+            resend: Default::default(),
+            pre_roll: None,
+            post_roll: None,
+            keepalive: None,
+            codec: AudioCodec::Raw,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            rate_limit_kbps: None,
+            eof_action: EofAction::Stop,
+            normalize: false,
+            pongs: Default::default(),
+            pings: None,
+            control: Default::default(),
+        }
+    }
+}
+
+impl SendOptions {
+    // Streams not started by a server connection, which runs its own, get a
+    // keepalive task for as long as the one returned is kept.
+    pub(crate) fn start_keepalive(&mut self) -> Option<KeepaliveTask> {
+        if self.pings.is_some() {
+            return None;
+        }
+        let task = KeepaliveTask::spawn(self.keepalive?);
+        self.pongs = task.pongs();
+        self.pings = Some(task.pings());
+        Some(task)
+    }
+}
+
+// Message carrying one audio frame of a stream in `header`, as the client's
+// protocol version expects it. Frames are stamped when they leave so the
+// timestamp is as close as possible to the actual send time. AUDIO_FRAMEs
+// are kept in `resend` as sent.
+pub(crate) fn audio_frame_message(
+    header: &protocol::AudioHeader,
+    mut data: Vec<u8>,
+    version: u8,
+    timestamps: bool,
+    next_sequence: &mut u64,
+    resend: &mut ResendBuffer,
+) -> Message {
+    if version >= protocol::AUDIO_FRAME_VERSION {
+        let sequence = *next_sequence;
+        *next_sequence += 1;
+        protocol::append_checksum(&mut data);
+        let frame = protocol::AudioFrame {
+            sequence,
+            timestamp_us: latency::now_micros(),
+            header: *header,
+            data,
+        };
+        resend.record(&frame);
+        Message::AudioFrame(frame)
+    } else if timestamps {
+        Message::TimedAudioData {
+            sent_at_us: latency::now_micros(),
+            data,
+        }
+    } else {
+        Message::AudioData(data)
+    }
+}
+
+// Sends frame `sequence` again after the client NACKed it, if it is still
+// in `resend`.
+pub(crate) async fn resend_frame<S>(
+    framed: &mut S,
+    resend: &ResendBuffer,
+    sequence: u64,
+) -> Result<()>
+where
+    S: Sink<Bytes, Error = std::io::Error> + Unpin,
+{
+    match resend.get(sequence) {
+        Some(frame) => send_message(framed, &Message::AudioFrame(frame.clone())).await,
+        None => {
+            tracing::warn!(
+                "Cannot resend audio frame {}, it is no longer buffered",
+                sequence
+            );
+            Ok(())
+        }
+    }
+}
+
+pub(crate) async fn send_stop_playing_message<S>(framed: &mut S) -> Result<()>
+where
+    S: Sink<Bytes, Error = std::io::Error> + Unpin,
+{
+    send_message(framed, &Message::StopPlaying).await
+}
+
+pub(crate) async fn send_cover_art<S>(cover_art: &protocol::CoverArt, framed: &mut S) -> Result<()>
+where
+    S: Sink<Bytes, Error = std::io::Error> + Unpin,
+{
+    if cover_art.data.len() > protocol::MAX_COVER_ART_SIZE {
+        tracing::warn!(
+            "Cover art of {} bytes is above the {} bytes limit, not sending it",
+            cover_art.data.len(),
+            protocol::MAX_COVER_ART_SIZE
+        );
+        return Ok(());
+    }
+    for message in Message::cover_art(cover_art) {
+        send_message(framed, &message).await?;
+    }
     Ok(())
 }
 
-async fn send_header(audio_reader: &mut WavFileRead, socket: &mut TcpStream) -> Result<()> {
+pub(crate) fn reader_header<R: AudioReader + ?Sized>(
+    audio_reader: &mut R,
+) -> protocol::AudioHeader {
     let mut header = protocol::AudioHeader::new();
     audio_reader.update_header(&mut header);
+    header
+}
+
+// Header announced to the client: the format of the source, coded with
+// `codec` when it supports that format.
+pub(crate) fn stream_header<R: AudioReader + ?Sized>(
+    audio_reader: &mut R,
+    codec: AudioCodec,
+) -> protocol::AudioHeader {
+    let mut header = reader_header(audio_reader);
+    if codec == AudioCodec::Opus && opus_supports(&header) {
+        header.set_codec(AudioCodec::Opus);
+    }
+    header
+}
+
+// Announces `header` with the length of the whole stream, in frames, or 0
+// when it is not known in advance. Encrypted streams first announce the
+// salt of their cipher.
+pub(crate) async fn send_header(
+    header: &protocol::AudioHeader,
+    total_samples: u64,
+    cipher: Option<&FrameCipher>,
+    socket: &mut FramedTransport,
+) -> Result<()> {
+    if let Some(cipher) = cipher {
+        send_message(socket, &Message::StreamSalt(cipher.salt())).await?;
+    }
+    let mut header = *header;
+    header.set_total_samples(total_samples);
+    send_message(socket, &Message::AudioHeader(header)).await
+}
+
+// Streams one source inside an ongoing stream. It is preceded by an in-band
+// AUDIO_HEADER when its format differs from the one the client has.
+pub(crate) async fn send_source<R, S>(
+    audio_reader: &mut R,
+    client_header: &mut protocol::AudioHeader,
+    framed: &mut S,
+    options: &mut SendOptions,
+) -> Result<()>
+where
+    R: AudioReader + ?Sized,
+    S: Sink<Bytes, Error = std::io::Error> + Unpin,
+{
+    let header = stream_header(audio_reader, options.codec);
+    if header != *client_header {
+        send_message(framed, &Message::AudioHeader(header)).await?;
+        *client_header = header;
+    }
+
+    if let Some(cover_art) = audio_reader.cover_art() {
+        send_cover_art(&cover_art, framed).await?;
+    }
+
+    read_and_send(audio_reader, header.get_codec(), framed, options).await
+}
+
+// Frames are read ahead into a queue drained by the socket writer. The queue
+// is accounted in `options.buffer`, whose policy decides what happens when the
+// client does not keep up. Each seek starts a new epoch, and frames queued
+// during an earlier one are dropped instead of sent. Both sides wait while
+// the client has paused the stream. The client's volume is applied to
+// samples as they are read. With the Opus codec, every queued frame is one
+// Opus packet.
+pub(crate) async fn read_and_send<R, S>(
+    audio_reader: &mut R,
+    codec: AudioCodec,
+    framed: &mut S,
+    options: &mut SendOptions,
+) -> Result<()>
+where
+    R: AudioReader + ?Sized,
+    S: Sink<Bytes, Error = std::io::Error> + Unpin,
+{
+    let (queue_tx, mut queue_rx) = mpsc::unbounded_channel::<(u64, Vec<u8>)>();
+    let account = options.buffer.clone();
+    let control = options.control.clone();
+    let epoch = AtomicU64::new(0);
+    let sent = options.sent.clone();
+    let cipher = &mut options.cipher;
+    let timestamps = options.timestamps;
+    let version = options.version;
+    let next_sequence = &mut options.next_sequence;
+    let resend = &mut options.resend;
+    let chunk_size = options.chunk_size.max(1);
+    let mut rate_limiter = options.rate_limit_kbps.map(RateLimiter::new);
+    let mut pings = options.pings.clone();
+    let pongs = options.pongs.clone();
+
+    // Follows the VOLUME_CONTROL messages of the client
+    let mut audio_reader = GainFilter::with_shared_gain(audio_reader, control.gain_handle());
+    let header = reader_header(&mut audio_reader);
+    let mut frame_header = header;
+    frame_header.set_codec(codec);
+    let mut encoder = match codec {
+        AudioCodec::Raw => None,
+        AudioCodec::Opus => Some(OpusEncoder::new(
+            header.get_sample_rate(),
+            header.get_channels(),
+        )?),
+    };
+
+    let producer = async {
+        let mut buffer = vec![0u8; chunk_size];
+
+        // Readers fill the buffer with whole samples only, so a short read
+        // does not mean the source is over.
+        loop {
+            control.playing().await;
+            if let Some(offset) = control.take_seek() {
+                match audio_reader.seek_to_sample(offset) {
+                    Ok(()) => {
+                        epoch.fetch_add(1, Ordering::SeqCst);
+                    }
+                    Err(e) => tracing::warn!("Cannot seek to sample {}: {}", offset, e),
+                }
+            }
+
+            let n = audio_reader.read(&mut buffer[..])?;
+            if n == 0 {
+                break;
+            }
 
-    let header_bytes = protocol::audio_header_to_bytes(&header);
+            let payloads = match encoder.as_mut() {
+                Some(encoder) => encoder.encode(&convert::bytes_to_f32(&buffer[..n], &header)?)?,
+                None => vec![buffer[..n].to_vec()],
+            };
+            for payload in payloads {
+                if account.reserve(payload.len()).await? {
+                    queue_tx.send((epoch.load(Ordering::SeqCst), payload))?;
+                }
+            }
 
-    socket.write_all(&header_bytes).await?;
+            tokio::task::yield_now().await;
+        }
+        // The end of the source, padded to a whole Opus frame
+        if let Some(payload) = encoder.as_mut().map(|e| e.flush()).transpose()?.flatten()
+            && account.reserve(payload.len()).await?
+        {
+            queue_tx.send((epoch.load(Ordering::SeqCst), payload))?;
+        }
+        drop(queue_tx);
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let consumer = async {
+        loop {
+            let payload = tokio::select! {
+                payload = async {
+                    control.playing().await;
+                    queue_rx.recv().await
+                } => payload,
+                ping = keepalive::next_ping(&mut pings) => {
+                    ping?;
+                    send_message(framed, &Message::Ping).await?;
+                    continue;
+                }
+                sequence = control.next_resend() => {
+                    resend_frame(framed, resend, sequence).await?;
+                    continue;
+                }
+            };
+            let Some((payload_epoch, payload)) = payload else {
+                break;
+            };
+            if payload_epoch != epoch.load(Ordering::SeqCst) {
+                account.release(payload.len());
+                continue;
+            }
+            // Encrypting here rather than when reading keeps frames dropped
+            // by a seek from using up nonces.
+            let len = payload.len();
+            rate::pace(&mut rate_limiter, len).await;
+            let payload = match cipher.as_mut() {
+                Some(cipher) => cipher.encrypt(&payload)?,
+                None => payload,
+            };
+            let frame = audio_frame_message(
+                &frame_header,
+                payload,
+                version,
+                timestamps,
+                next_sequence,
+                resend,
+            );
+            send_message(framed, &frame).await?;
+            pongs.record_progress();
+            sent.record(len);
+            account.release(len);
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::try_join!(producer, consumer)?;
     Ok(())
 }
 
-async fn read_and_send(
-    audio_reader: &mut WavFileRead,
-    framed: &mut Framed<&mut TcpStream, LengthDelimitedCodec>,
+// Streams any audio source once the client asked to start playing: header,
+// OK from the client, audio frames, then STOP_PLAY.
+pub async fn send_reader<R: AudioReader + Send>(
+    audio_reader: &mut R,
+    socket: &mut FramedTransport,
+    options: SendOptions,
+) -> Result<()> {
+    send_readers(&mut [audio_reader], socket, options).await
+}
+
+// Streams several sources back to back as a single stream. The client gets
+// the header of the first one up front, and an in-band header before any
+// later source in another format.
+pub async fn send_readers(
+    audio_readers: &mut [&mut (dyn AudioReader + Send)],
+    socket: &mut FramedTransport,
+    mut options: SendOptions,
 ) -> Result<()> {
-    let mut buffer = vec![0u8; 4096];
+    let _keepalive = options.start_keepalive();
+    let first = audio_readers
+        .first_mut()
+        .ok_or_else(|| anyhow::anyhow!("No audio source to stream"))?;
+    let mut client_header = stream_header(*first, options.codec);
+    // Unknown as soon as the length of one source is
+    let total_samples = audio_readers
+        .iter()
+        .map(|audio_reader| audio_reader.total_samples())
+        .try_fold(0u64, |total, samples| {
+            (samples > 0).then(|| total.saturating_add(samples))
+        })
+        .unwrap_or(0);
+    send_header(
+        &client_header,
+        total_samples,
+        options.cipher.as_ref(),
+        socket,
+    )
+    .await?;
+
+    expect_stream_ok(socket, &options.pongs).await?;
 
-    let mut last_buffer = false;
-    while !last_buffer {
-        let n = audio_reader.read(&mut buffer[..])?;
-        if n == 0 {
-            break;
+    let (mut framed, mut reader) = socket.split();
+    let pongs = options.pongs.clone();
+    let control = options.control.clone();
+
+    let sources = async {
+        for audio_reader in audio_readers.iter_mut() {
+            send_source(*audio_reader, &mut client_header, &mut framed, &mut options).await?;
         }
+        Ok::<(), anyhow::Error>(())
+    };
+    tokio::select! {
+        result = sources => result?,
+        result = listen_client(&mut reader, &pongs, &control) => result?,
+    }
 
-        let chunk = Bytes::copy_from_slice(&buffer[..n]);
-        framed.send(chunk).await?;
+    // Frames still queued when the stream was interrupted were dropped with it.
+    options.buffer.release(options.buffer.used());
 
-        last_buffer = n < buffer.len();
+    send_stop_playing_message(&mut framed).await
+}
+
+// Handles the messages a client may send while a stream is running. Returns
+// when the client asks to stop the stream, or when the server shuts down.
+pub(crate) async fn listen_client<R>(
+    reader: &mut R,
+    pongs: &PongClock,
+    control: &StreamControl,
+) -> Result<()>
+where
+    R: Stream<Item = std::io::Result<BytesMut>> + Unpin + ?Sized,
+{
+    loop {
+        let message = tokio::select! {
+            message = expect_message(reader) => message?,
+            _ = control.shutdown() => return Ok(()),
+        };
+        match message {
+            Message::Pong => pongs.record_pong(),
+            Message::Seek(sample_offset) => control.request_seek(sample_offset),
+            Message::Nack(sequence) => control.request_resend(sequence),
+            Message::VolumeControl(gain) => control.set_gain(gain),
+            Message::Pause => control.set_state(StreamState::Paused),
+            Message::Resume => control.set_state(StreamState::Playing),
+            Message::StopPlaying => return Ok(()),
+            message => {
+                return Err(anyhow::anyhow!(
+                    "Unexpected message type during stream: {:?}",
+                    message.message_type()
+                ));
+            }
+        }
     }
-    Ok(())
 }
 
-async fn send_wav_file(socket: &mut TcpStream, file_path: &str) -> Result<()> {
+// A file that cannot be opened is reported to the client as unknown, one
+// that cannot be parsed as unsupported.
+fn open_wav_file(file_path: &str) -> Result<WavFileRead> {
     let mut audio_reader = WavFileRead::new();
-    audio_reader.open_file(file_path)?;
+    audio_reader.open_file(file_path).map_err(|e| {
+        let code = match e.downcast_ref::<hound::Error>() {
+            Some(hound::Error::IoError(_)) => ProtocolErrorCode::UnknownFile,
+            _ => ProtocolErrorCode::UnsupportedFormat,
+        };
+        ProtocolError::rejected(code, format!("Cannot open {}: {}", file_path, e))
+    })?;
+    Ok(audio_reader)
+}
 
-    send_header(&mut audio_reader, socket).await?;
+fn open_flac_file(file_path: &str) -> Result<FlacFileRead> {
+    let mut audio_reader = FlacFileRead::new();
+    audio_reader.open_file(file_path).map_err(|e| {
+        let code = match e.downcast_ref::<claxon::Error>() {
+            Some(claxon::Error::IoError(_)) => ProtocolErrorCode::UnknownFile,
+            _ => ProtocolErrorCode::UnsupportedFormat,
+        };
+        ProtocolError::rejected(code, format!("Cannot open {}: {}", file_path, e))
+    })?;
+    Ok(audio_reader)
+}
 
-    expect_ok_message(socket).await?;
+fn open_mp3_file(file_path: &str) -> Result<Mp3FileRead> {
+    let mut audio_reader = Mp3FileRead::new();
+    audio_reader.open_file(file_path).map_err(|e| {
+        let code = match e.downcast_ref::<std::io::Error>() {
+            Some(_) => ProtocolErrorCode::UnknownFile,
+            None => ProtocolErrorCode::UnsupportedFormat,
+        };
+        ProtocolError::rejected(code, format!("Cannot open {}: {}", file_path, e))
+    })?;
+    Ok(audio_reader)
+}
 
-    let mut framed: Framed<&mut TcpStream, LengthDelimitedCodec> =
-        Framed::new(socket, LengthDelimitedCodec::new());
+fn open_ogg_file(file_path: &str) -> Result<OggVorbisFileRead> {
+    let mut audio_reader = OggVorbisFileRead::new();
+    audio_reader.open_file(file_path).map_err(|e| {
+        let code = match e.downcast_ref::<std::io::Error>() {
+            Some(_) => ProtocolErrorCode::UnknownFile,
+            None => ProtocolErrorCode::UnsupportedFormat,
+        };
+        ProtocolError::rejected(code, format!("Cannot open {}: {}", file_path, e))
+    })?;
+    Ok(audio_reader)
+}
 
-    read_and_send(&mut audio_reader, &mut framed).await?;
+fn open_opus_file(file_path: &str) -> Result<OpusFileRead> {
+    let mut audio_reader = OpusFileRead::new();
+    audio_reader.open_file(file_path).map_err(|e| {
+        let code = match e.downcast_ref::<std::io::Error>() {
+            Some(_) => ProtocolErrorCode::UnknownFile,
+            None => ProtocolErrorCode::UnsupportedFormat,
+        };
+        ProtocolError::rejected(code, format!("Cannot open {}: {}", file_path, e))
+    })?;
+    Ok(audio_reader)
+}
 
-    send_stop_playing_message(&mut framed).await?;
+// Seeks `reader` back to its first frame when it ends, as many times as
+// `eof_action` allows. Sources that cannot seek end the stream with an error.
+struct LoopingReader<'a> {
+    reader: &'a mut (dyn AudioReader + Send),
+    eof_action: EofAction,
+    loops_completed: u32,
+}
 
-    Ok(())
+impl LoopingReader<'_> {
+    fn restarts(&self) -> bool {
+        match self.eof_action {
+            EofAction::Stop => false,
+            EofAction::Loop => true,
+            EofAction::LoopN(n) => self.loops_completed + 1 < n,
+        }
+    }
+}
+
+impl AudioReader for LoopingReader<'_> {
+    // A source still empty right after a restart ends the stream, rather
+    // than being looped forever.
+    fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        let n = self.reader.read(data)?;
+        if n > 0 || !self.restarts() {
+            return Ok(n);
+        }
+        self.reader.seek_to_sample(0)?;
+        self.loops_completed += 1;
+        self.reader.read(data)
+    }
+
+    fn open_file(&mut self, file_path: &str) -> Result<()> {
+        self.reader.open_file(file_path)
+    }
+
+    fn update_header(&mut self, header: &mut protocol::AudioHeader) {
+        self.reader.update_header(header)
+    }
+
+    fn cover_art(&self) -> Option<protocol::CoverArt> {
+        self.reader.cover_art()
+    }
+
+    fn seek_to_sample(&mut self, offset: u64) -> Result<()> {
+        self.reader.seek_to_sample(offset)
+    }
+
+    // Unknown when looping until the client stops
+    fn total_samples(&self) -> u64 {
+        match self.eof_action {
+            EofAction::Stop => self.reader.total_samples(),
+            EofAction::Loop => 0,
+            EofAction::LoopN(n) => self.reader.total_samples() * n.max(1) as u64,
+        }
+    }
+}
+
+// Streams `audio_reader` between the pre-roll and post-roll, which are WAV
+// files whatever the format of the main file.
+async fn send_with_rolls(
+    socket: &mut FramedTransport,
+    audio_reader: &mut (dyn AudioReader + Send),
+    options: SendOptions,
+) -> Result<()> {
+    let mut audio_reader = LoopingReader {
+        reader: audio_reader,
+        eof_action: options.eof_action,
+        loops_completed: 0,
+    };
+    let mut pre_roll = options.pre_roll.as_deref().map(open_wav_file).transpose()?;
+    let mut post_roll = options
+        .post_roll
+        .as_deref()
+        .map(open_wav_file)
+        .transpose()?;
+
+    let mut audio_readers: Vec<&mut (dyn AudioReader + Send)> = vec![];
+    if let Some(pre_roll) = pre_roll.as_mut() {
+        audio_readers.push(pre_roll);
+    }
+    audio_readers.push(&mut audio_reader);
+    if let Some(post_roll) = post_roll.as_mut() {
+        audio_readers.push(post_roll);
+    }
+
+    send_readers(&mut audio_readers, socket, options).await
+}
+
+pub(crate) fn open_audio_file(
+    file_format: FileFormat,
+    file: &str,
+) -> Result<Box<dyn AudioReader + Send>> {
+    Ok(match file_format {
+        FileFormat::Wav => Box::new(open_wav_file(file)?),
+        FileFormat::Flac => Box::new(open_flac_file(file)?),
+        FileFormat::Mp3 => Box::new(open_mp3_file(file)?),
+        FileFormat::Ogg => Box::new(open_ogg_file(file)?),
+        FileFormat::Opus => Box::new(open_opus_file(file)?),
+    })
+}
+
+// Length of `file`, from its header where the format has one.
+pub(crate) fn file_duration(file_format: FileFormat, file: &str) -> Result<Duration> {
+    match file_format {
+        FileFormat::Wav => crate::audio::wav::wav_duration(file),
+        FileFormat::Flac => crate::audio::flac::flac_duration(file),
+        FileFormat::Mp3 => crate::audio::mp3::mp3_duration(file),
+        FileFormat::Ogg => crate::audio::ogg::ogg_duration(file),
+        FileFormat::Opus => crate::audio::opus::opus_duration(file),
+    }
 }
 
 pub async fn send_file(
     file_format: FileFormat,
-    mut socket: &mut TcpStream,
+    socket: &mut FramedTransport,
     file: &str,
+    options: SendOptions,
 ) -> Result<()> {
-    match file_format {
-        FileFormat::Wav => send_wav_file(&mut socket, file).await,
+    let audio_reader = open_audio_file(file_format, file)?;
+    if options.normalize {
+        let mut audio_reader = AudioNormalizer::new(audio_reader)?;
+        return send_with_rolls(socket, &mut audio_reader, options).await;
     }
+    let mut audio_reader = audio_reader;
+    send_with_rolls(socket, audio_reader.as_mut(), options).await
 }
@@ -0,0 +1,168 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Interval;
+
+// PING frames sent to the client for as long as it is connected. The client
+// answers each one with PONG; the connection is closed when no PONG arrives
+// within `timeout` of a PING. Checked every `interval`.
+#[derive(Debug, Clone, Copy)]
+pub struct Keepalive {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+// Keepalive state of one connection, shared by whoever reads client messages
+// and the keepalive task.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PongClock {
+    last_pong: Arc<Mutex<Option<Instant>>>,
+}
+
+impl PongClock {
+    pub(crate) fn record_pong(&self) {
+        *self.last_pong.lock().unwrap() = Some(Instant::now());
+    }
+
+    // The socket taking audio after a PING shows that the client still
+    // reads the stream. Its PONG may only be late behind the audio it has
+    // not read yet.
+    pub(crate) fn record_progress(&self) {
+        self.record_pong();
+    }
+
+    fn answered(&self, ping: Instant) -> bool {
+        self.last_pong
+            .lock()
+            .unwrap()
+            .is_some_and(|pong| pong >= ping)
+    }
+}
+
+pub(crate) struct Pinger {
+    keepalive: Keepalive,
+    ticker: Interval,
+    pongs: PongClock,
+    pending_ping: Option<Instant>,
+}
+
+impl Pinger {
+    pub(crate) fn new(keepalive: Keepalive, pongs: PongClock) -> Self {
+        let start = tokio::time::Instant::now() + keepalive.interval;
+        Self {
+            keepalive,
+            ticker: tokio::time::interval_at(start, keepalive.interval),
+            pongs,
+            pending_ping: None,
+        }
+    }
+
+    // Waits for the next tick. Returns whether a PING should be sent, or an
+    // error when the previous one was not answered in time.
+    pub(crate) async fn tick(&mut self) -> anyhow::Result<bool> {
+        self.ticker.tick().await;
+        if let Some(ping) = self.pending_ping
+            && !self.pongs.answered(ping)
+        {
+            if ping.elapsed() >= self.keepalive.timeout {
+                return Err(anyhow::anyhow!(
+                    "No PONG from the client within {:?}",
+                    self.keepalive.timeout
+                ));
+            }
+            return Ok(false);
+        }
+        self.pending_ping = Some(Instant::now());
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PingRequest {
+    Ping,
+    TimedOut(Duration),
+}
+
+// PINGs the keepalive task asks for. Whoever holds the socket writer at the
+// time sends them: the request loop between streams, the stream otherwise.
+#[derive(Debug, Clone)]
+pub(crate) struct PingRequests(watch::Receiver<PingRequest>);
+
+impl PingRequests {
+    // Waits until a PING is to be sent. Fails once one was not answered in
+    // time.
+    async fn next(&mut self) -> anyhow::Result<()> {
+        let timed_out = matches!(*self.0.borrow(), PingRequest::TimedOut(_));
+        // The sender is gone once the connection is over.
+        if !timed_out && self.0.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+        match *self.0.borrow_and_update() {
+            PingRequest::Ping => Ok(()),
+            PingRequest::TimedOut(timeout) => Err(anyhow::anyhow!(
+                "No PONG from the client within {:?}",
+                timeout
+            )),
+        }
+    }
+}
+
+// Runs the pinger of one connection in the background until dropped, so
+// that idle clients are checked as well as streaming ones.
+pub(crate) struct KeepaliveTask {
+    task: JoinHandle<()>,
+    pings: PingRequests,
+    pongs: PongClock,
+}
+
+impl KeepaliveTask {
+    pub(crate) fn spawn(keepalive: Keepalive) -> Self {
+        let pongs = PongClock::default();
+        let (sender, receiver) = watch::channel(PingRequest::Ping);
+        let mut pinger = Pinger::new(keepalive, pongs.clone());
+        let task = tokio::spawn(async move {
+            loop {
+                match pinger.tick().await {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        sender.send_replace(PingRequest::Ping);
+                    }
+                    Err(_) => {
+                        sender.send_replace(PingRequest::TimedOut(keepalive.timeout));
+                        // Kept open so that the timeout stays visible.
+                        std::future::pending::<()>().await;
+                    }
+                }
+            }
+        });
+        Self {
+            task,
+            pings: PingRequests(receiver),
+            pongs,
+        }
+    }
+
+    pub(crate) fn pings(&self) -> PingRequests {
+        self.pings.clone()
+    }
+
+    pub(crate) fn pongs(&self) -> PongClock {
+        self.pongs.clone()
+    }
+}
+
+impl Drop for KeepaliveTask {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Next PING to send, if the connection has a keepalive; never resolves
+// without one.
+pub(crate) async fn next_ping(pings: &mut Option<PingRequests>) -> anyhow::Result<()> {
+    match pings {
+        Some(pings) => pings.next().await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyEstimate {
+    // Smoothed variation of the transit time between consecutive frames
+    pub jitter: Duration,
+    // Smoothed transit time above the fastest frame seen so far
+    pub delay_above_min: Duration,
+    pub frames: u64,
+}
+
+// Rolling latency estimate from server send timestamps. Server and client
+// clocks are not synchronized, so a single transit time (receive time minus
+// send time) includes an unknown clock offset. Only differences between
+// transit times are reported, which cancel that offset out.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    min_transit: Option<i64>,
+    last_transit: Option<i64>,
+    // Both smoothed with a 1/16 gain as for RTP jitter (RFC 3550)
+    jitter: f64,
+    delay_above_min: f64,
+    frames: u64,
+}
+
+impl LatencyTracker {
+    pub fn record(&mut self, sent_at_us: u64, received_at_us: u64) {
+        let transit = received_at_us as i64 - sent_at_us as i64;
+        if let Some(last) = self.last_transit {
+            let d = (transit - last).abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        let min_transit = self.min_transit.map_or(transit, |min| min.min(transit));
+        self.min_transit = Some(min_transit);
+        let above_min = (transit - min_transit) as f64;
+        self.delay_above_min += (above_min - self.delay_above_min) / 16.0;
+        self.last_transit = Some(transit);
+        self.frames += 1;
+    }
+
+    pub fn estimate(&self) -> Option<LatencyEstimate> {
+        if self.frames == 0 {
+            return None;
+        }
+        Some(LatencyEstimate {
+            jitter: Duration::from_micros(self.jitter as u64),
+            delay_above_min: Duration::from_micros(self.delay_above_min as u64),
+            frames: self.frames,
+        })
+    }
+}
@@ -0,0 +1,37 @@
+use anyhow::Result;
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+
+// In-memory connection end. Whatever is written to one end of a pair is read
+// from the other, without any socket.
+pub type LoopbackStream = DuplexStream;
+
+// Bytes buffered in each direction before writes wait for the reader.
+const LOOPBACK_BUFFER_SIZE: usize = 64 * 1024;
+
+pub fn create_loopback_pair() -> (LoopbackStream, LoopbackStream) {
+    tokio::io::duplex(LOOPBACK_BUFFER_SIZE)
+}
+
+// Opens connections to a server created with `Server::new_loopback`.
+#[derive(Clone)]
+pub struct LoopbackConnector {
+    tx: mpsc::UnboundedSender<LoopbackStream>,
+}
+
+impl LoopbackConnector {
+    // Returns the client end of a new connection, whose server end is
+    // accepted by the server.
+    pub fn connect(&self) -> Result<LoopbackStream> {
+        let (client, server) = create_loopback_pair();
+        self.tx
+            .send(server)
+            .map_err(|_| anyhow::anyhow!("Loopback server is gone"))?;
+        Ok(client)
+    }
+}
+
+pub(crate) fn channel() -> (LoopbackConnector, mpsc::UnboundedReceiver<LoopbackStream>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (LoopbackConnector { tx }, rx)
+}
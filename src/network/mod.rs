@@ -1,2 +1,19 @@
+pub mod broadcast;
+pub mod buffer;
 pub mod common;
+pub mod control;
+pub mod crypto;
 pub mod file;
+pub mod keepalive;
+pub mod latency;
+pub mod loopback;
+pub mod playlist;
+pub mod radio;
+pub mod rate;
+pub mod rate_limit;
+pub mod reorder;
+pub mod resend;
+pub mod tls;
+pub mod trace;
+pub mod transport;
+pub mod udp;
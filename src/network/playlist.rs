@@ -0,0 +1,164 @@
+use crate::{
+    audio::{file::AudioReader, file::FileFormat, silence::SilenceReader},
+    network::{
+        common::{FramedTransport, expect_stream_ok, send_message},
+        file::{
+            SendOptions, listen_client, open_audio_file, reader_header, send_header, send_source,
+            send_stop_playing_message, stream_header,
+        },
+    },
+    protocol::{AudioHeader, Message, ProtocolError, ProtocolErrorCode},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{Sink, StreamExt};
+use std::time::Duration;
+
+pub const DEFAULT_TRACK_GAP: Duration = Duration::from_secs(1);
+
+// Files played one after the other, once, to every client.
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    paths: Vec<String>,
+    position: usize,
+}
+
+impl Playlist {
+    pub fn new(paths: Vec<String>) -> Self {
+        Self { paths, position: 0 }
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    fn next_path(&mut self) -> Option<String> {
+        let path = self.paths.get(self.position)?.clone();
+        self.position += 1;
+        Some(path)
+    }
+}
+
+// Entries of an M3U playlist, `#` lines being comments or extended M3U
+// directives. Relative entries are resolved from the playlist's directory.
+pub fn read_m3u(path: &str) -> Result<Vec<String>> {
+    let dir = std::path::Path::new(path)
+        .parent()
+        .map(|dir| dir.to_path_buf())
+        .unwrap_or_default();
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|entry| dir.join(entry).to_string_lossy().into_owned())
+        .collect())
+}
+
+// The tracks of one client's stream.
+struct Tracks<V> {
+    playlist: Playlist,
+    file_format: FileFormat,
+    gap: Duration,
+    // Checked before each track is played
+    validate: V,
+}
+
+impl<V: Fn(&str) -> Result<()>> Tracks<V> {
+    fn open(&self, path: &str) -> Result<Box<dyn AudioReader + Send>> {
+        (self.validate)(path)?;
+        open_audio_file(self.file_format.clone(), path)
+    }
+
+    // Tracks that fail to open or to validate are skipped.
+    fn next(&mut self) -> Option<Box<dyn AudioReader + Send>> {
+        while let Some(path) = self.playlist.next_path() {
+            match self.open(&path) {
+                Ok(track) => return Some(track),
+                Err(e) => tracing::warn!("Skipping track {}: {}", path, e),
+            }
+        }
+        None
+    }
+}
+
+async fn stream_tracks<S, V>(
+    mut track: Box<dyn AudioReader + Send>,
+    tracks: &mut Tracks<V>,
+    client_header: &mut AudioHeader,
+    framed: &mut S,
+    options: &mut SendOptions,
+) -> Result<()>
+where
+    S: Sink<Bytes, Error = std::io::Error> + Unpin,
+    V: Fn(&str) -> Result<()>,
+{
+    loop {
+        send_source(track.as_mut(), client_header, framed, options).await?;
+        let Some(mut next) = tracks.next() else {
+            return Ok(());
+        };
+
+        let source = reader_header(track.as_mut());
+        send_source(
+            &mut SilenceReader::new(source, tracks.gap.as_millis() as u64),
+            client_header,
+            framed,
+            options,
+        )
+        .await?;
+
+        let header = stream_header(next.as_mut(), options.codec);
+        send_message(framed, &Message::TrackChange(header)).await?;
+        *client_header = header;
+        track = next;
+    }
+}
+
+// Streams the tracks of `playlist` in order as a single stream, separated by
+// `gap` of silence. Each track after the first is announced with
+// TRACK_CHANGE. `validate` is checked before each track is played.
+pub async fn send_playlist(
+    playlist: Playlist,
+    file_format: FileFormat,
+    gap: Duration,
+    socket: &mut FramedTransport,
+    mut options: SendOptions,
+    validate: impl Fn(&str) -> Result<()>,
+) -> Result<()> {
+    let _keepalive = options.start_keepalive();
+    let mut tracks = Tracks {
+        playlist,
+        file_format,
+        gap,
+        validate,
+    };
+    let mut track = tracks.next().ok_or_else(|| {
+        ProtocolError::rejected(
+            ProtocolErrorCode::UnknownFile,
+            "No playable track in the playlist".to_string(),
+        )
+    })?;
+
+    // Tracks are opened one at a time, so the length of the playlist is not
+    // known up front.
+    let mut client_header = stream_header(track.as_mut(), options.codec);
+    send_header(&client_header, 0, options.cipher.as_ref(), socket).await?;
+
+    expect_stream_ok(socket, &options.pongs).await?;
+
+    let (mut framed, mut reader) = socket.split();
+    let pongs = options.pongs.clone();
+    let control = options.control.clone();
+
+    tokio::select! {
+        result = stream_tracks(track, &mut tracks, &mut client_header, &mut framed, &mut options) => {
+            result?;
+        }
+        result = listen_client(&mut reader, &pongs, &control) => result?,
+    }
+
+    // Frames still queued when the stream was interrupted were dropped with it.
+    options.buffer.release(options.buffer.used());
+
+    send_stop_playing_message(&mut framed).await
+}
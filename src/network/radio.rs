@@ -0,0 +1,165 @@
+use crate::{
+    audio::{file::AudioReader, wav, wav::WavFileRead},
+    network::{
+        common::{FramedTransport, expect_stream_ok, send_message},
+        file::{
+            SendOptions, listen_client, send_header, send_source, send_stop_playing_message,
+            stream_header,
+        },
+    },
+    protocol::{Message, ProtocolError, ProtocolErrorCode, TrackInfo},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{Sink, StreamExt};
+use rand::seq::SliceRandom;
+
+// Endless playlist over the WAV files of a directory. The directory is listed
+// again and reshuffled every time the previous shuffle has been played.
+struct RadioPlaylist {
+    dir: String,
+    queue: Vec<String>,
+    listed: usize,
+}
+
+impl RadioPlaylist {
+    fn new(dir: &str) -> Result<Self> {
+        let mut playlist = Self {
+            dir: dir.to_string(),
+            queue: vec![],
+            listed: 0,
+        };
+        playlist.reshuffle()?;
+        Ok(playlist)
+    }
+
+    fn reshuffle(&mut self) -> Result<()> {
+        let mut tracks: Vec<String> = std::fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+            })
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        if tracks.is_empty() {
+            return Err(anyhow::anyhow!("No WAV file in directory {}", self.dir));
+        }
+        tracks.shuffle(&mut rand::rng());
+        self.listed = tracks.len();
+        self.queue = tracks;
+        Ok(())
+    }
+
+    fn next_path(&mut self) -> Result<String> {
+        if self.queue.is_empty() {
+            self.reshuffle()?;
+        }
+        self.queue
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("Empty playlist"))
+    }
+}
+
+struct Track {
+    reader: WavFileRead,
+    info: TrackInfo,
+}
+
+fn open_track(path: &str, validate: &impl Fn(&str) -> Result<()>) -> Result<Track> {
+    validate(path)?;
+    let mut reader = WavFileRead::new();
+    reader.open_file(path)?;
+    let title = std::path::Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let info = TrackInfo {
+        title,
+        duration: wav::wav_duration(path)?,
+    };
+    Ok(Track { reader, info })
+}
+
+// Tracks that fail to open or to validate are skipped. Gives up once a whole
+// listing of the directory has been skipped.
+fn next_track(
+    playlist: &mut RadioPlaylist,
+    validate: &impl Fn(&str) -> Result<()>,
+) -> Result<Track> {
+    let mut skipped = 0;
+    loop {
+        let path = playlist.next_path()?;
+        match open_track(&path, validate) {
+            Ok(track) => return Ok(track),
+            Err(e) => {
+                tracing::warn!("Skipping track {}: {}", path, e);
+                skipped += 1;
+                if skipped >= playlist.listed {
+                    return Err(anyhow::anyhow!("No playable track in {}", playlist.dir));
+                }
+            }
+        }
+    }
+}
+
+async fn stream_tracks<S>(
+    mut track: Track,
+    playlist: &mut RadioPlaylist,
+    validate: &impl Fn(&str) -> Result<()>,
+    framed: &mut S,
+    options: &mut SendOptions,
+) -> Result<()>
+where
+    S: Sink<Bytes, Error = std::io::Error> + Unpin,
+{
+    let mut client_header = stream_header(&mut track.reader, options.codec);
+    loop {
+        send_message(framed, &Message::TrackInfo(track.info.clone())).await?;
+
+        send_source(&mut track.reader, &mut client_header, framed, options).await?;
+
+        send_message(framed, &Message::EndOfTrack).await?;
+
+        track = next_track(playlist, validate)?;
+    }
+}
+
+// Streams the WAV files of `dir` in random order, forever. The stream only
+// ends when the client sends STOP_PLAY, or on error. `validate` is checked
+// before each track is played.
+pub async fn send_radio(
+    dir: &str,
+    socket: &mut FramedTransport,
+    mut options: SendOptions,
+    validate: impl Fn(&str) -> Result<()>,
+) -> Result<()> {
+    let _keepalive = options.start_keepalive();
+    let mut playlist = RadioPlaylist::new(dir)
+        .map_err(|e| ProtocolError::rejected(ProtocolErrorCode::UnknownFile, e.to_string()))?;
+    let mut track = next_track(&mut playlist, &validate)
+        .map_err(|e| ProtocolError::rejected(ProtocolErrorCode::UnknownFile, e.to_string()))?;
+
+    let header = stream_header(&mut track.reader, options.codec);
+    send_header(&header, 0, options.cipher.as_ref(), socket).await?;
+
+    expect_stream_ok(socket, &options.pongs).await?;
+
+    let (mut framed, mut reader) = socket.split();
+    let pongs = options.pongs.clone();
+    let control = options.control.clone();
+
+    tokio::select! {
+        result = stream_tracks(track, &mut playlist, &validate, &mut framed, &mut options) => {
+            result?;
+        }
+        result = listen_client(&mut reader, &pongs, &control) => result?,
+    }
+
+    // Frames still queued when the stream was interrupted were dropped with it.
+    options.buffer.release(options.buffer.used());
+
+    send_stop_playing_message(&mut framed).await
+}
@@ -0,0 +1,29 @@
+use crate::network::rate_limit::TokenBucket;
+
+// Paces the audio sent to one client to `kbps` kilobits per second. Time
+// spent waiting on the client, e.g. while it is paused, is not made up for
+// with a burst afterwards.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    bucket: TokenBucket,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(kbps: u64) -> Self {
+        Self {
+            bucket: TokenBucket::new(kbps.max(1) * 1000 / 8),
+        }
+    }
+
+    // Waits until `len` more bytes may be sent.
+    pub(crate) async fn wait(&mut self, len: usize) {
+        tokio::time::sleep(self.bucket.take(len)).await;
+    }
+}
+
+// Waits on an optional limiter; returns right away without one.
+pub(crate) async fn pace(limiter: &mut Option<RateLimiter>, len: usize) {
+    if let Some(limiter) = limiter {
+        limiter.wait(len).await;
+    }
+}
@@ -0,0 +1,33 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Budget of bytes refilled at `rate_bytes_per_sec`. It holds no burst: time
+// spent idle, e.g. while the client is paused, is not saved up for later.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate_bytes_per_sec: u64,
+    next_send: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate_bytes_per_sec: u64) -> Self {
+        Self {
+            rate_bytes_per_sec: rate_bytes_per_sec.max(1),
+            next_send: Instant::now(),
+        }
+    }
+
+    pub fn rate_bytes_per_sec(&self) -> u64 {
+        self.rate_bytes_per_sec
+    }
+
+    // Takes `n` bytes from the bucket. Returns how long to sleep before
+    // sending them to honour the rate, zero when they may go right away.
+    pub fn take(&mut self, n: usize) -> Duration {
+        let now = Instant::now();
+        let wait = self.next_send.saturating_duration_since(now);
+        self.next_send = self.next_send.max(now)
+            + Duration::from_secs_f64(n as f64 / self.rate_bytes_per_sec as f64);
+        wait
+    }
+}
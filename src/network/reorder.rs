@@ -0,0 +1,103 @@
+use crate::protocol::AudioFrame;
+use std::collections::BTreeMap;
+
+pub const DEFAULT_REORDER_WINDOW: usize = 4;
+
+// Releases the AUDIO_FRAMEs of a stream in sequence order. Frames arriving
+// ahead of a gap are held, up to `window` of them; once one more arrives,
+// the missing frames are given up on and counted as underruns. Frames
+// arriving after their turn are dropped.
+#[derive(Debug)]
+pub struct FrameReorderBuffer {
+    window: usize,
+    // Sequence number of the next frame to release
+    next: u64,
+    held: BTreeMap<u64, AudioFrame>,
+    underrun_count: u64,
+}
+
+impl FrameReorderBuffer {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            next: 0,
+            held: BTreeMap::new(),
+            underrun_count: 0,
+        }
+    }
+
+    // Frames that can be released now that `frame` arrived, in order.
+    pub fn push(&mut self, frame: AudioFrame) -> Vec<AudioFrame> {
+        if frame.sequence < self.next || self.held.contains_key(&frame.sequence) {
+            tracing::warn!(
+                "Dropping audio frame {} received too late or twice",
+                frame.sequence
+            );
+            return Vec::new();
+        }
+        self.held.insert(frame.sequence, frame);
+
+        let mut released = self.release();
+        while self.held.len() > self.window {
+            self.skip_gap();
+            released.extend(self.release());
+        }
+        released
+    }
+
+    // Everything still held, in order, e.g. at the end of the stream. Gaps
+    // left are counted as underruns.
+    pub fn flush(&mut self) -> Vec<AudioFrame> {
+        let mut released = self.release();
+        while !self.held.is_empty() {
+            self.skip_gap();
+            released.extend(self.release());
+        }
+        released
+    }
+
+    // Starts over for a new stream, numbered from 0 again. Underruns are
+    // kept.
+    pub fn reset(&mut self) {
+        self.next = 0;
+        self.held.clear();
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // Frames skipped because they did not arrive within the window.
+    pub fn underrun_count(&self) -> u64 {
+        self.underrun_count
+    }
+
+    fn release(&mut self) -> Vec<AudioFrame> {
+        let mut released = Vec::new();
+        while let Some(frame) = self.held.remove(&self.next) {
+            released.push(frame);
+            self.next += 1;
+        }
+        released
+    }
+
+    // Moves on to the first frame held, past the missing ones.
+    fn skip_gap(&mut self) {
+        let Some(&first) = self.held.keys().next() else {
+            return;
+        };
+        tracing::warn!(
+            "Audio frames {} to {} missing, skipping them",
+            self.next,
+            first - 1
+        );
+        self.underrun_count += first - self.next;
+        self.next = first;
+    }
+}
+
+impl Default for FrameReorderBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_REORDER_WINDOW)
+    }
+}
@@ -0,0 +1,44 @@
+use crate::protocol::AudioFrame;
+use std::collections::VecDeque;
+
+// Frames kept per stream to answer NACKs.
+pub const RESEND_BUFFER_FRAMES: usize = 32;
+
+// Last AUDIO_FRAMEs sent to a client, as they were sent, oldest first.
+#[derive(Debug)]
+pub(crate) struct ResendBuffer {
+    capacity: usize,
+    frames: VecDeque<AudioFrame>,
+}
+
+impl ResendBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn record(&mut self, frame: &AudioFrame) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame.clone());
+    }
+
+    pub(crate) fn get(&self, sequence: u64) -> Option<&AudioFrame> {
+        // Sequence numbers are consecutive, so the frame's place is known
+        let first = self.frames.front()?.sequence;
+        let index = usize::try_from(sequence.checked_sub(first)?).ok()?;
+        self.frames.get(index)
+    }
+}
+
+impl Default for ResendBuffer {
+    fn default() -> Self {
+        Self::new(RESEND_BUFFER_FRAMES)
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use std::sync::Arc;
+use tokio_rustls::rustls::{self, crypto::ring};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::network::transport::Transport;
+
+// PEM files of the certificate chain the server presents and of its private
+// key.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+pub(crate) fn server_acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(&config.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow::anyhow!("Cannot read certificates {}: {}", config.cert_path, e))?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path)
+        .map_err(|e| anyhow::anyhow!("Cannot read private key {}: {}", config.key_path, e))?;
+    let server_config = rustls::ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+// Servers are verified against the certificates of `ca_path` when given, and
+// against the web PKI roots otherwise.
+pub(crate) fn client_connector(ca_path: Option<&str>) -> Result<TlsConnector> {
+    let mut roots = rustls::RootCertStore::empty();
+    match ca_path {
+        Some(ca_path) => {
+            for cert in CertificateDer::pem_file_iter(ca_path)
+                .map_err(|e| anyhow::anyhow!("Cannot read certificates {}: {}", ca_path, e))?
+            {
+                roots.add(cert?)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let client_config = rustls::ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+pub(crate) async fn accept(
+    acceptor: &TlsAcceptor,
+    socket: Box<dyn Transport>,
+) -> Result<Box<dyn Transport>> {
+    let stream = acceptor
+        .accept(socket)
+        .await
+        .map_err(|e| anyhow::anyhow!("TLS handshake failed: {}", e))?;
+    Ok(Box::new(stream))
+}
+
+pub(crate) async fn connect(
+    connector: &TlsConnector,
+    server_name: &str,
+    socket: Box<dyn Transport>,
+) -> Result<Box<dyn Transport>> {
+    let server_name = ServerName::try_from(server_name.to_string())?;
+    let stream = connector
+        .connect(server_name, socket)
+        .await
+        .map_err(|e| anyhow::anyhow!("TLS handshake failed: {}", e))?;
+    Ok(Box::new(stream))
+}
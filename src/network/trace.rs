@@ -0,0 +1,97 @@
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+// Protocol trace: one event per control message and per frame sent or
+// received, without the payloads. Disabled until a sink is installed.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub connection: String,
+    pub direction: Direction,
+    pub message: String,
+    pub size: usize,
+}
+
+impl std::fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let direction = match self.direction {
+            Direction::Sent => "sent",
+            Direction::Received => "recv",
+        };
+        write!(
+            f,
+            "protocol-trace conn={} dir={} msg={} bytes={}",
+            self.connection, direction, self.message, self.size
+        )
+    }
+}
+
+type Sink = Arc<dyn Fn(&TraceEvent) + Send + Sync>;
+
+static SINK: RwLock<Option<Sink>> = RwLock::new(None);
+
+tokio::task_local! {
+    static CONNECTION: String;
+}
+
+// Writes trace lines to stderr.
+pub fn enable() {
+    set_sink(|event| eprintln!("{}", event));
+}
+
+pub fn set_sink(sink: impl Fn(&TraceEvent) + Send + Sync + 'static) {
+    *SINK.write().unwrap() = Some(Arc::new(sink));
+}
+
+pub fn disable() {
+    *SINK.write().unwrap() = None;
+}
+
+// Events emitted while `future` runs are tagged with `connection`.
+pub async fn scope<F: Future>(connection: String, future: F) -> F::Output {
+    CONNECTION.scope(connection, future).await
+}
+
+pub fn event(direction: Direction, message: &str, size: usize) {
+    let Some(sink) = SINK.read().unwrap().clone() else {
+        return;
+    };
+    let connection = CONNECTION
+        .try_with(|connection| connection.clone())
+        .unwrap_or_else(|_| "-".to_string());
+    sink(&TraceEvent {
+        connection,
+        direction,
+        message: message.to_string(),
+        size,
+    });
+}
+
+fn message_name(data: &[u8]) -> String {
+    match crate::protocol::extract_message_type(data) {
+        Some(message_type) => format!("{:?}", message_type),
+        None => "Unknown".to_string(),
+    }
+}
+
+fn is_enabled() -> bool {
+    SINK.read().unwrap().is_some()
+}
+
+pub fn sent(data: &[u8]) {
+    if is_enabled() {
+        event(Direction::Sent, &message_name(data), data.len());
+    }
+}
+
+pub fn received(data: &[u8]) {
+    if is_enabled() {
+        event(Direction::Received, &message_name(data), data.len());
+    }
+}
@@ -0,0 +1,153 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+use crate::network::loopback::{self, LoopbackConnector, LoopbackStream};
+
+// Byte stream the protocol runs over: a TCP connection, a Unix domain socket
+// or an in-memory loopback stream. Framing and messages are the same on both.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+// Identifies a connected client. Unix socket and loopback peers are unnamed,
+// so they are numbered in accept order instead, across every listener.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    Unix(u64),
+    Loopback(u64),
+}
+
+impl std::fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "{}", addr),
+            PeerAddr::Unix(id) => write!(f, "unix client #{}", id),
+            PeerAddr::Loopback(id) => write!(f, "loopback client #{}", id),
+        }
+    }
+}
+
+// IPv6 addresses are accepted with or without brackets, "::1" or "[::1]".
+fn strip_brackets(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+// Addresses `host` stands for, a name or an IP address.
+pub fn resolve(host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+    Ok((strip_brackets(host), port).to_socket_addrs()?.collect())
+}
+
+// "host:port", with IPv6 addresses in brackets.
+pub fn host_port(host: &str, port: u16) -> String {
+    let host = strip_brackets(host);
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+static NEXT_PEER_ID: AtomicU64 = AtomicU64::new(0);
+
+fn next_peer_id() -> u64 {
+    NEXT_PEER_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+    Loopback {
+        incoming: tokio::sync::Mutex<mpsc::UnboundedReceiver<LoopbackStream>>,
+    },
+}
+
+impl Listener {
+    pub async fn bind_tcp(address: &str, port: u16) -> std::io::Result<Self> {
+        let addrs = resolve(address, port)?;
+        Ok(Listener::Tcp(TcpListener::bind(&addrs[..]).await?))
+    }
+
+    // `addr` is "host:port", e.g. "0.0.0.0:8080" for every IPv4 interface.
+    pub async fn bind_tcp_addr(addr: &str) -> std::io::Result<Self> {
+        Ok(Listener::Tcp(TcpListener::bind(addr).await?))
+    }
+
+    // A socket file left behind by a server that is gone is removed before
+    // binding. A socket with a live server behind it is left alone.
+    pub async fn bind_unix(path: &str) -> std::io::Result<Self> {
+        let path = Path::new(path);
+        if path.exists() && UnixStream::connect(path).await.is_err() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(Listener::Unix {
+            listener: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    // Accepts the connections opened with the returned connector.
+    pub fn loopback() -> (Self, LoopbackConnector) {
+        let (connector, incoming) = loopback::channel();
+        let listener = Listener::Loopback {
+            incoming: tokio::sync::Mutex::new(incoming),
+        };
+        (listener, connector)
+    }
+
+    pub async fn accept(&self) -> std::io::Result<(Box<dyn Transport>, PeerAddr)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (socket, addr) = listener.accept().await?;
+                Ok((Box::new(socket), PeerAddr::Tcp(addr)))
+            }
+            Listener::Unix { listener, .. } => {
+                let (socket, _) = listener.accept().await?;
+                Ok((Box::new(socket), PeerAddr::Unix(next_peer_id())))
+            }
+            Listener::Loopback { incoming } => {
+                // Once every connector is dropped, no client can come anymore
+                let Some(stream) = incoming.lock().await.recv().await else {
+                    return std::future::pending().await;
+                };
+                Ok((Box::new(stream), PeerAddr::Loopback(next_peer_id())))
+            }
+        }
+    }
+
+    // Address a TCP listener is bound to, e.g. to learn the port picked for
+    // port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            _ => None,
+        }
+    }
+}
+
+// Accepts the first connection coming on any of `listeners`. Waits forever
+// without listeners.
+pub async fn accept_any(listeners: &[Listener]) -> std::io::Result<(Box<dyn Transport>, PeerAddr)> {
+    if listeners.is_empty() {
+        return std::future::pending().await;
+    }
+    let accepts = listeners.iter().map(|listener| Box::pin(listener.accept()));
+    futures::future::select_all(accepts).await.0
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix { path, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
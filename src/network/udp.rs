@@ -0,0 +1,56 @@
+use anyhow::Result;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+use crate::network::trace;
+use crate::network::transport;
+use crate::protocol::Message;
+
+// Largest UDP payload over IPv4. Audio frames must fit in one datagram, so
+// chunks stay well below it.
+pub const MAX_DATAGRAM_LEN: usize = 65507;
+
+// Every datagram holds one message, encoded as over TCP without the length
+// prefix: the datagram boundaries delimit messages. The client asks with
+// START_PLAY and gets the AUDIO_HEADER, then one AUDIO_FRAME per datagram,
+// each starting with its sequence number, and STOP_PLAY at the end. Nothing
+// lost is sent again.
+
+// Bound to any address of the family of `peer`, on a port picked by the
+// system.
+pub(crate) async fn bind_for(peer: &SocketAddr) -> std::io::Result<UdpSocket> {
+    let any: SocketAddr = if peer.is_ipv6() {
+        "[::]:0".parse().unwrap()
+    } else {
+        "0.0.0.0:0".parse().unwrap()
+    };
+    UdpSocket::bind(any).await
+}
+
+pub(crate) async fn bind(address: &str, port: u16) -> std::io::Result<UdpSocket> {
+    let addrs = transport::resolve(address, port)?;
+    UdpSocket::bind(&addrs[..]).await
+}
+
+pub(crate) async fn send_to(socket: &UdpSocket, message: &Message, peer: SocketAddr) -> Result<()> {
+    let encoded = message.encode();
+    trace::sent(&encoded);
+    socket
+        .send_to(&encoded, peer)
+        .await
+        .map_err(|e| anyhow::anyhow!("Error sending {:?}: {}", message.message_type(), e))?;
+    Ok(())
+}
+
+// Next datagram and who sent it. `buffer` must hold MAX_DATAGRAM_LEN bytes.
+pub(crate) async fn recv_from(
+    socket: &UdpSocket,
+    buffer: &mut [u8],
+) -> Result<(Message, SocketAddr)> {
+    let (len, peer) = socket
+        .recv_from(buffer)
+        .await
+        .map_err(|e| anyhow::anyhow!("Error reading from socket: {}", e))?;
+    trace::received(&buffer[..len]);
+    Ok((Message::decode(&buffer[..len])?, peer))
+}
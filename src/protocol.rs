@@ -11,7 +11,94 @@ use serde::{Deserialize, Serialize};
 // adding unnecessary complexity.
 // ===============================================
 
-const PROTOCOL_MAGIC: u32 = 0xA1B2C3D4;
+pub const PROTOCOL_MAGIC: u32 = 0xA1B2C3D4;
+
+// Range of protocol versions this implementation speaks.
+pub const PROTOCOL_VERSION_MIN: u8 = 1;
+pub const PROTOCOL_VERSION_MAX: u8 = 2;
+// First version streaming AUDIO_FRAME instead of AUDIO_DATA.
+pub const AUDIO_FRAME_VERSION: u8 = 2;
+
+// Reason of a request refused by the server, sent in an ERROR message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum ProtocolErrorCode {
+    UnknownFile = 1,
+    UnsupportedFormat = 2,
+    AuthFailed = 3,
+    UnexpectedMessage = 4,
+    LimitExceeded = 5,
+    InternalError = 6,
+    ServerFull = 7,
+}
+
+impl ProtocolErrorCode {
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            1 => Some(Self::UnknownFile),
+            2 => Some(Self::UnsupportedFormat),
+            3 => Some(Self::AuthFailed),
+            4 => Some(Self::UnexpectedMessage),
+            5 => Some(Self::LimitExceeded),
+            6 => Some(Self::InternalError),
+            7 => Some(Self::ServerFull),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    // The two peers have no protocol version in common
+    VersionMismatch {
+        local: (u8, u8),
+        remote: (u8, u8),
+    },
+    // A client request refused by the server. Unknown codes sent by a newer
+    // server are reported as InternalError.
+    Rejected {
+        code: ProtocolErrorCode,
+        reason: String,
+    },
+    // Bytes that do not form a valid message
+    Malformed(String),
+}
+
+impl ProtocolError {
+    pub fn rejected(code: ProtocolErrorCode, reason: impl Into<String>) -> Self {
+        ProtocolError::Rejected {
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::VersionMismatch { local, remote } => write!(
+                f,
+                "No common protocol version: versions {}-{} supported, peer supports {}-{}",
+                local.0, local.1, remote.0, remote.1
+            ),
+            ProtocolError::Rejected { code, reason } => {
+                write!(f, "Request rejected ({:?}): {}", code, reason)
+            }
+            ProtocolError::Malformed(reason) => write!(f, "Malformed message: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+// Highest version within both ranges.
+pub fn negotiate_version(local: (u8, u8), remote: (u8, u8)) -> Result<u8, ProtocolError> {
+    let version = local.1.min(remote.1);
+    if version < local.0.max(remote.0) {
+        return Err(ProtocolError::VersionMismatch { local, remote });
+    }
+    Ok(version)
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Encode, Decode, PartialEq)]
 pub enum MessageType {
@@ -21,43 +108,136 @@ pub enum MessageType {
     StartPlaying,
     StopPlaying,
     AudioHeader,
+    AudioData,
+    CoverArt,
+    TimedAudioData,
+    TrackInfo,
+    EndOfTrack,
+    RequestFile,
+    Error,
+    Ping,
+    Pong,
+    Seek,
+    Pause,
+    Resume,
+    VolumeControl,
+    TrackChange,
+    RawData,
+    AudioFrame,
+    Nack,
+    StreamSalt,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Encode, Decode)]
-
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub enum SampleFormat {
+    #[default]
     Int,
     Float,
+    // 64-bit IEEE float samples, which hound cannot read or write
+    Float64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Encode, Decode)]
+// How audio frames are coded. Opus streams decode to 16-bit integer samples
+// in the format given by the rest of the header.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+pub enum AudioCodec {
+    Raw,
+    Opus,
+}
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Encode, Decode, PartialEq)]
 pub struct ProtocolInfo {
+    // Negotiated version, followed by the range supported by the server
     version: u8,
+    version_min: u8,
+    version_max: u8,
+    encrypted_audio: bool,
+    timestamped_frames: bool,
 }
 
 impl ProtocolInfo {
-    fn new() -> Self {
-        const VERSION: u8 = 1;
-        Self { version: VERSION }
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION_MAX,
+            version_min: PROTOCOL_VERSION_MIN,
+            version_max: PROTOCOL_VERSION_MAX,
+            encrypted_audio: false,
+            timestamped_frames: false,
+        }
+    }
+
+    pub fn with_version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn with_version_range(mut self, version_min: u8, version_max: u8) -> Self {
+        self.version_min = version_min;
+        self.version_max = version_max;
+        self
+    }
+
+    pub fn version_range(&self) -> (u8, u8) {
+        (self.version_min, self.version_max)
+    }
+
+    pub fn with_encrypted_audio(mut self, encrypted_audio: bool) -> Self {
+        self.encrypted_audio = encrypted_audio;
+        self
+    }
+
+    pub fn is_audio_encrypted(&self) -> bool {
+        self.encrypted_audio
+    }
+
+    pub fn with_timestamped_frames(mut self, timestamped_frames: bool) -> Self {
+        self.timestamped_frames = timestamped_frames;
+        self
+    }
+
+    pub fn has_timestamped_frames(&self) -> bool {
+        self.timestamped_frames
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Encode, Decode)]
+impl Default for ProtocolInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Every field is an integer or a fieldless enum, so headers compare and
+// hash exactly.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct AudioHeader {
     sample_rate: u32,
     channels: u8,
     bits_per_sample: u8,
     sample_format: SampleFormat,
+    codec: AudioCodec,
+    // Length of the stream in frames, 0 when unknown
+    total_samples: u64,
 }
 
 impl AudioHeader {
     pub fn new() -> Self {
-        Self {
-            sample_rate: 0,
-            channels: 0,
-            bits_per_sample: 0,
-            sample_format: SampleFormat::Int,
+        Self::default()
+    }
+
+    pub fn get_codec(&self) -> AudioCodec {
+        self.codec
+    }
+
+    // Opus frames decode to 16-bit integer samples.
+    pub fn set_codec(&mut self, codec: AudioCodec) {
+        self.codec = codec;
+        if codec == AudioCodec::Opus {
+            self.bits_per_sample = 16;
+            self.sample_format = SampleFormat::Int;
         }
     }
 
@@ -77,101 +257,346 @@ impl AudioHeader {
         self.channels
     }
 
+    pub fn get_total_samples(&self) -> u64 {
+        self.total_samples
+    }
+
+    pub fn set_total_samples(&mut self, total_samples: u64) {
+        self.total_samples = total_samples;
+    }
+
+    // Bits per second of the uncompressed audio.
+    pub fn bit_rate(&self) -> u32 {
+        self.sample_rate * self.channels as u32 * self.bits_per_sample as u32
+    }
+
+    // Bytes of one sample for every channel.
+    pub fn frame_size(&self) -> usize {
+        self.channels as usize * (self.bits_per_sample as usize / 8)
+    }
+
+    // Bytes of uncompressed audio playing for `secs` seconds, not rounded to
+    // whole frames.
+    pub fn bytes_for_duration(&self, secs: f64) -> u64 {
+        (self.sample_rate as f64 * secs * self.frame_size() as f64) as u64
+    }
+
     pub fn to_wavspec(&self) -> hound::WavSpec {
         hound::WavSpec {
             channels: self.channels as u16,
             sample_rate: self.sample_rate,
             bits_per_sample: self.bits_per_sample as u16,
             sample_format: match self.sample_format {
-                SampleFormat::Float => hound::SampleFormat::Float,
+                SampleFormat::Float | SampleFormat::Float64 => hound::SampleFormat::Float,
                 SampleFormat::Int => hound::SampleFormat::Int,
             },
         }
     }
 
-    pub fn update_wavspec(&mut self, spec: &hound::WavSpec) {
-        self.channels = spec.channels as u8;
-        self.sample_rate = spec.sample_rate;
-        self.bits_per_sample = spec.bits_per_sample as u8;
-        self.sample_format = match spec.sample_format {
-            hound::SampleFormat::Float => SampleFormat::Float,
-            hound::SampleFormat::Int => SampleFormat::Int,
-        };
+    pub fn from_wav_spec(spec: &hound::WavSpec) -> Self {
+        Self {
+            channels: spec.channels as u8,
+            sample_rate: spec.sample_rate,
+            bits_per_sample: spec.bits_per_sample as u8,
+            sample_format: match spec.sample_format {
+                hound::SampleFormat::Float if spec.bits_per_sample == 64 => SampleFormat::Float64,
+                hound::SampleFormat::Float => SampleFormat::Float,
+                hound::SampleFormat::Int => SampleFormat::Int,
+            },
+            ..Self::new()
+        }
+    }
+
+    // Output stream config playing this format, with the default buffer size.
+    pub fn to_cpal_config(&self) -> cpal::StreamConfig {
+        cpal::StreamConfig {
+            channels: self.channels as cpal::ChannelCount,
+            sample_rate: cpal::SampleRate(self.sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        }
+    }
+
+    // FLAC samples are streamed as 16-bit integers up to 16 bits and as
+    // 32-bit integers above, e.g. for 24-bit files.
+    pub fn update_from_flac(&mut self, info: &claxon::metadata::StreamInfo) {
+        self.channels = info.channels as u8;
+        self.sample_rate = info.sample_rate;
+        self.bits_per_sample = if info.bits_per_sample <= 16 { 16 } else { 32 };
+        self.sample_format = SampleFormat::Int;
+    }
+
+    // Vorbis samples are decoded to floats and streamed as 16-bit integers.
+    pub fn update_from_vorbis(&mut self, ident: &lewton::header::IdentHeader) {
+        self.channels = ident.audio_channels;
+        self.sample_rate = ident.audio_sample_rate;
+        self.bits_per_sample = 16;
+        self.sample_format = SampleFormat::Int;
+    }
+
+    // MP3 files are decoded to 16-bit samples.
+    pub fn update_from_mp3_frame(&mut self, frame: &minimp3::Frame) {
+        self.channels = frame.channels as u8;
+        self.sample_rate = frame.sample_rate as u32;
+        self.bits_per_sample = 16;
+        self.sample_format = SampleFormat::Int;
+    }
+}
+
+// CD quality: stereo 44.1 kHz 16-bit PCM.
+impl Default for AudioHeader {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            channels: 2,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+            codec: AudioCodec::Raw,
+            total_samples: 0,
+        }
+    }
+}
+
+impl From<&hound::WavSpec> for AudioHeader {
+    fn from(spec: &hound::WavSpec) -> Self {
+        Self::from_wav_spec(spec)
+    }
+}
+
+// The format a device records or plays in with `config`.
+impl From<&cpal::SupportedStreamConfig> for AudioHeader {
+    fn from(config: &cpal::SupportedStreamConfig) -> Self {
+        let format = config.sample_format();
+        Self {
+            channels: config.channels() as u8,
+            sample_rate: config.sample_rate().0,
+            bits_per_sample: (format.sample_size() * 8) as u8,
+            sample_format: match format {
+                cpal::SampleFormat::F64 => SampleFormat::Float64,
+                format if format.is_float() => SampleFormat::Float,
+                _ => SampleFormat::Int,
+            },
+            ..Self::new()
+        }
     }
 }
 
+pub const MAX_COVER_ART_SIZE: usize = 1 << 20;
+const COVER_ART_CHUNK_SIZE: usize = 32 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CoverArt {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+// Metadata of one track of a multi-track stream.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrackInfo {
+    pub title: String,
+    pub duration: std::time::Duration,
+}
+
+// One frame of a stream, numbered from 0 in the order frames are sent and
+// stamped with its send time in microseconds since the UNIX epoch, on the
+// server clock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioFrame {
+    pub sequence: u64,
+    pub timestamp_us: u64,
+    pub header: AudioHeader,
+    pub data: Vec<u8>,
+}
+
+pub const FRAME_CHECKSUM_LEN: usize = 4;
+
+// Most bytes an AUDIO_FRAME adds to the audio it carries: message type,
+// sequence number, timestamp and header, the authentication tag of an
+// encrypted stream and the checksum.
+pub const MAX_AUDIO_FRAME_OVERHEAD: usize = 64;
+
+// Appends the CRC32 of `data` to it, as sent at the end of AUDIO_FRAME data.
+pub fn append_checksum(data: &mut Vec<u8>) {
+    let checksum = crc32fast::hash(data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+}
+
+// Removes the CRC32 ending `data`, returning whether it matches the bytes
+// before it. Data too short to hold one never does.
+pub fn strip_checksum(data: &mut Vec<u8>) -> bool {
+    let Some(len) = data.len().checked_sub(FRAME_CHECKSUM_LEN) else {
+        return false;
+    };
+    let checksum = u32::from_le_bytes(data[len..].try_into().unwrap());
+    data.truncate(len);
+    crc32fast::hash(data) == checksum
+}
+
 // ===============================================
-// Authentication Process
+// Wire representation
 // ===============================================
 //
-// [client -> server]  [Magic][HELLO]
-//   - Magic: 4 bytes constant used for protocol sync
-//   - HELLO: u8 (0x01)
-//   => Client initiates handshake
-//
-// [server -> client]  [HELLO][PROTOCOL INFO]
-//   - HELLO: u8 (0x01)
-//   - PROTOCOL INFO: variable bytes
-//   => Server acknowledges and shares capabilities
-//
-// [client -> server]  [OK]
-//   - OK: u8 (0x02)
-//   => Client confirms handshake success
+// Everything that goes on the wire is encoded with
+// bincode's standard config through `encode` and
+// decoded through `decode`. The in-memory types
+// above are converted to/from the wire types below
+// so the wire layout can evolve independently.
 
-pub fn make_client_hello_message() -> Vec<u8> {
-    let config = bincode::config::standard();
+#[derive(Debug, Clone, Copy, Encode, Decode, PartialEq)]
+pub enum WireSampleFormat {
+    Int,
+    Float,
+    Float64,
+}
 
-    let mut message = bincode::encode_to_vec(PROTOCOL_MAGIC, config).unwrap();
-    message.push(MessageType::Hello as u8);
-    message
+#[derive(Debug, Clone, Copy, Encode, Decode, PartialEq)]
+pub struct WireAudioHeader {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    pub sample_format: WireSampleFormat,
+    pub codec: AudioCodec,
+    pub total_samples: u64,
 }
 
-fn get_hello_message_size() -> usize {
-    make_client_hello_message().len()
+impl From<SampleFormat> for WireSampleFormat {
+    fn from(format: SampleFormat) -> Self {
+        match format {
+            SampleFormat::Int => WireSampleFormat::Int,
+            SampleFormat::Float => WireSampleFormat::Float,
+            SampleFormat::Float64 => WireSampleFormat::Float64,
+        }
+    }
 }
 
-pub fn check_client_hello_message(data: &[u8]) -> bool {
-    if data.len() != get_hello_message_size() {
-        return false;
+impl From<WireSampleFormat> for SampleFormat {
+    fn from(format: WireSampleFormat) -> Self {
+        match format {
+            WireSampleFormat::Int => SampleFormat::Int,
+            WireSampleFormat::Float => SampleFormat::Float,
+            WireSampleFormat::Float64 => SampleFormat::Float64,
+        }
     }
+}
 
-    let config = bincode::config::standard();
-    let (magic, _): (u32, usize) = match bincode::decode_from_slice(&data[0..4], config) {
-        Ok(result) => result,
-        Err(_) => return false,
-    };
+impl From<&AudioHeader> for WireAudioHeader {
+    fn from(header: &AudioHeader) -> Self {
+        Self {
+            sample_rate: header.sample_rate,
+            channels: header.channels,
+            bits_per_sample: header.bits_per_sample,
+            sample_format: header.sample_format.into(),
+            codec: header.codec,
+            total_samples: header.total_samples,
+        }
+    }
+}
 
-    let msg_type = data[4];
+impl From<WireAudioHeader> for AudioHeader {
+    fn from(header: WireAudioHeader) -> Self {
+        Self {
+            sample_rate: header.sample_rate,
+            channels: header.channels,
+            bits_per_sample: header.bits_per_sample,
+            sample_format: header.sample_format.into(),
+            codec: header.codec,
+            total_samples: header.total_samples,
+        }
+    }
+}
 
-    magic == PROTOCOL_MAGIC && msg_type == MessageType::Hello as u8
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+pub struct WireTrackInfo {
+    pub title: String,
+    pub duration_ms: u64,
 }
 
-pub fn make_server_hello_message() -> Vec<u8> {
-    let config = bincode::config::standard();
+impl From<&TrackInfo> for WireTrackInfo {
+    fn from(info: &TrackInfo) -> Self {
+        Self {
+            title: info.title.clone(),
+            duration_ms: info.duration.as_millis() as u64,
+        }
+    }
+}
 
-    let protocol_info = ProtocolInfo::new();
-    let protocol_info_bytes = bincode::encode_to_vec(&protocol_info, config).unwrap();
+impl From<WireTrackInfo> for TrackInfo {
+    fn from(info: WireTrackInfo) -> Self {
+        Self {
+            title: info.title,
+            duration: std::time::Duration::from_millis(info.duration_ms),
+        }
+    }
+}
 
-    let mut message = bincode::encode_to_vec(MessageType::Hello, config).unwrap();
-    message.extend_from_slice(&protocol_info_bytes);
-    message
+#[derive(Debug, Clone, Encode, Decode, PartialEq)]
+pub struct WireCoverArtChunk {
+    pub mime_type: String,
+    pub total_len: u32,
+    pub offset: u32,
+    pub data: Vec<u8>,
 }
 
-pub fn extract_protocol_info(data: &[u8]) -> Option<ProtocolInfo> {
-    let config = bincode::config::standard();
+fn encode<T: Encode>(value: T) -> Vec<u8> {
+    bincode::encode_to_vec(value, bincode::config::standard())
+        .expect("encoding into a Vec cannot fail")
+}
 
-    let (protocol_info, _): (ProtocolInfo, usize) =
-        bincode::decode_from_slice(&data[1..], config).ok()?;
+// Frames are at most 64 KiB unless configured otherwise, and the 8 MiB
+// default of LengthDelimitedCodec is the most that makes sense. Longer
+// strings or vectors in a payload are refused before bincode allocates for
+// them, which a corrupt length would otherwise make it do.
+const MAX_DECODE_LEN: usize = 8 * 1024 * 1024;
 
-    Some(protocol_info)
+fn decode<T: Decode<()>>(data: &[u8]) -> Option<(T, usize)> {
+    let config = bincode::config::standard().with_limit::<MAX_DECODE_LEN>();
+    bincode::decode_from_slice(data, config).ok()
 }
 
-pub fn make_ok_message() -> Vec<u8> {
-    let config = bincode::config::standard();
+// ===============================================
+// Authentication Process
+// ===============================================
+//
+// Every message, from the first HELLO on, is sent as its own length-delimited
+// frame (4 bytes big-endian length, then the message), so messages split or
+// merged by the network are still read whole, and audio payloads can never be
+// mistaken for control messages such as STOP_PLAY.
+//
+// [client -> server]  [Magic][HELLO][Version min][Version max][Token]
+//   - Magic: 4 bytes constant used for protocol sync, checked at the start
+//     of the first frame
+//   - HELLO: u8 (0x01)
+//   - Version min / max: u8, protocol versions the client supports
+//   - Token: optional string identifying the client
+//   => Client initiates handshake
+//
+// [server -> client]  [HELLO][PROTOCOL INFO]
+//   - HELLO: u8 (0x01)
+//   - PROTOCOL INFO: variable bytes
+//     (negotiated version, versions the server supports,
+//      whether audio frames are encrypted,
+//      whether audio frames carry a send timestamp)
+//   => Server acknowledges and shares capabilities
+//
+// Both sides pick the highest version in both ranges. Without one, the
+// server still sends its PROTOCOL INFO so the client can report the
+// mismatch, then closes the connection.
+//
+// [client -> server]  [OK]
+//   - OK: u8 (0x02)
+//   => Client confirms handshake success
+
+type WireClientHello = (u32, MessageType, u8, u8, Option<String>);
 
-    bincode::encode_to_vec(MessageType::Ok, config).unwrap()
+fn decode_client_hello(data: &[u8]) -> Option<WireClientHello> {
+    match decode::<WireClientHello>(data)? {
+        (hello @ (PROTOCOL_MAGIC, MessageType::Hello, ..), len) if len == data.len() => Some(hello),
+        _ => None,
+    }
 }
 
+// Longest string (token, requested file) accepted from a client.
+pub const MAX_CLIENT_STRING_LEN: usize = 4096;
+
 // ===============================================
 // Audio Streaming Process
 // ===============================================
@@ -180,68 +605,142 @@ pub fn make_ok_message() -> Vec<u8> {
 //   - START_PLAY: u8 (0x10)
 //   => Client requests to start receiving audio
 //
+// [client -> server]  [REQUEST_FILE][Name]
+//   - Name: string, path relative to the server's
+//     media directory
+//   => Same as START_PLAY for a file picked by the
+//      client, if the server lets it access that file
+//
 // [server -> client]  [WAV_HEADER]
 //   - WAV_HEADER: u8 (0x11)
-//   - Data: fixed-size WAV header (44 bytes for PCM)
+//   - Data: fixed-size WAV header (44 bytes for PCM),
+//     followed by the codec of the audio frames and
+//     the length of the stream in frames (varint, 0
+//     when unknown, e.g. for live or endless streams)
 //   => Sent once before audio stream
+//
+// [server -> client]  [STREAM_SALT][Salt]
+//   - Salt: 16 random bytes, drawn for each stream
+//   - Sent just before WAV_HEADER when PROTOCOL INFO
+//     says audio frames are encrypted
 // [client -> server]  [OK]
-// [server -> client]  [AUDIO_DATA]
+// [server -> client]  [AUDIO_DATA][Data]
 //   - AUDIO_DATA: u8 (0x12)
-//   - Data: raw PCM samples or encoded chunk
+//   - Data: raw PCM samples, or one Opus packet of
+//     OPUS_FRAME_DURATION_MS when the codec is Opus
+//     (XChaCha20-Poly1305 sealed when PROTOCOL INFO says so,
+//      nonce = STREAM_SALT then the frame sequence number,
+//      u64 little endian)
 //   => Streamed continuously until stopped
+//
+// [server -> client]  [TIMED_AUDIO_DATA][Send time][Data]
+//   - Replaces AUDIO_DATA when PROTOCOL INFO says so
+//   - Send time: varint microseconds since the UNIX
+//     epoch, on the server clock
+//
+// [server -> client]  [COVER_ART][mime][total len][offset][bytes]
+//   - Optional, sent before the first AUDIO_DATA when
+//     the source embeds a picture. Large pictures are
+//     split over several messages; pictures above
+//     MAX_COVER_ART_SIZE are never sent.
+//
+// [server -> client]  [TRACK_INFO][title][duration ms]
+// [server -> client]  [AUDIO_HEADER][Data]
+// [server -> client]  [END_OF_TRACK]
+//   - Multi-track streams (radio) announce every track
+//     with TRACK_INFO, resend AUDIO_HEADER when the
+//     format changes, and close each track with
+//     END_OF_TRACK. They never end on their own: the
+//     client sends STOP_PLAY to leave, and the server
+//     answers with STOP_PLAY. Clients may leave any
+//     other stream early the same way.
+//
+// [server -> client]  [TRACK_CHANGE][Data]
+//   - Data: AUDIO_HEADER data of the next track
+//   - Playlists send it before every track after the
+//     first, following a gap of silence in the format
+//     of the previous track
+//
+// [client -> server]  [SEEK][Sample offset]
+//   - Sample offset: varint, in frames from the start
+//     of the source being streamed
+//   => Audio queued but not sent yet is dropped and the
+//      stream resumes from the new position
+//
+// [client -> server]  [VOLUME_CONTROL][Gain]
+//   - Gain: f32, little-endian, from MIN_VOLUME_GAIN
+//     to MAX_VOLUME_GAIN
+//   => Applied by the server to the samples it reads
+//      from then on
+//
+// [client -> server]  [PAUSE]
+// [client -> server]  [RESUME]
+//   => No audio is read nor sent between the two, the
+//      stream then goes on where it stopped. PINGs are
+//      still sent while paused
+//
+// [any]  [RAW_DATA][Header][Data]
+//   - Header: AUDIO_HEADER data describing Data
+//   - Data: raw PCM samples
+//   => Self-describing audio frame, for library users
+//      building frames outside of a stream. The server
+//      never sends it in a stream
+//
+// [server -> client]  [AUDIO_FRAME][Sequence][Send time][Header][Data]
+//   - Replaces AUDIO_DATA and TIMED_AUDIO_DATA from
+//     protocol version 2 on
+//   - Sequence: varint, 0 for the first frame of a
+//     stream and one more for every frame after it
+//   - Send time: as in TIMED_AUDIO_DATA
+//   - Header: AUDIO_HEADER data of the stream
+//   - Data: as in AUDIO_DATA, followed by the CRC32
+//     of those bytes (u32 little endian)
+//   => Numbered so that clients can detect gaps, and
+//      timed so that they can measure the jitter
+//
+// [client -> server]  [NACK][Sequence]
+//   - Sequence: varint, of an AUDIO_FRAME received
+//     with a wrong CRC32
+//   => The server sends that frame again if it is
+//      among the last RESEND_BUFFER_FRAMES it sent,
+//      and ignores the request otherwise
+//
+// [server -> client]  [PING]
+// [client -> server]  [PONG]
+//   - Keepalive: the server may send PING at any point
+//     of a stream and expects a PONG back, or it closes
+//     the connection
 
-pub fn make_start_playing_message() -> Vec<u8> {
-    let config = bincode::config::standard();
+pub const MIN_VOLUME_GAIN: f32 = 0.0;
+pub const MAX_VOLUME_GAIN: f32 = 2.0;
 
-    bincode::encode_to_vec(MessageType::StartPlaying, config).unwrap()
+pub fn is_valid_volume_gain(gain: f32) -> bool {
+    (MIN_VOLUME_GAIN..=MAX_VOLUME_GAIN).contains(&gain)
 }
 
+// Type of a message, without decoding its payload. A client HELLO, which
+// starts with the protocol magic, is reported as Hello.
 pub fn extract_message_type(data: &[u8]) -> Option<MessageType> {
-    if data.is_empty() {
-        return None;
+    if decode_client_hello(data).is_some() {
+        return Some(MessageType::Hello);
     }
-
-    let config = bincode::config::standard();
-    let (msg_type, _): (MessageType, usize) = match bincode::decode_from_slice(&data[0..1], config)
-    {
-        Ok(result) => result,
-        Err(_) => return None,
-    };
-
-    Some(msg_type)
-}
-
-pub fn extract_wav_header(data: &[u8]) -> Option<AudioHeader> {
-    let config = bincode::config::standard();
-
-    let (header, _): (AudioHeader, usize) = bincode::decode_from_slice(&data[1..], config).ok()?;
-
-    Some(header)
-}
-
-pub fn audio_header_to_bytes(header: &AudioHeader) -> Vec<u8> {
-    let config = bincode::config::standard();
-
-    let mut message = bincode::encode_to_vec(MessageType::AudioHeader, config).unwrap();
-    let header_bytes = bincode::encode_to_vec(header, config).unwrap();
-    message.extend_from_slice(&header_bytes);
-    message
+    decode::<MessageType>(data).map(|(msg_type, _)| msg_type)
 }
 
-pub fn check_ok_message(data: &[u8]) -> bool {
-    if data.len() != 1 {
-        return false;
-    }
-
-    let config = bincode::config::standard();
-    let (msg_type, _): (MessageType, usize) = match bincode::decode_from_slice(&data[0..1], config)
-    {
-        Ok(result) => result,
-        Err(_) => return false,
-    };
+// ===============================================
+// Errors
+// ===============================================
+//
+// [server -> client]  [ERROR][Code][Reason]
+//   - Code: u16, see ProtocolErrorCode
+//   - Reason: UTF-8 string, at most MAX_ERROR_REASON_LEN bytes
+//   => Sent instead of the AUDIO_HEADER, or of the
+//      STREAM_SALT before it, when the server
+//      refuses a request (unknown or unreadable file,
+//      access denied, unexpected message), then the
+//      server closes the connection
 
-    msg_type == MessageType::Ok
-}
+pub const MAX_ERROR_REASON_LEN: usize = 1024;
 
 // ===============================================
 // End / Termination Process
@@ -261,44 +760,302 @@ pub fn check_ok_message(data: &[u8]) -> bool {
 //
 //
 
-pub fn make_stop_playing_message() -> Vec<u8> {
-    let config = bincode::config::standard();
+// ===============================================
+// Messages
+// ===============================================
+//
+// Every message above, as built by one side and decoded by the other. A
+// message is encoded as its type followed by its payload, except for the
+// client HELLO which starts with the protocol magic.
 
-    bincode::encode_to_vec(MessageType::StopPlaying, config).unwrap()
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    // Client HELLO
+    Hello {
+        magic: u32,
+        version_min: u8,
+        version_max: u8,
+        token: Option<String>,
+    },
+    // Server HELLO
+    ProtocolInfo(ProtocolInfo),
+    Ok,
+    Bye,
+    StartPlaying,
+    StopPlaying,
+    RequestFile(String),
+    AudioHeader(AudioHeader),
+    AudioData(Vec<u8>),
+    TimedAudioData {
+        sent_at_us: u64,
+        data: Vec<u8>,
+    },
+    CoverArt(WireCoverArtChunk),
+    TrackInfo(TrackInfo),
+    TrackChange(AudioHeader),
+    EndOfTrack,
+    Error {
+        code: u16,
+        reason: String,
+    },
+    Ping,
+    Pong,
+    Seek(u64),
+    Pause,
+    Resume,
+    VolumeControl(f32),
+    RawData {
+        header: AudioHeader,
+        data: Vec<u8>,
+    },
+    AudioFrame(AudioFrame),
+    Nack(u64),
+    StreamSalt([u8; 16]),
 }
 
-pub fn make_bye_message() -> Vec<u8> {
-    let config = bincode::config::standard();
+impl Message {
+    // HELLO of a client speaking every version of this implementation.
+    pub fn hello(token: Option<&str>) -> Self {
+        Message::Hello {
+            magic: PROTOCOL_MAGIC,
+            version_min: PROTOCOL_VERSION_MIN,
+            version_max: PROTOCOL_VERSION_MAX,
+            token: token.map(str::to_string),
+        }
+    }
 
-    bincode::encode_to_vec(MessageType::Bye, config).unwrap()
-}
+    // ERROR with `reason` cut to MAX_ERROR_REASON_LEN bytes.
+    pub fn error(code: ProtocolErrorCode, reason: &str) -> Self {
+        let mut end = reason.len().min(MAX_ERROR_REASON_LEN);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        Message::Error {
+            code: code as u16,
+            reason: reason[..end].to_string(),
+        }
+    }
 
-pub fn check_bye_message(data: &[u8]) -> bool {
-    if data.len() != 1 {
-        return false;
+    // COVER_ART messages carrying `cover_art`, none above MAX_COVER_ART_SIZE.
+    pub fn cover_art(cover_art: &CoverArt) -> Vec<Self> {
+        if cover_art.data.len() > MAX_COVER_ART_SIZE {
+            return Vec::new();
+        }
+
+        cover_art
+            .data
+            .chunks(COVER_ART_CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| {
+                Message::CoverArt(WireCoverArtChunk {
+                    mime_type: cover_art.mime_type.clone(),
+                    total_len: cover_art.data.len() as u32,
+                    offset: (i * COVER_ART_CHUNK_SIZE) as u32,
+                    data: chunk.to_vec(),
+                })
+            })
+            .collect()
     }
 
-    let config = bincode::config::standard();
-    let (msg_type, _): (MessageType, usize) = match bincode::decode_from_slice(&data[0..1], config)
-    {
-        Ok(result) => result,
-        Err(_) => return false,
-    };
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Message::Hello { .. } | Message::ProtocolInfo(_) => MessageType::Hello,
+            Message::Ok => MessageType::Ok,
+            Message::Bye => MessageType::Bye,
+            Message::StartPlaying => MessageType::StartPlaying,
+            Message::StopPlaying => MessageType::StopPlaying,
+            Message::RequestFile(_) => MessageType::RequestFile,
+            Message::AudioHeader(_) => MessageType::AudioHeader,
+            Message::AudioData(_) => MessageType::AudioData,
+            Message::TimedAudioData { .. } => MessageType::TimedAudioData,
+            Message::CoverArt(_) => MessageType::CoverArt,
+            Message::TrackInfo(_) => MessageType::TrackInfo,
+            Message::TrackChange(_) => MessageType::TrackChange,
+            Message::EndOfTrack => MessageType::EndOfTrack,
+            Message::Error { .. } => MessageType::Error,
+            Message::Ping => MessageType::Ping,
+            Message::Pong => MessageType::Pong,
+            Message::Seek(_) => MessageType::Seek,
+            Message::Pause => MessageType::Pause,
+            Message::Resume => MessageType::Resume,
+            Message::VolumeControl(_) => MessageType::VolumeControl,
+            Message::RawData { .. } => MessageType::RawData,
+            Message::AudioFrame(_) => MessageType::AudioFrame,
+            Message::Nack(_) => MessageType::Nack,
+            Message::StreamSalt(_) => MessageType::StreamSalt,
+        }
+    }
 
-    msg_type == MessageType::Bye
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = match self {
+            Message::Hello {
+                magic,
+                version_min,
+                version_max,
+                token,
+            } => {
+                return encode((
+                    *magic,
+                    MessageType::Hello,
+                    *version_min,
+                    *version_max,
+                    token,
+                ));
+            }
+            _ => encode(self.message_type()),
+        };
+        match self {
+            Message::ProtocolInfo(info) => message.extend(encode(info)),
+            Message::RequestFile(file) => message.extend(encode(file)),
+            Message::AudioHeader(header) | Message::TrackChange(header) => {
+                message.extend(encode(WireAudioHeader::from(header)))
+            }
+            Message::AudioData(data) => message.extend_from_slice(data),
+            Message::TimedAudioData { sent_at_us, data } => {
+                message.extend(encode(sent_at_us));
+                message.extend_from_slice(data);
+            }
+            Message::CoverArt(chunk) => message.extend(encode(chunk)),
+            Message::TrackInfo(info) => message.extend(encode(WireTrackInfo::from(info))),
+            Message::Error { code, reason } => message.extend(encode((code, reason))),
+            Message::Seek(sample_offset) => message.extend(encode(sample_offset)),
+            Message::Nack(sequence) => message.extend(encode(sequence)),
+            Message::StreamSalt(salt) => message.extend(encode(salt)),
+            Message::VolumeControl(gain) => message.extend(encode(gain)),
+            Message::RawData { header, data } => {
+                message.extend(encode(WireAudioHeader::from(header)));
+                message.extend_from_slice(data);
+            }
+            Message::AudioFrame(frame) => {
+                message.extend(encode((
+                    frame.sequence,
+                    frame.timestamp_us,
+                    WireAudioHeader::from(&frame.header),
+                )));
+                message.extend_from_slice(&frame.data);
+            }
+            _ => {}
+        }
+        message
+    }
+
+    // Payloads must be decoded in full, except for audio data which takes
+    // whatever follows the type. Gains outside of the allowed range are
+    // rejected.
+    pub fn decode(data: &[u8]) -> Result<Self, ProtocolError> {
+        if let Some((magic, _, version_min, version_max, token)) = decode_client_hello(data) {
+            return Ok(Message::Hello {
+                magic,
+                version_min,
+                version_max,
+                token,
+            });
+        }
+        let (msg_type, len) = decode::<MessageType>(data)
+            .ok_or_else(|| ProtocolError::Malformed("unknown message type".to_string()))?;
+        let payload = &data[len..];
+        let message = match msg_type {
+            MessageType::Hello => Message::ProtocolInfo(decode_payload(msg_type, payload)?),
+            MessageType::RequestFile => Message::RequestFile(decode_payload(msg_type, payload)?),
+            MessageType::AudioHeader => {
+                Message::AudioHeader(decode_payload::<WireAudioHeader>(msg_type, payload)?.into())
+            }
+            MessageType::TrackChange => {
+                Message::TrackChange(decode_payload::<WireAudioHeader>(msg_type, payload)?.into())
+            }
+            MessageType::AudioData => Message::AudioData(payload.to_vec()),
+            MessageType::TimedAudioData => {
+                let (sent_at_us, ts_len) = decode::<u64>(payload)
+                    .ok_or_else(|| ProtocolError::Malformed(format!("invalid {:?}", msg_type)))?;
+                Message::TimedAudioData {
+                    sent_at_us,
+                    data: payload[ts_len..].to_vec(),
+                }
+            }
+            MessageType::CoverArt => Message::CoverArt(decode_payload(msg_type, payload)?),
+            MessageType::TrackInfo => {
+                Message::TrackInfo(decode_payload::<WireTrackInfo>(msg_type, payload)?.into())
+            }
+            MessageType::Error => {
+                let (code, reason) = decode_payload(msg_type, payload)?;
+                Message::Error { code, reason }
+            }
+            MessageType::Seek => Message::Seek(decode_payload(msg_type, payload)?),
+            MessageType::Nack => Message::Nack(decode_payload(msg_type, payload)?),
+            MessageType::StreamSalt => Message::StreamSalt(decode_payload(msg_type, payload)?),
+            MessageType::VolumeControl => {
+                let gain = decode_payload(msg_type, payload)?;
+                if !is_valid_volume_gain(gain) {
+                    return Err(ProtocolError::Malformed(format!(
+                        "volume gain {} out of range",
+                        gain
+                    )));
+                }
+                Message::VolumeControl(gain)
+            }
+            MessageType::RawData => {
+                let (header, header_len) = decode::<WireAudioHeader>(payload)
+                    .ok_or_else(|| ProtocolError::Malformed(format!("invalid {:?}", msg_type)))?;
+                Message::RawData {
+                    header: header.into(),
+                    data: payload[header_len..].to_vec(),
+                }
+            }
+            MessageType::AudioFrame => {
+                let ((sequence, timestamp_us, header), len) =
+                    decode::<(u64, u64, WireAudioHeader)>(payload).ok_or_else(|| {
+                        ProtocolError::Malformed(format!("invalid {:?}", msg_type))
+                    })?;
+                Message::AudioFrame(AudioFrame {
+                    sequence,
+                    timestamp_us,
+                    header: header.into(),
+                    data: payload[len..].to_vec(),
+                })
+            }
+            MessageType::Ok => control(Message::Ok, payload)?,
+            MessageType::Bye => control(Message::Bye, payload)?,
+            MessageType::StartPlaying => control(Message::StartPlaying, payload)?,
+            MessageType::StopPlaying => control(Message::StopPlaying, payload)?,
+            MessageType::EndOfTrack => control(Message::EndOfTrack, payload)?,
+            MessageType::Ping => control(Message::Ping, payload)?,
+            MessageType::Pong => control(Message::Pong, payload)?,
+            MessageType::Pause => control(Message::Pause, payload)?,
+            MessageType::Resume => control(Message::Resume, payload)?,
+        };
+        Ok(message)
+    }
 }
 
-pub fn is_stop_playing_message(data: &[u8]) -> bool {
-    if data.len() != 1 {
-        return false;
+// RAW_DATA frame carrying `payload`, described by `header`.
+pub fn make_full_message(header: &AudioHeader, payload: &[u8]) -> Vec<u8> {
+    Message::RawData {
+        header: *header,
+        data: payload.to_vec(),
     }
+    .encode()
+}
 
-    let config = bincode::config::standard();
-    let (msg_type, _): (crate::protocol::MessageType, usize) =
-        match bincode::decode_from_slice(&data[0..1], config) {
-            Ok(result) => result,
-            Err(_) => return false,
-        };
+fn decode_payload<T: Decode<()>>(
+    msg_type: MessageType,
+    payload: &[u8],
+) -> Result<T, ProtocolError> {
+    match decode::<T>(payload) {
+        Some((value, len)) if len == payload.len() => Ok(value),
+        _ => Err(ProtocolError::Malformed(format!(
+            "invalid {:?} payload",
+            msg_type
+        ))),
+    }
+}
 
-    msg_type == crate::protocol::MessageType::StopPlaying
+// Messages made of their type only, e.g. [OK] or [BYE].
+fn control(message: Message, payload: &[u8]) -> Result<Message, ProtocolError> {
+    if !payload.is_empty() {
+        return Err(ProtocolError::Malformed(format!(
+            "unexpected payload after {:?}",
+            message.message_type()
+        )));
+    }
+    Ok(message)
 }
@@ -2,59 +2,291 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use clap::Parser;
-use streamapp::audio::{cpal::CpalInterface, file::AudioRecorder};
+use streamapp::audio::split::AutoSplit;
+use streamapp::audio::{
+    cpal::{CpalInterface, CpalInterfaceWithDevice, CpalRecorderConfig},
+    file::FileFormat,
+};
+use streamapp::metrics::{DEFAULT_METRICS_PORT, PrometheusExporter};
+use streamapp::network::playlist::read_m3u;
+use streamapp::network::tls::TlsConfig;
+use streamapp::protocol::AudioCodec;
 use streamapp::server::server_manager;
+use streamapp::server::udp_server::UdpServer;
+use tokio::signal::unix::{SignalKind, signal};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Audio Streaming Server")]
 struct Args {
-    /// Mode: rec = microphone, file = read wav
+    /// Mode: rec = microphone, file = read wav, radio = shuffle a directory,
+    /// live = stream the microphone as it records
+    #[arg(long, required_unless_present = "list_devices")]
+    mode: Option<String>,
+
+    /// Print the available input devices and exit
+    #[arg(long, default_value_t = false)]
+    list_devices: bool,
+
+    /// Input device to record from instead of the default one (for
+    /// microphone)
     #[arg(long)]
-    mode: String,
+    input_device: Option<String>,
 
     /// Duration in seconds (for microphone)
     #[arg(long)]
     duration: Option<u64>,
 
+    /// Keep only what is recorded while the RMS level, in [0.0, 1.0], is
+    /// above this threshold, dropping silences (for microphone)
+    #[arg(long, conflicts_with_all = ["split_silence_ms", "stop_on_stdin"])]
+    vad_threshold: Option<f32>,
+
+    /// Record until Enter is pressed instead of for a fixed duration, or
+    /// until --duration elapses if given first (for microphone)
+    #[arg(long, default_value_t = false, conflicts_with = "split_silence_ms")]
+    stop_on_stdin: bool,
+
     /// File path (for file mode)
     #[arg(long)]
     path: Option<String>,
 
+    /// Files streamed one after the other instead of --path, or .m3u
+    /// playlists listing them (for file mode)
+    #[arg(long, num_args = 1..)]
+    playlist: Vec<String>,
+
+    /// Format of the streamed file: wav, flac, mp3, ogg or opus (for file
+    /// mode). Guessed from the file extension by default
+    #[arg(long)]
+    format: Option<String>,
+
+    /// Codec of the streamed audio: raw or opus
+    #[arg(long, default_value = "raw")]
+    codec: String,
+
+    /// Directory of WAV files (for radio mode)
+    #[arg(long)]
+    dir: Option<String>,
+
     /// File output path (for microphone mode)
     #[arg(long, default_value = "/tmp/recorded.wav")]
     output: String,
 
-    /// Server address
-    /// Default is localhost
-    #[arg(long, default_value = "localhost")]
-    address: String,
+    /// Split the recording into numbered files at silences longer than
+    /// this many milliseconds (for microphone)
+    #[arg(long)]
+    split_silence_ms: Option<u64>,
+
+    /// Peak level under which audio counts as silence for --split-silence-ms
+    #[arg(long, default_value_t = 0.01)]
+    split_threshold: f32,
+
+    /// Server address, a host name or an IPv4 or IPv6 address
+    /// Default is localhost, or :: with --ipv6
+    #[arg(long)]
+    address: Option<String>,
+
+    /// Default the server address to ::, every IPv6 address (and IPv4 ones
+    /// too where the system binds dual-stack)
+    #[arg(long, default_value_t = false)]
+    ipv6: bool,
 
     /// Server port
     /// Default is 8080
     #[arg(long, default_value_t = 8080)]
     port: u16,
+
+    /// Port serving Prometheus metrics at /metrics, on the server address
+    #[arg(long, default_value_t = DEFAULT_METRICS_PORT)]
+    metrics_port: u16,
+
+    /// Stream the file live to every client at once, read a single time
+    /// (file mode)
+    #[arg(long, default_value_t = false)]
+    broadcast: bool,
+
+    /// Bring the loudest sample of the streamed file to 90% of full scale
+    /// (file mode, without --playlist or --broadcast)
+    #[arg(long, default_value_t = false)]
+    normalize: bool,
+
+    /// WAV file streamed before every file (file mode)
+    #[arg(long)]
+    pre_roll: Option<String>,
+
+    /// WAV file streamed after every file (file mode)
+    #[arg(long)]
+    post_roll: Option<String>,
+
+    /// Only accept clients sending this token
+    #[arg(long)]
+    auth_token: Option<String>,
+
+    /// PEM certificate chain to serve over TLS, with --tls-key
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// PEM private key of --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// Bytes read from the source and sent at a time. Smaller chunks lower
+    /// latency, larger ones raise throughput, up to 65472 bytes
+    #[arg(long)]
+    chunk_size: Option<usize>,
+
+    /// Serve at most this many clients at once, others are refused
+    #[arg(long)]
+    max_clients: Option<usize>,
+
+    /// Transport to clients: tcp, or udp to stream without waiting for lost
+    /// audio (file mode, --path and --format only)
+    #[arg(long, default_value = "tcp")]
+    transport: String,
+
+    /// Listen on this Unix domain socket instead of TCP
+    #[arg(long)]
+    socket: Option<String>,
+
+    /// Log every protocol message sent and received to stderr
+    #[arg(long, default_value_t = false)]
+    trace: bool,
+
+    /// Most verbose log level written to stderr: error, warn, info, debug or
+    /// trace
+    #[arg(long, default_value = "info")]
+    log_level: tracing::Level,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(args.log_level)
+        .with_writer(std::io::stderr)
+        .init();
+    if args.list_devices {
+        for device in CpalInterface::list_input_devices()? {
+            println!("{}", device);
+        }
+        return Ok(());
+    }
+    if args.trace {
+        streamapp::network::trace::enable();
+    }
+    let mode = args.mode.unwrap_or_default();
 
-    let audio_interface = CpalInterface;
-    let path = match args.mode.as_str() {
-        "rec" => {
-            let duration = args.duration.unwrap_or(10);
-            println!("Recording from microphone for {} seconds...", duration);
+    let format = match args.format.as_deref() {
+        None => None,
+        Some("wav") => Some(FileFormat::Wav),
+        Some("flac") => Some(FileFormat::Flac),
+        Some("mp3") => Some(FileFormat::Mp3),
+        Some("ogg") => Some(FileFormat::Ogg),
+        Some("opus") => Some(FileFormat::Opus),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Invalid format. Use 'wav', 'flac', 'mp3', 'ogg' or 'opus'."
+            ));
+        }
+    };
+    let codec = match args.codec.as_str() {
+        "raw" => AudioCodec::Raw,
+        "opus" => AudioCodec::Opus,
+        _ => {
+            return Err(anyhow::anyhow!("Invalid codec. Use 'raw' or 'opus'."));
+        }
+    };
+
+    if let Some(threshold) = args.vad_threshold
+        && !(0.0..=1.0).contains(&threshold)
+    {
+        return Err(anyhow::anyhow!(
+            "Invalid VAD threshold {}. Use a level between 0.0 and 1.0.",
+            threshold
+        ));
+    }
+    let udp = match args.transport.as_str() {
+        "tcp" => false,
+        "udp" => true,
+        _ => return Err(anyhow::anyhow!("Invalid transport. Use 'tcp' or 'udp'.")),
+    };
+
+    let mut playlist = vec![];
+    for entry in args.playlist {
+        if entry.ends_with(".m3u") || entry.ends_with(".m3u8") {
+            playlist.extend(read_m3u(&entry)?);
+        } else {
+            playlist.push(entry);
+        }
+    }
+
+    let audio_interface = match &args.input_device {
+        Some(device) => CpalInterface::with_input_device(device)?,
+        None => CpalInterfaceWithDevice::default(),
+    };
+    let path = match mode.as_str() {
+        "rec" if args.stop_on_stdin => {
+            let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+            tracing::info!("Recording from microphone, press Enter to stop...");
+            let stop_tx = Arc::new(stop_tx);
+            if let Some(duration) = args.duration {
+                let stop_tx = Arc::clone(&stop_tx);
+                tokio::spawn(async move {
+                    tokio::time::sleep(std::time::Duration::from_secs(duration)).await;
+                    stop_tx.send_replace(true);
+                });
+            }
+            // Blocking read on a thread of its own, which does not keep the
+            // process alive once the recording is over
+            std::thread::spawn(move || {
+                let _ = std::io::stdin().read_line(&mut String::new());
+                stop_tx.send_replace(true);
+            });
             audio_interface
-                .record_into_file(
-                    duration,
-                    &args.output,
-                    streamapp::audio::file::FileFormat::Wav,
-                )
-                .await
-                .unwrap();
-            println!("Recording saved to {}", &args.output);
+                .record_until_signal(stop_rx, &args.output)
+                .await?;
+            tracing::info!("Recording saved to {}", &args.output);
             args.output
         }
+        "rec" => {
+            let duration = args.duration.unwrap_or(10);
+            tracing::info!("Recording from microphone for {} seconds...", duration);
+            match args.split_silence_ms {
+                Some(gap) => {
+                    let auto_split = AutoSplit {
+                        threshold: args.split_threshold,
+                        min_gap: std::time::Duration::from_millis(gap),
+                    };
+                    let takes = audio_interface
+                        .record_with_auto_split(duration, &args.output, auto_split)
+                        .await?;
+                    tracing::info!("Recorded takes: {:?}", takes);
+                    takes
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("No sound was recorded"))?
+                }
+                None => {
+                    let config = CpalRecorderConfig {
+                        device_name: args.input_device.clone(),
+                        duration,
+                        path: args.output.clone(),
+                        format: FileFormat::Wav,
+                        vad_threshold: args.vad_threshold,
+                    };
+                    CpalInterface::record_with_config_and_progress(config, |elapsed, total| {
+                        eprint!("\r{}/{}s", elapsed, total);
+                        if elapsed == total {
+                            eprintln!();
+                        }
+                    })
+                    .await?;
+                    tracing::info!("Recording saved to {}", &args.output);
+                    args.output
+                }
+            }
+        }
+        "file" if !playlist.is_empty() => playlist[0].clone(),
         "file" => {
             let path = args
                 .path
@@ -64,15 +296,111 @@ async fn main() -> Result<()> {
             }
             path
         }
+        "radio" => {
+            let dir = args
+                .dir
+                .ok_or_else(|| anyhow::anyhow!("The directory should be specified"))?;
+            if !std::path::Path::new(&dir).is_dir() {
+                return Err(anyhow::anyhow!(format!("Invalid directory {}", dir)));
+            }
+            dir
+        }
+        // Nothing is read from disk
+        "live" => String::new(),
         _ => {
-            return Err(anyhow::anyhow!("Invalid mode. Use 'rec' or 'file'."));
+            return Err(anyhow::anyhow!(
+                "Invalid mode. Use 'rec', 'file', 'radio' or 'live'."
+            ));
         }
     };
 
-    println!("Starting server...");
+    tracing::info!("Starting server...");
+
+    let address = args
+        .address
+        .clone()
+        .unwrap_or_else(|| if args.ipv6 { "::" } else { "localhost" }.to_string());
+
+    if udp {
+        if mode != "file" {
+            return Err(anyhow::anyhow!("Only file mode is served over UDP"));
+        }
+        let mut server = UdpServer::new(address, args.port, path).await?;
+        if let Some(format) = format {
+            server.set_file_format(format);
+        }
+        if let Some(chunk_size) = args.chunk_size {
+            server.set_chunk_size(chunk_size);
+        }
+        return tokio::select! {
+            result = Arc::new(server).run() => result,
+            result = wait_for_termination() => Ok(result?),
+        };
+    }
 
-    let server = Arc::new(server_manager::Server::new(args.address, args.port, path).await);
-    server.run().await;
+    let mut server = match args.socket {
+        Some(socket) => server_manager::Server::new_unix(socket, path).await?,
+        None => server_manager::Server::new(address.clone(), args.port, path).await?,
+    };
+    if mode == "file" && !playlist.is_empty() {
+        server = server.with_playlist(playlist);
+    }
+    server.set_radio_mode(mode == "radio");
+    server.set_live_mode(mode == "live");
+    if let Some(token) = args.auth_token {
+        server.set_auth_token(token);
+    }
+    if let (Some(cert_path), Some(key_path)) = (args.tls_cert, args.tls_key) {
+        server.set_tls(&TlsConfig {
+            cert_path,
+            key_path,
+        })?;
+    }
+    server.set_config(server_manager::ServerConfig {
+        max_clients: args.max_clients,
+        ..Default::default()
+    });
+    if let Some(chunk_size) = args.chunk_size {
+        server.set_chunk_size(chunk_size)?;
+    }
+    server.set_codec(codec);
+    if mode == "file" {
+        if let Some(format) = format {
+            server.set_file_format(format);
+        }
+        server.set_broadcast_mode(args.broadcast);
+        server.set_normalize(args.normalize);
+    } else {
+        // Recordings are always WAV, whatever the extension of --output
+        server.set_file_format(FileFormat::Wav);
+    }
+    if let Some(pre_roll) = args.pre_roll {
+        server.set_pre_roll(pre_roll);
+    }
+    if let Some(post_roll) = args.post_roll {
+        server.set_post_roll(post_roll);
+    }
+    let exporter = PrometheusExporter::new(server.registry());
+    tokio::spawn(async move {
+        if let Err(e) = exporter.serve(&address, args.metrics_port).await {
+            tracing::error!("Metrics not served: {}", e);
+        }
+    });
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(async move {
+        match wait_for_termination().await {
+            Ok(()) => shutdown.shutdown(),
+            Err(e) => tracing::error!("Cannot listen for termination signals: {}", e),
+        }
+    });
+    Arc::new(server).run().await
+}
 
-    Ok(())
+// SIGINT (Ctrl-C) or SIGTERM.
+async fn wait_for_termination() -> std::io::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = sigterm.recv() => Ok(()),
+    }
 }
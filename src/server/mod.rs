@@ -1 +1,3 @@
+pub mod registry;
 pub mod server_manager;
+pub mod udp_server;
@@ -0,0 +1,129 @@
+use crate::network::file::SentCounters;
+use crate::network::transport::PeerAddr;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+// What the server knows of a connected client, as of when it was asked.
+#[derive(Debug, Clone)]
+pub struct ClientSession {
+    pub addr: PeerAddr,
+    pub connected_at: Instant,
+    // Audio payload sent so far, before encryption
+    pub bytes_sent: u64,
+    pub frames_sent: u64,
+    // Empty until the client asks for a stream
+    pub current_file: String,
+}
+
+#[derive(Debug)]
+struct SessionEntry {
+    connected_at: Instant,
+    // Shared with the stream, which counts what it sends
+    sent: SentCounters,
+    current_file: String,
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    live: HashMap<PeerAddr, SessionEntry>,
+    // Sent by the sessions already closed
+    closed_bytes: u64,
+    closed_frames: u64,
+}
+
+// Clients connected to a server, from the end of their handshake until
+// their connection closes, and totals over every client served.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionRegistry {
+    sessions: Arc<RwLock<Sessions>>,
+    stream_errors: Arc<AtomicU64>,
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn register(&self, addr: &PeerAddr) {
+        self.sessions.write().unwrap().live.insert(
+            addr.clone(),
+            SessionEntry {
+                connected_at: Instant::now(),
+                sent: Default::default(),
+                current_file: String::new(),
+            },
+        );
+    }
+
+    pub(crate) fn unregister(&self, addr: &PeerAddr) {
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(entry) = sessions.live.remove(addr) {
+            sessions.closed_bytes += entry.sent.bytes();
+            sessions.closed_frames += entry.sent.frames();
+        }
+    }
+
+    // Counters a stream to `addr` adds what it sends to. Fresh ones,
+    // counted nowhere, for clients not registered.
+    pub(crate) fn sent_counters(&self, addr: &PeerAddr) -> SentCounters {
+        self.sessions
+            .read()
+            .unwrap()
+            .live
+            .get(addr)
+            .map(|entry| entry.sent.clone())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn set_current_file(&self, addr: &PeerAddr, file: &str) {
+        if let Some(entry) = self.sessions.write().unwrap().live.get_mut(addr) {
+            entry.current_file = file.to_string();
+        }
+    }
+
+    pub(crate) fn record_stream_error(&self) {
+        self.stream_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sessions(&self) -> Vec<ClientSession> {
+        self.sessions
+            .read()
+            .unwrap()
+            .live
+            .iter()
+            .map(|(addr, entry)| ClientSession {
+                addr: addr.clone(),
+                connected_at: entry.connected_at,
+                bytes_sent: entry.sent.bytes(),
+                frames_sent: entry.sent.frames(),
+                current_file: entry.current_file.clone(),
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.read().unwrap().live.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Audio payload bytes sent to every client since the server started
+    pub fn total_bytes_sent(&self) -> u64 {
+        let sessions = self.sessions.read().unwrap();
+        sessions.closed_bytes + sessions.live.values().map(|e| e.sent.bytes()).sum::<u64>()
+    }
+
+    pub fn total_frames_sent(&self) -> u64 {
+        let sessions = self.sessions.read().unwrap();
+        sessions.closed_frames + sessions.live.values().map(|e| e.sent.frames()).sum::<u64>()
+    }
+
+    // Sessions ended by an error, a refused request included
+    pub fn stream_errors(&self) -> u64 {
+        self.stream_errors.load(Ordering::Relaxed)
+    }
+}
@@ -1,91 +1,1125 @@
+use crate::audio::cpal::LiveCapture;
 use crate::audio::file::FileFormat;
 use crate::network;
-use crate::protocol::MessageType;
+use crate::network::broadcast::BROADCAST_CAPACITY;
+use crate::network::buffer::{BufferAccount, BufferPolicy, DEFAULT_MAX_BUFFERED_BYTES};
+use crate::network::common::{FramedTransport, LengthDelimitedCodecConfig};
+use crate::network::control::{self, StreamControl};
+use crate::network::crypto::{EncryptionKey, FrameCipher};
+use crate::network::file::{DEFAULT_CHUNK_SIZE, EofAction, SendOptions};
+use crate::network::keepalive::{self, Keepalive, KeepaliveTask};
+use crate::network::loopback::LoopbackConnector;
+use crate::network::playlist::{DEFAULT_TRACK_GAP, Playlist};
+use crate::network::tls::{self, TlsConfig};
+use crate::network::trace;
+use crate::network::transport::{self, Listener, PeerAddr, Transport};
+use crate::protocol::{
+    AudioCodec, AudioHeader, Message, ProtocolError, ProtocolErrorCode, ProtocolInfo,
+};
+use crate::server::registry::{ClientSession, ConnectionRegistry};
 use anyhow::Result;
-use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpListener;
-use tokio::net::TcpStream;
+use bytes::Bytes;
+use futures::SinkExt;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{Semaphore, broadcast, watch};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+// Clients still connected this long after a shutdown are disconnected.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_MAX_PENDING_REJECTIONS: usize = 16;
+
+/// Limits on the connections a server takes, see `Server::set_config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Clients served at once, unlimited when `None`. Connections beyond it
+    /// get a ServerFull error and are closed.
+    pub max_clients: Option<usize>,
+    /// How long a refused connection gets to send its HELLO and read the
+    /// ServerFull error before it is closed without one.
+    pub reject_timeout: Duration,
+    /// Refused connections waiting for their error at once. Connections
+    /// refused beyond it are closed right away, without an error.
+    pub max_pending_rejections: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_clients: None,
+            reject_timeout: DEFAULT_REJECT_TIMEOUT,
+            max_pending_rejections: DEFAULT_MAX_PENDING_REJECTIONS,
+        }
+    }
+}
+
+// Who a connection belongs to, as far as the server can tell.
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    pub addr: PeerAddr,
+    // Token sent by the client in its HELLO
+    pub token: Option<String>,
+    // Protocol version negotiated with the client
+    pub version: u8,
+}
+
+type FileAuthorizer = Box<dyn Fn(&ClientIdentity, &str) -> bool + Send + Sync>;
+type ConnectHook = Box<dyn Fn(&PeerAddr) + Send + Sync>;
+type DisconnectHook = Box<dyn Fn(&PeerAddr, Option<&anyhow::Error>) + Send + Sync>;
+
+// Stops a running server, see `Server::shutdown_handle`.
+#[derive(Debug, Clone)]
+pub struct ShutdownSender {
+    sender: watch::Sender<bool>,
+}
+
+impl ShutdownSender {
+    pub fn shutdown(&self) {
+        self.sender.send_replace(true);
+    }
+}
+
+// A single pass over the served file, read once and shared by every client.
+// Clients joining late get the header, then the stream from where it is.
+pub(crate) struct BroadcastServer {
+    sender: broadcast::Sender<Bytes>,
+    header: AudioHeader,
+    ended: Arc<AtomicBool>,
+    // Set when broadcasting the microphone rather than a file
+    _capture: Option<LiveCapture>,
+}
+
+impl BroadcastServer {
+    fn start(file_format: FileFormat, file_path: &str, codec: AudioCodec) -> Result<Self> {
+        let mut audio_reader = network::file::open_audio_file(file_format, file_path)?;
+        let header = network::file::stream_header(audio_reader.as_mut(), codec);
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        let ended = Arc::new(AtomicBool::new(false));
+
+        let publisher = sender.clone();
+        let publisher_ended = Arc::clone(&ended);
+        tokio::spawn(async move {
+            let result =
+                network::broadcast::read_and_publish(audio_reader.as_mut(), header, &publisher)
+                    .await;
+            if let Err(e) = result {
+                tracing::error!("Broadcast stopped: {}", e);
+            }
+            // Set first so that clients joining from now on are refused
+            // rather than left waiting for the end marker.
+            publisher_ended.store(true, Ordering::SeqCst);
+            let _ = publisher.send(Bytes::new());
+        });
+
+        Ok(Self {
+            sender,
+            header,
+            ended,
+            _capture: None,
+        })
+    }
+
+    // Broadcasts what the microphone records, raw whatever the codec, for
+    // as long as the server runs.
+    fn live() -> Result<Self> {
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        let capture = crate::audio::cpal::capture_live(sender.clone())?;
+        Ok(Self {
+            sender,
+            header: capture.header(),
+            ended: Arc::new(AtomicBool::new(false)),
+            _capture: Some(capture),
+        })
+    }
+
+    fn subscribe(&self) -> Result<broadcast::Receiver<Bytes>> {
+        let receiver = self.sender.subscribe();
+        if self.ended.load(Ordering::SeqCst) {
+            return Err(ProtocolError::rejected(
+                ProtocolErrorCode::UnknownFile,
+                "The broadcast has ended".to_string(),
+            )
+            .into());
+        }
+        Ok(receiver)
+    }
+}
+
+// Fluent alternative to `Server::new` followed by setters. Unlike `new`,
+// `build` checks that the served file can be read.
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+    address: String,
+    port: u16,
+    file_path: Option<String>,
+    // Listened on instead of address:port when there are any
+    binds: Vec<String>,
+    unix_sockets: Vec<String>,
+    config: ServerConfig,
+    chunk_size: usize,
+    rate_limit_kbps: Option<u64>,
+    codec_config: LengthDelimitedCodecConfig,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            address: "localhost".to_string(),
+            port: 8080,
+            file_path: None,
+            binds: vec![],
+            unix_sockets: vec![],
+            config: ServerConfig::default(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            rate_limit_kbps: None,
+            codec_config: Default::default(),
+        }
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn address(mut self, address: &str) -> Self {
+        self.address = address.to_string();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn file_path(mut self, file_path: &str) -> Self {
+        self.file_path = Some(file_path.to_string());
+        self
+    }
+
+    /// Listens on `addr` ("host:port", e.g. "0.0.0.0:8080" for every IPv4
+    /// interface) instead of `address` and `port`. Call it again, or
+    /// `bind_unix`, to listen on several addresses at once.
+    pub fn bind(mut self, addr: &str) -> Self {
+        self.binds.push(addr.to_string());
+        self
+    }
+
+    /// Listens on a Unix domain socket at `socket_path` as well, see
+    /// `Server::new_unix`.
+    pub fn bind_unix(mut self, socket_path: &str) -> Self {
+        self.unix_sockets.push(socket_path.to_string());
+        self
+    }
+
+    /// See `Server::set_config`.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// See `Server::set_max_clients`.
+    pub fn max_clients(mut self, clients: usize) -> Self {
+        self.config.max_clients = Some(clients);
+        self
+    }
+
+    /// See `Server::set_chunk_size`.
+    pub fn chunk_size(mut self, bytes: usize) -> Self {
+        self.chunk_size = bytes;
+        self
+    }
+
+    /// See `Server::set_rate_limit_kbps`.
+    pub fn rate_limit_kbps(mut self, kbps: u64) -> Self {
+        self.rate_limit_kbps = Some(kbps);
+        self
+    }
+
+    /// See `Server::set_codec_config`.
+    pub fn codec_config(mut self, config: LengthDelimitedCodecConfig) -> Self {
+        self.codec_config = config;
+        self
+    }
+
+    pub async fn build(self) -> Result<Server> {
+        let file_path = self
+            .file_path
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("No file to serve"))?;
+        std::fs::File::open(file_path)
+            .and_then(|file| file.metadata())
+            .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", file_path, e))
+            .and_then(|metadata| {
+                if metadata.is_file() {
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!("{} is not a file", file_path))
+                }
+            })?;
+        self.listen().await
+    }
+
+    async fn listen(self) -> Result<Server> {
+        let mut binds = self.binds.clone();
+        if binds.is_empty() && self.unix_sockets.is_empty() {
+            binds.push(transport::host_port(&self.address, self.port));
+        }
+        let mut listeners = vec![];
+        for addr in &binds {
+            let listener = Listener::bind_tcp_addr(addr)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to bind to {}: {}", addr, e))?;
+            tracing::info!("Server listening on {}", addr);
+            listeners.push(listener);
+        }
+        for socket_path in &self.unix_sockets {
+            let listener = Listener::bind_unix(socket_path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to bind to {}: {}", socket_path, e))?;
+            tracing::info!("Server listening on {}", socket_path);
+            listeners.push(listener);
+        }
+
+        let mut server = Server::with_listeners(listeners, self.file_path.unwrap_or_default());
+        server.set_config(self.config);
+        server.set_codec_config(self.codec_config)?;
+        server.set_chunk_size(self.chunk_size)?;
+        if let Some(kbps) = self.rate_limit_kbps {
+            server.set_rate_limit_kbps(kbps);
+        }
+        Ok(server)
+    }
+}
+
 pub struct Server {
     send_file_format: FileFormat,
     file_path: String,
-    listener: TcpListener,
+    listeners: Vec<Listener>,
+    accepting: AtomicBool,
+    encryption_key: Option<EncryptionKey>,
+    auth_token: Option<String>,
+    tls: Option<tokio_rustls::TlsAcceptor>,
+    // One permit per client connection being served
+    client_slots: Option<Arc<Semaphore>>,
+    // One permit per refused connection still waiting for its error
+    rejection_slots: Arc<Semaphore>,
+    reject_timeout: Duration,
+    chunk_size: usize,
+    rate_limit_kbps: Option<u64>,
+    codec_config: LengthDelimitedCodecConfig,
+    eof_action: EofAction,
+    normalize: bool,
+    max_file_size: Option<u64>,
+    max_file_duration: Option<Duration>,
+    max_buffered_bytes: usize,
+    keepalive: Option<Keepalive>,
+    buffer_policy: BufferPolicy,
+    frame_timestamps: bool,
+    codec: AudioCodec,
+    radio: bool,
+    broadcast: bool,
+    live: bool,
+    broadcaster: OnceLock<BroadcastServer>,
+    playlist: Option<Playlist>,
+    track_gap: Duration,
+    file_authorizer: Option<FileAuthorizer>,
+    on_connect: Option<ConnectHook>,
+    on_disconnect: Option<DisconnectHook>,
+    pre_roll: Option<String>,
+    post_roll: Option<String>,
+    connection_buffers: Mutex<HashMap<PeerAddr, BufferAccount>>,
+    registry: ConnectionRegistry,
+    shutdown: watch::Sender<bool>,
+    // Client handlers, waited for on shutdown
+    sessions: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl Server {
-    pub async fn new(address: String, port: u16, file_path: String) -> Self {
-        let listener = TcpListener::bind(format!("{}:{}", address, port))
+    /// Serves `file_path`, or the directory at `file_path` in radio mode,
+    /// without checking it until a client asks for it. The file format is
+    /// guessed from its extension.
+    pub async fn new(address: String, port: u16, file_path: String) -> Result<Self> {
+        ServerBuilder::new()
+            .address(&address)
+            .port(port)
+            .file_path(&file_path)
+            .listen()
             .await
-            .expect("Failed to bind to address");
+    }
+
+    /// Serves clients over a Unix domain socket at `socket_path` instead of
+    /// TCP. A stale socket file left by a previous server is replaced.
+    pub async fn new_unix(socket_path: String, file_path: String) -> Result<Self> {
+        let listener = Listener::bind_unix(&socket_path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind to {}: {}", socket_path, e))?;
+
+        tracing::info!("Server listening on {}", socket_path);
 
-        println!("Server listening on {}:{}", address, port);
+        Ok(Self::with_listener(listener, file_path))
+    }
+
+    /// Serves clients over in-memory connections opened with the returned
+    /// connector, e.g. with `ClientInterface::connect_loopback`, without
+    /// any socket.
+    pub fn new_loopback(file_path: String) -> (Self, LoopbackConnector) {
+        let (listener, connector) = Listener::loopback();
+        (Self::with_listener(listener, file_path), connector)
+    }
+
+    fn with_listener(listener: Listener, file_path: String) -> Self {
+        Self::with_listeners(vec![listener], file_path)
+    }
 
+    // The format is guessed from the extension of `file_path`, WAV when it
+    // has none that is known.
+    fn with_listeners(listeners: Vec<Listener>, file_path: String) -> Self {
         Self {
-            send_file_format: FileFormat::Wav,
+            send_file_format: FileFormat::from_path(&file_path).unwrap_or(FileFormat::Wav),
             file_path,
-            listener,
+            listeners,
+            accepting: AtomicBool::new(true),
+            encryption_key: None,
+            auth_token: None,
+            tls: None,
+            client_slots: None,
+            rejection_slots: Arc::new(Semaphore::new(DEFAULT_MAX_PENDING_REJECTIONS)),
+            reject_timeout: DEFAULT_REJECT_TIMEOUT,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            rate_limit_kbps: None,
+            codec_config: Default::default(),
+            eof_action: EofAction::Stop,
+            normalize: false,
+            max_file_size: None,
+            max_file_duration: None,
+            max_buffered_bytes: DEFAULT_MAX_BUFFERED_BYTES,
+            keepalive: None,
+            buffer_policy: BufferPolicy::Throttle,
+            frame_timestamps: false,
+            codec: AudioCodec::Raw,
+            radio: false,
+            broadcast: false,
+            live: false,
+            broadcaster: OnceLock::new(),
+            playlist: None,
+            track_gap: DEFAULT_TRACK_GAP,
+            file_authorizer: None,
+            on_connect: None,
+            on_disconnect: None,
+            pre_roll: None,
+            post_roll: None,
+            connection_buffers: Mutex::new(HashMap::new()),
+            registry: ConnectionRegistry::new(),
+            shutdown: watch::Sender::new(false),
+            sessions: Default::default(),
         }
     }
-    #[allow(unused)]
+    /// Streams `paths` one after the other to every client, in the format
+    /// set with `set_file_format`, instead of the file given to `new`.
+    pub fn with_playlist(mut self, paths: Vec<String>) -> Self {
+        self.playlist = Some(Playlist::new(paths));
+        self
+    }
+
+    /// Silence inserted between the tracks of a playlist.
+    pub fn set_track_gap(&mut self, gap: Duration) -> &mut Self {
+        self.track_gap = gap;
+        self
+    }
+
+    /// Overrides the format guessed from the extension of the served file.
     pub fn set_file_format(&mut self, format: FileFormat) -> &mut Self {
         self.send_file_format = format;
         self
     }
 
+    /// Encrypts every audio frame with the given pre-shared key. Clients must
+    /// be configured with the same key to decode the stream.
+    pub fn set_encryption_key(&mut self, key: EncryptionKey) -> &mut Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Only accepts clients identifying themselves with `token` in their
+    /// HELLO. Others get an AuthFailed error and are disconnected.
+    pub fn set_auth_token(&mut self, token: String) -> &mut Self {
+        self.auth_token = Some(token);
+        self
+    }
+
+    /// Runs every connection over TLS with the certificate and key of
+    /// `config`. Clients must connect with TLS too.
+    pub fn set_tls(&mut self, config: &TlsConfig) -> Result<&mut Self> {
+        self.tls = Some(tls::server_acceptor(config)?);
+        Ok(self)
+    }
+
+    /// Serves at most `clients` connections at once. Connections beyond it
+    /// get a ServerFull error and are closed right away.
+    pub fn set_max_clients(&mut self, clients: usize) -> &mut Self {
+        self.client_slots = Some(Arc::new(Semaphore::new(clients)));
+        self
+    }
+
+    /// Applies the connection limits of `config`, replacing any set before.
+    pub fn set_config(&mut self, config: ServerConfig) -> &mut Self {
+        self.client_slots = config
+            .max_clients
+            .map(|clients| Arc::new(Semaphore::new(clients)));
+        self.rejection_slots = Arc::new(Semaphore::new(config.max_pending_rejections));
+        self.reject_timeout = config.reject_timeout;
+        self
+    }
+
+    /// Reads sources `bytes` at a time, which bounds the size of raw audio
+    /// frames. Sources with samples of several bytes read a little less.
+    /// Fails when the frames would not fit in the frame limit, see
+    /// `LengthDelimitedCodecConfig::max_chunk_size`.
+    pub fn set_chunk_size(&mut self, bytes: usize) -> Result<&mut Self> {
+        Self::check_chunk_size(bytes, &self.codec_config)?;
+        self.chunk_size = bytes;
+        Ok(self)
+    }
+
+    /// Limits the frames exchanged with clients, 64 KiB by default. Fails
+    /// when the audio frames of the current chunk size would not fit, so
+    /// raise the limit before the chunk size and lower it after.
+    pub fn set_codec_config(&mut self, config: LengthDelimitedCodecConfig) -> Result<&mut Self> {
+        Self::check_chunk_size(self.chunk_size, &config)?;
+        self.codec_config = config;
+        Ok(self)
+    }
+
+    fn check_chunk_size(bytes: usize, config: &LengthDelimitedCodecConfig) -> Result<()> {
+        if bytes > config.max_chunk_size() {
+            return Err(anyhow::anyhow!(
+                "Chunk size {} does not fit in frames of {} bytes (at most {})",
+                bytes,
+                config.max_frame_length,
+                config.max_chunk_size()
+            ));
+        }
+        Ok(())
+    }
+
+    /// What to do once a requested file was sent whole: end the stream, the
+    /// default, or start it over, e.g. for hold music.
+    pub fn set_eof_action(&mut self, action: EofAction) -> &mut Self {
+        self.eof_action = action;
+        self
+    }
+
+    /// Scales the served file, and files clients request, so that their
+    /// loudest sample reaches 90% of full scale, e.g. for quiet recordings.
+    /// Each file is read twice, once to find its peak before it is
+    /// streamed. Playlists, radio and broadcasts are streamed as they are.
+    pub fn set_normalize(&mut self, enabled: bool) -> &mut Self {
+        self.normalize = enabled;
+        self
+    }
+
+    /// Caps the audio sent to each client to `kbps` kilobits per second.
+    pub fn set_rate_limit_kbps(&mut self, kbps: u64) -> &mut Self {
+        self.rate_limit_kbps = Some(kbps);
+        self
+    }
+
+    /// Files larger than `bytes` are refused instead of being streamed.
+    pub fn set_max_file_size(&mut self, bytes: u64) -> &mut Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Files longer than `duration` are refused instead of being streamed.
+    pub fn set_max_file_duration(&mut self, duration: Duration) -> &mut Self {
+        self.max_file_duration = Some(duration);
+        self
+    }
+
+    /// Caps the audio each connection may have queued but not yet written to
+    /// its socket, and what to do when a slow client reaches the cap.
+    pub fn set_max_buffered_bytes(&mut self, bytes: usize, policy: BufferPolicy) -> &mut Self {
+        self.max_buffered_bytes = bytes;
+        self.buffer_policy = policy;
+        self
+    }
+
+    /// Sends a PING every `interval` for as long as a client is connected,
+    /// between streams too, and closes the connection of clients that do
+    /// not answer within `timeout`.
+    pub fn set_keepalive(&mut self, interval: Duration, timeout: Duration) -> &mut Self {
+        self.keepalive = Some(Keepalive { interval, timeout });
+        self
+    }
+
+    /// Stamps every audio frame with its send time so clients can estimate
+    /// the stream latency. Clients speaking protocol version 2 or later get
+    /// timestamped frames either way.
+    pub fn set_frame_timestamps(&mut self, enabled: bool) -> &mut Self {
+        self.frame_timestamps = enabled;
+        self
+    }
+
+    /// Codes audio frames with `codec`. Sources whose format the codec does
+    /// not support (Opus: 8 to 48 kHz, mono or stereo) are still sent raw.
+    pub fn set_codec(&mut self, codec: AudioCodec) -> &mut Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Treats the path given to `new` as a directory whose WAV files are
+    /// streamed in random order, forever, until the client stops playing.
+    pub fn set_radio_mode(&mut self, enabled: bool) -> &mut Self {
+        self.radio = enabled;
+        self
+    }
+
+    /// Reads the served file once, in real time, from when `run` is called,
+    /// and streams it live to every client instead of from the start for
+    /// each of them. Every stream stops at the end of the file, after which
+    /// clients can no longer start playing.
+    pub fn set_broadcast_mode(&mut self, enabled: bool) -> &mut Self {
+        self.broadcast = enabled;
+        self
+    }
+
+    /// Streams the default input device live to every client instead of a
+    /// file, from when `run` is called. Audio recorded while no client
+    /// listens is lost.
+    pub fn set_live_mode(&mut self, enabled: bool) -> &mut Self {
+        self.live = enabled;
+        self
+    }
+
+    /// WAV file (e.g. a jingle) streamed before every file, as part of the
+    /// same stream.
+    pub fn set_pre_roll(&mut self, file_path: String) -> &mut Self {
+        self.pre_roll = Some(file_path);
+        self
+    }
+
+    /// WAV file streamed after every file, as part of the same stream.
+    pub fn set_post_roll(&mut self, file_path: String) -> &mut Self {
+        self.post_roll = Some(file_path);
+        self
+    }
+
+    /// Decides which files each client may request, from its identity and
+    /// the requested path relative to the media directory. Without an
+    /// authorizer every file of the media directory can be requested.
+    pub fn set_file_authorizer(
+        &mut self,
+        authorizer: impl Fn(&ClientIdentity, &str) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.file_authorizer = Some(Box::new(authorizer));
+        self
+    }
+
+    /// Called with the address of every client as its connection starts to
+    /// be served. Hooks run on the server tasks: blocking work belongs in
+    /// `tokio::task::spawn_blocking`.
+    pub fn on_client_connect(
+        &mut self,
+        hook: impl Fn(&PeerAddr) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_connect = Some(Box::new(hook));
+        self
+    }
+
+    /// Called when the connection of a client ends, with the error that
+    /// ended it if any.
+    pub fn on_client_disconnect(
+        &mut self,
+        hook: impl Fn(&PeerAddr, Option<&anyhow::Error>) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_disconnect = Some(Box::new(hook));
+        self
+    }
+
+    /// Addresses of the TCP listeners, with the actual port of those bound
+    /// to port 0.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(Listener::local_addr)
+            .collect()
+    }
+
+    /// Bytes currently queued for each connected client.
+    pub fn buffered_bytes(&self) -> HashMap<PeerAddr, usize> {
+        self.connection_buffers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(addr, account)| (addr.clone(), account.used()))
+            .collect()
+    }
+
+    /// Clients connected past their handshake, with what was streamed to
+    /// each so far.
+    pub fn sessions(&self) -> Vec<ClientSession> {
+        self.registry.sessions()
+    }
+
+    pub fn connected_count(&self) -> usize {
+        self.registry.len()
+    }
+
+    /// Shared view of the connected clients, usable while the server runs.
+    pub fn registry(&self) -> ConnectionRegistry {
+        self.registry.clone()
+    }
+
+    pub fn validate_file(&self, file_path: &str) -> Result<()> {
+        if let Some(max_size) = self.max_file_size {
+            let size = std::fs::metadata(file_path)
+                .map_err(|e| {
+                    ProtocolError::rejected(
+                        ProtocolErrorCode::UnknownFile,
+                        format!("Cannot read {}: {}", file_path, e),
+                    )
+                })?
+                .len();
+            if size > max_size {
+                return Err(ProtocolError::rejected(
+                    ProtocolErrorCode::LimitExceeded,
+                    format!(
+                        "File {} is {} bytes, above the {} bytes limit",
+                        file_path, size, max_size
+                    ),
+                )
+                .into());
+            }
+        }
+
+        if let Some(max_duration) = self.max_file_duration {
+            let duration =
+                network::file::file_duration(self.file_format(), file_path).map_err(|e| {
+                    ProtocolError::rejected(
+                        ProtocolErrorCode::UnsupportedFormat,
+                        format!("Cannot read {}: {}", file_path, e),
+                    )
+                })?;
+            if duration > max_duration {
+                return Err(ProtocolError::rejected(
+                    ProtocolErrorCode::LimitExceeded,
+                    format!(
+                        "File {} lasts {:.2}s, above the {:.2}s limit",
+                        file_path,
+                        duration.as_secs_f64(),
+                        max_duration.as_secs_f64()
+                    ),
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn protocol_info(&self) -> ProtocolInfo {
+        ProtocolInfo::new()
+            .with_encrypted_audio(self.encryption_key.is_some())
+            .with_timestamped_frames(self.frame_timestamps)
+    }
+
+    /// Stops handing new connections to client handlers. Connections accepted
+    /// while paused are closed immediately; active streams are left untouched.
+    pub fn pause_accepting(&self) {
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    pub fn resume_accepting(&self) {
+        self.accepting.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::SeqCst)
+    }
+
+    /// Handle making `run` return gracefully, e.g. from a signal handler.
+    pub fn shutdown_handle(&self) -> ShutdownSender {
+        ShutdownSender {
+            sender: self.shutdown.clone(),
+        }
+    }
+
+    // Requested files are looked up in the directory served by the server, or
+    // next to the file it serves.
+    fn media_directory(&self) -> std::path::PathBuf {
+        let path = std::path::Path::new(&self.file_path);
+        if path.is_dir() {
+            path.to_path_buf()
+        } else {
+            path.parent().map(|p| p.to_path_buf()).unwrap_or_default()
+        }
+    }
+
+    fn resolve_requested_file(&self, client: &ClientIdentity, file: &str) -> Result<String> {
+        let relative = std::path::Path::new(file);
+        if !relative
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            return Err(ProtocolError::rejected(
+                ProtocolErrorCode::UnknownFile,
+                format!("Invalid requested file {}", file),
+            )
+            .into());
+        }
+        if let Some(authorizer) = &self.file_authorizer
+            && !authorizer(client, file)
+        {
+            return Err(ProtocolError::rejected(
+                ProtocolErrorCode::AuthFailed,
+                format!("Client {} is not allowed to access {}", client.addr, file),
+            )
+            .into());
+        }
+        Ok(self
+            .media_directory()
+            .join(relative)
+            .to_string_lossy()
+            .into_owned())
+    }
+
     fn file_format(&self) -> FileFormat {
         self.send_file_format.clone()
     }
 
-    async fn send_bye_message(&self, socket: &mut TcpStream) -> Result<()> {
-        let bye_msg = crate::protocol::make_bye_message();
-        socket
-            .write_all(&bye_msg)
-            .await
-            .map_err(|e| anyhow::anyhow!("Error sending BYE message: {}", e))
+    fn send_options(
+        &self,
+        client: &ClientIdentity,
+        buffer: &BufferAccount,
+        keepalive: Option<&KeepaliveTask>,
+    ) -> SendOptions {
+        SendOptions {
+            cipher: self.encryption_key.as_ref().map(FrameCipher::new),
+            buffer: buffer.clone(),
+            sent: self.registry.sent_counters(&client.addr),
+            timestamps: self.frame_timestamps,
+            version: client.version,
+            next_sequence: 0,
+            resend: Default::default(),
+            pre_roll: self.pre_roll.clone(),
+            post_roll: self.post_roll.clone(),
+            keepalive: self.keepalive,
+            codec: self.codec,
+            chunk_size: self.chunk_size,
+            rate_limit_kbps: self.rate_limit_kbps,
+            eof_action: self.eof_action,
+            normalize: self.normalize,
+            pongs: keepalive.map(KeepaliveTask::pongs).unwrap_or_default(),
+            pings: keepalive.map(KeepaliveTask::pings),
+            control: StreamControl::default().with_shutdown(self.shutdown.subscribe()),
+        }
+    }
+
+    // Requests the server refuses are answered with an ERROR message before
+    // the connection is closed. Other failures, e.g. during a stream, close
+    // it right away.
+    async fn process_client_request(
+        &self,
+        socket: &mut FramedTransport,
+        client: &ClientIdentity,
+        buffer: &BufferAccount,
+        keepalive: Option<&KeepaliveTask>,
+    ) -> Result<()> {
+        let result = self.serve_requests(socket, client, buffer, keepalive).await;
+        Self::report_rejection(socket, result).await
+    }
+
+    async fn report_rejection<T>(socket: &mut FramedTransport, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result
+            && let Some(ProtocolError::Rejected { code, reason }) = e.downcast_ref()
+        {
+            network::common::send_error_message(socket, *code, reason).await?;
+        }
+        result
     }
 
-    async fn process_client_request(&self, socket: &mut TcpStream) -> Result<()> {
+    async fn serve_requests(
+        &self,
+        socket: &mut FramedTransport,
+        client: &ClientIdentity,
+        buffer: &BufferAccount,
+        keepalive: Option<&KeepaliveTask>,
+    ) -> Result<()> {
+        let mut shutdown = self.shutdown.subscribe();
+        let mut pings = keepalive.map(KeepaliveTask::pings);
+        // Once a stream is over the client ends the connection with BYE,
+        // which is waited for even when shutting down.
+        let mut streamed = false;
         loop {
-            let message_type = crate::network::common::expect_message_type(socket).await?;
-            match message_type {
-                MessageType::Bye => return self.send_bye_message(socket).await,
-                MessageType::StartPlaying => {
+            let message = tokio::select! {
+                message = network::common::expect_message(socket) => message?,
+                ping = keepalive::next_ping(&mut pings) => {
+                    ping?;
+                    network::common::send_message(socket, &Message::Ping).await?;
+                    continue;
+                }
+                _ = control::shutdown_requested(&mut shutdown), if !streamed => {
+                    return network::common::send_bye_message(socket).await;
+                }
+            };
+            match message {
+                Message::RequestFile(requested) => {
+                    streamed = true;
+                    let file = self.resolve_requested_file(client, &requested)?;
+                    self.validate_file(&file)?;
+                    self.registry.set_current_file(&client.addr, &file);
+                    let options = self.send_options(client, buffer, keepalive);
+                    network::file::send_file(self.file_format(), socket, &file, options).await?;
+                }
+                Message::Bye => return network::common::send_bye_message(socket).await,
+                Message::Pong => {
+                    if let Some(keepalive) = keepalive {
+                        keepalive.pongs().record_pong();
+                    }
+                }
+                // Sent while the last stream was ending, there is nothing
+                // left to apply them to
+                Message::Seek(_) | Message::VolumeControl(_) | Message::Pause | Message::Resume => {
+                }
+                Message::StartPlaying => {
+                    streamed = true;
+                    // The directory in radio mode, not the file playing
+                    self.registry
+                        .set_current_file(&client.addr, &self.file_path);
+                    let options = self.send_options(client, buffer, keepalive);
+                    if let Some(broadcaster) = self.broadcaster.get() {
+                        let receiver = broadcaster.subscribe()?;
+                        network::broadcast::send_broadcast(
+                            &broadcaster.header,
+                            receiver,
+                            socket,
+                            options,
+                        )
+                        .await?;
+                        continue;
+                    }
+                    if let Some(playlist) = &self.playlist {
+                        network::playlist::send_playlist(
+                            playlist.clone(),
+                            self.file_format(),
+                            self.track_gap,
+                            socket,
+                            options,
+                            |path| self.validate_file(path),
+                        )
+                        .await?;
+                        continue;
+                    }
+                    if self.radio {
+                        network::radio::send_radio(&self.file_path, socket, options, |path| {
+                            self.validate_file(path)
+                        })
+                        .await?;
+                        continue;
+                    }
                     let file = self.file_path.clone();
-                    network::file::send_file(self.file_format(), socket, &file).await?;
+                    self.validate_file(&file)?;
+                    network::file::send_file(self.file_format(), socket, &file, options).await?;
                 }
-                _ => {
-                    return Err(anyhow::anyhow!(
-                        "Unexpected message type from client: {:?}",
-                        message_type
-                    ));
+                message => {
+                    return Err(ProtocolError::rejected(
+                        ProtocolErrorCode::UnexpectedMessage,
+                        format!(
+                            "Unexpected message type from client: {:?}",
+                            message.message_type()
+                        ),
+                    )
+                    .into());
                 }
             }
         }
     }
 
-    async fn client_handler(&self, mut socket: TcpStream) -> Result<()> {
-        // First check hello
-        network::common::handshake_from_server(&mut socket).await?;
+    async fn secure(&self, socket: Box<dyn Transport>) -> Result<Box<dyn Transport>> {
+        match &self.tls {
+            Some(acceptor) => tls::accept(acceptor, socket).await,
+            None => Ok(socket),
+        }
+    }
 
-        self.process_client_request(&mut socket).await?;
+    // The HELLO is read first so that closing the connection does not reset
+    // it before the client gets the error. Clients that take longer than the
+    // reject timeout, TLS handshake included, are dropped without one.
+    async fn reject_server_full(&self, socket: Box<dyn Transport>) -> Result<()> {
+        tokio::time::timeout(self.reject_timeout, self.send_server_full(socket))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out refusing a connection"))?
+    }
 
+    async fn send_server_full(&self, socket: Box<dyn Transport>) -> Result<()> {
+        let mut socket = self.framed(socket).await?;
+        network::common::expect_hello(&mut socket).await?;
+        network::common::send_error_message(
+            &mut socket,
+            ProtocolErrorCode::ServerFull,
+            "Too many clients connected",
+        )
+        .await?;
+        SinkExt::<Bytes>::close(&mut socket).await?;
         Ok(())
     }
-    pub async fn run(self: Arc<Self>) {
+
+    async fn client_handler(&self, socket: Box<dyn Transport>, addr: PeerAddr) -> Result<()> {
+        if let Some(on_connect) = &self.on_connect {
+            on_connect(&addr);
+        }
+        let result = self.serve_client(socket, &addr).await;
+        if let Some(on_disconnect) = &self.on_disconnect {
+            on_disconnect(&addr, result.as_ref().err());
+        }
+        result
+    }
+
+    async fn framed(&self, socket: Box<dyn Transport>) -> Result<FramedTransport> {
+        let mut socket = network::common::new_framed(self.secure(socket).await?);
+        self.codec_config.apply(&mut socket);
+        Ok(socket)
+    }
+
+    async fn serve_client(&self, socket: Box<dyn Transport>, addr: &PeerAddr) -> Result<()> {
+        let mut socket = self.framed(socket).await?;
+        // First check hello
+        let handshake = network::common::handshake_from_server(
+            &mut socket,
+            &self.protocol_info(),
+            self.auth_token.as_deref(),
+        )
+        .await;
+        let (token, version) = Self::report_rejection(&mut socket, handshake).await?;
+        let client = ClientIdentity {
+            addr: addr.clone(),
+            token,
+            version,
+        };
+
+        let buffer = BufferAccount::new(self.max_buffered_bytes, self.buffer_policy);
+        self.connection_buffers
+            .lock()
+            .unwrap()
+            .insert(addr.clone(), buffer.clone());
+        self.registry.register(addr);
+
+        // Runs until the client is gone, between streams too.
+        let keepalive = self.keepalive.map(KeepaliveTask::spawn);
+        let result = self
+            .process_client_request(&mut socket, &client, &buffer, keepalive.as_ref())
+            .await;
+        if result.is_err() {
+            self.registry.record_stream_error();
+        }
+
+        self.connection_buffers.lock().unwrap().remove(addr);
+        self.registry.unregister(addr);
+        result
+    }
+    fn track_session(&self, session: JoinHandle<()>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|session| !session.is_finished());
+        sessions.push(session);
+    }
+
+    /// Serves clients until `shutdown_handle` is used.
+    pub async fn run(self: Arc<Self>) -> Result<()> {
+        let shutdown = self.shutdown.subscribe();
+        self.run_until_shutdown(shutdown).await
+    }
+
+    /// Serves clients until `shutdown` is set. New connections are then no
+    /// longer accepted, running streams are stopped with STOP_PLAY and idle
+    /// clients get BYE. Returns once every client is gone, or after a grace
+    /// period.
+    pub async fn run_until_shutdown(
+        self: Arc<Self>,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<()> {
+        if self.live && self.broadcaster.get().is_none() {
+            let _ = self.broadcaster.set(BroadcastServer::live()?);
+        }
+        if self.broadcast && self.broadcaster.get().is_none() {
+            self.validate_file(&self.file_path)?;
+            let broadcaster =
+                BroadcastServer::start(self.file_format(), &self.file_path, self.codec)?;
+            let _ = self.broadcaster.set(broadcaster);
+        }
         loop {
-            let (socket, addr) = self
-                .listener
-                .accept()
-                .await
-                .expect("Failed to accept connection");
-            println!("New connection from {}", addr);
+            let (socket, addr) = tokio::select! {
+                accepted = transport::accept_any(&self.listeners) => {
+                    accepted.map_err(|e| anyhow::anyhow!("Failed to accept connection: {}", e))?
+                }
+                _ = control::shutdown_requested(&mut shutdown) => break,
+            };
+            if !self.is_accepting() {
+                tracing::info!("Rejecting connection from {} (accepting paused)", addr);
+                drop(socket);
+                continue;
+            }
+            let permit = match &self.client_slots {
+                Some(slots) => match Arc::clone(slots).try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        tracing::warn!("Rejecting connection from {} (server full)", addr);
+                        let Ok(rejection) = Arc::clone(&self.rejection_slots).try_acquire_owned()
+                        else {
+                            // Too many refusals in flight already, e.g. a flood
+                            drop(socket);
+                            continue;
+                        };
+                        let server = Arc::clone(&self);
+                        let session =
+                            tokio::spawn(trace::scope(format!("server/{}", addr), async move {
+                                if let Err(e) = server.reject_server_full(socket).await {
+                                    tracing::warn!("Client connection error: {}", e);
+                                }
+                                drop(rejection);
+                            }));
+                        self.track_session(session);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            tracing::info!("New connection from {}", addr);
 
             let server = Arc::clone(&self);
-            tokio::spawn(async move {
-                if let Err(e) = server.client_handler(socket).await {
-                    eprintln!("Client connection error: {}", e);
+            let span = tracing::info_span!("client", addr = %addr);
+            let session = tokio::spawn(trace::scope(
+                format!("server/{}", addr),
+                async move {
+                    if let Err(e) = server.client_handler(socket, addr).await {
+                        tracing::warn!("Client connection error: {}", e);
+                    }
+                    drop(permit);
                 }
-            });
+                .instrument(span),
+            ));
+            self.track_session(session);
         }
+
+        self.shutdown.send_replace(true);
+        let sessions = std::mem::take(&mut *self.sessions.lock().unwrap());
+        tracing::info!("Shutting down, {} client(s) connected", sessions.len());
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_GRACE_PERIOD;
+        for mut session in sessions {
+            if tokio::time::timeout_at(deadline, &mut session)
+                .await
+                .is_err()
+            {
+                session.abort();
+            }
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,160 @@
+use crate::audio::file::FileFormat;
+use crate::network;
+use crate::network::file::DEFAULT_CHUNK_SIZE;
+use crate::network::latency;
+use crate::network::rate::RateLimiter;
+use crate::network::udp::{self, MAX_DATAGRAM_LEN};
+use crate::protocol::{self, AudioCodec, AudioFrame, Message, ProtocolErrorCode};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+
+// Streams a file to clients over UDP, see `network::udp`. Lost datagrams are
+// not sent again, so a client behind a lossy link hears a gap instead of
+// waiting for the audio. Without flow control, frames are paced at the
+// bit rate of the file unless a rate limit is set.
+pub struct UdpServer {
+    socket: Arc<UdpSocket>,
+    send_file_format: FileFormat,
+    file_path: String,
+    chunk_size: usize,
+    rate_limit_kbps: Option<u64>,
+    // Set to stop the stream of a client, e.g. on its STOP_PLAY
+    streams: Mutex<HashMap<SocketAddr, Arc<AtomicBool>>>,
+}
+
+impl UdpServer {
+    /// Serves `file_path` on `address` and `port`, the format guessed from
+    /// its extension.
+    pub async fn new(address: String, port: u16, file_path: String) -> Result<Self> {
+        let socket = udp::bind(&address, port)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind to {}:{}: {}", address, port, e))?;
+        tracing::info!("UDP server listening on {}", socket.local_addr()?);
+        Ok(Self {
+            socket: Arc::new(socket),
+            send_file_format: FileFormat::from_path(&file_path).unwrap_or(FileFormat::Wav),
+            file_path,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            rate_limit_kbps: None,
+            streams: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Overrides the format guessed from the extension of the served file.
+    pub fn set_file_format(&mut self, format: FileFormat) -> &mut Self {
+        self.send_file_format = format;
+        self
+    }
+
+    /// Reads the file `bytes` at a time, one datagram per chunk. Chunks are
+    /// capped to fit in a datagram.
+    pub fn set_chunk_size(&mut self, bytes: usize) -> &mut Self {
+        self.chunk_size = bytes.min(MAX_DATAGRAM_LEN - 1024);
+        self
+    }
+
+    /// Paces the audio sent to each client to `kbps` kilobits per second
+    /// instead of the bit rate of the file.
+    pub fn set_rate_limit_kbps(&mut self, kbps: u64) -> &mut Self {
+        self.rate_limit_kbps = Some(kbps);
+        self
+    }
+
+    /// Address the socket is bound to, with the actual port when bound to
+    /// port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Answers datagrams until an error occurs on the socket. Each client
+    /// starting to play gets its stream from a task of its own.
+    pub async fn run(self: Arc<Self>) -> Result<()> {
+        let mut buffer = vec![0u8; MAX_DATAGRAM_LEN];
+        loop {
+            let (message, peer) = match udp::recv_from(&self.socket, &mut buffer).await {
+                Ok(received) => received,
+                // A datagram that cannot be decoded only concerns its sender
+                Err(e) if e.downcast_ref::<protocol::ProtocolError>().is_some() => {
+                    tracing::warn!("Ignoring datagram: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            match message {
+                Message::StartPlaying => self.start_stream(peer),
+                Message::StopPlaying => {
+                    if let Some(stopped) = self.streams.lock().unwrap().get(&peer) {
+                        stopped.store(true, Ordering::SeqCst);
+                    }
+                }
+                message => tracing::warn!("Unexpected {:?} from {}", message.message_type(), peer),
+            }
+        }
+    }
+
+    // Clients repeat START_PLAY until the header arrives, so it may come
+    // again for a stream already running.
+    fn start_stream(self: &Arc<Self>, peer: SocketAddr) {
+        let stopped = Arc::new(AtomicBool::new(false));
+        {
+            let mut streams = self.streams.lock().unwrap();
+            if streams.contains_key(&peer) {
+                return;
+            }
+            streams.insert(peer, Arc::clone(&stopped));
+        }
+        tracing::info!("Streaming to {} over UDP", peer);
+        let server = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(e) = server.stream_to(peer, &stopped).await {
+                tracing::error!("UDP stream to {} failed: {}", peer, e);
+            }
+            server.streams.lock().unwrap().remove(&peer);
+        });
+    }
+
+    async fn stream_to(&self, peer: SocketAddr, stopped: &AtomicBool) -> Result<()> {
+        let mut audio_reader =
+            match network::file::open_audio_file(self.send_file_format.clone(), &self.file_path) {
+                Ok(audio_reader) => audio_reader,
+                Err(e) => {
+                    let error = Message::error(ProtocolErrorCode::UnknownFile, &e.to_string());
+                    udp::send_to(&self.socket, &error, peer).await?;
+                    return Err(e);
+                }
+            };
+        let header = network::file::stream_header(audio_reader.as_mut(), AudioCodec::Raw);
+        let mut announced = header;
+        announced.set_total_samples(audio_reader.total_samples());
+        udp::send_to(&self.socket, &Message::AudioHeader(announced), peer).await?;
+
+        let kbps = self
+            .rate_limit_kbps
+            .unwrap_or((header.bit_rate() as u64).div_ceil(1000));
+        let mut rate_limiter = RateLimiter::new(kbps);
+        let mut buffer = vec![0u8; self.chunk_size.max(1)];
+        let mut sequence = 0;
+        while !stopped.load(Ordering::SeqCst) {
+            let n = audio_reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            rate_limiter.wait(n).await;
+            let mut data = buffer[..n].to_vec();
+            protocol::append_checksum(&mut data);
+            let frame = AudioFrame {
+                sequence,
+                timestamp_us: latency::now_micros(),
+                header,
+                data,
+            };
+            udp::send_to(&self.socket, &Message::AudioFrame(frame), peer).await?;
+            sequence += 1;
+        }
+        udp::send_to(&self.socket, &Message::StopPlaying, peer).await
+    }
+}
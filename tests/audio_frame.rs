@@ -0,0 +1,44 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8127;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_audio_frame.wav";
+
+#[tokio::test]
+async fn test_frames_carry_sequence_and_timestamp() -> Result<()> {
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_chunk_size(4096)?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    assert_eq!(handler.last_sequence(), 0);
+    assert_eq!(handler.last_timestamp_us(), 0);
+    handler
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .start_playing()
+        .await?;
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+
+    // One frame per chunk, numbered from 0
+    let input = hound::WavReader::open(PATH_INPUT)?;
+    let data_len = input.len() as u64 * input.spec().bits_per_sample as u64 / 8;
+    assert_eq!(handler.last_sequence(), data_len.div_ceil(4096) - 1);
+
+    let now_us = SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as u64;
+    assert!(handler.last_timestamp_us() <= now_us);
+    assert!(now_us - handler.last_timestamp_us() < 60_000_000);
+    // Every frame is timed, without enabling frame timestamps
+    assert!(handler.latency().unwrap().frames > 0);
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8119;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_audio_stream_reader() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    let mut stream = client.into_audio_stream();
+    let mut received = Vec::new();
+    tokio::io::copy(&mut stream, &mut received).await?;
+
+    // The samples of the file, as little-endian 16-bit PCM
+    let mut reader = hound::WavReader::open(PATH_INPUT)?;
+    let expected: Vec<u8> = reader
+        .samples::<i16>()
+        .map(|sample| sample.map(i16::to_le_bytes))
+        .collect::<Result<Vec<_>, _>>()?
+        .concat();
+    assert_eq!(received.len(), expected.len());
+    assert!(received == expected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_audio_stream_reader_in_local_task() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), 0, PATH_INPUT.to_string()).await?;
+    let port = server.local_addrs()[0].port();
+    tokio::spawn(Arc::new(server).run());
+
+    // The reader is not Send, so a separate task reading it must be local
+    let local = tokio::task::LocalSet::new();
+    let received = local
+        .run_until(async move {
+            let client =
+                client_manager::ClientInterface::connect(ADDRESS.to_string(), port).await?;
+            let mut stream = client.into_audio_stream();
+            tokio::task::spawn_local(async move {
+                let mut received = Vec::new();
+                tokio::io::copy(&mut stream, &mut received).await?;
+                Ok::<_, anyhow::Error>(received)
+            })
+            .await?
+        })
+        .await?;
+
+    let reader = hound::WavReader::open(PATH_INPUT)?;
+    assert_eq!(received.len(), reader.len() as usize * 2);
+    Ok(())
+}
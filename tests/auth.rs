@@ -0,0 +1,60 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager::{self, ClientInterface};
+use streamapp::protocol::{ProtocolError, ProtocolErrorCode};
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8108;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_auth.wav";
+const TOKEN: &str = "s3cret";
+
+// Code of the ERROR message the server refused the connection with.
+fn rejection(result: Result<ClientInterface>) -> Option<ProtocolErrorCode> {
+    match result.err()?.downcast_ref::<ProtocolError>()? {
+        ProtocolError::Rejected { code, .. } => Some(*code),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn test_auth_token() -> Result<()> {
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_auth_token(TOKEN.to_string());
+    tokio::spawn(Arc::new(server).run());
+
+    assert_eq!(
+        rejection(ClientInterface::connect(ADDRESS.to_string(), PORT).await),
+        Some(ProtocolErrorCode::AuthFailed)
+    );
+    // Same length but one byte off, a prefix, and a longer token
+    let mut same_length = TOKEN.to_string().into_bytes();
+    *same_length.last_mut().unwrap() ^= 1;
+    let guesses = [
+        "guess".to_string(),
+        String::from_utf8(same_length)?,
+        TOKEN[..TOKEN.len() - 1].to_string(),
+        format!("{}x", TOKEN),
+    ];
+    for guess in guesses {
+        assert_eq!(
+            rejection(ClientInterface::connect_with_token(ADDRESS.to_string(), PORT, guess).await),
+            Some(ProtocolErrorCode::AuthFailed)
+        );
+    }
+
+    let mut client =
+        ClientInterface::connect_with_token(ADDRESS.to_string(), PORT, TOKEN.to_string()).await?;
+    client
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .start_playing()
+        .await?;
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    Ok(())
+}
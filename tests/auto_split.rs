@@ -0,0 +1,48 @@
+use anyhow::Result;
+use std::time::Duration;
+use streamapp::audio::split::{AutoSplit, SplitWavWriter};
+
+const PATH_OUTPUT: &str = "/tmp/test_auto_split.wav";
+const SAMPLE_RATE: u32 = 1000;
+
+#[test]
+fn test_auto_split_on_silence() -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let auto_split = AutoSplit {
+        threshold: 0.01,
+        min_gap: Duration::from_millis(500),
+    };
+    let mut recorder = SplitWavWriter::new(PATH_OUTPUT, spec, Some(auto_split))?;
+
+    let sound = vec![0.5f32; 2 * 1000];
+    let short_gap = vec![0.0f32; 2 * 200];
+    let long_gap = vec![0.0f32; 2 * 2000];
+    // Feed in callback-sized chunks: sound, a gap too short to split, more
+    // sound, a long gap, then the second take.
+    for block in [&sound, &short_gap, &sound, &long_gap, &sound] {
+        for chunk in block.chunks(256) {
+            recorder.write::<f32, i16>(chunk)?;
+        }
+    }
+    let files = recorder.finalize()?;
+
+    assert_eq!(
+        files,
+        vec![
+            "/tmp/test_auto_split_001.wav".to_string(),
+            "/tmp/test_auto_split_002.wav".to_string(),
+        ]
+    );
+    let first = hound::WavReader::open(&files[0])?;
+    let second = hound::WavReader::open(&files[1])?;
+    // The first take keeps its short gap and the start of the long one.
+    assert_eq!(first.duration(), 1000 + 200 + 1000 + 500);
+    assert_eq!(second.duration(), 1000);
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8106;
+const PATH_INPUT: &str = "/tmp/test_input_broadcast.wav";
+const PATH_OUTPUT_FIRST: &str = "/tmp/test_output_broadcast_first.wav";
+const PATH_OUTPUT_LATE: &str = "/tmp/test_output_broadcast_late.wav";
+const SAMPLE_RATE: u32 = 8000;
+// One second, broadcast in real time
+const FRAMES: usize = SAMPLE_RATE as usize;
+
+// Every sample is its own index, so where a client joined can be told from
+// its first sample.
+fn write_input(path: &str) -> Result<Vec<i16>> {
+    let samples: Vec<i16> = (0..FRAMES as i16).collect();
+    let mut writer = hound::WavWriter::create(
+        path,
+        hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        },
+    )?;
+    for &sample in &samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(samples)
+}
+
+async fn listen(path: &str, delay: Duration) -> Result<()> {
+    tokio::time::sleep(delay).await;
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(path.to_string()));
+    client.start_playing().await
+}
+
+fn read_output(path: &str) -> Result<Vec<i16>> {
+    let mut reader = hound::WavReader::open(path)?;
+    Ok(reader.samples::<i16>().collect::<Result<_, _>>()?)
+}
+
+#[tokio::test]
+async fn test_broadcast_to_late_client() -> Result<()> {
+    let input = write_input(PATH_INPUT)?;
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_broadcast_mode(true);
+    tokio::spawn(Arc::new(server).run());
+
+    // Both streams end with the broadcast
+    let started = std::time::Instant::now();
+    tokio::try_join!(
+        listen(PATH_OUTPUT_FIRST, Duration::ZERO),
+        listen(PATH_OUTPUT_LATE, Duration::from_millis(400)),
+    )?;
+    assert!(started.elapsed() >= Duration::from_millis(900));
+
+    // Both got the end of the file from where they joined
+    let first = read_output(PATH_OUTPUT_FIRST)?;
+    let late = read_output(PATH_OUTPUT_LATE)?;
+    assert!(input.ends_with(&first));
+    assert!(first.ends_with(&late));
+    assert!(!late.is_empty());
+    assert!(
+        late.len() < FRAMES * 3 / 4,
+        "late client got {}",
+        late.len()
+    );
+    assert!(first.len() > late.len());
+
+    // Nothing is left to join once the broadcast is over
+    assert!(
+        listen("/tmp/test_output_broadcast_ended.wav", Duration::ZERO)
+            .await
+            .is_err()
+    );
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::network::buffer::{BufferAccount, BufferPolicy};
+use streamapp::protocol::Message;
+use streamapp::server::server_manager;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8085;
+const PATH_LARGE_INPUT: &str = "/tmp/test_large_input.wav";
+
+fn write_large_wav(path: &str, seconds: u32) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let samples = spec.sample_rate * seconds * spec.channels as u32;
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    let mut sample_writer = writer.get_i16_writer(samples);
+    for i in 0..samples {
+        sample_writer.write_sample((i % 1000) as i16);
+    }
+    sample_writer.flush()?;
+    writer.finalize()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_buffer_policies() -> Result<()> {
+    let close = BufferAccount::new(100, BufferPolicy::Close);
+    assert!(close.reserve(80).await?);
+    assert!(close.reserve(40).await.is_err());
+    close.release(80);
+    assert!(close.reserve(40).await?);
+    assert_eq!(close.used(), 40);
+
+    let drop = BufferAccount::new(100, BufferPolicy::DropFrames);
+    assert!(drop.reserve(80).await?);
+    assert!(!drop.reserve(40).await?);
+    assert_eq!(drop.dropped_frames(), 1);
+    assert_eq!(drop.used(), 80);
+
+    let throttle = BufferAccount::new(100, BufferPolicy::Throttle);
+    assert!(throttle.reserve(80).await?);
+    let waiting = throttle.clone();
+    let pending = tokio::spawn(async move { waiting.reserve(40).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!pending.is_finished());
+    throttle.release(80);
+    assert!(pending.await??);
+    assert_eq!(throttle.used(), 40);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_close_policy_on_stalled_client() -> Result<()> {
+    write_large_wav(PATH_LARGE_INPUT, 120)?;
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_LARGE_INPUT.to_string())
+            .await?;
+    server.set_max_buffered_bytes(64 * 1024, BufferPolicy::Close);
+    let server = Arc::new(server);
+    tokio::spawn(Arc::clone(&server).run());
+
+    let socket = TcpStream::connect(format!("{}:{}", ADDRESS, PORT)).await?;
+    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+    framed
+        .send(Bytes::from(Message::hello(None).encode()))
+        .await?;
+    let info = framed.next().await.unwrap()?;
+    assert!(matches!(Message::decode(&info)?, Message::ProtocolInfo(_)));
+    framed.send(Bytes::from(Message::Ok.encode())).await?;
+    framed
+        .send(Bytes::from(Message::StartPlaying.encode()))
+        .await?;
+    let header = framed.next().await.unwrap()?;
+    assert!(matches!(Message::decode(&header)?, Message::AudioHeader(_)));
+    framed.send(Bytes::from(Message::Ok.encode())).await?;
+
+    // Stall without reading: the server queue grows past the cap and the
+    // connection gets closed before the stream completes.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(server.buffered_bytes().is_empty());
+
+    while let Some(Ok(frame)) = framed.next().await {
+        assert_ne!(Message::decode(&frame)?, Message::StopPlaying);
+    }
+
+    Ok(())
+}
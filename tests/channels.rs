@@ -0,0 +1,51 @@
+use streamapp::audio::convert::ChannelConverter;
+
+#[test]
+fn test_stereo_to_mono_averages() {
+    let stereo = [0.5f32, -0.5, 1.0, 0.0, -1.0, -0.5];
+    assert_eq!(ChannelConverter::convert(&stereo, 2, 1), [0.0, 0.5, -0.75]);
+
+    let stereo = [i16::MAX, i16::MAX, 1000, -1000];
+    let mono = ChannelConverter::convert(&stereo, 2, 1);
+    assert_eq!(mono.len(), 2);
+    assert!(mono[0] >= i16::MAX - 1);
+    assert_eq!(mono[1], 0);
+}
+
+#[test]
+fn test_mono_to_stereo_duplicates() {
+    let mono = [1i16, -2, 3];
+    assert_eq!(ChannelConverter::convert(&mono, 1, 2), [1, 1, -2, -2, 3, 3]);
+    assert_eq!(ChannelConverter::convert(&mono, 1, 1), mono);
+}
+
+#[test]
+fn test_other_layouts_keep_first_channels() {
+    let surround = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0];
+    assert_eq!(
+        ChannelConverter::convert(&surround, 3, 2),
+        [1.0, 2.0, 4.0, 5.0]
+    );
+    assert_eq!(
+        ChannelConverter::convert(&[1.0f32, 2.0], 2, 3),
+        [1.0, 2.0, 0.0]
+    );
+}
+
+#[test]
+fn test_process_carries_partial_frames() {
+    let stereo = [0.5f32, -0.5, 1.0, 0.0, -1.0, -0.5];
+    let mut converter = ChannelConverter::new(2, 1);
+    // Split in the middle of the second frame
+    let mut mono = converter.process(&stereo[..3]);
+    assert_eq!(mono, [0.0]);
+    mono.extend(converter.process(&stereo[3..]));
+    assert_eq!(mono, [0.0, 0.5, -0.75]);
+
+    let mut converter = ChannelConverter::new(3, 2);
+    let mut output = vec![];
+    for sample in [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+        output.extend(converter.process(&[sample]));
+    }
+    assert_eq!(output, [1.0, 2.0, 4.0, 5.0]);
+}
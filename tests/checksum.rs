@@ -0,0 +1,134 @@
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use std::sync::Arc;
+use streamapp::client::client_manager::{Capabilities, ClientInterface};
+use streamapp::protocol::{self, AudioFrame, AudioHeader, Message, ProtocolInfo};
+use streamapp::server::server_manager;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+const ADDRESS: &str = "localhost";
+const PORT_CLIENT: u16 = 8129;
+const PORT_SERVER: u16 = 8130;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/checksum_output.wav";
+
+type FramedSocket = Framed<TcpStream, LengthDelimitedCodec>;
+
+async fn read_message(framed: &mut FramedSocket) -> Result<Message> {
+    Ok(Message::decode(&framed.next().await.unwrap()?)?)
+}
+
+async fn send(framed: &mut FramedSocket, message: Message) -> Result<()> {
+    Ok(framed.send(Bytes::from(message.encode())).await?)
+}
+
+// One stereo 16-bit frame with both samples set to `sequence`, checksummed.
+fn frame(sequence: u64) -> AudioFrame {
+    let mut data = [(sequence as i16).to_le_bytes(); 2].concat();
+    protocol::append_checksum(&mut data);
+    AudioFrame {
+        sequence,
+        timestamp_us: 0,
+        header: AudioHeader::new(),
+        data,
+    }
+}
+
+// Corrupts frame 1, then sends it again once the client NACKs it.
+async fn serve_corrupted(listener: TcpListener) -> Result<()> {
+    let (socket, _) = listener.accept().await?;
+    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::ProtocolInfo(ProtocolInfo::new())).await?;
+    read_message(&mut framed).await?;
+
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::AudioHeader(AudioHeader::new())).await?;
+    read_message(&mut framed).await?;
+
+    let mut corrupted = frame(1);
+    corrupted.data[0] ^= 0xFF;
+    send(&mut framed, Message::AudioFrame(frame(0))).await?;
+    send(&mut framed, Message::AudioFrame(corrupted)).await?;
+    send(&mut framed, Message::AudioFrame(frame(2))).await?;
+    assert_eq!(read_message(&mut framed).await?, Message::Nack(1));
+    send(&mut framed, Message::AudioFrame(frame(1))).await?;
+
+    send(&mut framed, Message::StopPlaying).await?;
+    assert_eq!(read_message(&mut framed).await?, Message::Bye);
+    send(&mut framed, Message::Bye).await
+}
+
+#[tokio::test]
+async fn test_client_nacks_corrupted_frames() -> Result<()> {
+    let listener = TcpListener::bind(format!("{}:{}", ADDRESS, PORT_CLIENT)).await?;
+    let server = tokio::spawn(serve_corrupted(listener));
+
+    let mut client = ClientInterface::connect(ADDRESS.to_string(), PORT_CLIENT).await?;
+    client.add_capability(Capabilities::SaveToFile(PATH_OUTPUT.to_string()));
+    client.start_playing().await?;
+    server.await??;
+
+    assert_eq!(client.underrun_count(), 0);
+    let samples: Vec<i16> = hound::WavReader::open(PATH_OUTPUT)?
+        .into_samples::<i16>()
+        .step_by(2)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(samples, [0, 1, 2]);
+    Ok(())
+}
+
+async fn next_frame(framed: &mut FramedSocket) -> Result<AudioFrame> {
+    loop {
+        match read_message(framed).await? {
+            Message::AudioFrame(frame) => return Ok(frame),
+            Message::Ping | Message::CoverArt(_) => continue,
+            message => panic!("Expected an audio frame, got {:?}", message),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_server_resends_nacked_frames() -> Result<()> {
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT_SERVER, PATH_INPUT.to_string())
+            .await?;
+    server.set_rate_limit_kbps(4000);
+    tokio::spawn(Arc::new(server).run());
+
+    let socket = TcpStream::connect(format!("{}:{}", ADDRESS, PORT_SERVER)).await?;
+    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+    send(&mut framed, Message::hello(None)).await?;
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::Ok).await?;
+    send(&mut framed, Message::StartPlaying).await?;
+    assert!(matches!(
+        read_message(&mut framed).await?,
+        Message::AudioHeader(_)
+    ));
+    send(&mut framed, Message::Ok).await?;
+
+    let mut frames = vec![];
+    for _ in 0..3 {
+        frames.push(next_frame(&mut framed).await?);
+    }
+    let mut data = frames[1].data.clone();
+    assert!(protocol::strip_checksum(&mut data));
+
+    send(&mut framed, Message::Nack(1)).await?;
+    let resent = loop {
+        let frame = next_frame(&mut framed).await?;
+        if frame.sequence == 1 {
+            break frame;
+        }
+    };
+    assert_eq!(resent, frames[1]);
+
+    send(&mut framed, Message::StopPlaying).await?;
+    while read_message(&mut framed).await? != Message::StopPlaying {}
+    send(&mut framed, Message::Bye).await?;
+    assert_eq!(read_message(&mut framed).await?, Message::Bye);
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager::ClientBuilder;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8117;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_client_builder.wav";
+
+#[tokio::test]
+async fn test_client_builder() -> Result<()> {
+    // Refused before connecting to anything
+    assert!(
+        ClientBuilder::new()
+            .address(ADDRESS)
+            .port(PORT)
+            .connect()
+            .await
+            .is_err()
+    );
+    assert!(
+        ClientBuilder::new()
+            .save_to_file(PATH_OUTPUT)
+            .volume(3.0)
+            .connect()
+            .await
+            .is_err()
+    );
+
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = ClientBuilder::new()
+        .address(ADDRESS)
+        .port(PORT)
+        .save_to_file(PATH_OUTPUT)
+        .connect_timeout(Duration::from_secs(5))
+        .volume(1.0)
+        .connect()
+        .await?;
+    client.start_playing().await?;
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    Ok(())
+}
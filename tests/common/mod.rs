@@ -0,0 +1,178 @@
+#![allow(dead_code)]
+
+pub fn compare_wav_samples(file1: &str, file2: &str) -> bool {
+    let reader1 = hound::WavReader::open(file1).expect("Cannot open first WAV file");
+    let reader2 = hound::WavReader::open(file2).expect("Cannot open second WAV file");
+
+    let spec1 = reader1.spec();
+    let spec2 = reader2.spec();
+
+    if spec1.channels != spec2.channels {
+        eprintln!(
+            "Number of channels differ: {} != {}",
+            spec1.channels, spec2.channels
+        );
+        return false;
+    }
+    if spec1.sample_rate != spec2.sample_rate {
+        eprintln!(
+            "Sample rate differ: {} != {}",
+            spec1.sample_rate, spec2.sample_rate
+        );
+        return false;
+    }
+    if spec1.bits_per_sample != spec2.bits_per_sample {
+        eprintln!(
+            "Bits per sample differ: {} != {}",
+            spec1.bits_per_sample, spec2.bits_per_sample
+        );
+        return false;
+    }
+    if spec1.sample_format != spec2.sample_format {
+        eprintln!(
+            "Sample format differ: {:?} != {:?}",
+            spec1.sample_format, spec2.sample_format
+        );
+        return false;
+    }
+
+    if reader1.len() != reader2.len() {
+        eprintln!(
+            "Number of samples differ: {} != {}",
+            reader1.len(),
+            reader2.len()
+        );
+        return false;
+    }
+
+    match spec1.sample_format {
+        hound::SampleFormat::Int => {
+            if spec1.bits_per_sample == 16 {
+                let samples1 = reader1.into_samples::<i16>();
+                let samples2 = reader2.into_samples::<i16>();
+                samples1
+                    .zip(samples2)
+                    .all(|(a, b)| a.unwrap() == b.unwrap())
+            } else if spec1.bits_per_sample == 32 {
+                let samples1 = reader1.into_samples::<i32>();
+                let samples2 = reader2.into_samples::<i32>();
+                samples1
+                    .zip(samples2)
+                    .all(|(a, b)| a.unwrap() == b.unwrap())
+            } else {
+                panic!("Unsupported integer bit depth: {}", spec1.bits_per_sample);
+            }
+        }
+        hound::SampleFormat::Float => {
+            let samples1 = reader1.into_samples::<f32>();
+            let samples2 = reader2.into_samples::<f32>();
+            samples1
+                .zip(samples2)
+                .all(|(a, b)| (a.unwrap() - b.unwrap()).abs() < 1e-5)
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// Frame numbers are coded like UTF-8 code points, without their restrictions.
+fn flac_frame_number(n: u32) -> Vec<u8> {
+    if n < 0x80 {
+        return vec![n as u8];
+    }
+    let mut bytes = vec![];
+    let mut rest = n;
+    let mut first_byte_bits = 6;
+    while rest >= 1 << first_byte_bits {
+        bytes.insert(0, 0x80 | (rest & 0x3F) as u8);
+        rest >>= 6;
+        first_byte_bits -= 1;
+    }
+    let prefix = !(0xFFu8 >> (bytes.len() + 1));
+    bytes.insert(0, prefix | rest as u8);
+    bytes
+}
+
+// Writes interleaved 16 or 24-bit samples to an uncompressed (verbatim)
+// FLAC file.
+pub fn write_flac(path: &str, channels: u8, sample_rate: u32, bits: u8, samples: &[i32]) {
+    const BLOCK_SIZE: usize = 4096;
+    let frames = samples.len() / channels as usize;
+
+    let mut file = b"fLaC".to_vec();
+    // Last metadata block, STREAMINFO, 34 bytes
+    file.extend_from_slice(&[0x80, 0, 0, 34]);
+    file.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+    file.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+    file.extend_from_slice(&[0; 6]);
+    let info = (sample_rate as u64) << 44
+        | ((channels - 1) as u64) << 41
+        | ((bits - 1) as u64) << 36
+        | frames as u64;
+    file.extend_from_slice(&info.to_be_bytes());
+    file.extend_from_slice(&[0; 16]);
+
+    let bytes_per_sample = bits as usize / 8;
+    for (number, block) in samples.chunks(BLOCK_SIZE * channels as usize).enumerate() {
+        let block_frames = block.len() / channels as usize;
+        // Fixed blocking, block size in 16 bits at the end of the header,
+        // sample rate from STREAMINFO, independent channels
+        let depth_code = if bits == 16 { 0b100 } else { 0b110 };
+        let mut frame = vec![0xFF, 0xF8, 0x70, (channels - 1) << 4 | depth_code << 1];
+        frame.extend(flac_frame_number(number as u32));
+        frame.extend_from_slice(&((block_frames - 1) as u16).to_be_bytes());
+        frame.push(crc8(&frame));
+        for channel in 0..channels as usize {
+            // Verbatim subframe
+            frame.push(0x02);
+            for sample in block.iter().skip(channel).step_by(channels as usize) {
+                frame.extend_from_slice(&sample.to_be_bytes()[4 - bytes_per_sample..]);
+            }
+        }
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        file.extend(frame);
+    }
+    std::fs::write(path, file).expect("Cannot write FLAC file");
+}
+
+// Reads `reader` until it returns nothing more.
+pub fn read_to_end(reader: &mut dyn streamapp::audio::file::AudioReader) -> Vec<u8> {
+    let mut data = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buffer).expect("Cannot read source");
+        if n == 0 {
+            return data;
+        }
+        data.extend_from_slice(&buffer[..n]);
+    }
+}
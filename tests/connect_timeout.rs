@@ -0,0 +1,52 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use streamapp::client::client_manager::ClientInterface;
+use streamapp::server::server_manager;
+use tokio::net::TcpListener;
+
+const ADDRESS: &str = "localhost";
+const PORT_SILENT: u16 = 8133;
+const PORT: u16 = 8134;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_connect_timeout() -> Result<()> {
+    // Accepts connections but never answers the HELLO
+    let listener = TcpListener::bind(format!("{}:{}", ADDRESS, PORT_SILENT)).await?;
+    tokio::spawn(async move {
+        let mut sockets = vec![];
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+
+    let start = Instant::now();
+    let error = ClientInterface::connect_timeout(
+        ADDRESS.to_string(),
+        PORT_SILENT,
+        Duration::from_millis(200),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "Timed out connecting to {}:{} after 200ms",
+            ADDRESS, PORT_SILENT
+        )
+    );
+
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+    let client =
+        ClientInterface::connect_timeout(ADDRESS.to_string(), PORT, Duration::from_secs(5)).await?;
+    assert_eq!(
+        client.negotiated_version(),
+        streamapp::protocol::PROTOCOL_VERSION_MAX
+    );
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use anyhow::Result;
+use base64::Engine;
+use std::num::{NonZeroU8, NonZeroU32};
+use std::sync::Arc;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::flac::FlacFileRead;
+use streamapp::audio::mp3::Mp3FileRead;
+use streamapp::audio::ogg::OggVorbisFileRead;
+use streamapp::audio::tags;
+use streamapp::client::client_manager;
+use streamapp::protocol::CoverArt;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+fn id3_tag_with_picture(mime_type: &str, image: &[u8]) -> Vec<u8> {
+    let mut apic = vec![0u8];
+    apic.extend_from_slice(mime_type.as_bytes());
+    apic.extend_from_slice(&[0, 3]);
+    apic.extend_from_slice(b"cover\0");
+    apic.extend_from_slice(image);
+
+    let mut frames = b"APIC".to_vec();
+    frames.extend_from_slice(&(apic.len() as u32).to_be_bytes());
+    frames.extend_from_slice(&[0, 0]);
+    frames.extend_from_slice(&apic);
+
+    let size = frames.len();
+    let mut tag = b"ID3\x03\x00\x00".to_vec();
+    tag.extend(
+        [21, 14, 7, 0]
+            .iter()
+            .map(|shift| ((size >> shift) & 0x7F) as u8),
+    );
+    tag.extend_from_slice(&frames);
+    tag
+}
+
+// FLAC PICTURE block, as also carried base64-encoded by Vorbis comments.
+fn flac_picture(cover_art: &CoverArt) -> Vec<u8> {
+    let mut block = 3u32.to_be_bytes().to_vec();
+    block.extend((cover_art.mime_type.len() as u32).to_be_bytes());
+    block.extend_from_slice(cover_art.mime_type.as_bytes());
+    block.extend(5u32.to_be_bytes());
+    block.extend_from_slice(b"cover");
+    block.extend([0; 16]);
+    block.extend((cover_art.data.len() as u32).to_be_bytes());
+    block.extend_from_slice(&cover_art.data);
+    block
+}
+
+fn cover() -> CoverArt {
+    CoverArt {
+        mime_type: "image/jpeg".to_string(),
+        data: vec![0xFF, 0xD8, 0xFF, 0xE0, 1, 2, 3],
+    }
+}
+
+fn write_wav_with_cover_art(path: &str, cover_art: &CoverArt) -> Result<()> {
+    let mut wav = std::fs::read(PATH_INPUT)?;
+    let tag = id3_tag_with_picture(&cover_art.mime_type, &cover_art.data);
+    wav.extend_from_slice(b"id3 ");
+    wav.extend_from_slice(&(tag.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&tag);
+    if tag.len() % 2 == 1 {
+        wav.push(0);
+    }
+    let riff_size = (wav.len() - 8) as u32;
+    wav[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    std::fs::write(path, wav)?;
+    Ok(())
+}
+
+async fn stream(path: &str, port: u16, output: &str) -> Result<Option<CoverArt>> {
+    let server = server_manager::Server::new(ADDRESS.to_string(), port, path.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), port).await?;
+    handler
+        .add_capability(client_manager::Capabilities::SaveToFile(output.to_string()))
+        .start_playing()
+        .await?;
+    Ok(handler.cover_art().cloned())
+}
+
+#[test]
+fn test_extract_id3_picture() {
+    let tag = id3_tag_with_picture("image/jpeg", &[0xFF, 0xD8, 0xFF]);
+    let cover_art = tags::extract_id3_picture(&tag).unwrap();
+    assert_eq!(cover_art.mime_type, "image/jpeg");
+    assert_eq!(cover_art.data, vec![0xFF, 0xD8, 0xFF]);
+
+    assert!(tags::extract_id3_picture(b"ID3\x03\x00\x00\x00\x00\x00\x00").is_none());
+    assert!(tags::read_wav_cover_art(PATH_INPUT).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_cover_art_forwarded() -> Result<()> {
+    const PATH_WITH_ART: &str = "/tmp/test_input_cover_art.wav";
+    const PATH_OUTPUT: &str = "/tmp/test_output_cover_art.wav";
+
+    // Large enough to be split over several messages.
+    let cover_art = CoverArt {
+        mime_type: "image/png".to_string(),
+        data: (0..40_000u32).map(|i| (i % 251) as u8).collect(),
+    };
+    write_wav_with_cover_art(PATH_WITH_ART, &cover_art)?;
+
+    let received = stream(PATH_WITH_ART, 8086, PATH_OUTPUT).await?;
+    assert_eq!(received, Some(cover_art));
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_missing_cover_art() -> Result<()> {
+    let received = stream(PATH_INPUT, 8087, "/tmp/test_output_no_cover_art.wav").await?;
+    assert_eq!(received, None);
+    Ok(())
+}
+
+#[test]
+fn test_flac_cover_art() -> Result<()> {
+    const PATH: &str = "/tmp/test_input_cover_art.flac";
+    let samples: Vec<i32> = (0..2000).map(|i| (i % 100) * 50).collect();
+    common::write_flac(PATH, 1, 44100, 16, &samples);
+
+    // STREAMINFO is no longer the last metadata block.
+    let mut flac = std::fs::read(PATH)?;
+    flac[4] &= 0x7F;
+    let picture = flac_picture(&cover());
+    let mut block = vec![0x80 | 6];
+    block.extend_from_slice(&(picture.len() as u32).to_be_bytes()[1..]);
+    block.extend(picture);
+    flac.splice(42..42, block);
+    std::fs::write(PATH, flac)?;
+
+    let mut reader = FlacFileRead::new();
+    reader.open_file(PATH)?;
+    assert_eq!(reader.cover_art(), Some(cover()));
+    assert_eq!(common::read_to_end(&mut reader).len(), samples.len() * 2);
+    Ok(())
+}
+
+#[test]
+fn test_mp3_cover_art() -> Result<()> {
+    const PATH: &str = "/tmp/test_input_cover_art.mp3";
+    let cover_art = cover();
+    let mut mp3 = id3_tag_with_picture(&cover_art.mime_type, &cover_art.data);
+    // Silent MPEG-1 Layer III frames
+    for _ in 0..10 {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC0]);
+        mp3.extend(frame);
+    }
+    std::fs::write(PATH, mp3)?;
+
+    let mut reader = Mp3FileRead::new();
+    reader.open_file(PATH)?;
+    assert_eq!(reader.cover_art(), Some(cover_art));
+    Ok(())
+}
+
+#[test]
+fn test_vorbis_cover_art() -> Result<()> {
+    const PATH: &str = "/tmp/test_input_cover_art.ogg";
+    let picture = base64::engine::general_purpose::STANDARD.encode(flac_picture(&cover()));
+    let mut builder = vorbis_rs::VorbisEncoderBuilder::new_with_serial(
+        NonZeroU32::new(48000).unwrap(),
+        NonZeroU8::new(1).unwrap(),
+        std::fs::File::create(PATH)?,
+        1,
+    );
+    builder.comment_tag("METADATA_BLOCK_PICTURE", picture)?;
+    let mut encoder = builder.build()?;
+    encoder.encode_audio_block([vec![0.0f32; 4800]])?;
+    encoder.finish()?;
+
+    let mut reader = OggVorbisFileRead::new();
+    reader.open_file(PATH)?;
+    assert_eq!(reader.cover_art(), Some(cover()));
+    Ok(())
+}
+
+#[test]
+fn test_opus_cover_art() -> Result<()> {
+    const PATH: &str = "/tmp/test_input_cover_art.opus";
+    let mut head = b"OpusHead".to_vec();
+    head.extend([1, 1, 0, 0]);
+    head.extend(48000u32.to_le_bytes());
+    head.extend([0, 0, 0]);
+    let comment = format!(
+        "METADATA_BLOCK_PICTURE={}",
+        base64::engine::general_purpose::STANDARD.encode(flac_picture(&cover()))
+    );
+    let mut opus_tags = b"OpusTags".to_vec();
+    opus_tags.extend(0u32.to_le_bytes());
+    opus_tags.extend(1u32.to_le_bytes());
+    opus_tags.extend((comment.len() as u32).to_le_bytes());
+    opus_tags.extend_from_slice(comment.as_bytes());
+
+    let mut writer = ogg::PacketWriter::new(std::fs::File::create(PATH)?);
+    for packet in [head, opus_tags] {
+        writer.write_packet(packet.into(), 1, ogg::PacketWriteEndInfo::EndPage, 0)?;
+    }
+    drop(writer);
+
+    assert_eq!(tags::read_opus_cover_art(PATH)?, Some(cover()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cover_art_reset_between_tracks() -> Result<()> {
+    const PATH_WITH_ART: &str = "/tmp/test_input_cover_art_track.wav";
+    write_wav_with_cover_art(PATH_WITH_ART, &cover())?;
+
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), 0, PATH_WITH_ART.to_string()).await?;
+    let server = server.with_playlist(vec![PATH_WITH_ART.to_string(), PATH_INPUT.to_string()]);
+    let port = server.local_addrs()[0].port();
+    tokio::spawn(Arc::new(server).run());
+
+    // The second track has no picture, so the first one's is not kept.
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), port).await?;
+    handler
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            "/tmp/test_output_cover_art_track.wav".to_string(),
+        ))
+        .start_playing()
+        .await?;
+    assert_eq!(handler.cover_art(), None);
+    Ok(())
+}
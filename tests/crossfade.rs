@@ -0,0 +1,73 @@
+use streamapp::audio::crossfade::Crossfader;
+
+const CHANNELS: usize = 2;
+const FADE_FRAMES: usize = 100;
+
+fn constant(value: f32, frames: usize) -> Vec<f32> {
+    vec![value; frames * CHANNELS]
+}
+
+#[test]
+fn test_crossfade_sums_overlap() {
+    let mut crossfader = Crossfader::new(FADE_FRAMES, CHANNELS);
+    let mut output = vec![];
+    // Fed in odd-sized chunks to cross chunk boundaries during the fade
+    for chunk in constant(0.5, 1000).chunks(7 * CHANNELS) {
+        output.extend(crossfader.process(chunk));
+    }
+    crossfader.end_of_track();
+    for chunk in constant(-0.5, 1000).chunks(13 * CHANNELS) {
+        output.extend(crossfader.process(chunk));
+    }
+    output.extend(crossfader.flush());
+
+    // The fade overlaps the two tracks, so they are shorter together
+    assert_eq!(output.len(), (2000 - FADE_FRAMES) * CHANNELS);
+
+    let overlap_start = 1000 - FADE_FRAMES;
+    for (i, frame) in output.chunks(CHANNELS).enumerate() {
+        let expected = if i < overlap_start {
+            0.5
+        } else if i < 1000 {
+            let gain = ((i - overlap_start) as f32 + 0.5) / FADE_FRAMES as f32;
+            0.5 * (1.0 - gain) - 0.5 * gain
+        } else {
+            -0.5
+        };
+        for &sample in frame {
+            assert!(
+                (sample - expected).abs() < 1e-6,
+                "frame {}: {} != {}",
+                i,
+                sample,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn test_crossfade_keeps_level_of_equal_tracks() {
+    let mut crossfader = Crossfader::new(FADE_FRAMES, CHANNELS);
+    let mut output = crossfader.process(&constant(0.25, 500));
+    crossfader.end_of_track();
+    output.extend(crossfader.process(&constant(0.25, 500)));
+    output.extend(crossfader.flush());
+
+    assert_eq!(output.len(), (1000 - FADE_FRAMES) * CHANNELS);
+    assert!(output.iter().all(|&s| (s - 0.25).abs() < 1e-6));
+}
+
+#[test]
+fn test_crossfade_short_track_does_not_drop_audio() {
+    let mut crossfader = Crossfader::new(FADE_FRAMES, CHANNELS);
+    let mut output = crossfader.process(&constant(0.5, 500));
+    crossfader.end_of_track();
+    // Too short to complete the fade: the rest of the first track's tail
+    // follows the mixed part
+    output.extend(crossfader.process(&constant(0.5, 30)));
+    output.extend(crossfader.flush());
+
+    assert_eq!(output.len(), 500 * CHANNELS);
+    assert!(output.iter().all(|&s| (s - 0.5).abs() < 1e-6));
+}
@@ -0,0 +1,52 @@
+use futures::executor::block_on;
+use streamapp::audio::cpal::{CpalInterface, CpalRecorderConfig, select_output_device};
+use streamapp::audio::file::FileFormat;
+
+fn names(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_select_output_device() {
+    let preferred = names(&["USB Headset", "Bluetooth Speaker", "Built-in Audio"]);
+
+    let available = names(&["HDMI", "Built-in Audio", "USB Headset"]);
+    assert_eq!(
+        select_output_device(&preferred, &available),
+        Some("USB Headset")
+    );
+
+    // Headset unplugged: fall back to the next available device of the chain.
+    let available = names(&["HDMI", "Built-in Audio"]);
+    assert_eq!(
+        select_output_device(&preferred, &available),
+        Some("Built-in Audio")
+    );
+
+    // Nothing from the chain is available, or no chain: use the default.
+    assert_eq!(select_output_device(&preferred, &names(&["HDMI"])), None);
+    assert_eq!(select_output_device(&[], &available), None);
+}
+
+#[test]
+fn test_unknown_device_rejected() {
+    // Selecting by name only accepts devices currently listed
+    let name = "No Such Audio Device";
+    if let Ok(outputs) = CpalInterface::list_output_devices() {
+        assert!(!outputs.iter().any(|device| device == name));
+    }
+    assert!(CpalInterface::with_output_device(name).is_err());
+    assert!(CpalInterface::with_input_device(name).is_err());
+    let config = CpalRecorderConfig {
+        device_name: Some(name.to_string()),
+        duration: 1,
+        path: "/tmp/test_output_unknown_device.wav".to_string(),
+        format: FileFormat::Wav,
+        vad_threshold: None,
+    };
+    assert!(block_on(CpalInterface::record_with_config(config)).is_err());
+    assert!(!std::path::Path::new("/tmp/test_output_unknown_device.wav").exists());
+    assert!(
+        streamapp::audio::cpal::play_audio_from_wav("/nonexistent/file.wav", Some(name)).is_err()
+    );
+}
@@ -0,0 +1,194 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use streamapp::audio::cpal::{
+    CpalFileWrite, DeviceProvider, OutputBuffer, OutputCallback, OutputDevice, OutputStream,
+    StreamErrorCallback,
+};
+use streamapp::audio::file::AudioWriter;
+use streamapp::protocol::AudioHeader;
+
+// A machine without any output device.
+struct NoDevices;
+
+impl DeviceProvider for NoDevices {
+    fn output_devices(&self) -> Result<Vec<Box<dyn OutputDevice>>> {
+        Ok(vec![])
+    }
+
+    fn default_output_device(&self) -> Result<Box<dyn OutputDevice>> {
+        Err(anyhow::anyhow!("No output device in this test"))
+    }
+}
+
+// Sound card whose f32 output goes to `played`.
+#[derive(Clone)]
+struct MockDevice {
+    sample_rate: u32,
+    channels: u16,
+    played: Arc<Mutex<Vec<f32>>>,
+}
+
+impl DeviceProvider for MockDevice {
+    fn output_devices(&self) -> Result<Vec<Box<dyn OutputDevice>>> {
+        Ok(vec![Box::new(self.clone())])
+    }
+
+    fn default_output_device(&self) -> Result<Box<dyn OutputDevice>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+impl OutputDevice for MockDevice {
+    fn device_name(&self) -> Result<String> {
+        Ok("mock".to_string())
+    }
+
+    fn native_format(&self) -> Result<(u32, u16)> {
+        Ok((self.sample_rate, self.channels))
+    }
+
+    fn open_output_stream(
+        &self,
+        _config: &cpal::StreamConfig,
+        format: cpal::SampleFormat,
+        data: OutputCallback,
+        _error: StreamErrorCallback,
+    ) -> Result<Box<dyn OutputStream>> {
+        assert_eq!(format, cpal::SampleFormat::F32);
+        Ok(Box::new(MockStream {
+            data: Arc::new(Mutex::new(data)),
+            played: Arc::clone(&self.played),
+            running: Arc::new(AtomicBool::new(false)),
+        }))
+    }
+}
+
+// Pulls 256 samples at a time from a thread of its own while started, like
+// an audio callback.
+struct MockStream {
+    data: Arc<Mutex<OutputCallback>>,
+    played: Arc<Mutex<Vec<f32>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl OutputStream for MockStream {
+    fn start(&self) -> Result<()> {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let (data, played, running) = (
+            Arc::clone(&self.data),
+            Arc::clone(&self.played),
+            Arc::clone(&self.running),
+        );
+        std::thread::spawn(move || {
+            let mut output = [0.0f32; 256];
+            while running.load(Ordering::SeqCst) {
+                (data.lock().unwrap())(OutputBuffer::F32(&mut output));
+                played.lock().unwrap().extend_from_slice(&output);
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        });
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.running.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl Drop for MockStream {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+fn float_header(channels: u16, sample_rate: u32) -> AudioHeader {
+    AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    })
+}
+
+fn to_bytes(samples: &[f32]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+#[test]
+fn test_playback_uses_injected_provider() -> Result<()> {
+    let header = AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    });
+    let mut playback = CpalFileWrite::new_with_provider(Box::new(NoDevices));
+    playback.update_format(&header)?;
+
+    // Past the pre-buffer, playback starts and needs a device
+    let error = playback.write(&vec![0u8; 44100 * 4]).unwrap_err();
+    assert_eq!(error.to_string(), "No output device in this test");
+    Ok(())
+}
+
+#[test]
+fn test_playback_plays_samples_in_order() -> Result<()> {
+    let device = MockDevice {
+        sample_rate: 48000,
+        channels: 2,
+        played: Default::default(),
+    };
+    let played = Arc::clone(&device.played);
+    let mut playback = CpalFileWrite::new_with_provider(Box::new(device));
+    playback.update_format(&float_header(2, 48000))?;
+
+    let samples: Vec<f32> = (0..20_000).map(|i| (i % 1000) as f32 / 1000.0).collect();
+    for chunk in samples.chunks(1000) {
+        playback.write(&to_bytes(chunk))?;
+    }
+    playback.finalize()?;
+
+    // Played whole and in order, then silence once the buffer ran dry
+    let played = played.lock().unwrap();
+    assert!(played.len() >= samples.len());
+    assert_eq!(played[..samples.len()], samples[..]);
+    assert!(played[samples.len()..].iter().all(|&s| s == 0.0));
+    Ok(())
+}
+
+#[test]
+fn test_playback_resamples_unaligned_writes() -> Result<()> {
+    let device = MockDevice {
+        sample_rate: 48000,
+        channels: 2,
+        played: Default::default(),
+    };
+    let played = Arc::clone(&device.played);
+    let mut playback = CpalFileWrite::new_with_provider(Box::new(device));
+    playback.update_format(&float_header(2, 24000))?;
+
+    // A ramp on both channels, written in chunks that split samples and
+    // frames: linear interpolation at twice the rate keeps it a ramp.
+    const FRAMES: usize = 10_000;
+    let samples: Vec<f32> = (0..FRAMES * 2)
+        .map(|i| (i / 2) as f32 / FRAMES as f32)
+        .collect();
+    for chunk in to_bytes(&samples).chunks(1001) {
+        playback.write(chunk)?;
+    }
+    playback.finalize()?;
+
+    let played = played.lock().unwrap();
+    let output_frames = (FRAMES - 1) * 2;
+    assert!(played.len() >= output_frames * 2);
+    for (i, frame) in played[..output_frames * 2].chunks_exact(2).enumerate() {
+        let expected = i as f32 / 2.0 / FRAMES as f32;
+        assert!((frame[0] - expected).abs() < 1e-6, "frame {}", i);
+        assert_eq!(frame[0], frame[1]);
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use streamapp::client::client_manager;
+use streamapp::network::crypto::{EncryptionKey, FrameCipher, StreamSalt};
+use streamapp::protocol::{self, Message};
+use streamapp::server::server_manager;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8082;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_encrypted.wav";
+const KEY: EncryptionKey = [7u8; 32];
+const WRONG_KEY: EncryptionKey = [9u8; 32];
+
+async fn start_encrypted_server() {
+    let mut server = server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string())
+        .await
+        .unwrap();
+    server.set_encryption_key(KEY);
+    tokio::spawn(Arc::new(server).run());
+}
+
+async fn client_task(key: EncryptionKey, output: &str) -> Result<()> {
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    handler
+        .set_encryption_key(key)
+        .add_capability(client_manager::Capabilities::SaveToFile(output.to_string()))
+        .start_playing()
+        .await
+}
+
+#[test]
+fn test_frame_cipher_round_trip() -> Result<()> {
+    let frames: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 64]).collect();
+
+    let mut encryptor = FrameCipher::new(&KEY);
+    let mut decryptor = FrameCipher::with_salt(&KEY, encryptor.salt());
+    let mut wrong = FrameCipher::with_salt(&WRONG_KEY, encryptor.salt());
+    for frame in &frames {
+        let sealed = encryptor.encrypt(frame)?;
+        assert_ne!(&sealed[..frame.len()], &frame[..]);
+        assert!(wrong.decrypt(&sealed).is_err());
+        assert_eq!(&decryptor.decrypt(&sealed)?, frame);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_encrypted_streaming() -> Result<()> {
+    start_encrypted_server().await;
+
+    client_task(KEY, PATH_OUTPUT).await?;
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+
+    let result = client_task(WRONG_KEY, "/tmp/test_output_wrong_key.wav").await;
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_streams_do_not_share_nonces() -> Result<()> {
+    let frame = vec![0u8; 64];
+    let mut first = FrameCipher::new(&KEY);
+    let mut second = FrameCipher::new(&KEY);
+    assert_ne!(first.salt(), second.salt());
+    let sealed = first.encrypt(&frame)?;
+    assert_ne!(sealed, second.encrypt(&frame)?);
+
+    // Frame 0 of another stream does not decrypt it
+    let mut other_stream = FrameCipher::with_salt(&KEY, second.salt());
+    assert!(other_stream.decrypt(&sealed).is_err());
+    Ok(())
+}
+
+type FramedSocket = Framed<TcpStream, LengthDelimitedCodec>;
+
+async fn read_message(framed: &mut FramedSocket) -> Result<Message> {
+    Ok(Message::decode(&framed.next().await.unwrap()?)?)
+}
+
+async fn send(framed: &mut FramedSocket, message: Message) -> Result<()> {
+    Ok(framed.send(Bytes::from(message.encode())).await?)
+}
+
+// Salt and sealed first frame of one stream, read off the wire.
+async fn first_sealed_frame(addr: SocketAddr) -> Result<(StreamSalt, Vec<u8>)> {
+    let mut framed = Framed::new(TcpStream::connect(addr).await?, LengthDelimitedCodec::new());
+    send(&mut framed, Message::hello(None)).await?;
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::Ok).await?;
+
+    send(&mut framed, Message::StartPlaying).await?;
+    let Message::StreamSalt(salt) = read_message(&mut framed).await? else {
+        panic!("Encrypted stream without a salt");
+    };
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::Ok).await?;
+    let Message::AudioFrame(mut frame) = read_message(&mut framed).await? else {
+        panic!("Expected an audio frame");
+    };
+    assert!(protocol::strip_checksum(&mut frame.data));
+    assert_eq!(frame.sequence, 0);
+    Ok((salt, frame.data))
+}
+
+#[tokio::test]
+async fn test_each_stream_gets_its_own_salt() -> Result<()> {
+    let mut server =
+        server_manager::Server::new("127.0.0.1".to_string(), 0, PATH_INPUT.to_string()).await?;
+    server.set_encryption_key(KEY);
+    let addr = server.local_addrs()[0];
+    tokio::spawn(Arc::new(server).run());
+
+    let (first_salt, first) = first_sealed_frame(addr).await?;
+    let (second_salt, second) = first_sealed_frame(addr).await?;
+    assert_ne!(first_salt, second_salt);
+    // The same audio, sealed under different nonces
+    assert_ne!(first, second);
+    assert_eq!(
+        FrameCipher::with_salt(&KEY, first_salt).decrypt(&first)?,
+        FrameCipher::with_salt(&KEY, second_salt).decrypt(&second)?
+    );
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::wav::WavFileRead;
+use streamapp::client::client_manager;
+use streamapp::network::file::EofAction;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8122;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[test]
+fn test_wav_reader_loops() -> Result<()> {
+    let mut reader = WavFileRead::new();
+    reader.open_file(PATH_INPUT)?;
+    reader.set_looping(true);
+    let file_len = hound::WavReader::open(PATH_INPUT)?.len() as usize * 2;
+
+    let mut buffer = vec![0u8; 4096];
+    let mut read = 0;
+    while read < file_len * 3 {
+        let n = reader.read(&mut buffer)?;
+        assert!(n > 0);
+        read += n;
+    }
+    assert_eq!(reader.loops_completed(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_sends_file_n_times() -> Result<()> {
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_eof_action(EofAction::LoopN(3));
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    let collected = client.collect_samples().await?;
+
+    let input_len = hound::WavReader::open(PATH_INPUT)?.len() as usize;
+    assert_eq!(collected.samples.len(), input_len * 3);
+    let channels = collected.header.get_channels() as u64;
+    assert_eq!(
+        client.stream_total_samples(),
+        Some(input_len as u64 / channels * 3)
+    );
+    // Each pass starts over from the first sample
+    assert_eq!(
+        collected.samples[..16],
+        collected.samples[input_len..input_len + 16]
+    );
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use anyhow::Result;
+use streamapp::audio::file::AudioWriter;
+use streamapp::audio::wav::WavFileWrite;
+use streamapp::protocol::AudioHeader;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8096;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_server_bind_errors() -> Result<()> {
+    let _server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    // The port is already taken by the first server
+    assert!(
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string())
+            .await
+            .is_err()
+    );
+
+    assert!(
+        server_manager::Server::new_unix(
+            "/nonexistent/rstream.sock".to_string(),
+            PATH_INPUT.to_string()
+        )
+        .await
+        .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_wav_write_unsupported_format() -> Result<()> {
+    let header = AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: 8,
+        sample_format: hound::SampleFormat::Int,
+    });
+    let mut writer = WavFileWrite::new("/tmp/test_output_8bit.wav".to_string());
+    writer.update_format(&header)?;
+    writer.write(&[0u8; 600])?;
+    assert!(writer.finalize().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_play_missing_file() {
+    assert!(streamapp::audio::cpal::play_audio_from_wav("/nonexistent/file.wav", None).is_err());
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use streamapp::client::client_manager;
+use streamapp::protocol::{ProtocolError, ProtocolErrorCode};
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8092;
+const MEDIA_DIR: &str = "/tmp/test_file_access";
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+async fn request(token: &str, file: &str) -> Result<()> {
+    let mut handler = client_manager::ClientInterface::connect_with_token(
+        ADDRESS.to_string(),
+        PORT,
+        token.into(),
+    )
+    .await?;
+    let output = format!("/tmp/test_output_access_{}_{}", token, file);
+    handler
+        .add_capability(client_manager::Capabilities::SaveToFile(output.clone()))
+        .request_file(file)
+        .await?;
+    assert!(common::compare_wav_samples(PATH_INPUT, &output));
+    Ok(())
+}
+
+// Code of the ERROR message the server refused the request with.
+fn rejection(result: Result<()>) -> Option<ProtocolErrorCode> {
+    match result.err()?.downcast_ref::<ProtocolError>()? {
+        ProtocolError::Rejected { code, .. } => Some(*code),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn test_per_token_file_access() -> Result<()> {
+    std::fs::create_dir_all(MEDIA_DIR)?;
+    for file in ["public.wav", "premium.wav"] {
+        std::fs::copy(PATH_INPUT, format!("{}/{}", MEDIA_DIR, file))?;
+    }
+    std::fs::write(format!("{}/notes.wav", MEDIA_DIR), "not a wav file")?;
+
+    let access: HashMap<&str, Vec<&str>> = HashMap::from([
+        ("free", vec!["public.wav"]),
+        (
+            "paid",
+            vec!["public.wav", "premium.wav", "notes.wav", "missing.wav"],
+        ),
+    ]);
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, MEDIA_DIR.to_string()).await?;
+    server.set_file_authorizer(move |client, file| {
+        client
+            .token
+            .as_deref()
+            .and_then(|token| access.get(token))
+            .is_some_and(|files| files.contains(&file))
+    });
+    tokio::spawn(Arc::new(server).run());
+
+    request("free", "public.wav").await?;
+    assert_eq!(
+        rejection(request("free", "premium.wav").await),
+        Some(ProtocolErrorCode::AuthFailed)
+    );
+    request("paid", "public.wav").await?;
+    request("paid", "premium.wav").await?;
+    assert_eq!(
+        rejection(request("unknown", "public.wav").await),
+        Some(ProtocolErrorCode::AuthFailed)
+    );
+    // Paths escaping the media directory are never served.
+    assert_eq!(
+        rejection(request("paid", "../test_file_access/public.wav").await),
+        Some(ProtocolErrorCode::UnknownFile)
+    );
+    assert_eq!(
+        rejection(request("paid", "missing.wav").await),
+        Some(ProtocolErrorCode::UnknownFile)
+    );
+    assert_eq!(
+        rejection(request("paid", "notes.wav").await),
+        Some(ProtocolErrorCode::UnsupportedFormat)
+    );
+
+    Ok(())
+}
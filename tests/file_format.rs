@@ -0,0 +1,51 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::audio::file::FileFormat;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8126;
+const PATH_INPUT: &str = "/tmp/test_input_detected.flac";
+
+#[test]
+fn test_format_from_extension() {
+    let formats = [
+        ("song.wav", FileFormat::Wav),
+        ("song.flac", FileFormat::Flac),
+        ("song.mp3", FileFormat::Mp3),
+        ("song.ogg", FileFormat::Ogg),
+        ("song.opus", FileFormat::Opus),
+        ("/music/album.v2/Song.FLAC", FileFormat::Flac),
+    ];
+    for (path, format) in formats {
+        assert_eq!(FileFormat::from_path(path), Some(format), "{path}");
+    }
+}
+
+#[test]
+fn test_unknown_or_missing_extension() {
+    for path in ["song", "song.aiff", "/music/album.v2/song", ".wav", ""] {
+        assert_eq!(FileFormat::from_path(path), None, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn test_server_detects_format() -> Result<()> {
+    let samples: Vec<i32> = (0..20_000).map(|i| (i % 2000) - 1000).collect();
+    common::write_flac(PATH_INPUT, 2, 44100, 16, &samples);
+
+    // No set_file_format, the extension is enough
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    let audio = client.collect_samples().await?;
+    assert_eq!(audio.header.get_channels(), 2);
+    assert_eq!(audio.header.get_sample_rate(), 44100);
+    assert_eq!(audio.samples.len(), samples.len());
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::audio::file::{AudioReader, FileFormat};
+use streamapp::audio::flac::FlacFileRead;
+use streamapp::client::client_manager;
+use streamapp::network::file::EofAction;
+use streamapp::protocol::{AudioHeader, SampleFormat};
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8102;
+const PATH_INPUT_16: &str = "/tmp/test_input_16.flac";
+const PATH_INPUT_24: &str = "/tmp/test_input_24.flac";
+const PATH_OUTPUT: &str = "/tmp/test_output_flac.wav";
+
+fn test_samples(frames: usize, channels: usize, amplitude: f64) -> Vec<i32> {
+    (0..frames * channels)
+        .map(|i| ((i as f64 * 0.01).sin() * amplitude) as i32)
+        .collect()
+}
+
+#[test]
+fn test_read_24bit_flac() -> Result<()> {
+    // Not a multiple of the block size, nor of the read buffer
+    let samples = test_samples(10_001, 2, 8_000_000.0);
+    common::write_flac(PATH_INPUT_24, 2, 48000, 24, &samples);
+
+    let mut reader = FlacFileRead::new();
+    reader.open_file(PATH_INPUT_24)?;
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    assert_eq!(header.get_channels(), 2);
+    assert_eq!(header.get_sample_rate(), 48000);
+    // 24-bit samples are streamed as 32-bit ones
+    assert_eq!(header.get_bits_per_sample(), 32);
+    assert!(matches!(header.get_sample_format(), SampleFormat::Int));
+
+    let mut read = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        read.extend(
+            buffer[..n]
+                .chunks_exact(4)
+                .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+        if n < buffer.len() {
+            break;
+        }
+    }
+    let expected: Vec<i32> = samples.iter().map(|s| s << 8).collect();
+    assert_eq!(read, expected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_flac_file() -> Result<()> {
+    let samples = test_samples(100_000, 2, 20_000.0);
+    common::write_flac(PATH_INPUT_16, 2, 44100, 16, &samples);
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT_16.to_string()).await?;
+    server.set_file_format(FileFormat::Flac);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    client.start_playing().await?;
+
+    let reader = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(reader.spec().channels, 2);
+    assert_eq!(reader.spec().sample_rate, 44100);
+    assert_eq!(reader.spec().bits_per_sample, 16);
+    let received: Vec<i32> = reader
+        .into_samples::<i16>()
+        .map(|s| s.map(i32::from))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(received, samples);
+    Ok(())
+}
+
+#[test]
+fn test_seek_flac() -> Result<()> {
+    const PATH_SEEK: &str = "/tmp/test_input_seek.flac";
+    let samples = test_samples(10_001, 2, 20_000.0);
+    common::write_flac(PATH_SEEK, 2, 48000, 16, &samples);
+
+    let mut reader = FlacFileRead::new();
+    reader.open_file(PATH_SEEK)?;
+    assert_eq!(reader.total_samples(), 10_001);
+    let whole = common::read_to_end(&mut reader);
+    assert_eq!(whole.len(), 10_001 * 2 * 2);
+
+    // Back to the start once read whole, then in the middle of a block
+    reader.seek_to_sample(0)?;
+    assert_eq!(common::read_to_end(&mut reader), whole);
+    reader.seek_to_sample(5000)?;
+    assert_eq!(common::read_to_end(&mut reader), whole[5000 * 4..]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_loop_flac_file() -> Result<()> {
+    const PATH_LOOP: &str = "/tmp/test_input_loop.flac";
+    let samples = test_samples(10_000, 2, 20_000.0);
+    common::write_flac(PATH_LOOP, 2, 44100, 16, &samples);
+
+    let mut server =
+        server_manager::Server::new("127.0.0.1".to_string(), 0, PATH_LOOP.to_string()).await?;
+    server
+        .set_file_format(FileFormat::Flac)
+        .set_eof_action(EofAction::LoopN(3));
+    let port = server.local_addrs()[0].port();
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client =
+        client_manager::ClientInterface::connect("127.0.0.1".to_string(), port).await?;
+    let collected = client.collect_samples().await?;
+    assert_eq!(collected.samples.len(), samples.len() * 3);
+    assert_eq!(client.stream_total_samples(), Some(10_000 * 3));
+    assert_eq!(
+        collected.samples[..16],
+        collected.samples[samples.len() * 2..samples.len() * 2 + 16]
+    );
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use streamapp::client::client_manager::{ClientBuilder, ClientInterface};
+use streamapp::network::common::LengthDelimitedCodecConfig;
+use streamapp::protocol::{self, AudioFrame, AudioHeader, Message, ProtocolInfo};
+use streamapp::server::server_manager::ServerBuilder;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+const ADDRESS: &str = "localhost";
+const PORT_CONFIGURED: u16 = 8131;
+const PORT_DEFAULT: u16 = 8132;
+const PATH_OUTPUT: &str = "/tmp/frame_limit_output.wav";
+
+type FramedSocket = Framed<TcpStream, LengthDelimitedCodec>;
+
+async fn read_message(framed: &mut FramedSocket) -> Result<Message> {
+    Ok(Message::decode(&framed.next().await.unwrap()?)?)
+}
+
+async fn send(framed: &mut FramedSocket, message: Message) -> Result<()> {
+    Ok(framed.send(Bytes::from(message.encode())).await?)
+}
+
+// Streams a single audio frame of `len` bytes, however long that is.
+async fn serve_frame(listener: TcpListener, len: usize) -> Result<()> {
+    let (socket, _) = listener.accept().await?;
+    let codec = LengthDelimitedCodec::builder()
+        .max_frame_length(usize::MAX)
+        .new_codec();
+    let mut framed = Framed::new(socket, codec);
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::ProtocolInfo(ProtocolInfo::new())).await?;
+    read_message(&mut framed).await?;
+
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::AudioHeader(AudioHeader::new())).await?;
+    read_message(&mut framed).await?;
+
+    let mut data = vec![0u8; len];
+    protocol::append_checksum(&mut data);
+    let frame = AudioFrame {
+        sequence: 0,
+        timestamp_us: 0,
+        header: AudioHeader::new(),
+        data,
+    };
+    send(&mut framed, Message::AudioFrame(frame)).await?;
+    send(&mut framed, Message::StopPlaying).await?;
+    // The client is gone by now
+    let _ = framed.next().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_configured_frame_limit() -> Result<()> {
+    let listener = TcpListener::bind(format!("{}:{}", ADDRESS, PORT_CONFIGURED)).await?;
+    let server = tokio::spawn(serve_frame(listener, 2048));
+
+    let mut client = ClientBuilder::new()
+        .address(ADDRESS)
+        .port(PORT_CONFIGURED)
+        .save_to_file(PATH_OUTPUT)
+        .codec_config(LengthDelimitedCodecConfig {
+            max_frame_length: 1024,
+        })
+        .connect()
+        .await?;
+    assert!(client.start_playing().await.is_err());
+    drop(client);
+    server.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_default_frame_limit() -> Result<()> {
+    assert_eq!(
+        LengthDelimitedCodecConfig::default().max_frame_length,
+        64 * 1024
+    );
+    let listener = TcpListener::bind(format!("{}:{}", ADDRESS, PORT_DEFAULT)).await?;
+    let server = tokio::spawn(serve_frame(listener, 100_000));
+
+    let mut client = ClientInterface::connect(ADDRESS.to_string(), PORT_DEFAULT).await?;
+    client.add_capability(streamapp::client::client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    assert!(client.start_playing().await.is_err());
+    drop(client);
+    server.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chunk_size_must_fit_frame_limit() -> Result<()> {
+    const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+    const PATH_LARGE_OUTPUT: &str = "/tmp/frame_limit_large_chunk_output.wav";
+    let config = LengthDelimitedCodecConfig::default();
+    let mut server = ServerBuilder::new()
+        .bind("127.0.0.1:0")
+        .file_path(PATH_INPUT)
+        .build()
+        .await?;
+    assert!(server.set_chunk_size(config.max_chunk_size()).is_ok());
+    assert!(server.set_chunk_size(config.max_chunk_size() + 1).is_err());
+    assert!(server.set_chunk_size(64 * 1024).is_err());
+    assert!(
+        ServerBuilder::new()
+            .bind("127.0.0.1:0")
+            .file_path(PATH_INPUT)
+            .chunk_size(64 * 1024)
+            .build()
+            .await
+            .is_err()
+    );
+    // Lowering the limit below the chunk size in use is refused as well
+    assert!(
+        server
+            .set_codec_config(LengthDelimitedCodecConfig {
+                max_frame_length: 1024,
+            })
+            .is_err()
+    );
+
+    // Chunks of 64 KiB stream whole once both ends accept their frames
+    let large = LengthDelimitedCodecConfig {
+        max_frame_length: 128 * 1024,
+    };
+    let server = ServerBuilder::new()
+        .bind("127.0.0.1:0")
+        .file_path(PATH_INPUT)
+        .codec_config(large)
+        .chunk_size(64 * 1024)
+        .build()
+        .await?;
+    let port = server.local_addrs()[0].port();
+    tokio::spawn(std::sync::Arc::new(server).run());
+    ClientBuilder::new()
+        .address("127.0.0.1")
+        .port(port)
+        .save_to_file(PATH_LARGE_OUTPUT)
+        .codec_config(large)
+        .connect()
+        .await?
+        .start_playing()
+        .await?;
+    assert_eq!(
+        hound::WavReader::open(PATH_LARGE_OUTPUT)?.len(),
+        hound::WavReader::open(PATH_INPUT)?.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::generator::GeneratorReader;
+use streamapp::client::client_manager;
+use streamapp::network;
+use streamapp::network::file::SendOptions;
+use streamapp::protocol::{AudioHeader, MessageType, ProtocolInfo};
+use tokio::net::TcpListener;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8084;
+const PORT_COLLECT: u16 = 8088;
+const PATH_OUTPUT: &str = "/tmp/test_output_generator.wav";
+const SAMPLE_RATE: u32 = 8000;
+const CHANNELS: u16 = 2;
+const TOTAL_FRAMES: u64 = 10_000;
+
+fn ramp(frame: u64, channel: u16) -> f32 {
+    let value = (frame % 100) as f32 / 100.0;
+    if channel == 0 { value } else { -value }
+}
+
+#[test]
+fn test_generator_reader_samples() -> Result<()> {
+    let mut reader = GeneratorReader::new(SAMPLE_RATE, CHANNELS, ramp);
+
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    assert_eq!(header.get_sample_rate(), SAMPLE_RATE);
+    assert_eq!(header.get_channels() as u16, CHANNELS);
+    assert_eq!(header.get_bits_per_sample(), 32);
+
+    // An unbounded generator always fills whole samples and never hits EOF.
+    let mut buffer = vec![0u8; 4098];
+    for _ in 0..3 {
+        assert_eq!(reader.read(&mut buffer)?, 4096);
+    }
+
+    let mut reader = GeneratorReader::new(SAMPLE_RATE, CHANNELS, ramp).with_total_frames(3);
+    let n = reader.read(&mut buffer)?;
+    assert_eq!(n, 3 * CHANNELS as usize * 4);
+    let samples: Vec<f32> = buffer[..n]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    assert_eq!(samples, vec![0.0, -0.0, 0.01, -0.01, 0.02, -0.02]);
+    assert_eq!(reader.read(&mut buffer)?, 0);
+
+    Ok(())
+}
+
+// Serves a single client with TOTAL_FRAMES of the ramp signal.
+async fn spawn_generator_server(port: u16) -> Result<()> {
+    let listener = TcpListener::bind(format!("{}:{}", ADDRESS, port)).await?;
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await?;
+        let mut socket = network::common::new_framed(Box::new(socket));
+        network::common::handshake_from_server(&mut socket, &ProtocolInfo::new(), None).await?;
+        let message_type = network::common::expect_message(&mut socket)
+            .await?
+            .message_type();
+        assert_eq!(message_type, MessageType::StartPlaying);
+
+        let mut reader =
+            GeneratorReader::new(SAMPLE_RATE, CHANNELS, ramp).with_total_frames(TOTAL_FRAMES);
+        network::file::send_reader(&mut reader, &mut socket, SendOptions::default()).await?;
+
+        let message_type = network::common::expect_message(&mut socket)
+            .await?
+            .message_type();
+        assert_eq!(message_type, MessageType::Bye);
+        network::common::send_bye_message(&mut socket).await
+    });
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_generator_streaming() -> Result<()> {
+    spawn_generator_server(PORT).await?;
+
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    handler
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .start_playing()
+        .await?;
+
+    let reader = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(reader.duration() as u64, TOTAL_FRAMES);
+    for (i, sample) in reader.into_samples::<f32>().enumerate() {
+        let expected = ramp(i as u64 / CHANNELS as u64, (i % CHANNELS as usize) as u16);
+        assert_eq!(sample?, expected);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collect_samples() -> Result<()> {
+    spawn_generator_server(PORT_COLLECT).await?;
+
+    let mut handler =
+        client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT_COLLECT).await?;
+    let collected = handler.collect_samples().await?;
+
+    assert_eq!(collected.header.get_sample_rate(), SAMPLE_RATE);
+    assert_eq!(
+        collected.samples.len() as u64,
+        TOTAL_FRAMES * CHANNELS as u64
+    );
+    for channel in 0..CHANNELS {
+        let samples = collected.channel(channel as usize);
+        assert_eq!(samples.len() as u64, TOTAL_FRAMES);
+        for (frame, sample) in samples.into_iter().enumerate() {
+            assert_eq!(sample, ramp(frame as u64, channel));
+        }
+    }
+
+    Ok(())
+}
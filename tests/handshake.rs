@@ -0,0 +1,138 @@
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use std::sync::Arc;
+use streamapp::client::client_manager::ClientInterface;
+use streamapp::protocol::{self, Message, ProtocolErrorCode, ProtocolInfo};
+use streamapp::server::server_manager;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Encoder, Framed, LengthDelimitedCodec};
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8089;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+async fn start_server() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+    Ok(())
+}
+
+type FramedSocket = Framed<TcpStream, LengthDelimitedCodec>;
+
+async fn connect() -> Result<FramedSocket> {
+    let socket = TcpStream::connect(format!("{}:{}", ADDRESS, PORT)).await?;
+    Ok(Framed::new(socket, LengthDelimitedCodec::new()))
+}
+
+// Frames `messages` and sends them in a single write.
+async fn send_pipelined(framed: &mut FramedSocket, messages: Vec<Vec<u8>>) -> Result<()> {
+    let mut pipelined = BytesMut::new();
+    for message in messages {
+        framed
+            .codec_mut()
+            .encode(Bytes::from(message), &mut pipelined)?;
+    }
+    framed.get_mut().write_all(&pipelined).await?;
+    Ok(())
+}
+
+async fn read_protocol_info(framed: &mut FramedSocket) -> Result<ProtocolInfo> {
+    let info = framed.next().await.unwrap()?;
+    match Message::decode(&info)? {
+        Message::ProtocolInfo(info) => Ok(info),
+        message => Err(anyhow::anyhow!("Expected protocol info, got {:?}", message)),
+    }
+}
+
+#[tokio::test]
+async fn test_pipelined_handshake() -> Result<()> {
+    start_server().await?;
+
+    // Pipelined after a complete handshake: served normally.
+    let mut framed = connect().await?;
+    send_pipelined(
+        &mut framed,
+        vec![
+            Message::hello(None).encode(),
+            Message::Ok.encode(),
+            Message::StartPlaying.encode(),
+        ],
+    )
+    .await?;
+    read_protocol_info(&mut framed).await?;
+
+    let header = framed.next().await.unwrap()?;
+    assert!(matches!(Message::decode(&header)?, Message::AudioHeader(_)));
+    framed.send(Bytes::from(Message::Ok.encode())).await?;
+
+    let mut audio_frames = 0;
+    while let Some(frame) = framed.next().await {
+        let frame = frame?;
+        match Message::decode(&frame)? {
+            Message::StopPlaying => break,
+            message => assert!(matches!(message, Message::AudioFrame(_))),
+        }
+        audio_frames += 1;
+    }
+    assert!(audio_frames > 0);
+    framed.send(Bytes::from(Message::Bye.encode())).await?;
+    let bye = framed.next().await.unwrap()?;
+    assert_eq!(Message::decode(&bye)?, Message::Bye);
+
+    // StartPlaying sent before confirming the handshake: rejected with an
+    // ERROR, then the connection is closed.
+    let mut framed = connect().await?;
+    send_pipelined(
+        &mut framed,
+        vec![
+            Message::hello(None).encode(),
+            Message::StartPlaying.encode(),
+        ],
+    )
+    .await?;
+    read_protocol_info(&mut framed).await?;
+    let error = framed.next().await.unwrap()?;
+    let Message::Error { code, .. } = Message::decode(&error)? else {
+        panic!("Expected an ERROR message");
+    };
+    assert_eq!(
+        ProtocolErrorCode::from_code(code),
+        Some(ProtocolErrorCode::UnexpectedMessage)
+    );
+    assert!(framed.next().await.is_none());
+
+    // No version in common: the server reports its range, then closes.
+    let mut framed = connect().await?;
+    let max = protocol::PROTOCOL_VERSION_MAX;
+    let hello = Message::Hello {
+        magic: protocol::PROTOCOL_MAGIC,
+        version_min: max + 1,
+        version_max: max + 2,
+        token: None,
+    };
+    framed.send(Bytes::from(hello.encode())).await?;
+    let info = read_protocol_info(&mut framed).await?;
+    assert_eq!(
+        info.version_range(),
+        (
+            protocol::PROTOCOL_VERSION_MIN,
+            protocol::PROTOCOL_VERSION_MAX
+        )
+    );
+    assert!(framed.next().await.is_none());
+
+    // HELLO sent without framing: the magic is not found where the first
+    // frame starts, and the client is dropped.
+    let mut socket = TcpStream::connect(format!("{}:{}", ADDRESS, PORT)).await?;
+    socket.write_all(&Message::hello(None).encode()).await?;
+    let mut recv_buf = [0u8; 4096];
+    assert_eq!(socket.read(&mut recv_buf).await?, 0);
+
+    let client = ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    assert_eq!(client.negotiated_version(), protocol::PROTOCOL_VERSION_MAX);
+
+    Ok(())
+}
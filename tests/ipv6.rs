@@ -0,0 +1,31 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+mod common;
+
+const PORT: u16 = 8135;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_stream_over_ipv6_loopback() -> Result<()> {
+    let server =
+        server_manager::Server::new("::1".to_string(), PORT, PATH_INPUT.to_string()).await?;
+    assert!(server.local_addrs().iter().all(|addr| addr.is_ipv6()));
+    tokio::spawn(Arc::new(server).run());
+
+    // Both forms of the address reach the server
+    for (address, output) in [
+        ("::1", "/tmp/test_output_ipv6.wav"),
+        ("[::1]", "/tmp/test_output_ipv6_brackets.wav"),
+    ] {
+        client_manager::ClientInterface::connect(address.to_string(), PORT)
+            .await?
+            .add_capability(client_manager::Capabilities::SaveToFile(output.to_string()))
+            .start_playing()
+            .await?;
+        assert!(common::compare_wav_samples(PATH_INPUT, output));
+    }
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::audio::ring;
+use streamapp::client::client_manager;
+use streamapp::protocol::Message;
+use streamapp::server::server_manager;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8097;
+const PATH_INPUT: &str = "/tmp/test_keepalive_input.wav";
+const PATH_LARGE_INPUT: &str = "/tmp/test_keepalive_large_input.wav";
+
+fn write_wav(path: &str, frames: u32) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for i in 0..frames * 2 {
+        writer.write_sample((i % 1000) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+async fn handshake(framed: &mut Framed<TcpStream, LengthDelimitedCodec>) -> Result<()> {
+    framed
+        .send(Bytes::from(Message::hello(None).encode()))
+        .await?;
+    let info = framed.next().await.unwrap()?;
+    assert!(matches!(Message::decode(&info)?, Message::ProtocolInfo(_)));
+    framed.send(Bytes::from(Message::Ok.encode())).await?;
+    Ok(())
+}
+
+// PINGs sent before the header are left unanswered.
+async fn start_stream(framed: &mut Framed<TcpStream, LengthDelimitedCodec>) -> Result<()> {
+    handshake(framed).await?;
+    framed
+        .send(Bytes::from(Message::StartPlaying.encode()))
+        .await?;
+    let header = loop {
+        let message = Message::decode(&framed.next().await.unwrap()?)?;
+        if message != Message::Ping {
+            break message;
+        }
+    };
+    assert!(matches!(header, Message::AudioHeader(_)));
+    framed.send(Bytes::from(Message::Ok.encode())).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_keepalive() -> Result<()> {
+    // About 1 MB of audio, read slowly enough to span many keepalive periods
+    write_wav(PATH_INPUT, 256 * 1024)?;
+    // Larger than what the socket buffers hold
+    write_wav(PATH_LARGE_INPUT, 4 * 1024 * 1024)?;
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_keepalive(Duration::from_millis(10), Duration::from_millis(100));
+    tokio::spawn(Arc::new(server).run());
+
+    // A client answering PINGs gets the whole stream.
+    let (writer, mut reader) = ring::ring_buffer(16 * 1024);
+    let drain = std::thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        let mut received = 0;
+        while !reader.is_finished() {
+            received += reader.read(&mut buffer);
+            std::thread::sleep(Duration::from_micros(500));
+        }
+        received
+    });
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    handler
+        .add_capability(client_manager::Capabilities::RingBuffer(writer))
+        .start_playing()
+        .await?;
+    let data_size = hound::WavReader::open(PATH_INPUT)?.len() as usize * 2;
+    assert_eq!(drain.join().unwrap(), data_size);
+
+    // A client that reads the stream but never answers gets disconnected
+    // before the end of it.
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT + 1, PATH_LARGE_INPUT.to_string())
+            .await?;
+    server.set_keepalive(Duration::from_millis(10), Duration::from_millis(100));
+    tokio::spawn(Arc::new(server).run());
+
+    let socket = TcpStream::connect(format!("{}:{}", ADDRESS, PORT + 1)).await?;
+    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+    start_stream(&mut framed).await?;
+    let mut pings = 0;
+    while let Some(Ok(frame)) = framed.next().await {
+        let message = Message::decode(&frame)?;
+        assert_ne!(message, Message::StopPlaying);
+        if message == Message::Ping {
+            pings += 1;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert!(pings > 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_keepalive_between_streams() -> Result<()> {
+    const PATH: &str = "/tmp/test_keepalive_idle_input.wav";
+    write_wav(PATH, 4410)?;
+    let mut server = server_manager::Server::new(ADDRESS.to_string(), 0, PATH.to_string()).await?;
+    server.set_keepalive(Duration::from_millis(10), Duration::from_millis(100));
+    let port = server.local_addrs()[0].port();
+    tokio::spawn(Arc::new(server).run());
+
+    // An idle client answering PINGs stays connected and can still stream.
+    let socket = TcpStream::connect(format!("{}:{}", ADDRESS, port)).await?;
+    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+    handshake(&mut framed).await?;
+    let mut pings = 0;
+    let idle = tokio::time::sleep(Duration::from_millis(300));
+    tokio::pin!(idle);
+    loop {
+        let frame = tokio::select! {
+            frame = framed.next() => frame.unwrap()?,
+            _ = &mut idle => break,
+        };
+        assert_eq!(Message::decode(&frame)?, Message::Ping);
+        pings += 1;
+        framed.send(Bytes::from(Message::Pong.encode())).await?;
+    }
+    assert!(pings > 0);
+    framed
+        .send(Bytes::from(Message::StartPlaying.encode()))
+        .await?;
+    let header = loop {
+        let message = Message::decode(&framed.next().await.unwrap()?)?;
+        if message != Message::Ping {
+            break message;
+        }
+        framed.send(Bytes::from(Message::Pong.encode())).await?;
+    };
+    assert!(matches!(header, Message::AudioHeader(_)));
+
+    // One that does not answer is disconnected without ever streaming.
+    let socket = TcpStream::connect(format!("{}:{}", ADDRESS, port)).await?;
+    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+    handshake(&mut framed).await?;
+    let closed = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(frame)) = framed.next().await {
+            assert_eq!(Message::decode(&frame)?, Message::Ping);
+        }
+        Ok::<(), anyhow::Error>(())
+    })
+    .await;
+    assert!(closed.is_ok());
+
+    Ok(())
+}
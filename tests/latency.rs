@@ -0,0 +1,65 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::network::latency::LatencyTracker;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8090;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_timestamped.wav";
+
+#[test]
+fn test_latency_tracker_ignores_clock_offset() {
+    let mut tracker = LatencyTracker::default();
+    assert!(tracker.estimate().is_none());
+
+    // The client clock is 5 s ahead: a constant transit time has no jitter
+    // and no delay above the minimum, whatever the offset.
+    let offset = 5_000_000;
+    for i in 0..100u64 {
+        tracker.record(i * 1000, i * 1000 + offset + 2000);
+    }
+    let estimate = tracker.estimate().unwrap();
+    assert_eq!(estimate.frames, 100);
+    assert_eq!(estimate.jitter, Duration::ZERO);
+    assert_eq!(estimate.delay_above_min, Duration::ZERO);
+
+    // Transit alternating between 2 ms and 12 ms converges to 10 ms jitter.
+    for i in 100..400u64 {
+        let transit = if i % 2 == 0 { 2000 } else { 12_000 };
+        tracker.record(i * 1000, i * 1000 + offset + transit);
+    }
+    let estimate = tracker.estimate().unwrap();
+    assert!(estimate.jitter > Duration::from_millis(9));
+    assert!(estimate.jitter <= Duration::from_millis(10));
+    assert!(estimate.delay_above_min > Duration::from_millis(3));
+    assert!(estimate.delay_above_min < Duration::from_millis(7));
+}
+
+#[tokio::test]
+async fn test_timestamped_streaming() -> Result<()> {
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_frame_timestamps(true);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    assert!(handler.latency().is_none());
+    handler
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .start_playing()
+        .await?;
+
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    let estimate = handler.latency().unwrap();
+    assert!(estimate.frames > 0);
+    assert!(estimate.jitter < Duration::from_secs(1));
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager;
+use streamapp::network::loopback;
+use streamapp::server::server_manager;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_loopback.wav";
+
+#[tokio::test]
+async fn test_loopback_pair() -> Result<()> {
+    let (mut a, mut b) = loopback::create_loopback_pair();
+    a.write_all(b"ping").await?;
+    let mut received = [0u8; 4];
+    b.read_exact(&mut received).await?;
+    assert_eq!(&received, b"ping");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_audio_streaming_over_loopback() -> Result<()> {
+    let (server, connector) = server_manager::Server::new_loopback(PATH_INPUT.to_string());
+    tokio::spawn(Arc::new(server).run());
+
+    for _ in 0..2 {
+        let mut client = client_manager::ClientInterface::connect_loopback(&connector).await?;
+        client
+            .add_capability(client_manager::Capabilities::SaveToFile(
+                PATH_OUTPUT.to_string(),
+            ))
+            .start_playing()
+            .await?;
+        assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    }
+    Ok(())
+}
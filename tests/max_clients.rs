@@ -0,0 +1,75 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager::ClientInterface;
+use streamapp::protocol::{ProtocolError, ProtocolErrorCode};
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8110;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const MAX_CLIENTS: usize = 2;
+
+fn rejection(result: Result<ClientInterface>) -> Option<ProtocolErrorCode> {
+    match result.err()?.downcast_ref::<ProtocolError>()? {
+        ProtocolError::Rejected { code, .. } => Some(*code),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn test_max_clients() -> Result<()> {
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_max_clients(MAX_CLIENTS);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut clients = vec![];
+    for _ in 0..MAX_CLIENTS {
+        clients.push(ClientInterface::connect(ADDRESS.to_string(), PORT).await?);
+    }
+    assert_eq!(
+        rejection(ClientInterface::connect(ADDRESS.to_string(), PORT).await),
+        Some(ProtocolErrorCode::ServerFull)
+    );
+
+    // A slot is freed once its client is gone
+    drop(clients.pop());
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    clients.push(ClientInterface::connect(ADDRESS.to_string(), PORT).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refused_connections_are_bounded() -> Result<()> {
+    use tokio::io::AsyncReadExt;
+
+    let mut server =
+        server_manager::Server::new("127.0.0.1".to_string(), 0, PATH_INPUT.to_string()).await?;
+    server.set_config(server_manager::ServerConfig {
+        max_clients: Some(0),
+        reject_timeout: std::time::Duration::from_millis(200),
+        max_pending_rejections: 1,
+    });
+    let addr = server.local_addrs()[0];
+    tokio::spawn(Arc::new(server).run());
+
+    // Never says HELLO, so it holds the only rejection slot until it times out
+    let mut silent = tokio::net::TcpStream::connect(addr).await?;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    // Closed right away, without waiting for a HELLO
+    let mut flood = tokio::net::TcpStream::connect(addr).await?;
+    let closed = tokio::time::timeout(std::time::Duration::from_millis(100), async {
+        flood.read(&mut [0u8; 16]).await
+    })
+    .await?;
+    assert!(matches!(closed, Ok(0) | Err(_)));
+
+    // The silent client is dropped once the reject timeout is over
+    let closed = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+        silent.read(&mut [0u8; 16]).await
+    })
+    .await?;
+    assert!(matches!(closed, Ok(0) | Err(_)));
+    Ok(())
+}
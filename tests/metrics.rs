@@ -0,0 +1,60 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::metrics::PrometheusExporter;
+use streamapp::server::server_manager;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8124;
+const METRICS_PORT: u16 = 8125;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+async fn scrape(path: &str) -> Result<String> {
+    let mut stream = tokio::net::TcpStream::connect((ADDRESS, METRICS_PORT)).await?;
+    let request = format!("GET {path} HTTP/1.1\r\nHost: {ADDRESS}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response)
+}
+
+fn metric(response: &str, name: &str) -> u64 {
+    response
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.trim().parse().ok())
+        .unwrap_or_else(|| panic!("{name} missing from {response}"))
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    let registry = server.registry();
+    tokio::spawn(Arc::new(server).run());
+    tokio::spawn(PrometheusExporter::new(registry.clone()).serve(ADDRESS, METRICS_PORT));
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.start_playing().await?;
+    drop(client);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !registry.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+
+    let response = scrape("/metrics").await?;
+    assert!(response.starts_with("HTTP/1.1 200"));
+    // The WAV data chunk, whatever the header around it
+    let bytes_sent = metric(&response, "rstream_bytes_sent_total");
+    assert!(bytes_sent > 1_000_000);
+    assert!(bytes_sent < std::fs::metadata(PATH_INPUT)?.len());
+    assert!(metric(&response, "rstream_frames_sent_total") > 0);
+    assert_eq!(metric(&response, "rstream_connected_clients_gauge"), 0);
+    assert_eq!(metric(&response, "rstream_stream_errors_total"), 0);
+
+    assert!(scrape("/").await?.starts_with("HTTP/1.1 404"));
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::audio::file::{AudioReader, FileFormat};
+use streamapp::audio::mp3::Mp3FileRead;
+use streamapp::client::client_manager;
+use streamapp::protocol::AudioHeader;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8103;
+const PATH_INPUT: &str = "/tmp/test_input.mp3";
+const PATH_OUTPUT: &str = "/tmp/test_output_mp3.wav";
+const MP3_FRAMES: usize = 40;
+const SAMPLES_PER_FRAME: usize = 1152;
+
+// MPEG-1 Layer III frames at 128 kbit/s and 44.1 kHz whose side information
+// and main data are all zeros, which decode to silence.
+fn write_silent_mp3(path: &str, stereo: bool) -> Result<()> {
+    let mode = if stereo { 0x00 } else { 0xC0 };
+    let mut file = vec![];
+    for _ in 0..MP3_FRAMES {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, mode]);
+        file.extend(frame);
+    }
+    std::fs::write(path, file)?;
+    Ok(())
+}
+
+#[test]
+fn test_read_mp3_frames() -> Result<()> {
+    write_silent_mp3(PATH_INPUT, false)?;
+
+    let mut reader = Mp3FileRead::new();
+    reader.open_file(PATH_INPUT)?;
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    assert_eq!(header.get_channels(), 1);
+    assert_eq!(header.get_sample_rate(), 44100);
+    assert_eq!(header.get_bits_per_sample(), 16);
+
+    // Reads smaller than a frame are served from the decoded frame
+    let mut total = 0;
+    let mut buffer = [0xAAu8; 1000];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        assert!(buffer[..n].iter().all(|&b| b == 0));
+        total += n;
+        if n < buffer.len() {
+            break;
+        }
+    }
+    assert_eq!(total, MP3_FRAMES * SAMPLES_PER_FRAME * 2);
+
+    assert!(
+        Mp3FileRead::new()
+            .open_file("/nonexistent/file.mp3")
+            .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_mp3_file() -> Result<()> {
+    write_silent_mp3("/tmp/test_input_stereo.mp3", true)?;
+
+    let mut server = server_manager::Server::new(
+        ADDRESS.to_string(),
+        PORT,
+        "/tmp/test_input_stereo.mp3".to_string(),
+    )
+    .await?;
+    server.set_file_format(FileFormat::Mp3);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    client.start_playing().await?;
+
+    let reader = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(reader.spec().channels, 2);
+    assert_eq!(reader.spec().sample_rate, 44100);
+    assert_eq!(reader.spec().bits_per_sample, 16);
+    assert_eq!(reader.duration() as usize, MP3_FRAMES * SAMPLES_PER_FRAME);
+    Ok(())
+}
+
+#[test]
+fn test_seek_mp3() -> Result<()> {
+    const PATH_SEEK: &str = "/tmp/test_input_seek.mp3";
+    write_silent_mp3(PATH_SEEK, true)?;
+
+    let mut reader = Mp3FileRead::new();
+    reader.open_file(PATH_SEEK)?;
+    // Not stored in the file
+    assert_eq!(reader.total_samples(), 0);
+    let whole = common::read_to_end(&mut reader);
+    assert_eq!(whole.len(), MP3_FRAMES * SAMPLES_PER_FRAME * 2 * 2);
+
+    reader.seek_to_sample(0)?;
+    assert_eq!(common::read_to_end(&mut reader).len(), whole.len());
+    reader.seek_to_sample(1000)?;
+    assert_eq!(
+        common::read_to_end(&mut reader).len(),
+        whole.len() - 1000 * 2 * 2
+    );
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::normalize::AudioNormalizer;
+use streamapp::audio::wav::WavFileRead;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8139;
+
+fn write_wav<S: hound::Sample + Copy>(
+    path: &str,
+    bits_per_sample: u16,
+    sample_format: hound::SampleFormat,
+    samples: &[S],
+) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample,
+        sample_format,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+fn normalized(path: &str) -> Result<(f32, Vec<u8>)> {
+    let mut wav = WavFileRead::new();
+    wav.open_file(path)?;
+    let mut normalizer = AudioNormalizer::new(wav)?;
+    let mut read = vec![];
+    // Odd buffer sizes still get whole samples
+    let mut buffer = vec![0u8; 1001];
+    loop {
+        let n = normalizer.read(&mut buffer)?;
+        if n == 0 {
+            return Ok((normalizer.gain(), read));
+        }
+        read.extend_from_slice(&buffer[..n]);
+    }
+}
+
+fn ramp(peak: f32) -> Vec<f32> {
+    (0..1000)
+        .map(|i| peak * ((i % 200) as f32 / 100.0 - 1.0))
+        .collect()
+}
+
+#[test]
+fn test_quiet_i16_brought_up() -> Result<()> {
+    let path = "/tmp/test_normalize_i16.wav";
+    let samples: Vec<i16> = ramp(1000.0).iter().map(|&s| s as i16).collect();
+    write_wav(path, 16, hound::SampleFormat::Int, &samples)?;
+
+    let (gain, read) = normalized(path)?;
+    assert!(gain > 1.0);
+    let read: Vec<i16> = read
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    assert_eq!(read.len(), samples.len());
+    let peak = read.iter().map(|s| s.unsigned_abs()).max().unwrap();
+    assert!((29_400..=29_500).contains(&peak));
+    // The whole file, from its first sample
+    assert_eq!(read[0], -29_491);
+    Ok(())
+}
+
+#[test]
+fn test_loud_f32_brought_down_without_clipping() -> Result<()> {
+    let path = "/tmp/test_normalize_f32.wav";
+    let samples = ramp(1.0);
+    write_wav(path, 32, hound::SampleFormat::Float, &samples)?;
+
+    let (gain, read) = normalized(path)?;
+    assert!((gain - 0.9).abs() < 1e-6);
+    let read: Vec<f32> = read
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    for (read, sample) in read.iter().zip(&samples) {
+        assert!((read - sample * 0.9).abs() < 1e-6);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_i32_and_silence() -> Result<()> {
+    let path = "/tmp/test_normalize_i32.wav";
+    let samples: Vec<i32> = ramp((1 << 20) as f32).iter().map(|&s| s as i32).collect();
+    write_wav(path, 32, hound::SampleFormat::Int, &samples)?;
+    let (_, read) = normalized(path)?;
+    let peak = read
+        .chunks_exact(4)
+        .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]).unsigned_abs())
+        .max()
+        .unwrap();
+    let target = 0.9 * i32::MAX as f64;
+    assert!((peak as f64 - target).abs() / target < 1e-3);
+
+    let path = "/tmp/test_normalize_silence.wav";
+    write_wav(path, 16, hound::SampleFormat::Int, &[0i16; 100])?;
+    let (gain, read) = normalized(path)?;
+    assert_eq!(gain, 1.0);
+    assert!(read.iter().all(|&b| b == 0));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_streams_normalized_file() -> Result<()> {
+    let path_input = "/tmp/test_normalize_served.wav";
+    let path_output = "/tmp/test_output_normalize.wav";
+    let samples: Vec<i16> = ramp(2000.0).iter().map(|&s| s as i16).collect();
+    write_wav(path_input, 16, hound::SampleFormat::Int, &samples)?;
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, path_input.to_string()).await?;
+    server.set_normalize(true);
+    tokio::spawn(Arc::new(server).run());
+
+    client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT)
+        .await?
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            path_output.to_string(),
+        ))
+        .start_playing()
+        .await?;
+
+    let received: Vec<i16> = hound::WavReader::open(path_output)?
+        .into_samples::<i16>()
+        .collect::<Result<_, _>>()?;
+    assert_eq!(received.len(), samples.len());
+    let peak = received.iter().map(|s| s.unsigned_abs()).max().unwrap();
+    assert!((29_400..=29_500).contains(&peak));
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use anyhow::Result;
+use std::num::{NonZeroU8, NonZeroU32};
+use std::sync::Arc;
+use streamapp::audio::file::{AudioReader, FileFormat};
+use streamapp::audio::ogg::OggVorbisFileRead;
+use streamapp::client::client_manager;
+use streamapp::protocol::AudioHeader;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8104;
+const PATH_INPUT: &str = "/tmp/test_input.ogg";
+const PATH_OUTPUT: &str = "/tmp/test_output_ogg.wav";
+// Long enough to span several Ogg pages: lewton trims the padding of the
+// last packet from the position of the previous page.
+const FRAMES: usize = 48000 * 5;
+
+// A 440 Hz tone at half scale on both channels.
+fn write_ogg(path: &str) -> Result<Vec<f32>> {
+    let tone: Vec<f32> = (0..FRAMES)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 48000.0).sin() * 0.5)
+        .collect();
+    let file = std::fs::File::create(path)?;
+    let mut encoder = vorbis_rs::VorbisEncoderBuilder::new_with_serial(
+        NonZeroU32::new(48000).unwrap(),
+        NonZeroU8::new(2).unwrap(),
+        file,
+        1,
+    )
+    .build()?;
+    for block in tone.chunks(1024) {
+        encoder.encode_audio_block([block, block])?;
+    }
+    encoder.finish()?;
+    Ok(tone)
+}
+
+#[test]
+fn test_read_ogg_vorbis() -> Result<()> {
+    let tone = write_ogg(PATH_INPUT)?;
+
+    let mut reader = OggVorbisFileRead::new();
+    reader.open_file(PATH_INPUT)?;
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    assert_eq!(header.get_channels(), 2);
+    assert_eq!(header.get_sample_rate(), 48000);
+    assert_eq!(header.get_bits_per_sample(), 16);
+
+    let mut samples = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        samples.extend(
+            buffer[..n]
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0),
+        );
+        if n < buffer.len() {
+            break;
+        }
+    }
+    assert_eq!(samples.len(), FRAMES * 2);
+
+    // Lossy, but close to the original tone
+    let error = samples
+        .chunks_exact(2)
+        .zip(&tone)
+        .map(|(frame, expected)| (frame[0] - expected).abs())
+        .sum::<f32>()
+        / FRAMES as f32;
+    assert!(error < 0.01, "mean error {}", error);
+
+    assert!(
+        OggVorbisFileRead::new()
+            .open_file("/nonexistent/file.ogg")
+            .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_ogg_file() -> Result<()> {
+    write_ogg("/tmp/test_input_stream.ogg")?;
+
+    let mut server = server_manager::Server::new(
+        ADDRESS.to_string(),
+        PORT,
+        "/tmp/test_input_stream.ogg".to_string(),
+    )
+    .await?;
+    server.set_file_format(FileFormat::Ogg);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    client.start_playing().await?;
+
+    let reader = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(reader.spec().channels, 2);
+    assert_eq!(reader.spec().sample_rate, 48000);
+    assert_eq!(reader.spec().bits_per_sample, 16);
+    assert_eq!(reader.duration() as usize, FRAMES);
+    Ok(())
+}
+
+#[test]
+fn test_seek_ogg_vorbis() -> Result<()> {
+    const PATH_SEEK: &str = "/tmp/test_input_seek.ogg";
+    write_ogg(PATH_SEEK)?;
+
+    let mut reader = OggVorbisFileRead::new();
+    reader.open_file(PATH_SEEK)?;
+    assert_eq!(reader.total_samples(), FRAMES as u64);
+    let whole = common::read_to_end(&mut reader);
+    assert_eq!(whole.len(), FRAMES * 2 * 2);
+
+    reader.seek_to_sample(0)?;
+    assert_eq!(common::read_to_end(&mut reader), whole);
+    reader.seek_to_sample(10_000)?;
+    assert_eq!(common::read_to_end(&mut reader), whole[10_000 * 4..]);
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::audio::file::{AudioReader, FileFormat};
+use streamapp::audio::opus::{OpusDecoder, OpusEncoder, OpusFileRead};
+use streamapp::client::client_manager;
+use streamapp::protocol::{AudioCodec, AudioHeader};
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8105;
+const PATH_INPUT: &str = "/tmp/test_input.opus";
+const PATH_INPUT_WAV: &str = "/tmp/test_input_opus.wav";
+const PATH_OUTPUT: &str = "/tmp/test_output_opus.wav";
+// Samples per channel in a 10 ms frame at 48 kHz
+const FRAME: usize = 480;
+// Encoder lookahead of the restricted low-delay mode: 2.5 ms at 48 kHz
+const PRE_SKIP: usize = 120;
+// Not a whole number of frames
+const FRAMES: usize = 48000 + 100;
+
+// A 440 Hz tone at half scale.
+fn tone() -> Vec<f32> {
+    (0..FRAMES)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 48000.0).sin() * 0.5)
+        .collect()
+}
+
+fn stereo(mono: &[f32]) -> Vec<f32> {
+    mono.iter().flat_map(|&s| [s, s]).collect()
+}
+
+fn mean_error(samples: &[i16], tone: &[f32]) -> f32 {
+    samples
+        .chunks_exact(2)
+        .zip(tone)
+        .map(|(frame, expected)| (frame[0] as f32 / 32768.0 - expected).abs())
+        .sum::<f32>()
+        / tone.len() as f32
+}
+
+fn write_ogg_opus(path: &str, tone: &[f32]) -> Result<()> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(2);
+    head.extend((PRE_SKIP as u16).to_le_bytes());
+    head.extend(48000u32.to_le_bytes());
+    head.extend(0i16.to_le_bytes());
+    head.push(0);
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend(0u32.to_le_bytes());
+    tags.extend(0u32.to_le_bytes());
+
+    // Pad with the lookahead so that the end of the tone gets out of the
+    // encoder.
+    let mut encoder = OpusEncoder::new(48000, 2)?;
+    let mut packets = encoder.encode(&stereo(tone))?;
+    packets.extend(encoder.encode(&vec![0.0; PRE_SKIP * 2])?);
+    packets.extend(encoder.flush()?);
+
+    let mut writer = ogg::PacketWriter::new(std::fs::File::create(path)?);
+    writer.write_packet(
+        head.into_boxed_slice(),
+        1,
+        ogg::PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+    writer.write_packet(
+        tags.into_boxed_slice(),
+        1,
+        ogg::PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+    let count = packets.len();
+    for (i, packet) in packets.into_iter().enumerate() {
+        let (end_info, position) = if i + 1 == count {
+            (ogg::PacketWriteEndInfo::EndStream, PRE_SKIP + tone.len())
+        } else {
+            (ogg::PacketWriteEndInfo::NormalPacket, (i + 1) * FRAME)
+        };
+        writer.write_packet(packet.into_boxed_slice(), 1, end_info, position as u64)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_opus_round_trip() -> Result<()> {
+    let tone = tone();
+    let mut encoder = OpusEncoder::new(48000, 2)?;
+    let mut decoder = OpusDecoder::new(48000, 2)?;
+
+    let mut packets = encoder.encode(&stereo(&tone))?;
+    // Partial frames wait for the flush
+    assert_eq!(packets.len(), FRAMES / FRAME);
+    packets.extend(encoder.flush()?);
+    assert_eq!(packets.len(), FRAMES.div_ceil(FRAME));
+
+    let mut samples = vec![];
+    for packet in &packets {
+        let decoded = decoder.decode(packet)?;
+        assert_eq!(decoded.len(), FRAME * 2);
+        samples.extend(decoded);
+    }
+
+    // Lossy and delayed by the encoder lookahead, but close to the tone
+    let error = mean_error(&samples[PRE_SKIP * 2..], &tone[..FRAMES - PRE_SKIP]);
+    assert!(error < 0.05, "mean error {}", error);
+    Ok(())
+}
+
+#[test]
+fn test_read_ogg_opus() -> Result<()> {
+    let tone = tone();
+    write_ogg_opus(PATH_INPUT, &tone)?;
+
+    let mut reader = OpusFileRead::new();
+    reader.open_file(PATH_INPUT)?;
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    assert_eq!(header.get_channels(), 2);
+    assert_eq!(header.get_sample_rate(), 48000);
+    assert_eq!(header.get_bits_per_sample(), 16);
+
+    let mut samples = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        samples.extend(
+            buffer[..n]
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]])),
+        );
+        if n < buffer.len() {
+            break;
+        }
+    }
+    // Pre-skip and end trimming give back the exact length
+    assert_eq!(samples.len(), FRAMES * 2);
+    let error = mean_error(&samples, &tone);
+    assert!(error < 0.05, "mean error {}", error);
+
+    let duration = streamapp::audio::opus::opus_duration(PATH_INPUT)?;
+    assert_eq!(
+        duration,
+        std::time::Duration::from_secs_f64(FRAMES as f64 / 48000.0)
+    );
+
+    assert!(
+        OpusFileRead::new()
+            .open_file("/nonexistent/file.opus")
+            .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_opus_codec() -> Result<()> {
+    let mut writer = hound::WavWriter::create(
+        PATH_INPUT_WAV,
+        hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        },
+    )?;
+    let tone = tone();
+    for sample in stereo(&tone) {
+        writer.write_sample((sample * 32767.0) as i16)?;
+    }
+    writer.finalize()?;
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT_WAV.to_string()).await?;
+    server.set_file_format(FileFormat::Wav);
+    server.set_codec(AudioCodec::Opus);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    client.start_playing().await?;
+
+    let mut reader = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(reader.spec().channels, 2);
+    assert_eq!(reader.spec().sample_rate, 48000);
+    assert_eq!(reader.spec().bits_per_sample, 16);
+    // The last frame is padded with silence
+    assert_eq!(reader.duration() as usize, FRAMES.div_ceil(FRAME) * FRAME);
+
+    let samples = reader.samples::<i16>().collect::<Result<Vec<_>, _>>()?;
+    let error = mean_error(&samples[PRE_SKIP * 2..], &tone[..FRAMES - PRE_SKIP]);
+    assert!(error < 0.05, "mean error {}", error);
+    Ok(())
+}
+
+#[test]
+fn test_seek_ogg_opus() -> Result<()> {
+    const PATH_SEEK: &str = "/tmp/test_input_seek.opus";
+    write_ogg_opus(PATH_SEEK, &tone())?;
+
+    let mut reader = OpusFileRead::new();
+    reader.open_file(PATH_SEEK)?;
+    assert_eq!(reader.total_samples(), FRAMES as u64);
+    let whole = common::read_to_end(&mut reader);
+    assert_eq!(whole.len(), FRAMES * 2 * 2);
+
+    reader.seek_to_sample(0)?;
+    assert_eq!(common::read_to_end(&mut reader), whole);
+    reader.seek_to_sample(1000)?;
+    assert_eq!(common::read_to_end(&mut reader), whole[1000 * 4..]);
+    Ok(())
+}
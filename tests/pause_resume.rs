@@ -0,0 +1,77 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use streamapp::audio::ring;
+use streamapp::client::client_manager;
+use streamapp::network::buffer::BufferPolicy;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8100;
+const PATH_INPUT: &str = "/tmp/test_pause_resume_input.wav";
+const FRAMES: u32 = 1024 * 1024;
+
+#[tokio::test]
+async fn test_pause_and_resume() -> Result<()> {
+    // Every sample holds its own frame index
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 44100,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(PATH_INPUT, spec)?;
+    for i in 0..FRAMES {
+        writer.write_sample(i as i32)?;
+    }
+    writer.finalize()?;
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_max_buffered_bytes(16 * 1024, BufferPolicy::Throttle);
+    server.set_keepalive(Duration::from_millis(10), Duration::from_millis(100));
+    tokio::spawn(Arc::new(server).run());
+
+    // Drained slowly enough for the stream to last about a second
+    let (writer, mut reader) = ring::ring_buffer(16 * 1024);
+    let received_bytes = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&received_bytes);
+    let drain = std::thread::spawn(move || {
+        let mut received = vec![];
+        let mut buffer = [0u8; 4096];
+        while !reader.is_finished() {
+            let n = reader.read(&mut buffer);
+            received.extend_from_slice(&buffer[..n]);
+            counter.store(received.len(), Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        received
+    });
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    let controls = client.stream_controls();
+    client.add_capability(client_manager::Capabilities::RingBuffer(writer));
+    let pause_and_resume = async {
+        while received_bytes.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        controls.pause()?;
+        // Audio already in flight still arrives, then nothing until RESUME.
+        // Keepalive keeps the connection up in the meantime.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let paused_at = received_bytes.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(received_bytes.load(Ordering::SeqCst), paused_at);
+        assert!(paused_at < FRAMES as usize * 4);
+        controls.resume()
+    };
+    tokio::try_join!(client.start_playing(), pause_and_resume)?;
+    let received = drain.join().unwrap();
+    let samples = received
+        .chunks_exact(4)
+        .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()));
+    assert!(samples.eq(0..FRAMES as i32));
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use anyhow::Result;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::generator::GeneratorReader;
+use streamapp::audio::pipeline::{AudioPipeline, ChannelDownmix, GainFilter, SilencePad};
+use streamapp::audio::wav::WavFileRead;
+use streamapp::protocol::AudioHeader;
+
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+fn read_all(reader: &mut impl AudioReader, buffer_size: usize) -> Result<Vec<u8>> {
+    let mut read = vec![];
+    let mut buffer = vec![0u8; buffer_size];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(read);
+        }
+        read.extend_from_slice(&buffer[..n]);
+    }
+}
+
+fn to_f32(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+fn header_of(reader: &mut impl AudioReader) -> AudioHeader {
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    header
+}
+
+#[test]
+fn test_gain_and_silence_pad() -> Result<()> {
+    let source = GeneratorReader::new(8000, 2, |frame, channel| {
+        if channel == 0 {
+            0.5
+        } else {
+            -(frame as f32) / 100.0
+        }
+    })
+    .with_total_frames(10);
+    let mut pipeline = AudioPipeline::new(source)
+        .with_filter(GainFilter(0.5))
+        .with_filter(SilencePad(5));
+
+    let samples = to_f32(&read_all(&mut pipeline, 4096)?);
+    assert_eq!(samples.len(), 15 * 2);
+    assert_eq!(samples[0], 0.25);
+    assert_eq!(samples[19], -0.045);
+    assert!(samples[20..].iter().all(|&s| s == 0.0));
+    // Padded once only
+    assert_eq!(pipeline.read(&mut [0u8; 64])?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_downmix_keeps_sample_format() -> Result<()> {
+    let mut wav = WavFileRead::new();
+    wav.open_file(PATH_INPUT)?;
+    let source = header_of(&mut wav);
+    let mut pipeline = AudioPipeline::new(wav).with_filter(ChannelDownmix {
+        from: source.get_channels(),
+        to: 1,
+    });
+    let header = header_of(&mut pipeline);
+    assert_eq!(header.get_channels(), 1);
+    assert_eq!(header.get_sample_rate(), source.get_sample_rate());
+    assert_eq!(header.get_bits_per_sample(), source.get_bits_per_sample());
+
+    // Odd buffer sizes still get whole samples
+    let mono = read_all(&mut pipeline, 1001)?;
+    let stereo: Vec<i16> = hound::WavReader::open(PATH_INPUT)?
+        .into_samples::<i16>()
+        .collect::<Result<_, _>>()?;
+    assert_eq!(mono.len(), stereo.len());
+    let mono: Vec<i16> = mono
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    for (sample, frame) in mono.iter().zip(stereo.chunks_exact(2)).step_by(997) {
+        let average = (frame[0] as i32 + frame[1] as i32) / 2;
+        assert!((*sample as i32 - average).abs() <= 1);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_pipeline_over_boxed_reader_and_seek() -> Result<()> {
+    let source: Box<dyn AudioReader + Send> =
+        Box::new(GeneratorReader::new(8000, 1, |frame, _| {
+            frame as f32 / 1000.0
+        }));
+    let mut pipeline = AudioPipeline::new(source).with_filter(GainFilter(2.0));
+
+    let mut buffer = [0u8; 16];
+    pipeline.read(&mut buffer)?;
+    assert_eq!(to_f32(&buffer), vec![0.0, 0.002, 0.004, 0.006]);
+    pipeline.seek_to_sample(100)?;
+    pipeline.read(&mut buffer)?;
+    assert_eq!(to_f32(&buffer)[0], 0.2);
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::network::playlist::read_m3u;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8107;
+const PATH_FIRST: &str = "/tmp/test_input_playlist_1.wav";
+const PATH_SECOND: &str = "/tmp/test_input_playlist_2.wav";
+const PATH_M3U: &str = "/tmp/test_input_playlist.m3u";
+const PATH_OUTPUT: &str = "/tmp/test_output_playlist.wav";
+const SAMPLE_RATE: u32 = 8000;
+const GAP: Duration = Duration::from_millis(100);
+
+fn write_track(path: &str, samples: &[i16]) -> Result<()> {
+    let mut writer = hound::WavWriter::create(
+        path,
+        hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        },
+    )?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+#[test]
+fn test_read_m3u() -> Result<()> {
+    std::fs::write(
+        PATH_M3U,
+        "#EXTM3U\n#EXTINF:1,First\ntest_input_playlist_1.wav\n\n/abs/second.wav\n",
+    )?;
+    assert_eq!(
+        read_m3u(PATH_M3U)?,
+        vec![PATH_FIRST.to_string(), "/abs/second.wav".to_string()]
+    );
+    assert!(read_m3u("/nonexistent/playlist.m3u").is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_playlist() -> Result<()> {
+    let first: Vec<i16> = (1..=2000).collect();
+    let second: Vec<i16> = (-3000..0).collect();
+    write_track(PATH_FIRST, &first)?;
+    write_track(PATH_SECOND, &second)?;
+
+    // The missing track is skipped
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_FIRST.to_string()).await?;
+    let mut server = server.with_playlist(vec![
+        PATH_FIRST.to_string(),
+        "/nonexistent/track.wav".to_string(),
+        PATH_SECOND.to_string(),
+    ]);
+    server.set_track_gap(GAP);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    client.start_playing().await?;
+
+    let mut reader = hound::WavReader::open(PATH_OUTPUT)?;
+    let samples: Vec<i16> = reader.samples::<i16>().collect::<Result<_, _>>()?;
+    let gap = (GAP.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+    let mut expected = first.clone();
+    expected.extend(vec![0; gap]);
+    expected.extend(&second);
+    assert_eq!(samples, expected);
+    Ok(())
+}
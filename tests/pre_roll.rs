@@ -0,0 +1,64 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager;
+use streamapp::protocol::SampleFormat;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8093;
+const PATH_JINGLE: &str = "/tmp/test_pre_roll_jingle.wav";
+const PATH_CONTENT: &str = "/tmp/test_pre_roll_content.wav";
+const PATH_OUTRO: &str = "/tmp/test_pre_roll_outro.wav";
+
+fn write_constant_wav(path: &str, frames: usize, float: bool, value: f32) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample: if float { 32 } else { 16 },
+        sample_format: if float {
+            hound::SampleFormat::Float
+        } else {
+            hound::SampleFormat::Int
+        },
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for _ in 0..frames * 2 {
+        if float {
+            writer.write_sample(value)?;
+        } else {
+            writer.write_sample((value * 32768.0) as i16)?;
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pre_and_post_roll() -> Result<()> {
+    // The jingle is in another format than the content, the outro is not.
+    write_constant_wav(PATH_JINGLE, 400, true, 0.25)?;
+    write_constant_wav(PATH_CONTENT, 1000, false, 0.5)?;
+    write_constant_wav(PATH_OUTRO, 300, false, -0.25)?;
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_CONTENT.to_string()).await?;
+    server
+        .set_pre_roll(PATH_JINGLE.to_string())
+        .set_post_roll(PATH_OUTRO.to_string());
+    tokio::spawn(Arc::new(server).run());
+
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    let collected = handler.collect_samples().await?;
+
+    assert!(matches!(
+        collected.header.get_sample_format(),
+        SampleFormat::Int
+    ));
+    let mut expected = vec![0.25; 400];
+    expected.extend(vec![0.5; 1000]);
+    expected.extend(vec![-0.25; 300]);
+    assert_eq!(collected.channel(0), expected);
+    assert_eq!(collected.channel(1), expected);
+
+    Ok(())
+}
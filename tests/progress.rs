@@ -0,0 +1,80 @@
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8114;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_progress.wav";
+
+#[tokio::test]
+async fn test_progress_callback() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let totals = Arc::new(Mutex::new(vec![]));
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    let totals_seen = Arc::clone(&totals);
+    client
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .set_progress_callback(move |bytes| {
+            totals_seen.lock().unwrap().push(bytes);
+        })
+        .start_playing()
+        .await?;
+
+    let reader = hound::WavReader::open(PATH_INPUT)?;
+    let audio_bytes = reader.len() as u64 * reader.spec().bits_per_sample as u64 / 8;
+    // Every frame has been reported by the time the stream returns, in order
+    let totals = totals.lock().unwrap();
+    assert!(totals.len() > 1);
+    assert!(totals.windows(2).all(|pair| pair[0] < pair[1]));
+    // Frames carry a few bytes of framing on top of the audio
+    assert!(*totals.last().unwrap() >= audio_bytes);
+    // Announced before the stream, enough for a progress bar
+    assert_eq!(
+        client.stream_total_samples(),
+        Some(reader.duration() as u64)
+    );
+    Ok(())
+}
+
+async fn progress_percents(server: server_manager::Server) -> Result<Vec<u8>> {
+    let port = server.local_addrs()[0].port();
+    tokio::spawn(Arc::new(server).run());
+
+    let percents = Arc::new(Mutex::new(vec![]));
+    let percents_seen = Arc::clone(&percents);
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), port).await?;
+    client
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            "/tmp/test_output_progress_percent.wav".to_string(),
+        ))
+        .on_progress_percent(move |percent| {
+            percents_seen.lock().unwrap().push(percent);
+        })
+        .start_playing()
+        .await?;
+    Ok(std::mem::take(&mut *percents.lock().unwrap()))
+}
+
+#[tokio::test]
+async fn test_progress_percent() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), 0, PATH_INPUT.to_string()).await?;
+    let percents = progress_percents(server).await?;
+    assert!(percents.len() > 1);
+    assert!(percents.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(percents.last(), Some(&100));
+
+    // Playlists do not announce their length.
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), 0, PATH_INPUT.to_string()).await?;
+    let server = server.with_playlist(vec![PATH_INPUT.to_string()]);
+    assert!(progress_percents(server).await?.is_empty());
+    Ok(())
+}
@@ -0,0 +1,494 @@
+use streamapp::protocol::{
+    self, AudioCodec, AudioHeader, Message, MessageType, ProtocolError, ProtocolInfo,
+};
+
+fn wav_spec() -> hound::WavSpec {
+    hound::WavSpec {
+        channels: 2,
+        sample_rate: 48000,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    }
+}
+
+fn round_trip(message: &Message) -> Message {
+    Message::decode(&message.encode()).unwrap()
+}
+
+#[test]
+fn test_hello_round_trip() {
+    let hello = Message::hello(None);
+    assert_eq!(
+        hello,
+        Message::Hello {
+            magic: protocol::PROTOCOL_MAGIC,
+            version_min: protocol::PROTOCOL_VERSION_MIN,
+            version_max: protocol::PROTOCOL_VERSION_MAX,
+            token: None,
+        }
+    );
+    assert_eq!(round_trip(&hello), hello);
+    assert_eq!(
+        protocol::extract_message_type(&hello.encode()),
+        Some(MessageType::Hello)
+    );
+
+    let mut bad_magic = hello.encode();
+    bad_magic[1] ^= 0xFF;
+    assert!(!matches!(
+        Message::decode(&bad_magic),
+        Ok(Message::Hello { .. })
+    ));
+
+    let hello = Message::hello(Some("secret"));
+    assert_eq!(round_trip(&hello), hello);
+
+    let info = ProtocolInfo::new().with_encrypted_audio(true);
+    let server_hello = Message::ProtocolInfo(info).encode();
+    assert_eq!(
+        protocol::extract_message_type(&server_hello),
+        Some(MessageType::Hello)
+    );
+    let Ok(Message::ProtocolInfo(decoded)) = Message::decode(&server_hello) else {
+        panic!("server HELLO not decoded as protocol info");
+    };
+    assert!(decoded.is_audio_encrypted());
+}
+
+#[test]
+fn test_control_messages_round_trip() {
+    let messages = [
+        (Message::Ok, MessageType::Ok),
+        (Message::Bye, MessageType::Bye),
+        (Message::StartPlaying, MessageType::StartPlaying),
+        (Message::StopPlaying, MessageType::StopPlaying),
+        (Message::EndOfTrack, MessageType::EndOfTrack),
+    ];
+
+    for (message, msg_type) in &messages {
+        let bytes = message.encode();
+        assert_eq!(bytes.len(), 1);
+        assert_eq!(protocol::extract_message_type(&bytes), Some(*msg_type));
+        assert_eq!(message.message_type(), *msg_type);
+        assert_eq!(&round_trip(message), message);
+
+        // Control messages carry no payload
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            Message::decode(&trailing),
+            Err(ProtocolError::Malformed(_))
+        ));
+    }
+}
+
+#[test]
+fn test_audio_header_round_trip() {
+    let mut header = AudioHeader::from_wav_spec(&wav_spec());
+
+    let bytes = Message::AudioHeader(header).encode();
+    assert_eq!(
+        protocol::extract_message_type(&bytes),
+        Some(MessageType::AudioHeader)
+    );
+
+    let Ok(Message::AudioHeader(decoded)) = Message::decode(&bytes) else {
+        panic!("AUDIO_HEADER not decoded");
+    };
+    assert_eq!(decoded.to_wavspec(), wav_spec());
+    assert_eq!(decoded.get_total_samples(), 0);
+
+    header.set_total_samples(48000 * 180);
+    assert_eq!(
+        round_trip(&Message::AudioHeader(header)),
+        Message::AudioHeader(header)
+    );
+
+    // A header payload behind another message type is rejected.
+    let mut wrong_type = bytes.clone();
+    wrong_type[0] = Message::Ok.encode()[0];
+    assert!(Message::decode(&wrong_type).is_err());
+    assert!(Message::decode(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn test_track_change_round_trip() {
+    let header = AudioHeader::from_wav_spec(&wav_spec());
+
+    let message = Message::TrackChange(header);
+    assert_eq!(
+        protocol::extract_message_type(&message.encode()),
+        Some(MessageType::TrackChange)
+    );
+    // Not mistaken for an in-band AUDIO_HEADER, nor the other way around
+    assert_eq!(round_trip(&message), message);
+    assert_eq!(
+        round_trip(&Message::AudioHeader(header)),
+        Message::AudioHeader(header)
+    );
+}
+
+#[test]
+fn test_audio_header_codec_round_trip() {
+    let mut header = AudioHeader::from_wav_spec(&wav_spec());
+    assert_eq!(header.get_codec(), AudioCodec::Raw);
+
+    // Opus packets decode to 16-bit integer samples
+    header.set_codec(AudioCodec::Opus);
+    assert_eq!(header.get_bits_per_sample(), 16);
+
+    let Message::AudioHeader(decoded) = round_trip(&Message::AudioHeader(header)) else {
+        panic!("AUDIO_HEADER not decoded");
+    };
+    assert_eq!(decoded, header);
+    assert_eq!(decoded.get_codec(), AudioCodec::Opus);
+}
+
+#[test]
+fn test_audio_header_default_and_hash() {
+    let header = AudioHeader::default();
+    assert_eq!(header, AudioHeader::new());
+    assert_eq!(header.get_sample_rate(), 44100);
+    assert_eq!(header.get_channels(), 2);
+    assert_eq!(header.get_bits_per_sample(), 16);
+    assert_eq!(
+        header.get_sample_format(),
+        protocol::SampleFormat::default()
+    );
+    assert_eq!(header.get_sample_format(), protocol::SampleFormat::Int);
+
+    let float = AudioHeader::from_wav_spec(&wav_spec());
+    let mut float64_spec = wav_spec();
+    float64_spec.bits_per_sample = 64;
+    let float64 = AudioHeader::from_wav_spec(&float64_spec);
+    let mut opus = header;
+    opus.set_codec(AudioCodec::Opus);
+
+    let mut names = std::collections::HashMap::new();
+    names.insert(header, "cd");
+    names.insert(float, "float");
+    names.insert(float64, "float64");
+    names.insert(opus, "opus");
+    names.insert(AudioHeader::default(), "default");
+    assert_eq!(names.len(), 4);
+    assert_eq!(names[&header], "default");
+    assert_eq!(names[&float64], "float64");
+}
+
+#[test]
+fn test_audio_header_conversions() {
+    let header = AudioHeader::from(&wav_spec());
+    assert_eq!(header, AudioHeader::from_wav_spec(&wav_spec()));
+    assert_eq!(header.to_wavspec(), wav_spec());
+
+    let config = header.to_cpal_config();
+    assert_eq!(config.channels, 2);
+    assert_eq!(config.sample_rate, cpal::SampleRate(48000));
+    assert_eq!(config.buffer_size, cpal::BufferSize::Default);
+
+    let device_config = cpal::SupportedStreamConfig::new(
+        2,
+        cpal::SampleRate(48000),
+        cpal::SupportedBufferSize::Unknown,
+        cpal::SampleFormat::F32,
+    );
+    assert_eq!(AudioHeader::from(&device_config), header);
+    let device_config = cpal::SupportedStreamConfig::new(
+        1,
+        cpal::SampleRate(44100),
+        cpal::SupportedBufferSize::Unknown,
+        cpal::SampleFormat::I16,
+    );
+    let header = AudioHeader::from(&device_config);
+    assert_eq!(header.get_channels(), 1);
+    assert_eq!(header.get_bits_per_sample(), 16);
+    assert!(matches!(
+        header.get_sample_format(),
+        protocol::SampleFormat::Int
+    ));
+}
+
+#[test]
+fn test_audio_header_sizes() {
+    let header = |channels, sample_rate, bits_per_sample, sample_format| {
+        AudioHeader::from_wav_spec(&hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample,
+            sample_format,
+        })
+    };
+
+    let cd = header(2, 44100, 16, hound::SampleFormat::Int);
+    assert_eq!(cd.bit_rate(), 1_411_200);
+    assert_eq!(cd.frame_size(), 4);
+    assert_eq!(cd.bytes_for_duration(1.0), 176_400);
+    assert_eq!(cd.bytes_for_duration(0.5), 88_200);
+
+    let mono = header(1, 22050, 8, hound::SampleFormat::Int);
+    assert_eq!(mono.bit_rate(), 176_400);
+    assert_eq!(mono.frame_size(), 1);
+    assert_eq!(mono.bytes_for_duration(2.0), 44_100);
+
+    let float = header(2, 48000, 32, hound::SampleFormat::Float);
+    assert_eq!(float.bit_rate(), 3_072_000);
+    assert_eq!(float.frame_size(), 8);
+    assert_eq!(float.bytes_for_duration(0.01), 3_840);
+    assert_eq!(float.bytes_for_duration(0.0), 0);
+}
+
+#[test]
+fn test_audio_frame_never_mistaken_for_control() {
+    let stop = Message::StopPlaying.encode();
+    let frame = Message::AudioData(stop.clone());
+
+    assert_eq!(
+        protocol::extract_message_type(&frame.encode()),
+        Some(MessageType::AudioData)
+    );
+    assert_eq!(round_trip(&frame), Message::AudioData(stop));
+    assert_eq!(
+        round_trip(&Message::AudioData(Vec::new())),
+        Message::AudioData(Vec::new())
+    );
+}
+
+#[test]
+fn test_timed_audio_frame_round_trip() {
+    let frame = Message::TimedAudioData {
+        sent_at_us: 1_700_000_000_123_456,
+        data: vec![1, 2, 3, 4],
+    };
+
+    assert_eq!(
+        protocol::extract_message_type(&frame.encode()),
+        Some(MessageType::TimedAudioData)
+    );
+    assert_eq!(round_trip(&frame), frame);
+
+    let info = ProtocolInfo::new().with_timestamped_frames(true);
+    let Message::ProtocolInfo(decoded) = round_trip(&Message::ProtocolInfo(info)) else {
+        panic!("server HELLO not decoded as protocol info");
+    };
+    assert!(decoded.has_timestamped_frames());
+    assert!(!decoded.is_audio_encrypted());
+}
+
+#[test]
+fn test_sequenced_audio_frame_round_trip() {
+    let mut header = AudioHeader::from_wav_spec(&wav_spec());
+    header.set_total_samples(48_000);
+    let frame = Message::AudioFrame(protocol::AudioFrame {
+        sequence: 41,
+        timestamp_us: 1_700_000_000_123_456,
+        header,
+        data: Message::StopPlaying.encode(),
+    });
+
+    assert_eq!(
+        protocol::extract_message_type(&frame.encode()),
+        Some(MessageType::AudioFrame)
+    );
+    assert_eq!(round_trip(&frame), frame);
+    assert!(matches!(
+        Message::decode(&frame.encode()[..4]),
+        Err(ProtocolError::Malformed(_))
+    ));
+}
+
+#[test]
+fn test_frame_checksum() {
+    let mut data = b"123456789".to_vec();
+    protocol::append_checksum(&mut data);
+    // CRC-32/ISO-HDLC check value
+    assert_eq!(data[9..], 0xCBF43926u32.to_le_bytes());
+    assert!(protocol::strip_checksum(&mut data));
+    assert_eq!(data, b"123456789");
+
+    let mut corrupted = data.clone();
+    protocol::append_checksum(&mut corrupted);
+    corrupted[0] ^= 1;
+    assert!(!protocol::strip_checksum(&mut corrupted));
+    assert!(!protocol::strip_checksum(&mut vec![0; 3]));
+
+    let nack = Message::Nack(1 << 40);
+    assert_eq!(
+        protocol::extract_message_type(&nack.encode()),
+        Some(MessageType::Nack)
+    );
+    assert_eq!(round_trip(&nack), nack);
+}
+
+#[test]
+fn test_stream_salt_round_trip() {
+    let salt = Message::StreamSalt([0xA5; 16]);
+    assert_eq!(
+        protocol::extract_message_type(&salt.encode()),
+        Some(MessageType::StreamSalt)
+    );
+    assert_eq!(round_trip(&salt), salt);
+    // A salt is always 16 bytes
+    let mut short = salt.encode();
+    short.pop();
+    assert!(Message::decode(&short).is_err());
+}
+
+#[test]
+fn test_request_file_round_trip() {
+    let long_name = "a".repeat(300);
+    for name in ["song.wav", long_name.as_str()] {
+        let request = Message::RequestFile(name.to_string());
+        assert_eq!(
+            protocol::extract_message_type(&request.encode()),
+            Some(MessageType::RequestFile)
+        );
+        assert_eq!(round_trip(&request), request);
+    }
+}
+
+#[test]
+fn test_version_negotiation() {
+    assert_eq!(protocol::negotiate_version((1, 3), (2, 5)), Ok(3));
+    assert_eq!(protocol::negotiate_version((2, 5), (1, 3)), Ok(3));
+    assert_eq!(protocol::negotiate_version((1, 1), (1, 1)), Ok(1));
+    assert_eq!(
+        protocol::negotiate_version((1, 2), (3, 4)),
+        Err(ProtocolError::VersionMismatch {
+            local: (1, 2),
+            remote: (3, 4)
+        })
+    );
+
+    let info = ProtocolInfo::new().with_version_range(2, 4).with_version(3);
+    let Message::ProtocolInfo(info) = round_trip(&Message::ProtocolInfo(info)) else {
+        panic!("server HELLO not decoded as protocol info");
+    };
+    assert_eq!(info.version_range(), (2, 4));
+    assert_eq!(info.version(), 3);
+}
+
+#[test]
+fn test_error_message_round_trip() {
+    let message = Message::error(protocol::ProtocolErrorCode::UnknownFile, "no such file");
+    assert_eq!(
+        protocol::extract_message_type(&message.encode()),
+        Some(MessageType::Error)
+    );
+    let code = protocol::ProtocolErrorCode::UnknownFile as u16;
+    assert_eq!(
+        round_trip(&message),
+        Message::Error {
+            code,
+            reason: "no such file".to_string()
+        }
+    );
+    assert_eq!(
+        protocol::ProtocolErrorCode::from_code(code),
+        Some(protocol::ProtocolErrorCode::UnknownFile)
+    );
+
+    // Long reasons are cut on a character boundary
+    let reason = "é".repeat(protocol::MAX_ERROR_REASON_LEN);
+    let message = Message::error(protocol::ProtocolErrorCode::UnknownFile, &reason);
+    let Message::Error {
+        reason: received, ..
+    } = round_trip(&message)
+    else {
+        panic!("ERROR not decoded");
+    };
+    assert_eq!(received.len(), protocol::MAX_ERROR_REASON_LEN);
+    assert!(reason.starts_with(&received));
+}
+
+#[test]
+fn test_ping_pong_messages() {
+    assert_eq!(round_trip(&Message::Ping), Message::Ping);
+    assert_eq!(Message::Ping.encode().len(), 1);
+    assert_eq!(
+        protocol::extract_message_type(&Message::Pong.encode()),
+        Some(MessageType::Pong)
+    );
+    assert_eq!(round_trip(&Message::Pong), Message::Pong);
+}
+
+#[test]
+fn test_seek_message_round_trip() {
+    for offset in [0, 250, 1 << 20, u64::MAX] {
+        let message = Message::Seek(offset);
+        assert_eq!(
+            protocol::extract_message_type(&message.encode()),
+            Some(MessageType::Seek)
+        );
+        assert_eq!(round_trip(&message), message);
+    }
+    assert!(Message::decode(&Message::Seek(1).encode()[..1]).is_err());
+}
+
+#[test]
+fn test_pause_resume_messages() {
+    assert_eq!(round_trip(&Message::Pause), Message::Pause);
+    assert_eq!(round_trip(&Message::Resume), Message::Resume);
+    assert_eq!(Message::Pause.encode().len(), 1);
+}
+
+#[test]
+fn test_volume_control_round_trip() {
+    for gain in [protocol::MIN_VOLUME_GAIN, 1.0, protocol::MAX_VOLUME_GAIN] {
+        let message = Message::VolumeControl(gain);
+        assert_eq!(round_trip(&message), message);
+    }
+    for gain in [-0.5, protocol::MAX_VOLUME_GAIN + 0.1, f32::NAN] {
+        assert!(matches!(
+            Message::decode(&Message::VolumeControl(gain).encode()),
+            Err(ProtocolError::Malformed(_))
+        ));
+    }
+}
+
+#[test]
+fn test_unknown_message_type_rejected() {
+    assert!(matches!(
+        Message::decode(&[0xFF]),
+        Err(ProtocolError::Malformed(_))
+    ));
+    assert!(Message::decode(&[]).is_err());
+}
+
+#[test]
+fn test_full_message_round_trip() {
+    let header = AudioHeader::from_wav_spec(&wav_spec());
+    let payload = [0u8, 1, 2, 3, 4, 5, 6, 7];
+
+    let message = protocol::make_full_message(&header, &payload);
+    assert_eq!(
+        protocol::extract_message_type(&message),
+        Some(MessageType::RawData)
+    );
+    assert_eq!(
+        Message::decode(&message),
+        Ok(Message::RawData {
+            header,
+            data: payload.to_vec()
+        })
+    );
+    assert_eq!(
+        Message::decode(&protocol::make_full_message(&header, &[])),
+        Ok(Message::RawData {
+            header,
+            data: Vec::new()
+        })
+    );
+}
+
+#[test]
+fn test_corrupt_length_rejected_without_allocating() {
+    // A file name claiming to be u64::MAX bytes long
+    let mut data = Message::RequestFile(String::new()).encode();
+    data.truncate(1);
+    data.push(0xFD);
+    data.extend_from_slice(&u64::MAX.to_le_bytes());
+    assert!(matches!(
+        Message::decode(&data),
+        Err(ProtocolError::Malformed(_))
+    ));
+}
@@ -0,0 +1,99 @@
+use proptest::prelude::*;
+use streamapp::protocol::{self, AudioCodec, AudioHeader, Message};
+
+fn audio_header() -> impl Strategy<Value = AudioHeader> {
+    let formats = prop_oneof![
+        Just((hound::SampleFormat::Int, 16)),
+        Just((hound::SampleFormat::Int, 24)),
+        Just((hound::SampleFormat::Int, 32)),
+        Just((hound::SampleFormat::Float, 32)),
+    ];
+    (
+        1..=768_000u32,
+        1..=32u16,
+        formats,
+        any::<u64>(),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(sample_rate, channels, (sample_format, bits), total, opus)| {
+                let mut header = AudioHeader::from_wav_spec(&hound::WavSpec {
+                    channels,
+                    sample_rate,
+                    bits_per_sample: bits,
+                    sample_format,
+                });
+                header.set_total_samples(total);
+                if opus {
+                    header.set_codec(AudioCodec::Opus);
+                }
+                header
+            },
+        )
+}
+
+fn round_trip(message: &Message) -> Message {
+    Message::decode(&message.encode()).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn prop_audio_header_round_trip(header in audio_header()) {
+        let message = Message::AudioHeader(header);
+        prop_assert_eq!(round_trip(&message), message);
+        let message = Message::TrackChange(header);
+        prop_assert_eq!(round_trip(&message), message);
+    }
+
+    #[test]
+    fn prop_full_message_round_trip(
+        header in audio_header(),
+        data in proptest::collection::vec(any::<u8>(), 0..1024),
+    ) {
+        let message = protocol::make_full_message(&header, &data);
+        prop_assert_eq!(Message::decode(&message), Ok(Message::RawData { header, data }));
+    }
+
+    #[test]
+    fn prop_hello_round_trip(
+        version_min in any::<u8>(),
+        version_max in any::<u8>(),
+        token in proptest::option::of(".{0,64}"),
+    ) {
+        let hello = Message::Hello {
+            magic: protocol::PROTOCOL_MAGIC,
+            version_min,
+            version_max,
+            token,
+        };
+        prop_assert_eq!(round_trip(&hello), hello);
+    }
+
+    #[test]
+    fn prop_audio_data_round_trip(
+        sent_at_us in any::<u64>(),
+        data in proptest::collection::vec(any::<u8>(), 0..1024),
+    ) {
+        let frame = Message::AudioData(data.clone());
+        prop_assert_eq!(round_trip(&frame), frame);
+        let frame = Message::TimedAudioData { sent_at_us, data };
+        prop_assert_eq!(round_trip(&frame), frame);
+    }
+
+    #[test]
+    fn prop_garbage_never_panics(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let _ = protocol::extract_message_type(&data);
+        let _ = Message::decode(&data);
+    }
+
+    // Garbage behind each valid message type byte reaches every decoder
+    #[test]
+    fn prop_garbage_payload_never_panics(
+        message_type in 0..32u8,
+        payload in proptest::collection::vec(any::<u8>(), 0..256),
+    ) {
+        let mut data = vec![message_type];
+        data.extend(payload);
+        let _ = Message::decode(&data);
+    }
+}
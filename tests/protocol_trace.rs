@@ -0,0 +1,101 @@
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use streamapp::client::client_manager;
+use streamapp::network::trace::{self, Direction, TraceEvent};
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8095;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_trace.wav";
+
+// Message sequence of one side of the connection, consecutive audio frames
+// collapsed into one entry.
+fn sequence(events: &[TraceEvent], side: &str) -> Vec<String> {
+    let mut sequence: Vec<String> = vec![];
+    for event in events.iter().filter(|e| e.connection.starts_with(side)) {
+        let direction = match event.direction {
+            Direction::Sent => "sent",
+            Direction::Received => "recv",
+        };
+        let entry = format!("{} {}", direction, event.message);
+        if event.message != "AudioFrame" || sequence.last() != Some(&entry) {
+            sequence.push(entry);
+        }
+    }
+    sequence
+}
+
+#[tokio::test]
+async fn test_trace_of_a_session() -> Result<()> {
+    let events = Arc::new(Mutex::new(Vec::<TraceEvent>::new()));
+    let captured = Arc::clone(&events);
+    trace::set_sink(move |event| captured.lock().unwrap().push(event.clone()));
+
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    client.start_playing().await?;
+    trace::disable();
+
+    let events = events.lock().unwrap().clone();
+
+    // Both sides name the connection after the client address
+    let client_connection = events
+        .iter()
+        .find(|e| e.connection.starts_with("client/"))
+        .map(|e| e.connection.clone())
+        .unwrap();
+    let address = client_connection.trim_start_matches("client/");
+    let server_connection = format!("server/{}", address);
+    assert!(
+        events
+            .iter()
+            .all(|e| e.connection == client_connection || e.connection == server_connection)
+    );
+
+    assert_eq!(
+        sequence(&events, "client/"),
+        [
+            "sent Hello",
+            "recv Hello",
+            "sent Ok",
+            "sent StartPlaying",
+            "recv AudioHeader",
+            "sent Ok",
+            "recv AudioFrame",
+            "recv StopPlaying",
+            "sent Bye",
+            "recv Bye",
+        ]
+    );
+    assert_eq!(
+        sequence(&events, "server/"),
+        [
+            "recv Hello",
+            "sent Hello",
+            "recv Ok",
+            "recv StartPlaying",
+            "sent AudioHeader",
+            "recv Ok",
+            "sent AudioFrame",
+            "sent StopPlaying",
+            "recv Bye",
+            "sent Bye",
+        ]
+    );
+
+    // Control messages are traced with their size, without payloads
+    let hello = &events[0];
+    assert_eq!(
+        hello.size,
+        streamapp::protocol::Message::hello(None).encode().len()
+    );
+
+    Ok(())
+}
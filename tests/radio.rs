@@ -0,0 +1,75 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8091;
+const RADIO_DIR: &str = "/tmp/test_radio";
+const SAMPLE_RATE: u32 = 8000;
+// (title, frames, constant sample value)
+const TRACKS: [(&str, u32, i16); 3] = [("a", 800, 1000), ("b", 1200, 2000), ("c", 1600, 3000)];
+
+fn write_radio_dir() -> Result<()> {
+    let _ = std::fs::remove_dir_all(RADIO_DIR);
+    std::fs::create_dir_all(RADIO_DIR)?;
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    for (title, frames, value) in TRACKS {
+        let mut writer = hound::WavWriter::create(format!("{}/{}.wav", RADIO_DIR, title), spec)?;
+        for _ in 0..frames * 2 {
+            writer.write_sample(value)?;
+        }
+        writer.finalize()?;
+    }
+    // Unreadable tracks and other files are skipped.
+    std::fs::write(format!("{}/broken.wav", RADIO_DIR), b"not a wav file")?;
+    std::fs::write(format!("{}/notes.txt", RADIO_DIR), b"playlist notes")?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_radio_streaming() -> Result<()> {
+    write_radio_dir()?;
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, RADIO_DIR.to_string()).await?;
+    server.set_radio_mode(true);
+    tokio::spawn(Arc::new(server).run());
+
+    // More tracks than the directory holds: the playlist is reshuffled and
+    // the stream goes on until the client leaves.
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    handler.set_track_limit(5);
+    let collected = handler.collect_samples().await?;
+
+    // Endless, hence of unknown length
+    assert_eq!(handler.stream_total_samples(), None);
+    let tracks = handler.tracks();
+    assert_eq!(tracks.len(), 5);
+    let mut first_pass: Vec<&str> = tracks[..3].iter().map(|t| t.title.as_str()).collect();
+    first_pass.sort();
+    assert_eq!(first_pass, vec!["a", "b", "c"]);
+    assert_ne!(tracks[3].title, tracks[4].title);
+
+    // Every track is streamed whole, in the announced order.
+    let mut samples = collected.channel(0).into_iter();
+    for track in tracks {
+        let (_, frames, value) = TRACKS.iter().find(|t| t.0 == track.title).unwrap();
+        assert_eq!(
+            track.duration,
+            Duration::from_millis(*frames as u64 * 1000 / SAMPLE_RATE as u64)
+        );
+        let expected = *value as f32 / 32768.0;
+        for _ in 0..*frames {
+            assert_eq!(samples.next(), Some(expected));
+        }
+    }
+    assert_eq!(samples.next(), None);
+
+    Ok(())
+}
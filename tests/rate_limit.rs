@@ -0,0 +1,26 @@
+use std::time::Duration;
+use streamapp::network::rate_limit::TokenBucket;
+
+#[test]
+fn test_token_bucket() {
+    let mut bucket = TokenBucket::new(1000);
+    assert_eq!(bucket.rate_bytes_per_sec(), 1000);
+
+    // The first bytes go right away, the next ones once they are paid for.
+    assert_eq!(bucket.take(500), Duration::ZERO);
+    let wait = bucket.take(500);
+    assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500));
+    let wait = bucket.take(100);
+    assert!(wait > Duration::from_millis(950) && wait <= Duration::from_millis(1000));
+}
+
+#[test]
+fn test_token_bucket_no_burst_after_idle() {
+    let mut bucket = TokenBucket::new(10_000);
+    assert_eq!(bucket.take(100), Duration::ZERO);
+    std::thread::sleep(Duration::from_millis(50));
+
+    // Idle time is not saved up: only the bytes just sent are waited for.
+    assert_eq!(bucket.take(100), Duration::ZERO);
+    assert!(bucket.take(100) > Duration::from_millis(5));
+}
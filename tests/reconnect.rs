@@ -0,0 +1,33 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager::ClientInterface;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8112;
+const UNUSED_PORT: u16 = 8113;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_connect_with_retry() -> Result<()> {
+    // The server only comes up after the first attempts failed
+    let start_server = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let server =
+            server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+        tokio::spawn(Arc::new(server).run());
+        Ok(())
+    };
+    tokio::try_join!(
+        ClientInterface::connect_with_retry(ADDRESS.to_string(), PORT, 10, 20),
+        start_server
+    )?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connect_with_retry_gives_up() {
+    let result = ClientInterface::connect_with_retry(ADDRESS.to_string(), UNUSED_PORT, 3, 10).await;
+    assert!(result.is_err());
+}
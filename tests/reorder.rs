@@ -0,0 +1,123 @@
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use streamapp::client::client_manager::{Capabilities, ClientInterface};
+use streamapp::network::reorder::FrameReorderBuffer;
+use streamapp::protocol::{self, AudioFrame, AudioHeader, Message, ProtocolInfo};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8128;
+const PATH_OUTPUT: &str = "/tmp/reorder_output.wav";
+
+type FramedSocket = Framed<TcpStream, LengthDelimitedCodec>;
+
+// One stereo 16-bit frame with both samples set to `sequence`.
+fn frame(sequence: u64) -> AudioFrame {
+    AudioFrame {
+        sequence,
+        timestamp_us: 1_000 * sequence,
+        header: AudioHeader::new(),
+        data: [(sequence as i16).to_le_bytes(); 2].concat(),
+    }
+}
+
+fn sequences(frames: Vec<AudioFrame>) -> Vec<u64> {
+    frames.into_iter().map(|frame| frame.sequence).collect()
+}
+
+#[test]
+fn test_frames_released_in_order() {
+    let mut buffer = FrameReorderBuffer::default();
+    assert_eq!(buffer.window(), 4);
+    assert_eq!(sequences(buffer.push(frame(0))), [0]);
+    assert!(buffer.push(frame(2)).is_empty());
+    assert!(buffer.push(frame(3)).is_empty());
+    assert_eq!(sequences(buffer.push(frame(1))), [1, 2, 3]);
+
+    // Too late, or already received
+    assert!(buffer.push(frame(1)).is_empty());
+    assert!(buffer.push(frame(5)).is_empty());
+    assert!(buffer.push(frame(5)).is_empty());
+    assert_eq!(sequences(buffer.push(frame(4))), [4, 5]);
+    assert_eq!(buffer.underrun_count(), 0);
+}
+
+#[test]
+fn test_gap_skipped_past_the_window() {
+    let mut buffer = FrameReorderBuffer::new(2);
+    assert_eq!(sequences(buffer.push(frame(0))), [0]);
+    assert!(buffer.push(frame(3)).is_empty());
+    assert!(buffer.push(frame(4)).is_empty());
+    // A third frame held: 1 and 2 are given up on
+    assert_eq!(sequences(buffer.push(frame(5))), [3, 4, 5]);
+    assert_eq!(buffer.underrun_count(), 2);
+    assert!(buffer.push(frame(2)).is_empty());
+
+    // The end of the stream releases what is held
+    assert!(buffer.push(frame(7)).is_empty());
+    assert_eq!(sequences(buffer.flush()), [7]);
+    assert_eq!(buffer.underrun_count(), 3);
+
+    // Without a window, gaps are skipped as soon as they show
+    let mut buffer = FrameReorderBuffer::new(0);
+    assert_eq!(sequences(buffer.push(frame(1))), [1]);
+    assert_eq!(buffer.underrun_count(), 1);
+
+    buffer.reset();
+    assert_eq!(sequences(buffer.push(frame(0))), [0]);
+    assert_eq!(buffer.underrun_count(), 1);
+}
+
+async fn read_message(framed: &mut FramedSocket) -> Result<Message> {
+    Ok(Message::decode(&framed.next().await.unwrap()?)?)
+}
+
+async fn send(framed: &mut FramedSocket, message: Message) -> Result<()> {
+    Ok(framed.send(Bytes::from(message.encode())).await?)
+}
+
+// Streams frames 0 to 7 out of order, without frame 4.
+async fn serve_shuffled(listener: TcpListener) -> Result<()> {
+    let (socket, _) = listener.accept().await?;
+    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::ProtocolInfo(ProtocolInfo::new())).await?;
+    read_message(&mut framed).await?;
+
+    read_message(&mut framed).await?;
+    send(&mut framed, Message::AudioHeader(AudioHeader::new())).await?;
+    read_message(&mut framed).await?;
+
+    for sequence in [0, 2, 1, 3, 6, 5, 7] {
+        let mut frame = frame(sequence);
+        protocol::append_checksum(&mut frame.data);
+        send(&mut framed, Message::AudioFrame(frame)).await?;
+    }
+    send(&mut framed, Message::StopPlaying).await?;
+    assert_eq!(read_message(&mut framed).await?, Message::Bye);
+    send(&mut framed, Message::Bye).await
+}
+
+#[tokio::test]
+async fn test_client_reorders_frames() -> Result<()> {
+    let listener = TcpListener::bind(format!("{}:{}", ADDRESS, PORT)).await?;
+    let server = tokio::spawn(serve_shuffled(listener));
+
+    let mut client = ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client
+        .with_reorder_window(2)
+        .add_capability(Capabilities::SaveToFile(PATH_OUTPUT.to_string()));
+    client.start_playing().await?;
+    server.await??;
+
+    assert_eq!(client.underrun_count(), 1);
+    assert_eq!(client.last_sequence(), 7);
+    let samples: Vec<i16> = hound::WavReader::open(PATH_OUTPUT)?
+        .into_samples::<i16>()
+        .step_by(2)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(samples, [0, 1, 2, 3, 5, 6, 7]);
+    Ok(())
+}
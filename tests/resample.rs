@@ -0,0 +1,80 @@
+use anyhow::Result;
+use streamapp::audio::convert::{bytes_to_f32, f32_to_bytes};
+use streamapp::audio::resample::Resampler;
+use streamapp::protocol::AudioHeader;
+
+const CHANNELS: usize = 2;
+
+#[test]
+fn test_resample_to_fixed_rate_sample_count() {
+    let input_frames = 44_100;
+    let input: Vec<f32> = (0..input_frames * CHANNELS)
+        .map(|i| (i / CHANNELS) as f32 / input_frames as f32)
+        .collect();
+
+    let mut resampler = Resampler::new(44_100, 48_000, CHANNELS);
+    assert_eq!(resampler.output_rate(), 48_000);
+    let mut output = Vec::new();
+    for chunk in input.chunks(1024 * CHANNELS) {
+        output.extend(resampler.process(chunk));
+    }
+
+    let output_frames = output.len() / CHANNELS;
+    assert!(output_frames.abs_diff(48_000) <= 1, "{output_frames}");
+
+    // A ramp stays a monotonic ramp across chunk boundaries.
+    for pair in output.chunks_exact(CHANNELS).collect::<Vec<_>>().windows(2) {
+        assert!(pair[1][0] > pair[0][0]);
+        assert_eq!(pair[1][0], pair[1][1]);
+    }
+}
+
+#[test]
+fn test_resample_same_rate_is_identity() {
+    let input: Vec<f32> = (0..200).map(|i| i as f32).collect();
+    let mut resampler = Resampler::new(48_000, 48_000, CHANNELS);
+
+    let mut output = resampler.process(&input[..100]);
+    output.extend(resampler.process(&input[100..]));
+    assert_eq!(output, input[..input.len() - CHANNELS]);
+}
+
+#[test]
+fn test_bytes_to_f32() -> Result<()> {
+    let mut header = AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels: 1,
+        sample_rate: 8000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    });
+
+    let bytes: Vec<u8> = [0i16, i16::MIN, 16384]
+        .iter()
+        .flat_map(|s| s.to_le_bytes())
+        .collect();
+    assert_eq!(bytes_to_f32(&bytes, &header)?, vec![0.0, -1.0, 0.5]);
+
+    header = AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels: 1,
+        sample_rate: 8000,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    });
+    let samples = vec![0.25f32, -0.75];
+    assert_eq!(bytes_to_f32(&f32_to_bytes(&samples), &header)?, samples);
+
+    Ok(())
+}
+
+#[test]
+fn test_resample_carries_partial_frames() {
+    let input: Vec<f32> = (0..200).map(|i| i as f32).collect();
+    let mut resampler = Resampler::new(48_000, 48_000, CHANNELS);
+
+    // Chunks of an odd number of samples split frames in two
+    let mut output = vec![];
+    for chunk in input.chunks(7) {
+        output.extend(resampler.process(chunk));
+    }
+    assert_eq!(output, input[..input.len() - CHANNELS]);
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use streamapp::audio::file::AudioWriter;
+use streamapp::audio::ring;
+use streamapp::protocol::AudioHeader;
+
+#[test]
+fn test_ring_buffer_preserves_bytes_in_order() -> Result<()> {
+    // Much more data than the ring holds: the writer has to wait for the
+    // reader to make room.
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let (mut writer, mut reader) = ring::ring_buffer(1024);
+
+    let header = AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels: 2,
+        sample_rate: 48000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    });
+    writer.update_format(&header)?;
+    assert_eq!(reader.header().unwrap().get_sample_rate(), 48000);
+
+    let consumer = std::thread::spawn(move || {
+        let mut received = vec![];
+        let mut buffer = [0u8; 300];
+        while !reader.is_finished() {
+            let n = reader.read(&mut buffer);
+            received.extend_from_slice(&buffer[..n]);
+            if n == 0 {
+                std::thread::yield_now();
+            }
+        }
+        received
+    });
+
+    for chunk in data.chunks(4096) {
+        writer.write(chunk)?;
+        while !writer.poll_ready()? {
+            std::thread::yield_now();
+        }
+    }
+    writer.finalize()?;
+
+    let received = consumer.join().unwrap();
+    assert_eq!(received, data);
+    Ok(())
+}
+
+#[test]
+fn test_ring_buffer_write_fails_without_reader() {
+    let (mut writer, reader) = ring::ring_buffer(16);
+    drop(reader);
+    assert!(writer.write(&[0u8; 64]).is_err());
+}
+
+#[test]
+fn test_ring_buffer_write_does_not_wait_for_room() -> Result<()> {
+    let (mut writer, mut reader) = ring::ring_buffer(16);
+    let data: Vec<u8> = (0..40).collect();
+    writer.write(&data)?;
+    assert!(!writer.poll_ready()?);
+
+    let mut received = vec![];
+    let mut buffer = [0u8; 16];
+    while !writer.poll_ready()? {
+        let n = reader.read(&mut buffer);
+        received.extend_from_slice(&buffer[..n]);
+    }
+    writer.finalize()?;
+    while !reader.is_finished() {
+        let n = reader.read(&mut buffer);
+        received.extend_from_slice(&buffer[..n]);
+    }
+    assert_eq!(received, data);
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager;
+use streamapp::network::buffer::BufferPolicy;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8099;
+const PATH_INPUT: &str = "/tmp/test_seek_input.wav";
+const PATH_OUTPUT: &str = "/tmp/test_seek_output.wav";
+const FRAMES: u32 = 2 * 1024 * 1024;
+const SEEK_OFFSET: u64 = 1_000_000;
+
+#[tokio::test]
+async fn test_seek_during_stream() -> Result<()> {
+    // Every sample holds its own frame index
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 44100,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(PATH_INPUT, spec)?;
+    for i in 0..FRAMES {
+        writer.write_sample(i as i32)?;
+    }
+    writer.finalize()?;
+
+    // A small buffer keeps the server from reading far ahead of the seek
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_max_buffered_bytes(16 * 1024, BufferPolicy::Throttle);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    client.seek(SEEK_OFFSET)?;
+    client.start_playing().await?;
+
+    let received: Vec<i32> = hound::WavReader::open(PATH_OUTPUT)?
+        .samples::<i32>()
+        .collect::<Result<_, _>>()?;
+    // What was sent before the seek, then the rest of the file from the offset
+    let jump = received
+        .iter()
+        .position(|&sample| sample as u64 == SEEK_OFFSET)
+        .unwrap();
+    assert!((jump as u64) < SEEK_OFFSET);
+    assert!(
+        received[..jump]
+            .iter()
+            .enumerate()
+            .all(|(i, &s)| s == i as i32)
+    );
+    let expected = SEEK_OFFSET as i32..FRAMES as i32;
+    assert!(received[jump..].iter().copied().eq(expected));
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use streamapp::client::client_manager;
+use streamapp::server::server_manager::ServerBuilder;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8115;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_server_builder.wav";
+
+#[tokio::test]
+async fn test_builder_rejects_unreadable_file() {
+    let result = ServerBuilder::new()
+        .address(ADDRESS)
+        .port(PORT + 1)
+        .file_path("/nonexistent/file.wav")
+        .build()
+        .await;
+    assert!(result.is_err());
+
+    let result = ServerBuilder::new()
+        .address(ADDRESS)
+        .port(PORT + 1)
+        .file_path(env!("CARGO_MANIFEST_DIR"))
+        .build()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_builder_rate_limit() -> Result<()> {
+    // Limited to stream the file in about half a second
+    let audio_bytes = std::fs::metadata(PATH_INPUT)?.len();
+    let kbps = audio_bytes * 8 / 1000 * 2;
+    let server = ServerBuilder::new()
+        .address(ADDRESS)
+        .port(PORT)
+        .file_path(PATH_INPUT)
+        .max_clients(1)
+        .chunk_size(1000)
+        .rate_limit_kbps(kbps)
+        .build()
+        .await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let start = Instant::now();
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .start_playing()
+        .await?;
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_builder_binds_several_addresses() -> Result<()> {
+    const SOCKET: &str = "/tmp/rstream_server_builder.sock";
+    let server = ServerBuilder::new()
+        .file_path(PATH_INPUT)
+        .bind("127.0.0.1:0")
+        .bind("0.0.0.0:0")
+        .bind_unix(SOCKET)
+        .build()
+        .await?;
+    let addrs = server.local_addrs();
+    assert_eq!(addrs.len(), 2);
+    assert!(addrs[0].ip().is_loopback());
+    assert!(addrs[1].ip().is_unspecified());
+    assert!(addrs.iter().all(|addr| addr.port() != 0));
+    assert_ne!(addrs[0].port(), addrs[1].port());
+    tokio::spawn(Arc::new(server).run());
+
+    let mut clients = vec![];
+    for addr in &addrs {
+        clients.push(
+            client_manager::ClientInterface::connect("127.0.0.1".to_string(), addr.port()).await?,
+        );
+    }
+    clients.push(client_manager::ClientInterface::connect_unix(SOCKET.to_string()).await?);
+    for (i, mut client) in clients.into_iter().enumerate() {
+        let output = format!("/tmp/test_output_server_builder_{}.wav", i);
+        client
+            .add_capability(client_manager::Capabilities::SaveToFile(output.clone()))
+            .start_playing()
+            .await?;
+        assert!(common::compare_wav_samples(PATH_INPUT, &output));
+    }
+    Ok(())
+}
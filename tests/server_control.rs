@@ -0,0 +1,57 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+// Servers bind to port 0, the port they get is read back
+const ADDRESS: &str = "127.0.0.1";
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_pause_and_resume_accepting() -> Result<()> {
+    let server = Arc::new(
+        server_manager::Server::new(ADDRESS.to_string(), 0, PATH_INPUT.to_string()).await?,
+    );
+    let port = server.local_addrs()[0].port();
+    tokio::spawn(Arc::clone(&server).run());
+
+    server.pause_accepting();
+    assert!(!server.is_accepting());
+    let refused = client_manager::ClientInterface::connect(ADDRESS.to_string(), port).await;
+    assert!(refused.is_err());
+
+    server.resume_accepting();
+    assert!(server.is_accepting());
+    client_manager::ClientInterface::connect(ADDRESS.to_string(), port).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_file_limits() -> Result<()> {
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), 0, PATH_INPUT.to_string()).await?;
+    let port = server.local_addrs()[0].port();
+    server.set_max_file_duration(Duration::from_secs(60));
+    assert!(server.validate_file(PATH_INPUT).is_ok());
+
+    server.set_max_file_size(1024);
+    assert!(server.validate_file(PATH_INPUT).is_err());
+    server.set_max_file_size(u64::MAX);
+
+    server.set_max_file_duration(Duration::from_secs(1));
+    assert!(server.validate_file(PATH_INPUT).is_err());
+    tokio::spawn(Arc::new(server).run());
+
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), port).await?;
+    let result = handler
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            "/tmp/test_output_limits.wav".to_string(),
+        ))
+        .start_playing()
+        .await;
+    assert!(result.is_err());
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8120;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_connect_disconnect_hooks() -> Result<()> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    let connects = events.clone();
+    server.on_client_connect(move |addr| connects.lock().unwrap().push(format!("connect {addr}")));
+    let disconnects = events.clone();
+    server.on_client_disconnect(move |addr, error| {
+        disconnects
+            .lock()
+            .unwrap()
+            .push(format!("disconnect {addr} {}", error.is_some()))
+    });
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.start_playing().await?;
+
+    // The server notices the end of the session shortly after the client
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while events.lock().unwrap().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+    let events = events.lock().unwrap();
+    assert!(events[0].starts_with("connect "));
+    assert!(events[1].starts_with("disconnect "));
+    assert!(events[1].ends_with(" false"));
+    Ok(())
+}
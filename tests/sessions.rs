@@ -0,0 +1,57 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8123;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_sessions_track_connected_clients() -> Result<()> {
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    // Slow enough for the stream to be seen while it runs
+    server.set_rate_limit_kbps(2000);
+    let server = Arc::new(server);
+    let registry = server.registry();
+    tokio::spawn(server.clone().run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    // Registered once the server has read the end of the handshake
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while server.connected_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+    let sessions = server.sessions();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].bytes_sent, 0);
+    assert!(sessions[0].current_file.is_empty());
+
+    let streaming = async {
+        while registry.sessions().iter().all(|s| s.bytes_sent == 0) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::select! {
+        result = client.start_playing() => panic!("stream ended early: {:?}", result),
+        _ = tokio::time::timeout(Duration::from_secs(5), streaming) => {}
+    }
+    let session = &registry.sessions()[0];
+    assert!(session.bytes_sent > 0);
+    assert_eq!(session.current_file, PATH_INPUT);
+    assert!(session.connected_at.elapsed() > Duration::ZERO);
+
+    drop(client);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !registry.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+    assert!(server.sessions().is_empty());
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8111;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_shutdown.wav";
+
+#[tokio::test]
+async fn test_graceful_shutdown() -> Result<()> {
+    // Broadcast streams in real time, so the stream is still running when
+    // the server shuts down
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    server.set_broadcast_mode(true);
+    let shutdown = server.shutdown_handle();
+    let running = tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    let stop = async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        shutdown.shutdown();
+        Ok(())
+    };
+    // The client gets STOP_PLAY and BYE instead of losing the connection
+    tokio::try_join!(client.start_playing(), stop)?;
+    tokio::time::timeout(Duration::from_secs(1), running).await???;
+
+    let received = hound::WavReader::open(PATH_OUTPUT)?.len();
+    let total = hound::WavReader::open(PATH_INPUT)?.len();
+    assert!(received > 0 && received < total);
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use anyhow::Result;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::silence::SilenceReader;
+use streamapp::protocol::AudioHeader;
+
+fn header(channels: u16, sample_rate: u32, bits_per_sample: u16) -> AudioHeader {
+    AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample,
+        sample_format: hound::SampleFormat::Int,
+    })
+}
+
+fn drain(reader: &mut SilenceReader, buffer_size: usize) -> Result<Vec<u8>> {
+    let mut read = vec![];
+    let mut buffer = vec![0xAAu8; buffer_size];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(read);
+        }
+        assert_eq!(n % 4, 0);
+        read.extend_from_slice(&buffer[..n]);
+    }
+}
+
+#[test]
+fn test_silence_for_duration() -> Result<()> {
+    let cd = header(2, 44100, 16);
+    let mut reader = SilenceReader::new(cd, 250);
+    assert_eq!(reader.total_samples(), 11025);
+
+    let mut announced = AudioHeader::new();
+    reader.update_header(&mut announced);
+    assert_eq!(announced, cd);
+
+    // Odd buffer sizes get whole frames only
+    let silence = drain(&mut reader, 1001)?;
+    assert_eq!(silence.len() as u64, cd.bytes_for_duration(0.25));
+    assert!(silence.iter().all(|&b| b == 0));
+    Ok(())
+}
+
+#[test]
+fn test_silence_rounded_to_frames() -> Result<()> {
+    // 44.1 frames in a millisecond
+    let mut reader = SilenceReader::new(header(2, 44100, 16), 1);
+    assert_eq!(reader.total_samples(), 44);
+    assert_eq!(drain(&mut reader, 4096)?.len(), 44 * 4);
+
+    assert!(drain(&mut SilenceReader::new(header(1, 8000, 16), 0), 64)?.is_empty());
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+mod common;
+
+const ADDRESS: &str = "127.0.0.1";
+const PORT: u16 = 8138;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_socks5.wav";
+
+// Unauthenticated SOCKS5 CONNECT to an IPv4 address or a domain name.
+async fn serve_socks5(mut client: TcpStream) -> Result<()> {
+    let mut greeting = [0u8; 2];
+    client.read_exact(&mut greeting).await?;
+    let mut methods = vec![0u8; greeting[1] as usize];
+    client.read_exact(&mut methods).await?;
+    assert!(methods.contains(&0));
+    client.write_all(&[5, 0]).await?;
+
+    let mut request = [0u8; 4];
+    client.read_exact(&mut request).await?;
+    assert_eq!(&request[..3], &[5, 1, 0]);
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip).await?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let mut name = vec![0u8; client.read_u8().await? as usize];
+            client.read_exact(&mut name).await?;
+            String::from_utf8(name)?
+        }
+        atyp => panic!("Unexpected address type {}", atyp),
+    };
+    let port = client.read_u16().await?;
+    let mut server = TcpStream::connect((host.as_str(), port)).await?;
+    client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connect_through_socks5_proxy() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let proxy = TcpListener::bind((ADDRESS, 0)).await?;
+    let proxy_addr = proxy.local_addr()?;
+    let tunnels = Arc::new(AtomicUsize::new(0));
+    let proxied = Arc::clone(&tunnels);
+    tokio::spawn(async move {
+        while let Ok((client, _)) = proxy.accept().await {
+            proxied.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(serve_socks5(client));
+        }
+    });
+
+    let mut handler = client_manager::ClientBuilder::new()
+        .address(ADDRESS)
+        .port(PORT)
+        .socks5_proxy(proxy_addr)
+        .save_to_file(PATH_OUTPUT)
+        .connect()
+        .await?;
+    handler.start_playing().await?;
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    assert_eq!(tunnels.load(Ordering::SeqCst), 1);
+
+    // The proxy resolves host names
+    client_manager::ClientInterface::connect_via_socks5(
+        proxy_addr,
+        "localhost".to_string(),
+        PORT,
+        None,
+    )
+    .await?;
+    assert_eq!(tunnels.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unreachable_proxy() -> Result<()> {
+    // Nothing listens on the port once the listener is dropped
+    let proxy_addr = TcpListener::bind((ADDRESS, 0)).await?.local_addr()?;
+    let Err(e) = client_manager::ClientInterface::connect_via_socks5(
+        proxy_addr,
+        ADDRESS.to_string(),
+        PORT,
+        None,
+    )
+    .await
+    else {
+        panic!("Connected through a proxy that is not running");
+    };
+    assert!(e.to_string().contains("through proxy"));
+    Ok(())
+}
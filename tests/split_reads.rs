@@ -0,0 +1,86 @@
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use std::time::Duration;
+use streamapp::client::client_manager::{Capabilities, ClientInterface};
+use streamapp::protocol::{self, Message, ProtocolInfo};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{Encoder, Framed, LengthDelimitedCodec};
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8118;
+const PATH_OUTPUT: &str = "/tmp/split_reads_output.wav";
+
+type FramedSocket = Framed<TcpStream, LengthDelimitedCodec>;
+
+// Sends the frame of `message` one byte per segment, as a slow network could
+// deliver it.
+async fn write_bytewise(framed: &mut FramedSocket, message: Vec<u8>) -> Result<()> {
+    let mut frame = BytesMut::new();
+    framed
+        .codec_mut()
+        .encode(Bytes::from(message), &mut frame)?;
+    for byte in frame {
+        framed.get_mut().write_all(&[byte]).await?;
+        framed.get_mut().flush().await?;
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    Ok(())
+}
+
+async fn read_message(framed: &mut FramedSocket) -> Result<BytesMut> {
+    Ok(framed.next().await.unwrap()?)
+}
+
+async fn serve_split(listener: TcpListener) -> Result<()> {
+    let (socket, _) = listener.accept().await?;
+    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+    assert!(matches!(
+        Message::decode(&read_message(&mut framed).await?)?,
+        Message::Hello { .. }
+    ));
+    write_bytewise(
+        &mut framed,
+        Message::ProtocolInfo(ProtocolInfo::new()).encode(),
+    )
+    .await?;
+    read_message(&mut framed).await?;
+
+    read_message(&mut framed).await?;
+    let header = protocol::AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    });
+    write_bytewise(&mut framed, Message::AudioHeader(header).encode()).await?;
+    read_message(&mut framed).await?;
+
+    framed
+        .send(Bytes::from(Message::AudioData(vec![0u8; 64]).encode()))
+        .await?;
+    write_bytewise(&mut framed, Message::StopPlaying.encode()).await?;
+    assert_eq!(
+        Message::decode(&read_message(&mut framed).await?)?,
+        Message::Bye
+    );
+    write_bytewise(&mut framed, Message::Bye.encode()).await
+}
+
+#[tokio::test]
+async fn test_messages_split_across_reads() -> Result<()> {
+    let listener = TcpListener::bind(format!("{}:{}", ADDRESS, PORT)).await?;
+    let server = tokio::spawn(serve_split(listener));
+
+    let mut client = ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client.add_capability(Capabilities::SaveToFile(PATH_OUTPUT.to_string()));
+    client.start_playing().await?;
+    server.await??;
+
+    let reader = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(reader.spec().sample_rate, 44100);
+    assert_eq!(reader.spec().channels, 2);
+    assert_eq!(reader.len(), 32);
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager::{self, ClientInterface};
+use streamapp::network::tls::TlsConfig;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8109;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_tls.wav";
+const PATH_CERT: &str = "/tmp/test_tls_cert.pem";
+const PATH_KEY: &str = "/tmp/test_tls_key.pem";
+
+#[tokio::test]
+async fn test_stream_over_tls() -> Result<()> {
+    let certified = rcgen::generate_simple_self_signed(vec![ADDRESS.to_string()])?;
+    std::fs::write(PATH_CERT, certified.cert.pem())?;
+    std::fs::write(PATH_KEY, certified.signing_key.serialize_pem())?;
+
+    let mut server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    assert!(
+        server
+            .set_tls(&TlsConfig {
+                cert_path: "/nonexistent/cert.pem".to_string(),
+                key_path: "/nonexistent/key.pem".to_string(),
+            })
+            .is_err()
+    );
+    server.set_tls(&TlsConfig {
+        cert_path: PATH_CERT.to_string(),
+        key_path: PATH_KEY.to_string(),
+    })?;
+    tokio::spawn(Arc::new(server).run());
+
+    // Plain TCP clients cannot complete the handshake
+    assert!(
+        ClientInterface::connect(ADDRESS.to_string(), PORT)
+            .await
+            .is_err()
+    );
+    // A self-signed certificate is not trusted by default
+    assert!(
+        ClientInterface::connect_tls(ADDRESS.to_string(), PORT, None, None)
+            .await
+            .is_err()
+    );
+
+    let mut client =
+        ClientInterface::connect_tls(ADDRESS.to_string(), PORT, Some(PATH_CERT.to_string()), None)
+            .await?;
+    client
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .start_playing()
+        .await?;
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    Ok(())
+}
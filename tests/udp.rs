@@ -0,0 +1,94 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager::Capabilities;
+use streamapp::client::udp_client::UdpClientInterface;
+use streamapp::protocol::{self, AudioFrame, AudioHeader, Message};
+use streamapp::server::udp_server::UdpServer;
+use tokio::net::UdpSocket;
+
+mod common;
+
+const ADDRESS: &str = "127.0.0.1";
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+
+#[tokio::test]
+async fn test_stream_over_udp() -> Result<()> {
+    const PATH_OUTPUT: &str = "/tmp/test_output_udp.wav";
+    let mut server = UdpServer::new(ADDRESS.to_string(), 8136, PATH_INPUT.to_string()).await?;
+    // Ten times real time
+    server.set_rate_limit_kbps(14_000);
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = UdpClientInterface::connect(ADDRESS.to_string(), 8136).await?;
+    client
+        .add_capability(Capabilities::SaveToFile(PATH_OUTPUT.to_string()))
+        .start_playing()
+        .await?;
+    assert_eq!(client.lost_frames(), 0);
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    Ok(())
+}
+
+fn frame(sequence: u64, header: AudioHeader, sample: i16) -> Vec<u8> {
+    let mut data = [sample.to_le_bytes(); 4].concat();
+    protocol::append_checksum(&mut data);
+    Message::AudioFrame(AudioFrame {
+        sequence,
+        timestamp_us: 0,
+        header,
+        data,
+    })
+    .encode()
+}
+
+#[tokio::test]
+async fn test_lost_frames_played_as_silence() -> Result<()> {
+    const PATH_OUTPUT: &str = "/tmp/test_output_udp_lost.wav";
+    let server = UdpSocket::bind((ADDRESS, 8137)).await?;
+    let fake_server = tokio::spawn(async move {
+        let mut buffer = [0u8; 64];
+        let (len, client) = server.recv_from(&mut buffer).await?;
+        assert_eq!(Message::decode(&buffer[..len])?, Message::StartPlaying);
+
+        // Mono 16-bit, 4 samples a frame. Frame 2 never arrives, frame 1
+        // arrives again after frame 3.
+        let header = AudioHeader::from_wav_spec(&hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        });
+        let mut datagrams = vec![Message::AudioHeader(header).encode()];
+        for (sequence, sample) in [(0, 100), (1, 200), (3, 400), (1, 200)] {
+            datagrams.push(frame(sequence, header, sample));
+        }
+        // Corrupted in transit
+        let mut corrupted = frame(4, header, 500);
+        *corrupted.last_mut().unwrap() ^= 1;
+        datagrams.push(corrupted);
+        datagrams.push(frame(5, header, 600));
+        datagrams.push(Message::StopPlaying.encode());
+        for datagram in datagrams {
+            server.send_to(&datagram, client).await?;
+        }
+        Ok::<(), anyhow::Error>(())
+    });
+
+    let mut client = UdpClientInterface::connect(ADDRESS.to_string(), 8137).await?;
+    client
+        .add_capability(Capabilities::SaveToFile(PATH_OUTPUT.to_string()))
+        .start_playing()
+        .await?;
+    fake_server.await??;
+
+    assert_eq!(client.lost_frames(), 2);
+    let samples: Vec<i16> = hound::WavReader::open(PATH_OUTPUT)?
+        .into_samples::<i16>()
+        .collect::<Result<_, _>>()?;
+    let expected: Vec<i16> = [100, 200, 0, 400, 0, 600]
+        .into_iter()
+        .flat_map(|sample| [sample; 4])
+        .collect();
+    assert_eq!(samples, expected);
+    Ok(())
+}
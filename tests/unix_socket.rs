@@ -0,0 +1,34 @@
+use anyhow::Result;
+use std::sync::Arc;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+mod common;
+
+const SOCKET_PATH: &str = "/tmp/test_rstream.sock";
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_unix.wav";
+
+#[tokio::test]
+async fn test_unix_socket_streaming() -> Result<()> {
+    // A socket file left behind by a dead server must not prevent binding.
+    let _ = std::fs::remove_file(SOCKET_PATH);
+    drop(std::os::unix::net::UnixListener::bind(SOCKET_PATH)?);
+    assert!(std::path::Path::new(SOCKET_PATH).exists());
+
+    let server =
+        server_manager::Server::new_unix(SOCKET_PATH.to_string(), PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut handler =
+        client_manager::ClientInterface::connect_unix(SOCKET_PATH.to_string()).await?;
+    handler
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .start_playing()
+        .await?;
+
+    assert!(common::compare_wav_samples(PATH_INPUT, PATH_OUTPUT));
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use streamapp::audio::split::SplitWavWriter;
+use streamapp::audio::vad::VadGate;
+
+const PATH_OUTPUT: &str = "/tmp/test_vad.wav";
+
+#[test]
+fn test_gate_follows_rolling_rms() {
+    let mut gate = VadGate::new(0.1, 4);
+    assert!(!gate.admit(&[0.0f32, 0.0]));
+    // One loud frame lifts the RMS of the window above the threshold
+    assert!(gate.admit(&[0.5f32, 0.5]));
+    assert!((gate.rms() - 0.125f32.sqrt()).abs() < 1e-6);
+    for _ in 0..3 {
+        assert!(gate.admit(&[0.0f32, 0.0]));
+    }
+    // The loud frame left the window
+    assert!(!gate.admit(&[0.0f32, 0.0]));
+    assert_eq!(gate.rms(), 0.0);
+
+    // Quiet on one channel only still counts
+    assert!(gate.admit(&[i16::MAX, 0]));
+}
+
+#[test]
+fn test_recording_drops_silence() -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 1000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut recorder =
+        SplitWavWriter::new(PATH_OUTPUT, spec, None)?.with_vad_gate(VadGate::new(0.05, 10));
+
+    let silence = vec![0.0f32; 2 * 500];
+    let speech = vec![0.5f32; 2 * 300];
+    for block in [&silence, &speech, &silence, &speech, &silence] {
+        for chunk in block.chunks(64) {
+            recorder.write::<f32, i16>(chunk)?;
+        }
+    }
+    assert_eq!(recorder.finalize()?, vec![PATH_OUTPUT.to_string()]);
+
+    // Speech, and the end of the window after it: 0.5 over 10 frames stays
+    // above 0.05 for 9 silent frames
+    let reader = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(reader.duration(), 2 * (300 + 9));
+    Ok(())
+}
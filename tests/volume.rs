@@ -0,0 +1,147 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use streamapp::audio::convert;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::filter::GainFilter;
+use streamapp::audio::wav::WavFileRead;
+use streamapp::client::client_manager;
+use streamapp::protocol::{self, AudioHeader, Message};
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8101;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_volume.wav";
+
+fn header(bits_per_sample: u16, sample_format: hound::SampleFormat) -> AudioHeader {
+    AudioHeader::from_wav_spec(&hound::WavSpec {
+        channels: 1,
+        sample_rate: 44100,
+        bits_per_sample,
+        sample_format,
+    })
+}
+
+#[test]
+fn test_gain_saturates_instead_of_wrapping() -> Result<()> {
+    let samples = [0i16, 100, -100, 20000, -20000, i16::MAX, i16::MIN];
+    let mut data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    convert::apply_gain(&mut data, &header(16, hound::SampleFormat::Int), 2.0)?;
+    let scaled: Vec<i16> = data
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    assert_eq!(
+        scaled,
+        [0, 200, -200, i16::MAX, i16::MIN, i16::MAX, i16::MIN]
+    );
+
+    let samples = [1 << 20, i32::MAX, i32::MIN];
+    let mut data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    convert::apply_gain(&mut data, &header(32, hound::SampleFormat::Int), 1.5)?;
+    let scaled: Vec<i32> = data
+        .chunks_exact(4)
+        .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    assert_eq!(scaled, [3 << 19, i32::MAX, i32::MIN]);
+
+    let samples = [0.25f32, 0.75, -0.75];
+    let mut data = convert::f32_to_bytes(&samples);
+    convert::apply_gain(&mut data, &header(32, hound::SampleFormat::Float), 2.0)?;
+    let scaled = convert::bytes_to_f32(&data, &header(32, hound::SampleFormat::Float))?;
+    assert_eq!(scaled, [0.5, 1.0, -1.0]);
+
+    let samples = [1000i32, 8_388_607, -8_388_608];
+    let mut data: Vec<u8> = samples
+        .iter()
+        .flat_map(|s| s.to_le_bytes()[..3].to_vec())
+        .collect();
+    convert::apply_gain(&mut data, &header(24, hound::SampleFormat::Int), 1.5)?;
+    let scaled: Vec<i32> = data
+        .chunks_exact(3)
+        .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8)
+        .collect();
+    assert_eq!(scaled, [1500, 8_388_607, -8_388_608]);
+
+    let mut data = vec![0u8; 6];
+    assert!(convert::apply_gain(&mut data, &header(8, hound::SampleFormat::Int), 0.5).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_gain_filter_follows_shared_gain() -> Result<()> {
+    let mut wav = WavFileRead::new();
+    wav.open_file(PATH_INPUT)?;
+    let gain = Arc::new(AtomicU32::new(0.5f32.to_bits()));
+    let mut filter = GainFilter::with_shared_gain(wav, gain.clone());
+    let mut source = hound::WavReader::open(PATH_INPUT)?.into_samples::<i16>();
+
+    let mut buffer = [0u8; 512];
+    for expected_gain in [0.5, 2.0, 1.0] {
+        gain.store(f32::to_bits(expected_gain), Ordering::Relaxed);
+        let n = filter.read(&mut buffer)?;
+        for b in buffer[..n].chunks_exact(2) {
+            let original = source.next().unwrap()? as f32;
+            let expected = (original * expected_gain).round() as i16;
+            assert_eq!(i16::from_le_bytes([b[0], b[1]]), expected);
+        }
+    }
+    assert_eq!(filter.gain(), 1.0);
+    filter.set_gain(0.0);
+    let n = filter.read(&mut buffer)?;
+    assert!(buffer[..n].iter().all(|&b| b == 0));
+    Ok(())
+}
+
+#[test]
+fn test_volume_message_round_trip() {
+    let message = Message::VolumeControl(0.5).encode();
+    assert_eq!(
+        protocol::extract_message_type(&message),
+        Some(protocol::MessageType::VolumeControl)
+    );
+    assert_eq!(Message::decode(&message), Ok(Message::VolumeControl(0.5)));
+    // Type and gain as a little-endian f32
+    assert_eq!(message.len(), 1 + std::mem::size_of::<f32>());
+    for gain in [-0.1, 2.5, f32::NAN] {
+        let message = Message::VolumeControl(gain).encode();
+        assert!(Message::decode(&message).is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_stream_at_half_volume() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    assert!(client.set_volume(3.0).is_err());
+    client.add_capability(client_manager::Capabilities::SaveToFile(
+        PATH_OUTPUT.to_string(),
+    ));
+    client.set_volume(0.5)?;
+    client.start_playing().await?;
+
+    let input: Vec<i16> = hound::WavReader::open(PATH_INPUT)?
+        .samples::<i16>()
+        .collect::<Result<_, _>>()?;
+    let output: Vec<i16> = hound::WavReader::open(PATH_OUTPUT)?
+        .samples::<i16>()
+        .collect::<Result<_, _>>()?;
+    assert_eq!(input.len(), output.len());
+    // The gain reaches the server after the first frames were read
+    let scaled = input
+        .iter()
+        .map(|&s| (s as f32 * 0.5).round() as i16)
+        .collect::<Vec<_>>();
+    let first_scaled = output
+        .iter()
+        .zip(&input)
+        .position(|(o, i)| o != i)
+        .unwrap_or(0);
+    assert_eq!(output[first_scaled..], scaled[first_scaled..]);
+    assert!(first_scaled < input.len() / 2);
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use streamapp::audio::wav;
+use streamapp::client::client_manager;
+use streamapp::server::server_manager;
+
+mod common;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8094;
+const PATH_TRAILING_CHUNK: &str = "/tmp/test_trailing_chunk.wav";
+const PATH_OUTPUT: &str = "/tmp/test_output_trailing_chunk.wav";
+const FRAMES: u32 = 8000;
+
+// Writes a WAV file followed by a LIST chunk after its data chunk.
+fn write_wav_with_trailing_list() -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(PATH_TRAILING_CHUNK, spec)?;
+    for i in 0..FRAMES * 2 {
+        writer.write_sample((i % 500) as i16)?;
+    }
+    writer.finalize()?;
+
+    let mut info = b"INFO".to_vec();
+    let software = b"RStream test\0\0";
+    info.extend_from_slice(b"ISFT");
+    info.extend_from_slice(&(software.len() as u32).to_le_bytes());
+    info.extend_from_slice(software);
+    let mut list = b"LIST".to_vec();
+    list.extend_from_slice(&(info.len() as u32).to_le_bytes());
+    list.extend_from_slice(&info);
+
+    let mut bytes = std::fs::read(PATH_TRAILING_CHUNK)?;
+    bytes.extend_from_slice(&list);
+    let riff_size = (bytes.len() - 8) as u32;
+    bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    std::fs::write(PATH_TRAILING_CHUNK, bytes)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_trailing_chunk_not_streamed() -> Result<()> {
+    write_wav_with_trailing_list()?;
+    assert_eq!(
+        wav::wav_duration(PATH_TRAILING_CHUNK)?,
+        Duration::from_secs(1)
+    );
+
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_TRAILING_CHUNK.to_string())
+            .await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut handler = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    handler
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ))
+        .start_playing()
+        .await?;
+
+    let output = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(output.duration(), FRAMES);
+    assert!(common::compare_wav_samples(
+        PATH_TRAILING_CHUNK,
+        PATH_OUTPUT
+    ));
+
+    Ok(())
+}
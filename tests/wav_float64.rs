@@ -0,0 +1,106 @@
+use anyhow::Result;
+use streamapp::audio::convert::bytes_to_f32;
+use streamapp::audio::file::AudioReader;
+use streamapp::audio::wav::{WavFileRead, wav_duration};
+use streamapp::protocol::{AudioHeader, Message, SampleFormat};
+
+const PATH_INPUT: &str = "/tmp/test_input_f64.wav";
+const CHANNELS: u16 = 2;
+const SAMPLE_RATE: u32 = 48000;
+
+// hound cannot write 64-bit float files. A LIST chunk before the audio
+// checks that unknown chunks are skipped.
+fn write_f64_wav(path: &str, samples: &[f64]) -> Result<()> {
+    let mut fmt = vec![];
+    fmt.extend(3u16.to_le_bytes());
+    fmt.extend(CHANNELS.to_le_bytes());
+    fmt.extend(SAMPLE_RATE.to_le_bytes());
+    fmt.extend((SAMPLE_RATE * CHANNELS as u32 * 8).to_le_bytes());
+    fmt.extend((CHANNELS * 8).to_le_bytes());
+    fmt.extend(64u16.to_le_bytes());
+    let list = b"INFO\0".to_vec();
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+    let mut body = b"WAVE".to_vec();
+    for (id, chunk) in [(b"fmt ", &fmt), (b"LIST", &list), (b"data", &data)] {
+        body.extend(id);
+        body.extend((chunk.len() as u32).to_le_bytes());
+        body.extend(chunk);
+        if chunk.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let mut file = b"RIFF".to_vec();
+    file.extend((body.len() as u32).to_le_bytes());
+    file.extend(body);
+    std::fs::write(path, file)?;
+    Ok(())
+}
+
+fn read_all(reader: &mut WavFileRead) -> Result<Vec<f64>> {
+    let mut samples = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        samples.extend(
+            buffer[..n]
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes(b.try_into().unwrap())),
+        );
+    }
+    Ok(samples)
+}
+
+#[test]
+fn test_read_f64_wav() -> Result<()> {
+    let samples: Vec<f64> = (0..SAMPLE_RATE as usize * CHANNELS as usize)
+        .map(|i| (i as f64 * 0.001).sin() * 0.5)
+        .collect();
+    write_f64_wav(PATH_INPUT, &samples)?;
+
+    let mut reader = WavFileRead::new();
+    reader.open_file(PATH_INPUT)?;
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    assert_eq!(header.get_sample_format(), SampleFormat::Float64);
+    assert_eq!(header.get_bits_per_sample(), 64);
+    assert_eq!(header.get_channels(), CHANNELS as u8);
+    assert_eq!(header.get_sample_rate(), SAMPLE_RATE);
+    assert_eq!(reader.total_samples(), SAMPLE_RATE as u64);
+    assert_eq!(reader.position(), 0);
+    assert_eq!(read_all(&mut reader)?, samples);
+    assert_eq!(reader.position(), reader.total_samples());
+    assert_eq!(wav_duration(PATH_INPUT)?, std::time::Duration::from_secs(1));
+
+    // Seeks are in frames
+    reader.seek_to_sample(SAMPLE_RATE as u64 / 2)?;
+    assert_eq!(reader.position(), SAMPLE_RATE as u64 / 2);
+    assert_eq!(read_all(&mut reader)?, samples[samples.len() / 2..]);
+    reader.seek_to_sample(u64::MAX)?;
+    assert!(read_all(&mut reader)?.is_empty());
+
+    let converted = bytes_to_f32(&0.25f64.to_le_bytes(), &header)?;
+    assert_eq!(converted, vec![0.25]);
+
+    let Message::AudioHeader(decoded) = Message::decode(&Message::AudioHeader(header).encode())?
+    else {
+        panic!("AUDIO_HEADER not decoded");
+    };
+    assert_eq!(decoded, header);
+    Ok(())
+}
+
+#[test]
+fn test_reject_invalid_wav() -> Result<()> {
+    std::fs::write("/tmp/test_input_invalid.wav", b"RIFF\x04\0\0\0WAVX")?;
+    assert!(
+        WavFileRead::new()
+            .open_file("/tmp/test_input_invalid.wav")
+            .is_err()
+    );
+    assert!(wav_duration("/tmp/test_input_invalid.wav").is_err());
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use streamapp::audio::convert::bytes_to_f32;
+use streamapp::audio::file::{AudioReader, AudioWriter};
+use streamapp::audio::wav::{WavFileRead, WavFileWrite};
+use streamapp::protocol::AudioHeader;
+
+const PATH_OUTPUT: &str = "/tmp/test_wav_write_batch.wav";
+const PATH_INPUT_24: &str = "/tmp/test_input_24bit.wav";
+const PATH_OUTPUT_24: &str = "/tmp/test_output_24bit.wav";
+const PATH_OUTPUT_ATOMIC: &str = "/tmp/test_wav_write_atomic.wav";
+const PATH_OUTPUT_FLUSH: &str = "/tmp/test_wav_write_flush.wav";
+
+#[test]
+fn test_no_samples_lost_across_batches() -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let header = AudioHeader::from_wav_spec(&spec);
+    let samples: Vec<i16> = (0..10_000).map(|i| (i % 2000 - 1000) as i16).collect();
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+    // Odd chunk and batch sizes split samples across both write calls and
+    // batch boundaries, and leave a partial batch for finalize to flush.
+    let mut writer = WavFileWrite::with_batch_size(PATH_OUTPUT.to_string(), 1001);
+    writer.update_format(&header)?;
+    for chunk in data.chunks(3) {
+        writer.write(chunk)?;
+    }
+    writer.finalize()?;
+
+    let mut reader = hound::WavReader::open(PATH_OUTPUT)?;
+    let written: Vec<i16> = reader.samples::<i16>().collect::<Result<_, _>>()?;
+    assert_eq!(written, samples);
+    Ok(())
+}
+
+#[test]
+fn test_24bit_round_trip() -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 48000,
+        bits_per_sample: 24,
+        sample_format: hound::SampleFormat::Int,
+    };
+    // Covers both ends of the 24-bit range
+    let samples: Vec<i32> = (0..10_000)
+        .map(|i| (i * 1677) % 16_777_216 - 8_388_608)
+        .collect();
+    let mut writer = hound::WavWriter::create(PATH_INPUT_24, spec)?;
+    for &sample in &samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+
+    let mut reader = WavFileRead::new();
+    reader.open_file(PATH_INPUT_24)?;
+    let mut header = AudioHeader::new();
+    reader.update_header(&mut header);
+    assert_eq!(header.get_bits_per_sample(), 24);
+
+    // Packed as 3 bytes per sample, and written back in chunks that split
+    // samples
+    let mut writer = WavFileWrite::with_batch_size(PATH_OUTPUT_24.to_string(), 1001);
+    writer.update_format(&header)?;
+    let mut data = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        assert_eq!(n % 3, 0);
+        data.extend_from_slice(&buffer[..n]);
+    }
+    assert_eq!(data.len(), samples.len() * 3);
+    // Positions count frames, not samples
+    assert_eq!(reader.total_samples(), samples.len() as u64 / 2);
+    assert_eq!(reader.position(), reader.total_samples());
+    reader.seek_to_sample(1000)?;
+    assert_eq!(reader.position(), 1000);
+    reader.seek_to_sample(0)?;
+    for chunk in data.chunks(4) {
+        writer.write(chunk)?;
+    }
+    writer.finalize()?;
+
+    let mut reader = hound::WavReader::open(PATH_OUTPUT_24)?;
+    assert_eq!(reader.spec(), spec);
+    let written: Vec<i32> = reader.samples::<i32>().collect::<Result<_, _>>()?;
+    assert_eq!(written, samples);
+
+    let converted = bytes_to_f32(&data[..6], &header)?;
+    assert_eq!(converted, vec![-1.0, samples[1] as f32 / 8_388_608.0]);
+    Ok(())
+}
+
+#[test]
+fn test_atomic_write() -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 8000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let header = AudioHeader::from_wav_spec(&spec);
+    let temporary = format!("{}.tmp", PATH_OUTPUT_ATOMIC);
+    let _ = std::fs::remove_file(PATH_OUTPUT_ATOMIC);
+
+    let mut writer = WavFileWrite::new_atomic(PATH_OUTPUT_ATOMIC.to_string());
+    writer.update_format(&header)?;
+    writer.write(&[1, 0, 2, 0])?;
+    // Nothing at the final path until the file is complete
+    assert!(!std::path::Path::new(PATH_OUTPUT_ATOMIC).exists());
+    assert!(std::path::Path::new(&temporary).exists());
+
+    writer.finalize()?;
+    assert!(!std::path::Path::new(&temporary).exists());
+    let mut reader = hound::WavReader::open(PATH_OUTPUT_ATOMIC)?;
+    let written: Vec<i16> = reader.samples::<i16>().collect::<Result<_, _>>()?;
+    assert_eq!(written, [1, 2]);
+    Ok(())
+}
+
+#[test]
+fn test_flush_before_finalize() -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 8000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let header = AudioHeader::from_wav_spec(&spec);
+    let read_back = || -> Result<Vec<i16>> {
+        let mut reader = hound::WavReader::open(PATH_OUTPUT_FLUSH)?;
+        Ok(reader.samples::<i16>().collect::<Result<_, _>>()?)
+    };
+
+    let mut writer = WavFileWrite::new(PATH_OUTPUT_FLUSH.to_string());
+    writer.set_flush_interval(8);
+    writer.update_format(&header)?;
+    writer.write(&[1, 0, 2, 0])?;
+    // Reaching the interval flushes on its own
+    writer.write(&[3, 0, 4, 0])?;
+    assert_eq!(read_back()?, [1, 2, 3, 4]);
+
+    writer.write(&[5, 0])?;
+    writer.flush()?;
+    assert_eq!(read_back()?, [1, 2, 3, 4, 5]);
+    writer.finalize()?;
+    Ok(())
+}
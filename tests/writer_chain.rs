@@ -0,0 +1,133 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use streamapp::audio::file::{AudioWriter, AudioWriterChain};
+use streamapp::client::client_manager;
+use streamapp::protocol::AudioHeader;
+use streamapp::server::server_manager;
+
+const ADDRESS: &str = "localhost";
+const PORT: u16 = 8121;
+const PATH_INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_input.wav");
+const PATH_OUTPUT: &str = "/tmp/test_output_writer_chain.wav";
+const PATH_UNWRITABLE: &str = "/tmp/no_such_directory/test_output_writer_chain.wav";
+
+// Records the calls it gets, failing writes when `fail` is set.
+struct MockWriter {
+    calls: Arc<Mutex<Vec<&'static str>>>,
+    fail: bool,
+}
+
+impl AudioWriter for MockWriter {
+    fn write(&mut self, _data: &[u8]) -> Result<()> {
+        self.calls.lock().unwrap().push("write");
+        if self.fail {
+            return Err(anyhow::anyhow!("write failed"));
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.calls.lock().unwrap().push("finalize");
+        Ok(())
+    }
+
+    fn update_format(&mut self, _header: &AudioHeader) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Not ready for its first `busy_polls` polls, like playback with a full
+// buffer.
+struct SlowWriter {
+    busy_polls: usize,
+}
+
+impl AudioWriter for SlowWriter {
+    fn write(&mut self, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn update_format(&mut self, _header: &AudioHeader) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll_ready(&mut self) -> Result<bool> {
+        if self.busy_polls == 0 {
+            return Ok(true);
+        }
+        self.busy_polls -= 1;
+        Ok(false)
+    }
+}
+
+#[tokio::test]
+async fn test_ready_waits_without_blocking_the_task() -> Result<()> {
+    let mut chain = AudioWriterChain::new(vec![Box::new(SlowWriter { busy_polls: 5 })]);
+    // Shares the single thread of the test runtime, so it only runs if the
+    // wait yields
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&ticks);
+    tokio::spawn(async move {
+        loop {
+            counted.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+        }
+    });
+    chain.ready().await?;
+    assert!(ticks.load(Ordering::SeqCst) > 0);
+    assert_eq!(chain.working(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_failing_writer_does_not_stop_others() -> Result<()> {
+    let failing = Arc::new(Mutex::new(vec![]));
+    let working = Arc::new(Mutex::new(vec![]));
+    let mut chain = AudioWriterChain::new(vec![
+        Box::new(MockWriter {
+            calls: Arc::clone(&failing),
+            fail: true,
+        }),
+        Box::new(MockWriter {
+            calls: Arc::clone(&working),
+            fail: false,
+        }),
+    ]);
+
+    assert!(chain.write(&[0, 0]).is_err());
+    assert_eq!(chain.working(), 1);
+    // The failed writer is skipped, but still finalized
+    chain.write(&[0, 0])?;
+    chain.finalize()?;
+    assert_eq!(*failing.lock().unwrap(), ["write", "finalize"]);
+    assert_eq!(*working.lock().unwrap(), ["write", "write", "finalize"]);
+    assert_eq!(chain.working(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_broken_capability_does_not_end_stream() -> Result<()> {
+    let server =
+        server_manager::Server::new(ADDRESS.to_string(), PORT, PATH_INPUT.to_string()).await?;
+    tokio::spawn(Arc::new(server).run());
+
+    let mut client = client_manager::ClientInterface::connect(ADDRESS.to_string(), PORT).await?;
+    client
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_UNWRITABLE.to_string(),
+        ))
+        .add_capability(client_manager::Capabilities::SaveToFile(
+            PATH_OUTPUT.to_string(),
+        ));
+    client.start_playing().await?;
+
+    let input = hound::WavReader::open(PATH_INPUT)?;
+    let output = hound::WavReader::open(PATH_OUTPUT)?;
+    assert_eq!(output.len(), input.len());
+    Ok(())
+}